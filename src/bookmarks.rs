@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+
+/// Named directories for `cd @NAME`, by name
+pub type Bookmarks = BTreeMap<String, PathBuf>;
+
+/// File the bookmarks are kept in, shared by every session
+pub fn file() -> Option<PathBuf> {
+    config::data_dir().map(|dir| dir.join("bookmarks.toml"))
+}
+
+/// Bookmarks saved in `file`; none if it is missing or unreadable
+pub fn load(file: &Path) -> Bookmarks {
+    match fs::read_to_string(file) {
+        Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring bookmarks in {}: {}", file.display(), e);
+            Bookmarks::new()
+        }),
+        Err(_) => Bookmarks::new(),
+    }
+}
+
+/// Bookmarks from the default file
+pub fn load_default() -> Bookmarks {
+    file().map(|file| load(&file)).unwrap_or_default()
+}
+
+/// Write `bookmarks` to `file`, replacing it in one step
+pub fn save(file: &Path, bookmarks: &Bookmarks) -> io::Result<()> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let text = toml::to_string(bookmarks).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let partial = file.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&partial, text)?;
+    fs::rename(&partial, file)
+}
+
+/// Letters, digits, `-`, `_` and `.`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// The directory `@NAME` or `@NAME/rest` refers to; `None` if `arg` doesn't
+/// start with `@` or names no bookmark
+pub fn resolve(arg: &str, bookmarks: &Bookmarks) -> Option<PathBuf> {
+    let (name, rest) = match arg.strip_prefix('@')?.split_once('/') {
+        Some((name, rest)) => (name, rest),
+        None => (&arg[1..], ""),
+    };
+    let dir = bookmarks.get(name)?;
+    Some(if rest.is_empty() { dir.clone() } else { dir.join(rest) })
+}
+
+/// Absolute form of a directory given to `bookmark add`, expanding a leading `~`
+pub fn absolute(dir: &str) -> io::Result<PathBuf> {
+    let path = match dir.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => match dirs::home_dir() {
+            Some(home) => home.join(rest.trim_start_matches('/')),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no home directory")),
+        },
+        _ => PathBuf::from(dir),
+    };
+    let path = if path.is_absolute() { path } else { env::current_dir()?.join(path) };
+    if !path.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "not a directory"));
+    }
+    // Without `..` and symlinks, so the bookmark stays put
+    path.canonicalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load_and_resolve() {
+        let dir = std::env::temp_dir().join(format!("shell-t-bookmarks-{}", std::process::id()));
+        let file = dir.join("data").join("bookmarks.toml");
+        assert!(load(&file).is_empty());
+
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.insert("work".to_string(), dir.clone());
+        bookmarks.insert("my-notes".to_string(), PathBuf::from("/srv/notes"));
+        save(&file, &bookmarks).unwrap();
+        assert_eq!(load(&file), bookmarks);
+
+        assert_eq!(resolve("@work", &bookmarks), Some(dir.clone()));
+        assert_eq!(resolve("@my-notes/2024/", &bookmarks), Some(PathBuf::from("/srv/notes/2024/")));
+        assert_eq!(resolve("@nope", &bookmarks), None);
+        assert_eq!(resolve("work", &bookmarks), None);
+
+        assert!(is_valid_name("my-notes.v2") && !is_valid_name("a/b") && !is_valid_name(""));
+        assert_eq!(absolute(&dir.join("data").join("..").display().to_string()).unwrap(), dir.canonicalize().unwrap());
+        assert!(absolute(&file.display().to_string()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,33 @@
 use std::env;
-use std::path::PathBuf;
-use std::process;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
 
 use std::sync::Arc;
+use std::time::Instant;
 use crate::security::SecurityManager;
-use crate::config::Config;
-use crate::error::{ShellResult, ShellError};
+use crate::bookmarks;
+use crate::completions::Completer;
+use crate::config::{CompletionRule, Config, OutputMode};
+use crate::container::Container;
+use crate::fetch;
+use crate::schedule::{self, Cron, Schedule};
+use crate::history::{self, History};
+use crate::inline::Language;
+use crate::jobs::{JobManager, JobState};
+use crate::persistent::InterpreterPool;
+use crate::picker;
+use crate::script::is_name;
+use crate::state::{parse_assignment, ShellState};
+use crate::error::{self, catalog, ShellError, ShellResult};
+use crate::executor;
+use crate::logging;
+use crate::signals;
+use crate::ssh::RemoteCommand;
+use crate::telemetry;
+use crate::trash::{self, Trash};
+use crate::envdiff::{self, Snapshot};
+use crate::warnings::{self, WarningCategory, WarningLevel};
+use serde_json::json;
 
 /// Built-in command types
 #[derive(Debug, Clone, PartialEq)]
@@ -15,44 +37,103 @@ pub enum BuiltinCommand {
     Exit,
     Help,
     History,
+    Hist,
+    Complete,
+    Rehash,
+    Bookmark,
+    SshRun,
+    In,
+    Fetch,
+    Config,
+    Security,
+    Schedule,
+    Trash,
+    Restore,
     Alias,
     Unalias,
     Export,
     Unset,
+    Envdiff,
     Jobs,
     Fg,
     Bg,
     Kill,
     Which,
     Type,
+    LogLevel,
+    Doctor,
+    Explain,
+    Set,
+    Test,
+    Lang,
+    Shift,
 }
 
 impl BuiltinCommand {
+    /// Names `from_name` accepts, for command-name completion
+    pub const NAMES: &[&str] = &[
+        "cd", "pwd", "exit", "help", "history", "hist", "complete", "rehash", "bookmark", "ssh-run", "in", "fetch", "config", "security", "schedule", "trash", "restore", "alias", "unalias", "export", "unset", "envdiff", "jobs",
+        "fg", "bg", "kill", "which", "type", "log-level", "doctor", "explain", "set", "test", "[", "lang", "shift",
+    ];
+
+    /// Builtins taking `--output text|json`
+    fn is_structured(&self) -> bool {
+        matches!(
+            self,
+            BuiltinCommand::Jobs
+                | BuiltinCommand::History
+                | BuiltinCommand::Which
+                | BuiltinCommand::Type
+                | BuiltinCommand::Config
+                | BuiltinCommand::Security
+        )
+    }
+
     /// Convert string to builtin command
-    pub fn from_str(s: &str) -> Option<Self> {
+    pub fn from_name(s: &str) -> Option<Self> {
         match s {
             "cd" => Some(BuiltinCommand::Cd),
             "pwd" => Some(BuiltinCommand::Pwd),
             "exit" => Some(BuiltinCommand::Exit),
             "help" => Some(BuiltinCommand::Help),
             "history" => Some(BuiltinCommand::History),
+            "hist" => Some(BuiltinCommand::Hist),
+            "complete" => Some(BuiltinCommand::Complete),
+            "rehash" => Some(BuiltinCommand::Rehash),
+            "bookmark" => Some(BuiltinCommand::Bookmark),
+            "ssh-run" => Some(BuiltinCommand::SshRun),
+            "in" => Some(BuiltinCommand::In),
+            "fetch" => Some(BuiltinCommand::Fetch),
+            "config" => Some(BuiltinCommand::Config),
+            "security" => Some(BuiltinCommand::Security),
+            "schedule" => Some(BuiltinCommand::Schedule),
+            "trash" => Some(BuiltinCommand::Trash),
+            "restore" => Some(BuiltinCommand::Restore),
             "alias" => Some(BuiltinCommand::Alias),
             "unalias" => Some(BuiltinCommand::Unalias),
             "export" => Some(BuiltinCommand::Export),
             "unset" => Some(BuiltinCommand::Unset),
+            "envdiff" => Some(BuiltinCommand::Envdiff),
             "jobs" => Some(BuiltinCommand::Jobs),
             "fg" => Some(BuiltinCommand::Fg),
             "bg" => Some(BuiltinCommand::Bg),
             "kill" => Some(BuiltinCommand::Kill),
             "which" => Some(BuiltinCommand::Which),
             "type" => Some(BuiltinCommand::Type),
+            "log-level" => Some(BuiltinCommand::LogLevel),
+            "doctor" => Some(BuiltinCommand::Doctor),
+            "explain" => Some(BuiltinCommand::Explain),
+            "set" => Some(BuiltinCommand::Set),
+            "test" | "[" => Some(BuiltinCommand::Test),
+            "lang" => Some(BuiltinCommand::Lang),
+            "shift" => Some(BuiltinCommand::Shift),
             _ => None,
         }
     }
 
     /// Check if a string represents a builtin command
     pub fn is_builtin(s: &str) -> bool {
-        Self::from_str(s).is_some()
+        Self::from_name(s).is_some()
     }
 }
 
@@ -63,62 +144,165 @@ pub enum BuiltinResult {
     Error(String),
     Info(String),
     Warning(String),
-    Exit,
+    /// Silent result with an explicit exit status, as from `test`
+    Status(i32),
+    /// Leave the shell with this status
+    Exit(i32),
 }
 
 /// Manager for built-in commands
 pub struct BuiltinManager {
     security: Arc<SecurityManager>,
     config: Config,
+    state: Arc<ShellState>,
+    jobs: Arc<JobManager>,
+    history: Arc<History>,
+    completer: Arc<Completer>,
 }
 
 impl BuiltinManager {
     /// Create a new builtin manager
-    pub fn new(security: Arc<SecurityManager>, config: Config) -> Self {
-        Self { security, config }
+    pub fn new(
+        security: Arc<SecurityManager>,
+        config: Config,
+        state: Arc<ShellState>,
+        jobs: Arc<JobManager>,
+        history: Arc<History>,
+        completer: Arc<Completer>,
+    ) -> Self {
+        Self { security, config, state, jobs, history, completer }
+    }
+
+    /// Act on the jobs in `jobs` from now on, as when switching tabs
+    pub fn set_jobs(&mut self, jobs: Arc<JobManager>) {
+        self.jobs = jobs;
+    }
+
+    /// Run a built-in command with what it prints going to `out` rather than
+    /// the terminal, as a pipeline stage or with its output redirected;
+    /// returns its exit status, or `None` if `command` isn't a builtin. Errors
+    /// still go to stderr, and `exit` only ends the stage.
+    pub fn execute_builtin_to(&self, command: &str, args: &[String], out: &mut dyn Write) -> ShellResult<Option<i32>> {
+        let Some(result) = self.execute_builtin(command, args)? else {
+            return Ok(None);
+        };
+        let status = match result {
+            BuiltinResult::Success(None) => 0,
+            BuiltinResult::Success(Some(text)) | BuiltinResult::Info(text) => {
+                writeln!(out, "{}", text)?;
+                0
+            }
+            BuiltinResult::Error(msg) => {
+                eprintln!("{}", msg);
+                1
+            }
+            BuiltinResult::Warning(msg) => {
+                warnings::warn(WarningLevel::Warning, WarningCategory::Builtin, &msg);
+                0
+            }
+            BuiltinResult::Status(status) | BuiltinResult::Exit(status) => status,
+        };
+        out.flush()?;
+        Ok(Some(status))
     }
 
     /// Execute a built-in command
     pub fn execute_builtin(&self, command: &str, args: &[String]) -> ShellResult<Option<BuiltinResult>> {
-        let builtin_cmd = match BuiltinCommand::from_str(command) {
+        // Under safe delete, the `rm` the policy blocks moves files to the trash
+        if command == "rm" && self.config.trash.safe_delete && self.config.security.blocked_commands.contains("rm") {
+            return Ok(Some(self.execute_rm(args)));
+        }
+        let builtin_cmd = match BuiltinCommand::from_name(command) {
             Some(cmd) => cmd,
             None => return Ok(None),
         };
+        tracing::debug!(builtin = command, ?args, "executing builtin");
+
+        let (format, args) = if builtin_cmd.is_structured() {
+            match self.output_format(args) {
+                Ok((format, args)) => (format, args),
+                Err(e) => return Ok(Some(BuiltinResult::Error(format!("{}: {}", command, e)))),
+            }
+        } else {
+            (Format::Text, args.to_vec())
+        };
+        let args = args.as_slice();
 
         match builtin_cmd {
             BuiltinCommand::Cd => Ok(Some(self.execute_cd(args)?)),
             BuiltinCommand::Pwd => Ok(Some(self.execute_pwd()?)),
-            BuiltinCommand::Exit => Ok(Some(BuiltinResult::Exit)),
+            BuiltinCommand::Exit => Ok(Some(self.execute_exit(args))),
             BuiltinCommand::Help => Ok(Some(self.execute_help()?)),
-            BuiltinCommand::History => Ok(Some(self.execute_history()?)),
+            BuiltinCommand::History => Ok(Some(self.execute_history(args, format))),
+            BuiltinCommand::Hist => Ok(Some(self.execute_hist(args))),
+            BuiltinCommand::Complete => Ok(Some(self.execute_complete(args))),
+            BuiltinCommand::Rehash => Ok(Some(self.execute_rehash(args))),
+            BuiltinCommand::Bookmark => Ok(Some(self.execute_bookmark(args))),
+            BuiltinCommand::SshRun => Ok(Some(self.execute_ssh_run(args)?)),
+            BuiltinCommand::In => Ok(Some(self.execute_in(args))),
+            BuiltinCommand::Fetch => Ok(Some(self.execute_fetch(args)?)),
+            BuiltinCommand::Config => Ok(Some(self.execute_config(args, format))),
+            BuiltinCommand::Security => Ok(Some(self.execute_security(args, format))),
+            BuiltinCommand::Schedule => Ok(Some(self.execute_schedule(args))),
+            BuiltinCommand::Trash => Ok(Some(self.execute_trash(args))),
+            BuiltinCommand::Restore => Ok(Some(self.execute_restore(args))),
             BuiltinCommand::Alias => Ok(Some(self.execute_alias(args)?)),
             BuiltinCommand::Unalias => Ok(Some(self.execute_unalias(args)?)),
             BuiltinCommand::Export => Ok(Some(self.execute_export(args)?)),
             BuiltinCommand::Unset => Ok(Some(self.execute_unset(args)?)),
-            BuiltinCommand::Jobs => Ok(Some(self.execute_jobs()?)),
-            BuiltinCommand::Fg => Ok(Some(self.execute_fg(args)?)),
-            BuiltinCommand::Bg => Ok(Some(self.execute_bg(args)?)),
-            BuiltinCommand::Kill => Ok(Some(self.execute_kill(args)?)),
-            BuiltinCommand::Which => Ok(Some(self.execute_which(args)?)),
-            BuiltinCommand::Type => Ok(Some(self.execute_type(args)?)),
+            BuiltinCommand::Envdiff => Ok(Some(self.execute_envdiff(args))),
+            BuiltinCommand::Jobs => Ok(Some(self.execute_jobs(args, format))),
+            BuiltinCommand::Fg => Ok(Some(self.execute_fg(args))),
+            BuiltinCommand::Bg => Ok(Some(self.execute_bg(args))),
+            BuiltinCommand::Kill => Ok(Some(self.execute_kill(args))),
+            BuiltinCommand::Which => Ok(Some(self.execute_which(args, format)?)),
+            BuiltinCommand::Type => Ok(Some(self.execute_type(args, format)?)),
+            BuiltinCommand::LogLevel => Ok(Some(self.execute_log_level(args)?)),
+            BuiltinCommand::Doctor => Ok(Some(self.execute_doctor()?)),
+            BuiltinCommand::Explain => Ok(Some(self.execute_explain(args)?)),
+            BuiltinCommand::Set => Ok(Some(self.execute_set(args)?)),
+            BuiltinCommand::Test => Ok(Some(self.execute_test(command, args)?)),
+            BuiltinCommand::Lang => Ok(Some(self.execute_lang(args)?)),
+            BuiltinCommand::Shift => Ok(Some(self.execute_shift(args))),
         }
     }
 
-    /// Execute cd command
+    /// Execute cd command. `cd -` goes back to `$OLDPWD` and prints it; every
+    /// change sets `OLDPWD` and `PWD`.
     fn execute_cd(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+        let back = args.first().is_some_and(|arg| arg == "-");
         let path = if args.is_empty() {
             match env::var("HOME") {
                 Ok(home) => home,
                 Err(_) => return Ok(BuiltinResult::Error("HOME environment variable not set".to_string())),
             }
+        } else if back {
+            match env::var("OLDPWD") {
+                Ok(previous) => previous,
+                Err(_) => return Ok(BuiltinResult::Error("cd: OLDPWD not set".to_string())),
+            }
+        } else if args[0].starts_with('@') {
+            match bookmarks::resolve(&args[0], &bookmarks::load_default()) {
+                Some(dir) => dir.display().to_string(),
+                None => return Ok(BuiltinResult::Error(format!("cd: {}: no such bookmark", args[0]))),
+            }
         } else {
             args[0].clone()
         };
 
-        match env::set_current_dir(&path) {
-            Ok(_) => Ok(BuiltinResult::Success(None)),
-            Err(e) => Ok(BuiltinResult::Error(format!("cd: {}: {}", path, e))),
+        let previous = env::current_dir().ok();
+        if let Err(e) = env::set_current_dir(&path) {
+            return Ok(BuiltinResult::Error(format!("cd: {}: {}", path, e)));
+        }
+        if let Some(previous) = previous {
+            env::set_var("OLDPWD", previous);
         }
+        let now = env::current_dir().map_or(path, |dir| dir.display().to_string());
+        env::set_var("PWD", &now);
+        Ok(match back {
+            true => BuiltinResult::Info(now),
+            false => BuiltinResult::Success(None),
+        })
     }
 
     /// Execute pwd command
@@ -134,26 +318,82 @@ impl BuiltinManager {
         let help_text = r#"Shell-T Built-in Commands:
 
 Navigation:
-  cd <dir>          Change directory
+  cd <dir>          Change directory (cd @NAME goes to a bookmark, cd - back
+                    to the previous one, $OLDPWD)
+  bookmark add NAME [DIR]  Bookmark DIR (default: here) for cd @NAME
+                    (bookmark lists them, bookmark rm NAME removes one)
   pwd               Print working directory
 
+Remote:
+  ssh-run HOST CMD  Run a policy-checked command on HOST over ssh (audited)
+  fetch [-X M] [-H H] [-d DATA] [-o FILE] URL
+                    HTTP request under the [fetch] policy (audited)
+  in docker:NAME [CMD]  Run CMD, or later commands until `in -`, in a container
+                    (podman:NAME for podman)
+
 Process Control:
-  jobs              List background jobs
-  fg [JOB]          Bring job to foreground
-  bg [JOB]          Send job to background
-  kill [PID]        Kill a process
+  CMD &             Run a command in the background
+  jobs [-l|-p]      List background jobs (jobs -o %N shows a job's output)
+  fg [JOB]          Bring job to foreground (JOB: %N, %+, %-, %name)
+  bg [JOB]          Resume a stopped job in the background
+  kill [-SIG] JOB   Signal a job (%N) or process ID (kill -l lists signals)
+  tab new [NAME]    Open a tab with its own jobs, directory and history
+                    (tab next, tab prev and tab N switch; tab split adds a
+                    pane below, tab focus moves between panes, tab close
+                    closes one; tab lists them)
 
 Environment:
-  export KEY=VALUE  Set environment variable
-  unset KEY         Unset environment variable
+  NAME=VALUE        Set a shell variable (expand with $NAME or ${NAME})
+  export NAME[=VAL] Export a variable to the environment
+  unset NAME        Remove a variable
+  envdiff snapshot  Save the variables and directory to compare against
+                    (envdiff show lists what changed since, envdiff revert
+                    undoes changes to exported variables)
+  set [-e|+e]       List variables, or change options (set -o lists them,
+                    set -o jsonpipes passes JSON records between scripts;
+                    set -f stops *.rs expanding, nullglob and failglob
+                    drop or refuse patterns matching nothing, dotglob
+                    lets * match .hidden names; set -C
+                    keeps > from replacing files, >| still does;
+                    set -o nosplit keeps $VAR one word; set -o pipefail
+                    fails a pipeline if any command in it fails)
+  source FILE [ARG...]
+                    Run a script in this shell (also . FILE); remote
+                    scripts need a pin: source URL sha256=HASH
+  shift [N]         Drop the first N script arguments ($1, $2, ..., $#, $@)
 
 Utilities:
-  alias             Manage command aliases
-  history           Show command history
+  alias [NAME=VAL]  List or define command aliases
+  unalias NAME      Remove an alias
+  history [N]       Show command history, or its last N entries
+                    (-v: time, duration, status and directory; --failed,
+                    --here: only failed commands / those run in this directory)
+                    history import FILE / history export [--format zsh] [FILE]
+                    convert bash, zsh and shell-t (json) history files
+  hist [QUERY]      Fuzzy-find a command in history to edit (also Ctrl-R)
+  complete -W WORDS NAME  Complete NAME's arguments from a word list
+                    (-G PATTERN: matching files, -C COMMAND: its output lines;
+                    complete -r NAME removes it, complete lists them)
+  rehash [-v]       Forget where commands were found and rescan PATH
   which COMMAND     Locate a command
+  lang LANGUAGE     Switch to a language REPL (lang python; exit returns)
+  test EXPR         Evaluate a condition, also [ EXPR ] (-f FILE, -z STR, =, -lt, ...)
   type COMMAND      Show command type
+  schedule "CRON" CMD  Run a script or command on a cron schedule
+                    (schedule list shows them, schedule rm ID removes one)
+  trash [list]      Show what rm moved to the trash under safe delete
+                    (trash empty deletes it for good)
+  restore ID        Put a trashed file back where it was deleted from
+  config [SECTION]  Show the effective configuration
+  security stats    Show commands run this session, with counts and times
+  log-level [LEVEL] Show or set log verbosity (e.g. debug, executor=trace)
+  doctor            Diagnose recent failures and configuration problems
+  explain CODE      Describe an error code (e.g. explain ST0302)
   help              Show this help
-  exit              Exit the shell
+  exit [N]          Exit the shell with status N (default: last status)
+
+jobs, history, which, type, config and security stats take --output json
+for machine-readable results (the default when stdout is not a terminal).
 
 Security Features:
 - Input validation and sanitization
@@ -167,95 +407,1362 @@ For more information, see the documentation."#;
         Ok(BuiltinResult::Info(help_text.to_string()))
     }
 
-    /// Execute history command
-    fn execute_history(&self) -> ShellResult<BuiltinResult> {
-        Ok(BuiltinResult::Info("Command history not yet implemented".to_string()))
+    /// Execute history command: `history [-v|--verbose] [--failed] [--here] [N]`
+    /// lists the last N entries, with their metadata, only failed commands or
+    /// only those run in the current directory
+    fn execute_history(&self, args: &[String], format: Format) -> BuiltinResult {
+        match args.first().map(String::as_str) {
+            Some("import") => return self.history_import(&args[1..]),
+            Some("export") => return self.history_export(&args[1..]),
+            _ => {}
+        }
+        let (mut verbose, mut failed, mut here, mut count) = (false, false, false, None);
+        for arg in args {
+            match arg.as_str() {
+                "-v" | "--verbose" => verbose = true,
+                "--failed" => failed = true,
+                "--here" => here = true,
+                n if count.is_none() && n.parse::<usize>().is_ok() => count = n.parse().ok(),
+                _ => {
+                    let usage = "history: usage: history [-v|--verbose] [--failed] [--here] [N]";
+                    return BuiltinResult::Error(usage.to_string());
+                }
+            }
+        }
+        let cwd = env::current_dir().ok();
+
+        let entries = self.history.entries();
+        let mut selected: Vec<_> = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !failed || entry.failed())
+            .filter(|(_, entry)| !here || (entry.cwd.is_some() && entry.cwd == cwd))
+            .collect();
+        if let Some(count) = count {
+            selected.drain(..selected.len().saturating_sub(count));
+        }
+        if format == Format::Json {
+            let list: Vec<_> = selected
+                .iter()
+                .map(|(index, entry)| {
+                    let mut value = json!(entry);
+                    value["index"] = json!(index + 1);
+                    value
+                })
+                .collect();
+            return json_result(list);
+        }
+        let lines: Vec<String> = selected.iter().map(|(index, entry)| entry.line(index + 1, verbose)).collect();
+        BuiltinResult::Success((!lines.is_empty()).then(|| lines.join("\n")))
+    }
+
+    /// `history import FILE [--format bash|zsh|json]`: add another shell's
+    /// history, detecting its format unless given
+    fn history_import(&self, args: &[String]) -> BuiltinResult {
+        let (format, files) = match history_format(args) {
+            Ok(parsed) => parsed,
+            Err(e) => return BuiltinResult::Error(e),
+        };
+        let [file] = files.as_slice() else {
+            return BuiltinResult::Error("history: usage: history import FILE [--format bash|zsh|json]".to_string());
+        };
+        let data = match std::fs::read(file) {
+            Ok(data) => data,
+            Err(e) => return BuiltinResult::Error(format!("history: {}: {}", file, e)),
+        };
+        let format = format.unwrap_or_else(|| history::Format::detect(&data));
+        let count = self.history.import(history::import(&data, format));
+        BuiltinResult::Info(format!("history: imported {} entries from {}", count, file))
+    }
+
+    /// `history export [--format bash|zsh|json] [FILE]`: write history in
+    /// another shell's format (bash by default), to FILE or stdout
+    fn history_export(&self, args: &[String]) -> BuiltinResult {
+        let (format, files) = match history_format(args) {
+            Ok(parsed) => parsed,
+            Err(e) => return BuiltinResult::Error(e),
+        };
+        let data = history::export(&self.history.entries(), format.unwrap_or(history::Format::Bash));
+        match files.as_slice() {
+            [] => BuiltinResult::Success(Some(String::from_utf8_lossy(&data).trim_end_matches('\n').to_string())),
+            [file] => match std::fs::write(file, data) {
+                Ok(()) => BuiltinResult::Success(None),
+                Err(e) => BuiltinResult::Error(format!("history: {}: {}", file, e)),
+            },
+            _ => BuiltinResult::Error("history: usage: history export [--format bash|zsh|json] [FILE]".to_string()),
+        }
+    }
+
+    /// Execute hist command: pick a command from history with the fuzzy
+    /// finder and put it in the next prompt's edit buffer
+    fn execute_hist(&self, args: &[String]) -> BuiltinResult {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return BuiltinResult::Error("hist: not a terminal".to_string());
+        }
+        let mut commands: Vec<String> = self.history.entries().into_iter().rev().map(|entry| entry.command).collect();
+        commands.dedup();
+        match picker::pick(&commands, &args.join(" ")) {
+            Ok(Some(command)) => {
+                self.state.set_next_input(&command);
+                BuiltinResult::Success(None)
+            }
+            Ok(None) => BuiltinResult::Status(1),
+            Err(e) => BuiltinResult::Error(format!("hist: {}", e)),
+        }
+    }
+
+    /// Execute complete command: `complete -W WORDS | -G PATTERN | -C COMMAND NAME...`
+    /// sets where NAME's arguments complete from, `complete -r [NAME...]`
+    /// removes rules and `complete [-p]` lists them
+    fn execute_complete(&self, args: &[String]) -> BuiltinResult {
+        let usage = "complete: usage: complete [-W WORDS | -G PATTERN | -C COMMAND] NAME... | complete -r [NAME...]";
+        match args.first().map(String::as_str) {
+            None | Some("-p") if args.len() <= 1 => {
+                let listing: Vec<String> = self
+                    .completer
+                    .rules()
+                    .into_iter()
+                    .map(|(name, rule)| match rule {
+                        CompletionRule::Words(words) => format!("complete -W '{}' {}", words.join(" "), name),
+                        CompletionRule::Glob(pattern) => format!("complete -G '{}' {}", pattern, name),
+                        CompletionRule::Command(command) => format!("complete -C '{}' {}", command, name),
+                    })
+                    .collect();
+                BuiltinResult::Success((!listing.is_empty()).then(|| listing.join("\n")))
+            }
+            Some("-r") if args.len() == 1 => {
+                self.completer.clear_rules();
+                BuiltinResult::Success(None)
+            }
+            Some("-r") => {
+                let missing: Vec<&String> = args[1..].iter().filter(|name| !self.completer.remove_rule(name)).collect();
+                match missing.first() {
+                    Some(name) => BuiltinResult::Error(format!("complete: {}: no completion specification", name)),
+                    None => BuiltinResult::Success(None),
+                }
+            }
+            Some(flag @ ("-W" | "-G" | "-C")) if args.len() >= 3 => {
+                let value = args[1].clone();
+                let rule = match flag {
+                    "-W" => CompletionRule::Words(value.split_whitespace().map(String::from).collect()),
+                    "-G" => CompletionRule::Glob(value),
+                    _ => CompletionRule::Command(value),
+                };
+                for name in &args[2..] {
+                    self.completer.set_rule(name, rule.clone());
+                }
+                BuiltinResult::Success(None)
+            }
+            _ => BuiltinResult::Error(usage.to_string()),
+        }
+    }
+
+    /// Execute bookmark command: `bookmark` lists bookmarks, `bookmark add
+    /// NAME [DIR]` saves DIR (default: the current directory) as `@NAME` and
+    /// `bookmark rm NAME...` removes them
+    fn execute_bookmark(&self, args: &[String]) -> BuiltinResult {
+        let usage = "bookmark: usage: bookmark [add NAME [DIR] | rm NAME...]";
+        let Some(file) = bookmarks::file() else {
+            return BuiltinResult::Error("bookmark: no data directory".to_string());
+        };
+        let mut saved = bookmarks::load(&file);
+        match args.first().map(String::as_str) {
+            None | Some("list") if args.len() <= 1 => {
+                let width = saved.keys().map(|name| name.chars().count()).max().unwrap_or(0) + 1;
+                let listing: Vec<String> =
+                    saved.iter().map(|(name, dir)| format!("{:width$}  {}", format!("@{}", name), dir.display())).collect();
+                return BuiltinResult::Success((!listing.is_empty()).then(|| listing.join("\n")));
+            }
+            Some("add") if (2..=3).contains(&args.len()) => {
+                let name = &args[1];
+                if !bookmarks::is_valid_name(name) {
+                    return BuiltinResult::Error(format!("bookmark: {}: names may only have letters, digits, '-', '_' and '.'", name));
+                }
+                let dir = args.get(2).map_or(".", String::as_str);
+                match bookmarks::absolute(dir) {
+                    Ok(dir) => saved.insert(name.clone(), dir),
+                    Err(e) => return BuiltinResult::Error(format!("bookmark: {}: {}", dir, e)),
+                };
+            }
+            Some("rm") if args.len() >= 2 => {
+                for name in &args[1..] {
+                    if saved.remove(name.trim_start_matches('@')).is_none() {
+                        return BuiltinResult::Error(format!("bookmark: {}: no such bookmark", name));
+                    }
+                }
+            }
+            _ => return BuiltinResult::Error(usage.to_string()),
+        }
+        match bookmarks::save(&file, &saved) {
+            Ok(()) => BuiltinResult::Success(None),
+            Err(e) => BuiltinResult::Error(format!("bookmark: {}: {}", file.display(), e)),
+        }
+    }
+
+    /// Execute ssh-run command: run one policy-checked command on a remote
+    /// host, its output going straight to the terminal
+    fn execute_ssh_run(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+        let remote = match RemoteCommand::parse(args) {
+            Ok(remote) => remote,
+            Err(e) => return Ok(BuiltinResult::Error(e)),
+        };
+        remote.check(&self.config)?;
+
+        let line = remote.remote_line();
+        if self.config.security.enable_auditing {
+            let user = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            error::logging::log_command_execution(&format!("{} (ssh {})", line, remote.host), &user);
+        }
+        tracing::debug!(host = %remote.host, command = %line, "running remote command");
+
+        let start = Instant::now();
+        let _process = self.security.register_process();
+        let status = remote
+            .command(&self.config.ssh)
+            .status()
+            .map_err(|e| ShellError::Spawn { program: self.config.ssh.program.clone(), source: e })?;
+        self.security.record_command(&format!("ssh-run {}", remote.words[0]), start.elapsed());
+        Ok(BuiltinResult::Status(executor::exit_code(status)))
     }
 
-    /// Execute alias command
-    fn execute_alias(&self, _args: &[String]) -> ShellResult<BuiltinResult> {
-        Ok(BuiltinResult::Info("Command aliasing not yet implemented".to_string()))
+    /// Execute in command: `in docker:NAME` sends later external commands
+    /// into a container, `in -` brings them back and `in` shows where they
+    /// run. `in TARGET CMD...` is handled by the session, which runs CMD there.
+    fn execute_in(&self, args: &[String]) -> BuiltinResult {
+        match args {
+            [] => BuiltinResult::Success(self.state.container().map(|container| container.to_string())),
+            [target] if target == "-" => {
+                self.state.set_container(None);
+                BuiltinResult::Success(None)
+            }
+            [target] => match Container::parse(target) {
+                Ok(container) => {
+                    self.state.set_container(Some(container));
+                    BuiltinResult::Success(None)
+                }
+                Err(e) => BuiltinResult::Error(format!("in: {}", e)),
+            },
+            _ => BuiltinResult::Error("in: usage: in [docker:NAME | podman:NAME | -] [COMMAND...]".to_string()),
+        }
+    }
+
+    /// Execute fetch command: make an HTTP request allowed by `[fetch]` and
+    /// write the body to stdout or `-o FILE`; HTTP errors give status 22
+    fn execute_fetch(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+        let request = match fetch::Request::parse(args) {
+            Ok(request) => request,
+            Err(e) => return Ok(BuiltinResult::Error(e)),
+        };
+        request.check(&self.config)?;
+        if self.config.security.enable_auditing {
+            let user = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            error::logging::log_command_execution(&format!("fetch {} {}", request.method, request.url), &user);
+        }
+
+        let start = Instant::now();
+        let response = request.send(&self.config.fetch)?;
+        self.security.record_command("fetch", start.elapsed());
+        match &request.output {
+            Some(file) => fs::write(file, &response.body).map_err(|e| ShellError::Redirect { path: file.clone(), source: e })?,
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(&response.body)?;
+                stdout.flush()?;
+            }
+        }
+        if response.status >= 400 {
+            eprintln!("fetch: {}: HTTP {} {}", request.url, response.status, response.status_text);
+            return Ok(BuiltinResult::Status(22));
+        }
+        Ok(BuiltinResult::Status(0))
+    }
+
+    /// Take `--output text|json` from `args`; without it `ui.builtin_output`
+    /// decides, going by whether stdout is a terminal only when set to `auto`
+    fn output_format(&self, args: &[String]) -> Result<(Format, Vec<String>), String> {
+        let mut format = match self.config.ui.builtin_output {
+            OutputMode::Auto if io::stdout().is_terminal() => Format::Text,
+            OutputMode::Auto | OutputMode::Json => Format::Json,
+            OutputMode::Text => Format::Text,
+        };
+        let mut rest = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = match arg.strip_prefix("--output=") {
+                Some(value) => value,
+                None if arg == "--output" => args.next().map(String::as_str).unwrap_or_default(),
+                None => {
+                    rest.push(arg.clone());
+                    continue;
+                }
+            };
+            format = match value {
+                "text" => Format::Text,
+                "json" => Format::Json,
+                _ => return Err(format!("--output: expected text or json, not '{}'", value)),
+            };
+        }
+        Ok((format, rest))
+    }
+
+    /// Execute config command: print the effective configuration, or one
+    /// section of it, as TOML or JSON
+    fn execute_config(&self, args: &[String], format: Format) -> BuiltinResult {
+        let section = match args {
+            [] => None,
+            [section] => Some(section.as_str()),
+            _ => return BuiltinResult::Error("config: usage: config [SECTION] [--output text|json]".to_string()),
+        };
+        let text = match format {
+            Format::Json => serde_json::to_value(&self.config).map_err(|e| e.to_string()).and_then(|value| match section {
+                Some(section) => value.get(section).map(|value| value.to_string()).ok_or_else(|| section.to_string()),
+                None => Ok(value.to_string()),
+            }),
+            Format::Text => toml::Value::try_from(&self.config).map_err(|e| e.to_string()).and_then(|value| match section {
+                Some(section) => value
+                    .get(section)
+                    .filter(|value| value.is_table())
+                    .ok_or_else(|| section.to_string())
+                    .and_then(|value| toml::to_string(value).map_err(|e| e.to_string())),
+                None => toml::to_string(&value).map_err(|e| e.to_string()),
+            }),
+        };
+        match text {
+            Ok(text) => BuiltinResult::Success(Some(text.trim_end().to_string())),
+            Err(e) if section == Some(e.as_str()) => BuiltinResult::Error(format!("config: {}: no such section", e)),
+            Err(e) => BuiltinResult::Error(format!("config: {}", e)),
+        }
+    }
+
+    /// Execute security command: `security stats` shows the commands run
+    /// this session with their counts and times
+    fn execute_security(&self, args: &[String], format: Format) -> BuiltinResult {
+        if args.len() != 1 || args[0] != "stats" {
+            return BuiltinResult::Error("security: usage: security stats [--output text|json]".to_string());
+        }
+        let stats = self.security.stats();
+        let active = self.security.active_processes();
+        if format == Format::Json {
+            let commands: Vec<_> = stats
+                .iter()
+                .map(|(command, stats)| {
+                    json!({
+                        "command": command,
+                        "count": stats.count,
+                        "total_ms": stats.total_time.as_millis() as u64,
+                        "last_secs_ago": stats.last_execution.elapsed().as_secs(),
+                    })
+                })
+                .collect();
+            let evicted = self.security.evicted_stats().map(|(commands, stats)| {
+                json!({
+                    "commands": commands,
+                    "count": stats.count,
+                    "total_ms": stats.total_time.as_millis() as u64,
+                })
+            });
+            return json_result(json!({ "active_processes": active, "commands": commands, "evicted": evicted }));
+        }
+        let width = stats.iter().map(|(command, _)| command.chars().count()).max().unwrap_or(0).max("COMMAND".len());
+        let mut lines = vec![
+            format!("active processes: {}", active),
+            format!("{:width$}  {:>5}  {:>10}  {:>9}", "COMMAND", "COUNT", "TOTAL", "LAST"),
+        ];
+        for (command, stats) in &stats {
+            lines.push(format!(
+                "{:width$}  {:>5}  {:>9.3}s  {:>6}s ago",
+                command,
+                stats.count,
+                stats.total_time.as_secs_f64(),
+                stats.last_execution.elapsed().as_secs()
+            ));
+        }
+        if let Some((commands, stats)) = self.security.evicted_stats() {
+            lines.push(format!(
+                "({} less recent commands: {} runs, {:.3}s)",
+                commands,
+                stats.count,
+                stats.total_time.as_secs_f64()
+            ));
+        }
+        BuiltinResult::Success(Some(lines.join("\n")))
+    }
+
+    /// Execute schedule command: `schedule "CRON" COMMAND...` runs a script
+    /// or command line at the times CRON matches, `schedule list` shows the
+    /// schedule and `schedule rm ID...` removes entries
+    fn execute_schedule(&self, args: &[String]) -> BuiltinResult {
+        let usage = "schedule: usage: schedule \"MIN HOUR DAY MONTH WEEKDAY\" COMMAND... | schedule list | schedule rm ID...";
+        let Some(file) = Schedule::file() else {
+            return BuiltinResult::Error("schedule: no data directory".to_string());
+        };
+        let mut saved = Schedule::load(&file);
+        match args.first().map(String::as_str) {
+            None | Some("list") if args.len() <= 1 => {
+                let now = chrono::Local::now();
+                let lines: Vec<String> = saved
+                    .entries
+                    .iter()
+                    .map(|entry| {
+                        let next = Cron::parse(&entry.cron).ok().and_then(|cron| cron.next(&now));
+                        let next = next.map_or_else(|| "never".to_string(), |time| schedule::format_time(&time));
+                        format!("{:>3}  {:<16} {}  (next: {}, in {})", entry.id, entry.cron, entry.command, next, entry.cwd.display())
+                    })
+                    .collect();
+                return BuiltinResult::Success((!lines.is_empty()).then(|| lines.join("\n")));
+            }
+            Some("rm") if args.len() >= 2 => {
+                for id in &args[1..] {
+                    if !id.parse().is_ok_and(|id| saved.remove(id)) {
+                        return BuiltinResult::Error(format!("schedule: {}: no such entry", id));
+                    }
+                }
+            }
+            Some(cron) if args.len() >= 2 => {
+                let cron = match Cron::parse(cron) {
+                    Ok(parsed) => parsed.next(&chrono::Local::now()).map(|next| (cron, next)),
+                    Err(e) => return BuiltinResult::Error(format!("schedule: {}", e)),
+                };
+                let Some((cron, next)) = cron else {
+                    return BuiltinResult::Error(format!("schedule: {}: never fires", args[0]));
+                };
+                let cwd = match env::current_dir() {
+                    Ok(cwd) => cwd,
+                    Err(e) => return BuiltinResult::Error(format!("schedule: {}", e)),
+                };
+                let id = saved.add(cron, &args[1..].join(" "), cwd);
+                if let Err(e) = saved.save(&file) {
+                    return BuiltinResult::Error(format!("schedule: {}: {}", file.display(), e));
+                }
+                let mut message = format!("scheduled #{}, next run {}", id, schedule::format_time(&next));
+                if !self.config.schedule.enabled {
+                    message.push_str(" (schedule.enabled is off: only `shell-t --scheduler` runs it)");
+                }
+                return BuiltinResult::Success(Some(message));
+            }
+            _ => return BuiltinResult::Error(usage.to_string()),
+        }
+        match saved.save(&file) {
+            Ok(()) => BuiltinResult::Success(None),
+            Err(e) => BuiltinResult::Error(format!("schedule: {}: {}", file.display(), e)),
+        }
+    }
+
+    /// Execute `rm [-rRfiv] [--] PATH...` under safe delete: move each target
+    /// to the trash, then purge older entries past the age and size limits
+    fn execute_rm(&self, args: &[String]) -> BuiltinResult {
+        let (mut recursive, mut force, mut verbose) = (false, false, false);
+        let mut targets = Vec::new();
+        let mut options = true;
+        for arg in args {
+            match arg.strip_prefix('-') {
+                Some("-") if options => options = false,
+                Some(flags) if options && !flags.is_empty() => {
+                    for flag in flags.chars() {
+                        match flag {
+                            'r' | 'R' => recursive = true,
+                            'f' => force = true,
+                            'v' => verbose = true,
+                            // Nothing is lost for good, so there is nothing to confirm
+                            'i' | 'I' => {}
+                            _ => return BuiltinResult::Error(format!("rm: invalid option -- '{}'", flag)),
+                        }
+                    }
+                }
+                _ => targets.push(arg.as_str()),
+            }
+        }
+        if targets.is_empty() {
+            return if force { BuiltinResult::Status(0) } else { BuiltinResult::Error("rm: missing operand".to_string()) };
+        }
+        let Some(dir) = Trash::dir() else {
+            return BuiltinResult::Error("rm: no data directory for the trash".to_string());
+        };
+        if self.config.security.enable_auditing {
+            let user = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            error::logging::log_command_execution(&format!("rm {} (trash)", args.join(" ")), &user);
+        }
+
+        let mut trash = Trash::load(&dir);
+        let (mut removed, mut errors, mut added) = (Vec::new(), Vec::new(), Vec::new());
+        for target in targets {
+            let path = std::path::Path::new(target);
+            if matches!(target.trim_end_matches('/'), "" | "." | "..") {
+                errors.push(format!("rm: refusing to remove '{}'", target));
+                continue;
+            }
+            if let Err(e) = crate::security::validation::validate_file_path(target, &self.config) {
+                errors.push(format!("rm: {}: {}", target, e));
+                continue;
+            }
+            match fs::symlink_metadata(path) {
+                Err(_) if force => continue,
+                Err(_) => errors.push(format!("rm: cannot remove '{}': No such file or directory", target)),
+                Ok(metadata) if metadata.is_dir() && !recursive => {
+                    errors.push(format!("rm: cannot remove '{}': Is a directory", target));
+                }
+                Ok(_) => match trash.put(path, &self.config.trash) {
+                    Ok(id) => {
+                        removed.push(format!("removed '{}' (trash #{})", target, id));
+                        added.push(id);
+                    }
+                    Err(e) => errors.push(format!("rm: cannot remove '{}': {}", target, e)),
+                },
+            }
+        }
+        trash.purge(&self.config.trash, &added);
+        if let Err(e) = trash.save() {
+            errors.push(format!("rm: {}: {}", dir.display(), e));
+        }
+
+        if verbose && !removed.is_empty() {
+            println!("{}", removed.join("\n"));
+        }
+        if errors.is_empty() {
+            BuiltinResult::Status(0)
+        } else {
+            BuiltinResult::Error(errors.join("\n"))
+        }
+    }
+
+    /// Execute `trash [list]` or `trash empty`
+    fn execute_trash(&self, args: &[String]) -> BuiltinResult {
+        let Some(dir) = Trash::dir() else {
+            return BuiltinResult::Error("trash: no data directory".to_string());
+        };
+        let mut trash = Trash::load(&dir);
+        let purged = trash.purge(&self.config.trash, &[]);
+        let result = match args.first().map(String::as_str) {
+            None | Some("list") if args.len() <= 1 => {
+                let lines: Vec<String> = trash
+                    .entries
+                    .iter()
+                    .map(|entry| {
+                        let deleted = entry.deleted.format("%Y-%m-%d %H:%M");
+                        format!("{:>3}  {}  {:>9}  {}", entry.id, deleted, trash::format_size(entry.size), entry.original.display())
+                    })
+                    .collect();
+                BuiltinResult::Success((!lines.is_empty()).then(|| lines.join("\n")))
+            }
+            Some("empty") if args.len() == 1 => match trash.empty() {
+                Ok(count) => BuiltinResult::Success(Some(format!("deleted {} entr{}", count, if count == 1 { "y" } else { "ies" }))),
+                Err(e) => BuiltinResult::Error(format!("trash: {}", e)),
+            },
+            _ => return BuiltinResult::Error("trash: usage: trash [list | empty]".to_string()),
+        };
+        if purged > 0 || matches!(args.first().map(String::as_str), Some("empty")) {
+            if let Err(e) = trash.save() {
+                return BuiltinResult::Error(format!("trash: {}: {}", dir.display(), e));
+            }
+        }
+        result
+    }
+
+    /// Execute `restore ID...`: move trashed entries back
+    fn execute_restore(&self, args: &[String]) -> BuiltinResult {
+        if args.is_empty() {
+            return BuiltinResult::Error("restore: usage: restore ID...".to_string());
+        }
+        let Some(dir) = Trash::dir() else {
+            return BuiltinResult::Error("restore: no data directory".to_string());
+        };
+        let mut trash = Trash::load(&dir);
+        let (mut restored, mut errors) = (Vec::new(), Vec::new());
+        for arg in args {
+            let Ok(id) = arg.trim_start_matches('#').parse() else {
+                errors.push(format!("restore: {}: not an entry id", arg));
+                continue;
+            };
+            match trash.restore(id) {
+                Ok(path) => restored.push(format!("restored {}", path.display())),
+                Err(e) => errors.push(format!("restore: {}: {}", arg, e)),
+            }
+        }
+        if let Err(e) = trash.save() {
+            errors.push(format!("restore: {}: {}", dir.display(), e));
+        }
+        if errors.is_empty() {
+            BuiltinResult::Success(Some(restored.join("\n")))
+        } else {
+            if !restored.is_empty() {
+                println!("{}", restored.join("\n"));
+            }
+            BuiltinResult::Error(errors.join("\n"))
+        }
+    }
+
+    /// Execute rehash command: forget where commands were found and rebuild
+    /// the index of executables on PATH, reporting how many were found with `-v`
+    fn execute_rehash(&self, args: &[String]) -> BuiltinResult {
+        self.state.forget_commands();
+        match args {
+            [] => {
+                self.completer.rehash();
+                BuiltinResult::Success(None)
+            }
+            [flag] if flag == "-v" => BuiltinResult::Success(Some(format!("{} commands on PATH", self.completer.rehash()))),
+            _ => BuiltinResult::Error("rehash: usage: rehash [-v]".to_string()),
+        }
+    }
+
+    /// Execute alias command: list, show, or define `NAME=VALUE`
+    fn execute_alias(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+        if args.is_empty() {
+            let listing: Vec<String> = self
+                .state
+                .aliases()
+                .into_iter()
+                .map(|(name, value)| format!("alias {}='{}'", name, value))
+                .collect();
+            return Ok(BuiltinResult::Success((!listing.is_empty()).then(|| listing.join("\n"))));
+        }
+
+        let mut shown = Vec::new();
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, _)) if name.is_empty() || BuiltinCommand::is_builtin(name) => {
+                    return Ok(BuiltinResult::Error(format!("alias: invalid alias name: {}", name)));
+                }
+                Some((name, value)) => self.state.set_alias(name, value),
+                None => match self.state.alias(arg) {
+                    Some(value) => shown.push(format!("alias {}='{}'", arg, value)),
+                    None => return Ok(BuiltinResult::Error(format!("alias: {}: not found", arg))),
+                },
+            }
+        }
+
+        Ok(BuiltinResult::Success((!shown.is_empty()).then(|| shown.join("\n"))))
     }
 
     /// Execute unalias command
-    fn execute_unalias(&self, _args: &[String]) -> ShellResult<BuiltinResult> {
-        Ok(BuiltinResult::Info("Command unaliasing not yet implemented".to_string()))
+    fn execute_unalias(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+        if args.is_empty() {
+            return Ok(BuiltinResult::Error("unalias: missing argument".to_string()));
+        }
+
+        for name in args {
+            if !self.state.remove_alias(name) {
+                return Ok(BuiltinResult::Error(format!("unalias: {}: not found", name)));
+            }
+        }
+        Ok(BuiltinResult::Success(None))
     }
 
-    /// Execute export command
+    /// Execute export command: `export NAME=VALUE` or promote `export NAME`
     fn execute_export(&self, args: &[String]) -> ShellResult<BuiltinResult> {
         if args.is_empty() {
-            return Ok(BuiltinResult::Error("export: missing argument".to_string()));
+            let mut vars: Vec<(String, String)> = env::vars().collect();
+            vars.sort();
+            let listing: Vec<String> = vars
+                .into_iter()
+                .map(|(name, value)| format!("export {}={}", name, quote_value(&value)))
+                .collect();
+            return Ok(BuiltinResult::Info(listing.join("\n")));
         }
 
-        let arg = &args[0];
-        if let Some(eq_pos) = arg.find('=') {
-            let key = &arg[..eq_pos];
-            let value = &arg[eq_pos + 1..];
-            env::set_var(key, value);
-            Ok(BuiltinResult::Success(None))
-        } else {
-            Ok(BuiltinResult::Error("export: invalid format, use KEY=VALUE".to_string()))
+        for arg in args {
+            match parse_assignment(arg) {
+                Some((name, value)) => {
+                    self.state.unset_var(name);
+                    env::set_var(name, value);
+                }
+                None if is_name(arg) => {
+                    // Promote a shell variable; exporting an unset name is a no-op
+                    if let Some(value) = self.state.unset_var(arg) {
+                        env::set_var(arg, value);
+                    }
+                }
+                None => return Ok(BuiltinResult::Error(format!("export: not a valid identifier: {}", arg))),
+            }
         }
+        Ok(BuiltinResult::Success(None))
     }
 
-    /// Execute unset command
+    /// Execute unset command: remove shell and environment variables
     fn execute_unset(&self, args: &[String]) -> ShellResult<BuiltinResult> {
         if args.is_empty() {
             return Ok(BuiltinResult::Error("unset: missing argument".to_string()));
         }
 
-        env::remove_var(&args[0]);
+        for name in args {
+            if !is_name(name) {
+                return Ok(BuiltinResult::Error(format!("unset: not a valid identifier: {}", name)));
+            }
+            self.state.unset_var(name);
+            env::remove_var(name);
+        }
         Ok(BuiltinResult::Success(None))
     }
 
-    /// Execute jobs command
-    fn execute_jobs(&self) -> ShellResult<BuiltinResult> {
-        Ok(BuiltinResult::Info("Background jobs not yet implemented".to_string()))
+    /// Execute jobs command: list jobs, with `-l` their pids or `-p` only
+    /// pids; `-o [JOB]` shows a job's buffered output
+    fn execute_jobs(&self, args: &[String], format: Format) -> BuiltinResult {
+        let (long, pids_only) = match args {
+            [] => (false, false),
+            [flag] if flag == "-l" => (true, false),
+            [flag] if flag == "-p" => (false, true),
+            [flag, spec @ ..] if flag == "-o" && spec.len() <= 1 => {
+                return match self.jobs.output(spec.first().map(String::as_str)) {
+                    Ok(output) => BuiltinResult::Success(Some(output.trim_end_matches('\n').to_string())),
+                    Err(e) => BuiltinResult::Error(format!("jobs: {}", e)),
+                };
+            }
+            _ => return BuiltinResult::Error("jobs: usage: jobs [-l|-p] | jobs -o [JOB]".to_string()),
+        };
+
+        let jobs = self.jobs.list();
+        let servers = self.jobs.servers();
+        if format == Format::Json {
+            let servers = servers.iter().map(|(pid, command)| {
+                json!({
+                    "id": null,
+                    "current": false,
+                    "pgid": pid,
+                    "pids": [pid],
+                    "state": JobState::Running.to_string(),
+                    "status": null,
+                    "command": command,
+                    "server": true,
+                })
+            });
+            let list: Vec<_> = jobs
+                .iter()
+                .map(|job| {
+                    json!({
+                        "id": job.id,
+                        "current": job.marker == '+',
+                        "pgid": job.pgid,
+                        "pids": job.pids,
+                        "state": job.state.to_string(),
+                        "status": match job.state {
+                            JobState::Done(status) => Some(status),
+                            _ => None,
+                        },
+                        "command": job.command,
+                        "seconds": job.started.elapsed().as_secs(),
+                        "buffered_bytes": job.buffered,
+                        "server": false,
+                    })
+                })
+                .chain(servers)
+                .collect();
+            self.jobs.forget(&jobs);
+            return json_result(list);
+        }
+        // Servers such as persistent interpreters follow the jobs, without a job number
+        let lines: Vec<String> = jobs
+            .iter()
+            .map(|job| if pids_only { job.pgid.to_string() } else { job.line(long) })
+            .chain(servers.iter().map(|(pid, command)| match (pids_only, long) {
+                (true, _) => pid.to_string(),
+                (false, true) => format!("[-]   {} {:<24}{}", pid, JobState::Running.to_string(), command),
+                (false, false) => format!("[-]   {:<24}{}", JobState::Running.to_string(), command),
+            }))
+            .collect();
+        // Finished jobs are listed once, then forgotten
+        self.jobs.forget(&jobs);
+        BuiltinResult::Success((!lines.is_empty()).then(|| lines.join("\n")))
     }
 
-    /// Execute fg command
-    fn execute_fg(&self, _args: &[String]) -> ShellResult<BuiltinResult> {
-        Ok(BuiltinResult::Info("Foreground job control not yet implemented".to_string()))
+    /// Execute fg command: wait for a job in the foreground
+    fn execute_fg(&self, args: &[String]) -> BuiltinResult {
+        if args.len() > 1 {
+            return BuiltinResult::Error("fg: usage: fg [JOB]".to_string());
+        }
+        let id = match self.jobs.resolve(args.first().map(String::as_str)) {
+            Ok(id) => id,
+            Err(e) => return BuiltinResult::Error(format!("fg: {}", e)),
+        };
+        if let Some(job) = self.jobs.get(id) {
+            println!("{}", job.command);
+        }
+        signals::take_interrupt();
+        match self.jobs.wait(id, signals::take_interrupt) {
+            Ok(status) => {
+                // Stopped again with Ctrl-Z
+                if let Some(job) = self.jobs.get(id) {
+                    eprintln!("\n{}", job.line(false));
+                }
+                BuiltinResult::Status(status)
+            }
+            Err(e) => BuiltinResult::Error(format!("fg: %{}: {}", id, e)),
+        }
     }
 
-    /// Execute bg command
-    fn execute_bg(&self, _args: &[String]) -> ShellResult<BuiltinResult> {
-        Ok(BuiltinResult::Info("Background job control not yet implemented".to_string()))
+    /// Execute bg command: resume a stopped job in the background
+    fn execute_bg(&self, args: &[String]) -> BuiltinResult {
+        if args.len() > 1 {
+            return BuiltinResult::Error("bg: usage: bg [JOB]".to_string());
+        }
+        let job = match self.jobs.resolve(args.first().map(String::as_str)).map(|id| self.jobs.get(id)) {
+            Ok(Some(job)) => job,
+            Ok(None) => return BuiltinResult::Error("bg: no current job".to_string()),
+            Err(e) => return BuiltinResult::Error(format!("bg: {}", e)),
+        };
+        match job.state {
+            JobState::Running => BuiltinResult::Error(format!("bg: job {} already in background", job.id)),
+            JobState::Done(_) => BuiltinResult::Error(format!("bg: job {} has terminated", job.id)),
+            JobState::Stopped => match self.jobs.resume(job.id) {
+                Ok(()) => BuiltinResult::Success(Some(format!("[{}]+ {} &", job.id, job.command))),
+                Err(e) => BuiltinResult::Error(format!("bg: %{}: {}", job.id, e)),
+            },
+        }
     }
 
-    /// Execute kill command
-    fn execute_kill(&self, _args: &[String]) -> ShellResult<BuiltinResult> {
-        Ok(BuiltinResult::Info("Process killing not yet implemented".to_string()))
+    /// Execute kill command: `kill [-s SIGNAL | -SIGNAL] %JOB|PID...`, or
+    /// `kill -l` to list signal names
+    fn execute_kill(&self, args: &[String]) -> BuiltinResult {
+        let (spec, targets) = match args {
+            [flag] if flag == "-l" => {
+                let names: Vec<&str> = SIGNALS.iter().map(|(name, _)| *name).collect();
+                return BuiltinResult::Success(Some(names.join(" ")));
+            }
+            [flag, name, rest @ ..] if flag == "-s" => (name.as_str(), rest),
+            [flag, rest @ ..] if flag.len() > 1 && flag.starts_with('-') => (&flag[1..], rest),
+            _ => ("TERM", args),
+        };
+        let Some(signal) = parse_signal(spec) else {
+            return BuiltinResult::Error(format!("kill: {}: invalid signal specification", spec));
+        };
+        if targets.is_empty() {
+            return BuiltinResult::Error("kill: usage: kill [-s SIGNAL | -SIGNAL] %JOB|PID...".to_string());
+        }
+
+        let mut errors = Vec::new();
+        for target in targets {
+            let result = if target.starts_with('%') {
+                self.jobs
+                    .resolve(Some(target))
+                    .and_then(|id| self.jobs.signal(id, signal).map_err(|e| format!("{}: {}", target, e)))
+            } else {
+                match target.parse::<u32>() {
+                    Ok(pid) => signal_process(pid, signal).map_err(|e| format!("({}) - {}", pid, e)),
+                    Err(_) => Err(format!("{}: arguments must be process or job IDs", target)),
+                }
+            };
+            if let Err(e) = result {
+                errors.push(format!("kill: {}", e));
+            }
+        }
+        if errors.is_empty() {
+            BuiltinResult::Success(None)
+        } else {
+            BuiltinResult::Error(errors.join("\n"))
+        }
     }
 
     /// Execute which command
-    fn execute_which(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+    fn execute_which(&self, args: &[String], format: Format) -> ShellResult<BuiltinResult> {
         if args.is_empty() {
             return Ok(BuiltinResult::Error("which: missing argument".to_string()));
         }
 
-        match which::which(&args[0]) {
-            Ok(path) => Ok(BuiltinResult::Info(path.display().to_string())),
-            Err(_) => Ok(BuiltinResult::Error(format!("which: {}: command not found", args[0]))),
+        match self.state.find_command(&args[0]) {
+            Some(path) if format == Format::Json => Ok(json_result(json!({ "name": args[0], "path": path }))),
+            Some(path) => Ok(BuiltinResult::Info(path.display().to_string())),
+            None => Ok(BuiltinResult::Error(format!("which: {}: command not found", args[0]))),
         }
     }
 
     /// Execute type command
-    fn execute_type(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+    fn execute_type(&self, args: &[String], format: Format) -> ShellResult<BuiltinResult> {
         if args.is_empty() {
             return Ok(BuiltinResult::Error("type: missing argument".to_string()));
         }
 
         let cmd = &args[0];
+        if format == Format::Json {
+            return Ok(if BuiltinCommand::is_builtin(cmd) {
+                json_result(json!({ "name": cmd, "type": "builtin" }))
+            } else {
+                match self.state.find_command(cmd) {
+                    Some(path) => json_result(json!({ "name": cmd, "type": "file", "path": path })),
+                    None => BuiltinResult::Error(format!("type: {}: not found", cmd)),
+                }
+            });
+        }
         if BuiltinCommand::is_builtin(cmd) {
             Ok(BuiltinResult::Info(format!("{} is a shell builtin", cmd)))
         } else {
-            match which::which(cmd) {
-                Ok(path) => Ok(BuiltinResult::Info(format!("{} is {}", cmd, path.display()))),
-                Err(_) => Ok(BuiltinResult::Error(format!("type: {}: not found", cmd))),
+            match self.state.find_command(cmd) {
+                Some(path) => Ok(BuiltinResult::Info(format!("{} is {}", cmd, path.display()))),
+                None => Ok(BuiltinResult::Error(format!("type: {}: not found", cmd))),
+            }
+        }
+    }
+
+    /// Execute log-level command
+    fn execute_log_level(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+        if args.is_empty() {
+            return Ok(BuiltinResult::Info(format!("log level: {}", logging::describe())));
+        }
+
+        for spec in args {
+            if let Err(e) = logging::set_level(spec) {
+                return Ok(BuiltinResult::Error(format!("log-level: {}", e)));
+            }
+        }
+        Ok(BuiltinResult::Success(None))
+    }
+
+    /// Execute doctor command
+    fn execute_doctor(&self) -> ShellResult<BuiltinResult> {
+        let mut report = vec!["Shell-T doctor".to_string(), String::new()];
+        let mut problems = 0;
+
+        report.push("Interpreters:".to_string());
+        for (lang, path) in [
+            ("python", &self.config.interpreters.python_path),
+            ("ruby", &self.config.interpreters.ruby_path),
+            ("node", &self.config.interpreters.node_path),
+        ] {
+            match which::which(path) {
+                Ok(found) => report.push(format!("  ok       {} ({})", lang, found.display())),
+                Err(_) => {
+                    problems += 1;
+                    report.push(format!("  missing  {} ({})", lang, path));
+                    report.push(format!(
+                        "           fix: install {} or set SHELL_T_{}_PATH",
+                        lang,
+                        lang.to_uppercase()
+                    ));
+                }
+            }
+        }
+
+        // Compiled-language toolchains are optional, so missing ones aren't problems
+        let interpreters = &self.config.interpreters;
+        report.push(String::new());
+        report.push("Toolchains:".to_string());
+        for (lang, ext, path, enabled) in [
+            ("rust", "rs", &interpreters.rust_path, interpreters.enable_rust),
+            ("go", "go", &interpreters.go_path, interpreters.enable_go),
+        ] {
+            match which::which(path) {
+                _ if !enabled => report.push(format!("  disabled {}", lang)),
+                Ok(found) => report.push(format!("  ok       {} ({})", lang, found.display())),
+                Err(_) => report.push(format!("  missing  {} ({}), .{} scripts won't run", lang, path, ext)),
+            }
+        }
+
+        report.push(String::new());
+        report.push("Configuration:".to_string());
+        let security = &self.config.security;
+        for cmd in security.allowed_commands.intersection(&security.blocked_commands) {
+            problems += 1;
+            report.push(format!("  '{}' is both allowed and blocked; blocking wins", cmd));
+            report.push("           fix: remove it from one of the lists".to_string());
+        }
+        let mut custom: Vec<&String> = interpreters.extensions.keys().collect();
+        custom.sort();
+        for ext in custom {
+            if !interpreters.allowed_extensions.iter().any(|allowed| allowed.trim_start_matches('.') == ext) {
+                problems += 1;
+                report.push(format!("  interpreters.extensions.{} is set but .{} scripts are not allowed", ext, ext));
+                report.push(format!("           fix: add \"{}\" to interpreters.allowed_extensions", ext));
+            }
+        }
+        if !security.allowed_commands.is_empty() && self.config.interpreters.enable_scripts {
+            for path in [
+                &self.config.interpreters.python_path,
+                &self.config.interpreters.ruby_path,
+                &self.config.interpreters.node_path,
+            ] {
+                if !security.allowed_commands.contains(path.as_str()) {
+                    problems += 1;
+                    report.push(format!("  scripts for '{}' will be rejected by the command whitelist", path));
+                    report.push(format!("           fix: add \"{}\" to security.allowed_commands", path));
+                }
+            }
+        }
+
+        report.push(String::new());
+        report.push("Recent failures:".to_string());
+        let telemetry = telemetry::global();
+        if !telemetry.is_enabled() {
+            report.push("  telemetry is disabled".to_string());
+            report.push("           fix: set [telemetry] enabled = true to track failures locally".to_string());
+        } else {
+            let data = telemetry.snapshot();
+            if data.recent.is_empty() {
+                report.push("  none recorded".to_string());
+            }
+            for (code, count) in &data.error_counts {
+                let title = catalog::lookup(code).map_or("", |entry| entry.title);
+                report.push(format!("  {:>5}  {} {}", count, code, title));
+            }
+            let mut commands: Vec<_> = data.failed_commands.iter().collect();
+            commands.sort_by(|a, b| b.1.cmp(a.1));
+            for (program, count) in commands.into_iter().take(5) {
+                report.push(format!("  {:>5}  failures of '{}'", count, program));
+            }
+        }
+
+        report.push(String::new());
+        report.push(match problems {
+            0 => "No problems found.".to_string(),
+            n => format!("{} problem(s) found.", n),
+        });
+
+        Ok(BuiltinResult::Info(report.join("\n")))
+    }
+
+    /// Execute explain command
+    fn execute_explain(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+        if args.is_empty() {
+            let codes: Vec<String> = catalog::ENTRIES
+                .iter()
+                .map(|entry| format!("  {}  {}", entry.code, entry.title))
+                .collect();
+            return Ok(BuiltinResult::Info(format!("Known error codes:\n{}", codes.join("\n"))));
+        }
+
+        match catalog::lookup(&args[0]) {
+            Some(entry) => Ok(BuiltinResult::Info(catalog::render(entry))),
+            None => Ok(BuiltinResult::Error(format!("explain: unknown error code: {}", args[0]))),
+        }
+    }
+
+    /// Execute envdiff command: `envdiff snapshot` saves the environment,
+    /// shell variables and directory; `envdiff [show]` lists what changed
+    /// since, and `envdiff revert` undoes the changes to exported variables
+    fn execute_envdiff(&self, args: &[String]) -> BuiltinResult {
+        let usage = "envdiff: usage: envdiff [snapshot | show | revert]";
+        let command = match args {
+            [] => "show",
+            [command] => command.as_str(),
+            _ => return BuiltinResult::Error(usage.to_string()),
+        };
+        if command == "snapshot" {
+            self.state.set_env_snapshot(Snapshot::take(&self.state));
+            return BuiltinResult::Success(None);
+        }
+        let Some(snapshot) = self.state.env_snapshot() else {
+            return BuiltinResult::Error("envdiff: no snapshot; take one with envdiff snapshot".to_string());
+        };
+        let now = Snapshot::take(&self.state);
+        let changes = snapshot.changes(&now);
+        match command {
+            "show" => {
+                let mut lines: Vec<String> = Vec::new();
+                if let (Some(before), Some(after)) = (&snapshot.cwd, &now.cwd) {
+                    if before != after {
+                        lines.push(format!("~ cd {} -> {}", before.display(), after.display()));
+                    }
+                }
+                lines.extend(changes.iter().map(ToString::to_string));
+                BuiltinResult::Success((!lines.is_empty()).then(|| lines.join("\n")))
+            }
+            "revert" => {
+                let count = envdiff::revert(&changes, &self.state);
+                BuiltinResult::Success(Some(format!("reverted {} variable{}", count, if count == 1 { "" } else { "s" })))
+            }
+            _ => BuiltinResult::Error(usage.to_string()),
+        }
+    }
+
+    /// Execute set command: `set -e`, `set +e`, `set -o errexit`; `set` lists
+    /// shell variables and `set -o` lists options
+    fn execute_set(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+        if args.is_empty() {
+            let listing: Vec<String> = self
+                .state
+                .vars()
+                .into_iter()
+                .map(|(name, value)| format!("{}={}", name, quote_value(&value)))
+                .collect();
+            return Ok(BuiltinResult::Success((!listing.is_empty()).then(|| listing.join("\n"))));
+        }
+        if args.len() == 1 && args[0] == "-o" {
+            let options = self.state.options();
+            let listing: Vec<String> = options
+                .list()
+                .into_iter()
+                .map(|(name, enabled)| format!("{:<12}{}", name, if enabled { "on" } else { "off" }))
+                .collect();
+            return Ok(BuiltinResult::Info(listing.join("\n")));
+        }
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // `set -- A B`: the rest are the new `$1..$N`
+            if arg == "--" {
+                self.state.set_params(args.cloned().collect());
+                break;
+            }
+            let (flags, value) = match arg.strip_prefix('-') {
+                Some(flags) => (flags, true),
+                None => match arg.strip_prefix('+') {
+                    Some(flags) => (flags, false),
+                    None => return Ok(BuiltinResult::Error(format!("set: unexpected argument: {}", arg))),
+                },
+            };
+
+            if flags == "o" {
+                let name = match args.next() {
+                    Some(name) => name,
+                    None => return Ok(BuiltinResult::Error("set: -o requires an option name".to_string())),
+                };
+                if let Err(e) = self.state.update_options(|options| options.set(name, value)) {
+                    return Ok(BuiltinResult::Error(format!("set: {}", e)));
+                }
+                continue;
+            }
+
+            for flag in flags.chars() {
+                if let Err(e) = self.state.update_options(|options| options.set(&flag.to_string(), value)) {
+                    return Ok(BuiltinResult::Error(format!("set: {}", e)));
+                }
+            }
+        }
+
+        Ok(BuiltinResult::Success(None))
+    }
+
+    /// Execute test/[ command: succeeds when the expression holds
+    fn execute_test(&self, command: &str, args: &[String]) -> ShellResult<BuiltinResult> {
+        let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+        if command == "[" {
+            if args.last() != Some(&"]") {
+                return Ok(BuiltinResult::Error("[: missing ']'".to_string()));
+            }
+            args.pop();
+        }
+
+        match evaluate_test(&args) {
+            Ok(true) => Ok(BuiltinResult::Status(0)),
+            Ok(false) => Ok(BuiltinResult::Status(1)),
+            Err(e) => Ok(BuiltinResult::Error(format!("{}: {}", command, e))),
+        }
+    }
+
+    /// Execute lang command: show the active sub-REPL, or enter one
+    fn execute_lang(&self, args: &[String]) -> ShellResult<BuiltinResult> {
+        let tag = match args {
+            [] => {
+                let mode = self.state.language_mode().map_or("shell", |language| language.name());
+                return Ok(BuiltinResult::Info(mode.to_string()));
+            }
+            [tag] => tag,
+            _ => return Ok(BuiltinResult::Error("lang: usage: lang LANGUAGE".to_string())),
+        };
+
+        let language = match Language::from_tag(tag) {
+            Some(language) => language,
+            None => return Ok(BuiltinResult::Error(format!("lang: unknown language: {}", tag))),
+        };
+        if !InterpreterPool::supports(language) {
+            return Ok(BuiltinResult::Error(format!(
+                "lang: {} has no persistent session; only python is supported",
+                language.name()
+            )));
+        }
+        if !self.config.interpreters.enable_scripts {
+            return Ok(BuiltinResult::Error("lang: script execution is disabled".to_string()));
+        }
+
+        self.state.set_language_mode(Some(language));
+        Ok(BuiltinResult::Info(format!(
+            "{} mode: shell variables are in `shell`; type exit or press Ctrl-D to return",
+            language.name()
+        )))
+    }
+
+    /// Execute shift command: drop positional parameters, 1 by default
+    fn execute_shift(&self, args: &[String]) -> BuiltinResult {
+        let count = match args {
+            [] => 1,
+            [n] => match n.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => return BuiltinResult::Error(format!("shift: {}: numeric argument required", n)),
+            },
+            _ => return BuiltinResult::Error("shift: too many arguments".to_string()),
+        };
+        if self.state.shift(count) {
+            BuiltinResult::Success(None)
+        } else {
+            BuiltinResult::Error(format!("shift: {}: shift count out of range", count))
+        }
+    }
+
+    /// Execute exit command
+    fn execute_exit(&self, args: &[String]) -> BuiltinResult {
+        match exit_status(args, self.state.last_status()) {
+            Ok(status) => BuiltinResult::Exit(status),
+            Err(msg) => BuiltinResult::Error(msg),
+        }
+    }
+}
+
+/// Status `exit [N]` leaves with: N modulo 256, or the last command's status
+pub fn exit_status(args: &[String], last_status: i32) -> Result<i32, String> {
+    match args {
+        [] => Ok(last_status),
+        [n] => n
+            .parse::<i64>()
+            .map(|n| n.rem_euclid(256) as i32)
+            .map_err(|_| format!("exit: {}: numeric argument required", n)),
+        _ => Err("exit: too many arguments".to_string()),
+    }
+}
+
+/// How a builtin with structured results prints them
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+/// Success printing `value` as one line of JSON
+fn json_result(value: impl serde::Serialize) -> BuiltinResult {
+    match serde_json::to_string(&value) {
+        Ok(text) => BuiltinResult::Success(Some(text)),
+        Err(e) => BuiltinResult::Error(e.to_string()),
+    }
+}
+
+/// Quote a value for display if it would not survive re-parsing as one word
+fn quote_value(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || "'\"$|<>&;".contains(c)) {
+        format!("'{}'", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Split `--format NAME` from the other arguments of `history import/export`
+fn history_format(args: &[String]) -> Result<(Option<history::Format>, Vec<&String>), String> {
+    let mut format = None;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = match arg.strip_prefix("--format=") {
+            Some(name) => name,
+            None if arg == "--format" => args.next().ok_or("history: --format needs bash, zsh or json")?,
+            None => {
+                rest.push(arg);
+                continue;
+            }
+        };
+        format = Some(history::Format::from_name(name).ok_or_else(|| format!("history: unknown format: {}", name))?);
+    }
+    Ok((format, rest))
+}
+
+/// Evaluate a `test` expression
+fn evaluate_test(args: &[&str]) -> Result<bool, String> {
+    match args {
+        [] => Ok(false),
+        ["!", rest @ ..] => evaluate_test(rest).map(|result| !result),
+        [value] => Ok(!value.is_empty()),
+        [op, operand] => {
+            let path = std::path::Path::new(operand);
+            match *op {
+                "-n" => Ok(!operand.is_empty()),
+                "-z" => Ok(operand.is_empty()),
+                "-e" => Ok(path.exists()),
+                "-f" => Ok(path.is_file()),
+                "-d" => Ok(path.is_dir()),
+                "-s" => Ok(path.metadata().map(|m| m.len() > 0).unwrap_or(false)),
+                _ => Err(format!("unknown unary operator: {}", op)),
             }
         }
+        [left, op, right] => match *op {
+            "=" | "==" => Ok(left == right),
+            "!=" => Ok(left != right),
+            "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+                let parse = |value: &str| {
+                    value.parse::<i64>().map_err(|_| format!("integer expected: {}", value))
+                };
+                let (left, right) = (parse(left)?, parse(right)?);
+                Ok(match *op {
+                    "-eq" => left == right,
+                    "-ne" => left != right,
+                    "-lt" => left < right,
+                    "-le" => left <= right,
+                    "-gt" => left > right,
+                    _ => left >= right,
+                })
+            }
+            _ => Err(format!("unknown binary operator: {}", op)),
+        },
+        _ => Err("too many arguments".to_string()),
     }
 }
 
+/// Signals `kill` accepts by name, with or without the SIG prefix
+#[cfg(unix)]
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+];
+
+#[cfg(not(unix))]
+const SIGNALS: &[(&str, i32)] = &[("KILL", 9), ("TERM", 15)];
+
+/// Signal number for a name (`TERM`, `SIGTERM`) or number (`15`)
+fn parse_signal(spec: &str) -> Option<i32> {
+    if let Ok(number) = spec.parse() {
+        return Some(number);
+    }
+    let name = spec.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS.iter().find(|(known, _)| *known == name).map(|(_, number)| *number)
+}
+
+#[cfg(unix)]
+fn signal_process(pid: u32, signal: i32) -> std::io::Result<()> {
+    // SAFETY: sending a signal has no memory-safety requirements
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn signal_process(_pid: u32, _signal: i32) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "kill needs a Unix system"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(args: &[&str]) -> Result<bool, String> {
+        evaluate_test(args)
+    }
+
+    #[test]
+    fn test_builtin_names() {
+        for name in BuiltinCommand::NAMES {
+            assert!(BuiltinCommand::is_builtin(name), "{} is not a builtin", name);
+        }
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("9"), Some(9));
+        assert_eq!(parse_signal("KILL"), Some(9));
+        assert_eq!(parse_signal("sigterm"), Some(15));
+        assert_eq!(parse_signal("BOGUS"), None);
+    }
+
+    #[test]
+    fn test_evaluate_test_expressions() {
+        assert_eq!(test(&["-d", "/"]), Ok(true));
+        assert_eq!(test(&["-f", "/"]), Ok(false));
+        assert_eq!(test(&["!", "-z", "x"]), Ok(true));
+        assert_eq!(test(&["a", "=", "a"]), Ok(true));
+        assert_eq!(test(&["3", "-lt", "10"]), Ok(true));
+        assert!(test(&["x", "-lt", "10"]).is_err());
+        assert_eq!(test(&[]), Ok(false));
+    }
+}
@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+
+/// Options parsed from the command line
+#[derive(Debug, Clone, Default)]
+pub struct CliOptions {
+    pub no_warn: Vec<String>,
+    /// Behave as a login shell (`-l`, or argv[0] starting with `-`)
+    pub login: bool,
+    /// Command string given with `-c`
+    pub command: Option<String>,
+    /// Script to run instead of the interactive prompt
+    pub script: Option<PathBuf>,
+    /// Arguments following the script path or `-c` string
+    pub script_args: Vec<String>,
+    /// Run the script under the step debugger (`--debug`)
+    pub debug: bool,
+    /// Parse scripts and startup files afresh instead of using the parse cache
+    pub no_cache: bool,
+    /// Serve the JSON-RPC kernel protocol on stdin/stdout (`--kernel`)
+    pub kernel: bool,
+    /// Run scheduled commands in the foreground until killed (`--scheduler`)
+    pub scheduler: bool,
+    /// Serve command requests on this Unix socket (`--serve PATH`)
+    pub serve: Option<PathBuf>,
+}
+
+impl CliOptions {
+    /// Parse command-line arguments (excluding the program name)
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" => match args.next() {
+                    Some(command) => {
+                        options.command = Some(command);
+                        options.script_args = args.collect();
+                        break;
+                    }
+                    None => return Err("-c requires a command string".to_string()),
+                },
+                "-l" | "--login" => options.login = true,
+                "--debug" => options.debug = true,
+                "--no-cache" => options.no_cache = true,
+                "--kernel" => options.kernel = true,
+                "--scheduler" => options.scheduler = true,
+                "--serve" => match args.next() {
+                    Some(path) => options.serve = Some(PathBuf::from(path)),
+                    None => return Err("--serve requires a socket path".to_string()),
+                },
+                "--no-warn" => match args.next() {
+                    Some(category) => options.no_warn.push(category),
+                    None => return Err("--no-warn requires a category".to_string()),
+                },
+                _ if arg.starts_with("--no-warn=") => {
+                    options.no_warn.push(arg["--no-warn=".len()..].to_string());
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => {
+                    // Everything after the script path belongs to the script
+                    options.script = Some(PathBuf::from(arg));
+                    options.script_args = args.collect();
+                    break;
+                }
+            }
+        }
+
+        if options.debug && options.script.is_none() {
+            return Err("--debug requires a script".to_string());
+        }
+        if options.kernel && (options.script.is_some() || options.command.is_some()) {
+            return Err("--kernel takes no script or command".to_string());
+        }
+        if options.scheduler && (options.kernel || options.script.is_some() || options.command.is_some()) {
+            return Err("--scheduler takes no script, command or --kernel".to_string());
+        }
+        if options.serve.is_some() && (options.scheduler || options.kernel || options.script.is_some() || options.command.is_some()) {
+            return Err("--serve takes no script, command, --kernel or --scheduler".to_string());
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliOptions, String> {
+        CliOptions::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_script_takes_remaining_arguments() {
+        let options = parse(&["--no-warn", "config", "deploy.sht", "--force", "prod"]).unwrap();
+        assert_eq!(options.no_warn, vec!["config"]);
+        assert_eq!(options.script, Some(PathBuf::from("deploy.sht")));
+        assert_eq!(options.script_args, vec!["--force", "prod"]);
+    }
+
+    #[test]
+    fn test_command_string() {
+        let options = parse(&["-c", "ls | wc -l", "name"]).unwrap();
+        assert_eq!(options.command.as_deref(), Some("ls | wc -l"));
+        assert!(options.script.is_none());
+        assert_eq!(options.script_args, vec!["name"]);
+        assert!(parse(&["-c"]).is_err());
+    }
+
+    #[test]
+    fn test_debug_flag_needs_script() {
+        let options = parse(&["--debug", "build.sht", "release"]).unwrap();
+        assert!(options.debug);
+        assert_eq!(options.script_args, vec!["release"]);
+        assert!(parse(&["--debug"]).is_err());
+        assert!(parse(&["--debug", "-c", "ls"]).is_err());
+    }
+
+    #[test]
+    fn test_kernel_flag() {
+        assert!(parse(&["--kernel"]).unwrap().kernel);
+        assert!(parse(&["--kernel", "-c", "ls"]).is_err());
+    }
+
+    #[test]
+    fn test_scheduler_flag() {
+        assert!(parse(&["--scheduler"]).unwrap().scheduler);
+        assert!(parse(&["--scheduler", "backup.sht"]).is_err());
+    }
+
+    #[test]
+    fn test_serve_option() {
+        assert_eq!(parse(&["--serve", "/run/shell-t.sock"]).unwrap().serve, Some(PathBuf::from("/run/shell-t.sock")));
+        assert!(parse(&["--serve"]).is_err());
+        assert!(parse(&["--serve", "s.sock", "--kernel"]).is_err());
+    }
+
+    #[test]
+    fn test_login_and_no_cache_flags() {
+        assert!(parse(&["-l"]).unwrap().login);
+        assert!(!parse(&[]).unwrap().login);
+        assert!(parse(&["--no-cache", "rc.sht"]).unwrap().no_cache);
+    }
+
+    #[test]
+    fn test_unknown_option_rejected() {
+        assert!(parse(&["--bogus"]).is_err());
+    }
+}
@@ -0,0 +1,927 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, CompletionConfig, CompletionRule, Config, SandboxConfig, SecurityConfig};
+use crate::bookmarks;
+use crate::builtins::BuiltinCommand;
+use crate::history::History;
+use crate::path_index::PathIndex;
+use crate::state::ShellState;
+use crate::{limits, sandbox, script};
+
+/// Recent history entries searched for tokens when nothing else matches
+const HISTORY_TOKEN_ENTRIES: usize = 1000;
+/// Commands run in the current directory rank as if this many times more recent
+const SAME_DIR_WEIGHT: usize = 4;
+
+/// Most output read from `--help`, a man page or a completion command
+const MAX_CAPTURE_BYTES: u64 = 1 << 20;
+
+/// Completion function supplied by the session, such as one registered from
+/// Lua: given the command, the word and the words between them, it returns
+/// candidates, or `None` when it has none for that command
+pub type Function<'a> = &'a dyn Fn(&str, &str, &[String]) -> Option<Vec<String>>;
+
+/// Specs shipped with the shell, one TOML file per command
+const BUILTIN_SPECS: &[(&str, &str)] = &[
+    ("cargo", include_str!("../completions/cargo.toml")),
+    ("docker", include_str!("../completions/docker.toml")),
+    ("git", include_str!("../completions/git.toml")),
+    ("kubectl", include_str!("../completions/kubectl.toml")),
+];
+
+/// Declarative completion spec for a command or one of its subcommands
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Spec {
+    /// Shown next to the subcommand in the completion list
+    pub description: Option<String>,
+    /// Flags accepted at this level, completed when the word starts with `-`
+    pub flags: Vec<String>,
+    /// Subcommands, each with its own spec
+    pub subcommands: BTreeMap<String, Spec>,
+    /// Fixed words accepted as arguments, such as resource types
+    pub args: Vec<String>,
+}
+
+impl Spec {
+    /// Add `other`'s flags, args and subcommands to this spec; a description
+    /// in `other` replaces this one
+    pub fn merge(&mut self, other: Spec) {
+        if other.description.is_some() {
+            self.description = other.description;
+        }
+        for flag in other.flags {
+            if !self.flags.contains(&flag) {
+                self.flags.push(flag);
+            }
+        }
+        for arg in other.args {
+            if !self.args.contains(&arg) {
+                self.args.push(arg);
+            }
+        }
+        for (name, spec) in other.subcommands {
+            self.subcommands.entry(name).or_default().merge(spec);
+        }
+    }
+}
+
+/// One way to finish the word under the cursor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// Replacement for the whole word
+    pub text: String,
+    pub description: Option<String>,
+    /// Inserted as it is rather than escaped, as for `$NAME`
+    pub verbatim: bool,
+}
+
+impl Candidate {
+    fn new(text: impl Into<String>) -> Self {
+        Self { text: text.into(), description: None, verbatim: false }
+    }
+
+    /// Whether completing to this candidate ends the word; directories and
+    /// variables don't, so typing can continue with `/...`
+    pub fn is_final(&self) -> bool {
+        !self.verbatim && !self.text.ends_with(std::path::is_separator)
+    }
+
+    /// Text to put on the command line
+    pub fn insertion(&self) -> String {
+        match self.verbatim {
+            true => self.text.clone(),
+            false => escape(&self.text),
+        }
+    }
+}
+
+/// Flags parsed from a command's help, cached on disk until the executable changes
+#[derive(Debug, Serialize, Deserialize)]
+struct HelpFlags {
+    path: PathBuf,
+    /// Modification time of `path`, in seconds
+    modified: u64,
+    flags: Vec<String>,
+}
+
+/// Tab completion for the line editor: variable names after `$`, rules set
+/// with `complete`, then subcommands and flags from the specs, flags parsed
+/// from `--help` when enabled, and file names otherwise; words typed in
+/// earlier commands when none of those match
+pub struct Completer {
+    /// Directory holding user specs, read on first use
+    user_dir: Option<PathBuf>,
+    specs: OnceLock<HashMap<String, Spec>>,
+    config: CompletionConfig,
+    sandbox: SandboxConfig,
+    security: SecurityConfig,
+    /// Flags parsed from help output this session, by command
+    help: Mutex<HashMap<String, Vec<String>>>,
+    /// Rules from `[completion.commands]` and the `complete` builtin
+    rules: Mutex<BTreeMap<String, CompletionRule>>,
+    /// Executables on `PATH`, loaded on first use
+    path_index: Mutex<Option<PathIndex>>,
+    /// Shell variables, for `$NAME`
+    state: Arc<ShellState>,
+    history: Arc<History>,
+}
+
+impl Completer {
+    /// A completer merging the built-in specs with those in `<config dir>/completions`
+    pub fn new(config: &Config, state: Arc<ShellState>, history: Arc<History>) -> Self {
+        Self {
+            user_dir: config::config_dir().map(|dir| dir.join("completions")),
+            specs: OnceLock::new(),
+            config: config.completion.clone(),
+            sandbox: config.sandbox.clone(),
+            security: config.security.clone(),
+            help: Mutex::new(HashMap::new()),
+            rules: Mutex::new(config.completion.commands.clone()),
+            path_index: Mutex::new(None),
+            state,
+            history,
+        }
+    }
+
+    /// Rescan `PATH` for executables, returning how many were found
+    pub fn rehash(&self) -> usize {
+        let index = PathIndex::build(&env::var("PATH").unwrap_or_default());
+        if let Some(file) = self.path_index_file() {
+            index.save_or_warn(&file);
+        }
+        let count = index.len();
+        *self.path_index.lock().unwrap() = Some(index);
+        count
+    }
+
+    fn cache_dir(&self) -> Option<PathBuf> {
+        self.config.cache_dir.clone().or_else(|| config::cache_dir().map(|dir| dir.join("completions")))
+    }
+
+    fn path_index_file(&self) -> Option<PathBuf> {
+        self.cache_dir().map(|dir| dir.join("path-index.json"))
+    }
+
+    /// Builtins and executables on `PATH` starting with `word`; the index is
+    /// reloaded when `PATH` has changed since it was built
+    fn complete_command_name(&self, word: &str) -> Vec<Candidate> {
+        let path = env::var("PATH").unwrap_or_default();
+        let mut index = self.path_index.lock().unwrap();
+        if !index.as_ref().is_some_and(|index| index.is_for(&path)) {
+            *index = Some(PathIndex::current(self.path_index_file().as_deref()));
+        }
+        let executables = index.iter().flat_map(|index| index.commands());
+        BuiltinCommand::NAMES
+            .iter()
+            .copied()
+            .chain(executables)
+            .filter(|name| name.starts_with(word))
+            .map(Candidate::new)
+            .collect()
+    }
+
+    /// Complete `command`'s arguments with `rule`
+    pub fn set_rule(&self, command: &str, rule: CompletionRule) {
+        self.rules.lock().unwrap().insert(command.to_string(), rule);
+    }
+
+    /// Drop the rule for `command`, returning whether there was one
+    pub fn remove_rule(&self, command: &str) -> bool {
+        self.rules.lock().unwrap().remove(command).is_some()
+    }
+
+    pub fn clear_rules(&self) {
+        self.rules.lock().unwrap().clear();
+    }
+
+    /// Rules by command name
+    pub fn rules(&self) -> BTreeMap<String, CompletionRule> {
+        self.rules.lock().unwrap().clone()
+    }
+
+    /// The spec for `command`, loading all specs on first use
+    pub fn spec(&self, command: &str) -> Option<&Spec> {
+        self.specs.get_or_init(|| load_specs(self.user_dir.as_deref())).get(command)
+    }
+
+    /// Complete the word ending at char `cursor` in `line`. Returns the char
+    /// index where that word starts and the candidates for it, sorted.
+    pub fn complete(&self, line: &str, cursor: usize) -> (usize, Vec<Candidate>) {
+        self.complete_with(line, cursor, None)
+    }
+
+    /// Like `complete`, trying `function` after any rule for the command
+    pub fn complete_with(&self, line: &str, cursor: usize, function: Option<Function>) -> (usize, Vec<Candidate>) {
+        let before: String = line.chars().take(cursor).collect();
+        if let Some((start, prefix, braced)) = variable_prefix(&before) {
+            return (start, self.complete_variable(prefix, braced));
+        }
+        let (words, start, word) = split_words(&before);
+
+        let mut candidates = match words.split_first() {
+            Some((command, args)) => {
+                let rule = self.rules.lock().unwrap().get(command).cloned();
+                match rule {
+                    Some(rule) => self.complete_rule(&rule, command, args, &word, &before),
+                    None => match function.and_then(|function| function(command, &word, args)) {
+                        Some(found) => found.into_iter().map(Candidate::new).collect(),
+                        None => self.complete_args(command, args, &word),
+                    },
+                }
+            }
+            None if word.contains(std::path::is_separator) => complete_files(&word),
+            None => self.complete_command_name(&word),
+        };
+        candidates.sort_by(|a, b| a.text.cmp(&b.text));
+        candidates.dedup_by(|a, b| a.text == b.text);
+        if candidates.is_empty() && !word.is_empty() {
+            candidates = self.complete_from_history(&word);
+        }
+        (start, candidates)
+    }
+
+    /// Shell and environment variables whose names start with `prefix`, as
+    /// `$NAME` or `${NAME}`, with their values as descriptions
+    fn complete_variable(&self, prefix: &str, braced: bool) -> Vec<Candidate> {
+        let mut vars: BTreeMap<String, String> = env::vars().collect();
+        // Shell variables shadow the environment
+        vars.extend(self.state.vars());
+        vars.into_iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, value)| Candidate {
+                text: if braced { format!("${{{}}}", name) } else { format!("${}", name) },
+                description: Some(value.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()),
+                verbatim: true,
+            })
+            .collect()
+    }
+
+    /// Words from recent commands that extend `word`, such as paths and
+    /// arguments typed before: the most recent first, counting commands run in
+    /// the current directory as more recent
+    fn complete_from_history(&self, word: &str) -> Vec<Candidate> {
+        let cwd = env::current_dir().ok();
+        // Token to its rank (lower is better) and the order it was first seen
+        let mut ranks: HashMap<String, (usize, usize)> = HashMap::new();
+        for (age, entry) in self.history.entries().into_iter().rev().take(HISTORY_TOKEN_ENTRIES).enumerate() {
+            let rank = if cwd.is_some() && entry.cwd == cwd { age / SAME_DIR_WEIGHT } else { age };
+            for token in script::split_words(&entry.command) {
+                // Skip operators such as `|` and `&&`
+                if token.len() <= word.len() || !token.starts_with(word) || !token.chars().any(char::is_alphanumeric) {
+                    continue;
+                }
+                let seen = ranks.len();
+                let best = ranks.entry(token).or_insert((rank, seen));
+                best.0 = best.0.min(rank);
+            }
+        }
+        let mut ranked: Vec<(String, (usize, usize))> = ranks.into_iter().collect();
+        ranked.sort_by_key(|(_, rank)| *rank);
+        ranked.into_iter().map(|(token, _)| Candidate::new(token)).collect()
+    }
+
+    /// Candidates from the command's spec, its `--help` flags or file names
+    fn complete_args(&self, command: &str, args: &[String], word: &str) -> Vec<Candidate> {
+        match (command, args) {
+            ("cd", []) if word.starts_with('@') && !word.contains('/') => {
+                return bookmarks::load_default()
+                    .into_keys()
+                    .map(|name| format!("@{}/", name))
+                    .filter(|name| name.starts_with(word))
+                    .map(Candidate::new)
+                    .collect();
+            }
+            ("bookmark", []) => return ["add", "rm"].into_iter().filter(|sub| sub.starts_with(word)).map(Candidate::new).collect(),
+            ("bookmark", [sub, ..]) if sub == "rm" => {
+                return bookmarks::load_default().into_keys().filter(|name| name.starts_with(word)).map(Candidate::new).collect();
+            }
+            _ => {}
+        }
+        match self.spec(command) {
+            Some(spec) => complete_spec(spec, args, word),
+            None if word.starts_with('-') && self.config.help_flags => {
+                self.help_flags(command).into_iter().filter(|flag| flag.starts_with(word)).map(Candidate::new).collect()
+            }
+            None => complete_files(word),
+        }
+    }
+
+    /// Candidates from a `complete` rule: words and glob matches are filtered by
+    /// the typed prefix, a command's output is taken as it is
+    fn complete_rule(&self, rule: &CompletionRule, command: &str, args: &[String], word: &str, line: &str) -> Vec<Candidate> {
+        match rule {
+            CompletionRule::Words(words) => words.iter().filter(|w| w.starts_with(word)).map(Candidate::new).collect(),
+            CompletionRule::Glob(pattern) => complete_glob(pattern, word),
+            CompletionRule::Command(source) => {
+                let mut program = script::split_words(source);
+                if program.is_empty() || !self.allowed(&program[0]) {
+                    return Vec::new();
+                }
+                let previous = args.last().map_or(command, String::as_str);
+                program.extend([command.to_string(), word.to_string(), previous.to_string()]);
+                let mut child = Command::new(&program[0]);
+                child.args(&program[1..]);
+                let env = [("COMP_LINE", line.to_string()), ("COMP_POINT", line.chars().count().to_string())];
+                let timeout = Duration::from_millis(self.config.timeout_ms);
+                capture(child, &env, &self.sandbox, timeout, false)
+                    .map(|output| output.lines().filter(|l| !l.is_empty()).map(Candidate::new).collect())
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    /// Whether the security policy would let `command` run
+    fn allowed(&self, command: &str) -> bool {
+        (self.security.allowed_commands.is_empty() || self.security.allowed_commands.contains(command))
+            && !self.security.blocked_commands.contains(command)
+    }
+
+    /// Flags for `command` from its `--help` output, or its man page when that
+    /// lists none; cached for the session and on disk per executable
+    fn help_flags(&self, command: &str) -> Vec<String> {
+        if let Some(flags) = self.help.lock().unwrap().get(command) {
+            return flags.clone();
+        }
+        let flags = self.load_help_flags(command).unwrap_or_default();
+        self.help.lock().unwrap().insert(command.to_string(), flags.clone());
+        flags
+    }
+
+    fn load_help_flags(&self, command: &str) -> Option<Vec<String>> {
+        if !self.allowed(command) {
+            return None;
+        }
+        let path = which::which(command).ok()?;
+        let name = path.file_name()?.to_string_lossy().to_string();
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let cache = self.cache_dir().map(|dir| dir.join("help").join(format!("{}.json", name)));
+
+        if let Some(cached) = cache.as_ref().and_then(|file| fs::read(file).ok()) {
+            if let Ok(cached) = serde_json::from_slice::<HelpFlags>(&cached) {
+                if cached.path == path && cached.modified == modified {
+                    return Some(cached.flags);
+                }
+            }
+        }
+
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let mut help = Command::new(&path);
+        help.arg("--help");
+        let mut flags = capture(help, &[], &self.sandbox, timeout, true).map(|text| parse_help_flags(&text)).unwrap_or_default();
+        if flags.is_empty() {
+            if let Ok(man) = which::which("man") {
+                let mut man = Command::new(man);
+                man.args(["-P", "cat", &name]);
+                flags = capture(man, &[], &self.sandbox, timeout, true)
+                    .map(|text| parse_help_flags(&strip_overstrike(&text)))
+                    .unwrap_or_default();
+            }
+        }
+
+        if let Some(file) = cache {
+            let entry = HelpFlags { path, modified, flags: flags.clone() };
+            let saved = file.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(&file, serde_json::to_vec(&entry)?));
+            if let Err(e) = saved {
+                tracing::debug!("Can't cache completion flags in {}: {}", file.display(), e);
+            }
+        }
+        Some(flags)
+    }
+}
+
+/// Run `command` under the sandbox restrictions, with `env` added, and return
+/// its output, including stderr when `with_stderr` is set; `None` if it fails
+/// to start or outlives `timeout`
+fn capture(mut command: Command, env: &[(&str, String)], sandbox: &SandboxConfig, timeout: Duration, with_stderr: bool) -> Option<String> {
+    let (mut reader, writer) = io::pipe().ok()?;
+    let stderr = if with_stderr { Stdio::from(writer.try_clone().ok()?) } else { Stdio::null() };
+    command.stdin(Stdio::null()).stdout(writer).stderr(stderr);
+    sandbox::restrict(&mut command, sandbox).ok()?;
+    command.envs(env.iter().map(|(name, value)| (name, value)));
+    let mut child = command.spawn().ok()?;
+    // Our copies of the write end must close for the reader to see EOF
+    drop(command);
+
+    let output = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = reader.by_ref().take(MAX_CAPTURE_BYTES).read_to_end(&mut output);
+        output
+    });
+    // A killed command's children may still hold the pipe, so don't wait on the reader then
+    limits::wait_until(&mut child, Some(Instant::now() + timeout)).ok()??;
+    let output = output.join().ok()?;
+    Some(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Drop the backspace overstrikes man uses for bold and underline
+fn strip_overstrike(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\x08' {
+            plain.pop();
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// Flags named in help output: on lines that start with a flag, each `-x` or
+/// `--long` before the description, without any `=VALUE` or `[ARG]`
+pub fn parse_help_flags(text: &str) -> Vec<String> {
+    static FLAG: OnceLock<Regex> = OnceLock::new();
+    let flag = FLAG.get_or_init(|| Regex::new(r"(?:^|[\s,/|\[])(--?[A-Za-z0-9?][A-Za-z0-9_-]*)").unwrap());
+
+    let mut flags: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_start();
+        if !line.starts_with('-') {
+            continue;
+        }
+        // The description starts after a run of spaces or a tab
+        let head = line.split("  ").next().unwrap_or(line).split('\t').next().unwrap_or(line);
+        for found in flag.captures_iter(head) {
+            let name = found[1].to_string();
+            if name != "-" && name != "--" && !flags.contains(&name) {
+                flags.push(name);
+            }
+        }
+    }
+    flags
+}
+
+/// Read the built-in specs, then merge in `<dir>/NAME.toml` for each command
+/// NAME; unreadable or malformed user specs are skipped with a warning
+fn load_specs(dir: Option<&Path>) -> HashMap<String, Spec> {
+    let mut specs = HashMap::new();
+    for (command, text) in BUILTIN_SPECS {
+        match toml::from_str::<Spec>(text) {
+            Ok(spec) => {
+                specs.insert(command.to_string(), spec);
+            }
+            Err(e) => tracing::warn!("Bad built-in completion spec for {}: {}", command, e),
+        }
+    }
+
+    let Some(entries) = dir.and_then(|dir| fs::read_dir(dir).ok()) else {
+        return specs;
+    };
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+            continue;
+        }
+        let Some(command) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|text| toml::from_str::<Spec>(&text).map_err(|e| e.to_string())) {
+            Ok(spec) => specs.entry(command.to_string()).or_default().merge(spec),
+            Err(e) => tracing::warn!("Skipping completion spec {}: {}", path.display(), e),
+        }
+    }
+    specs
+}
+
+/// A variable name being typed at the end of `text`, after `$` or `${`:
+/// the char index of the `$`, the partial name and whether it is braced
+fn variable_prefix(text: &str) -> Option<(usize, &str, bool)> {
+    let name_start = text.rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).map_or(0, |i| i + 1);
+    let (head, name) = text.split_at(name_start);
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let (head, braced) = match head.strip_suffix('{') {
+        Some(head) => (head, true),
+        None => (head, false),
+    };
+    let head = head.strip_suffix('$')?;
+    // `\$` is a literal dollar
+    if head.ends_with('\\') {
+        return None;
+    }
+    Some((head.chars().count(), name, braced))
+}
+
+/// Split the text before the cursor into the words of the current command and
+/// the partial word at the end, with the char index where that word starts.
+/// Quotes group words; `|`, `;` and `&` start a new command.
+fn split_words(text: &str) -> (Vec<String>, usize, String) {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (index, c) in text.chars().enumerate() {
+        if escaped {
+            word.push(c);
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\\') => escaped = true,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, c) if c.is_whitespace() || matches!(c, '|' | ';' | '&') => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                if !c.is_whitespace() {
+                    words.clear();
+                }
+                start = index + 1;
+            }
+            (None, c) => word.push(c),
+        }
+    }
+    (words, start, word)
+}
+
+/// Candidates from a spec: walk the subcommands named in `args`, then offer
+/// flags for a word starting with `-`, or subcommands, fixed args and files
+fn complete_spec(spec: &Spec, args: &[String], word: &str) -> Vec<Candidate> {
+    let mut spec = spec;
+    for arg in args {
+        if let Some(sub) = spec.subcommands.get(arg) {
+            spec = sub;
+        }
+    }
+
+    if word.starts_with('-') {
+        return spec.flags.iter().filter(|flag| flag.starts_with(word)).map(Candidate::new).collect();
+    }
+    let mut candidates: Vec<Candidate> = spec
+        .subcommands
+        .iter()
+        .filter(|(name, _)| name.starts_with(word))
+        .map(|(name, sub)| Candidate { description: sub.description.clone(), ..Candidate::new(name) })
+        .chain(spec.args.iter().filter(|arg| arg.starts_with(word)).map(Candidate::new))
+        .collect();
+    if spec.subcommands.is_empty() {
+        candidates.extend(complete_files(word));
+    }
+    candidates
+}
+
+/// Files matching `pattern` that start with `word`. Wildcards (`*`, `?` and
+/// `[...]`) are only matched in the pattern's last component.
+fn complete_glob(pattern: &str, word: &str) -> Vec<Candidate> {
+    let (dir, name_pattern) = match pattern.rfind(std::path::is_separator) {
+        Some(slash) => pattern.split_at(slash + 1),
+        None => ("", pattern),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { Path::new(".") } else { Path::new(dir) }) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| wildcard_match(name_pattern, name) && (!name.starts_with('.') || name_pattern.starts_with('.')))
+        .map(|name| format!("{}{}", dir, name))
+        .filter(|path| path.starts_with(word))
+        .map(Candidate::new)
+        .collect()
+}
+
+/// Whether `name` matches a shell wildcard `pattern`
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches_from(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && matches_from(rest, &name[1..]),
+        Some(('[', rest)) => {
+            let Some(close) = rest.iter().skip(1).position(|&c| c == ']').map(|i| i + 1) else {
+                return name.first() == Some(&'[') && matches_from(rest, &name[1..]);
+            };
+            let Some(&c) = name.first() else {
+                return false;
+            };
+            let (negated, class) = match rest[0] {
+                '!' | '^' => (true, &rest[1..close]),
+                _ => (false, &rest[..close]),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    found |= (class[i]..=class[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    found |= class[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && matches_from(&rest[close + 1..], &name[1..])
+        }
+        Some((&literal, rest)) => name.first() == Some(&literal) && matches_from(rest, &name[1..]),
+    }
+}
+
+/// File names starting with `word`, with `/` after directories. Hidden files
+/// are only offered once the word's last component starts with a dot.
+pub fn complete_files(word: &str) -> Vec<Candidate> {
+    // Windows takes `\` as well as `/`; directories complete with whichever the word uses
+    let (dir, prefix) = match word.rfind(std::path::is_separator) {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word),
+    };
+    let separator = if dir.ends_with('\\') { '\\' } else { '/' };
+    let search = match dir {
+        "" => PathBuf::from("."),
+        dir if dir.starts_with('~') && dir[1..].starts_with(std::path::is_separator) => match dirs::home_dir() {
+            Some(home) => home.join(&dir[2..]),
+            None => return Vec::new(),
+        },
+        dir if dir.starts_with('@') => match bookmarks::resolve(dir, &bookmarks::load_default()) {
+            Some(dir) => dir,
+            None => return Vec::new(),
+        },
+        dir => PathBuf::from(dir),
+    };
+    let Ok(entries) = fs::read_dir(&search) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // Follow symlinks so a link to a directory completes like one
+            let is_dir = entry.path().is_dir();
+            let text = format!("{}{}", dir, name);
+            Some(Candidate::new(if is_dir { format!("{}{}", text, separator) } else { text }))
+        })
+        .collect()
+}
+
+/// Escape `text` so the shell reads it back as one word
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_whitespace() || "\\'\"|;&<>()$`*?[]{}!#".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Longest prefix shared by every candidate
+pub fn common_prefix(candidates: &[Candidate]) -> String {
+    let Some((first, rest)) = candidates.split_first() else {
+        return String::new();
+    };
+    let mut prefix: Vec<char> = first.text.chars().collect();
+    for candidate in rest {
+        let shared = prefix.iter().zip(candidate.text.chars()).take_while(|(a, b)| **a == *b).count();
+        prefix.truncate(shared);
+    }
+    prefix.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Entry;
+
+    fn history() -> Arc<History> {
+        Arc::new(History::new(&crate::config::HistoryConfig::default()))
+    }
+
+    fn texts(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|candidate| candidate.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_builtin_specs_complete_subcommands_and_flags() {
+        let mut completer = Completer::new(&Config::default(), Arc::new(ShellState::new()), history());
+        completer.user_dir = None;
+        for (command, _) in BUILTIN_SPECS {
+            assert!(completer.spec(command).is_some(), "{} spec should parse", command);
+        }
+
+        let (start, candidates) = completer.complete("git che", 7);
+        assert_eq!((start, texts(&candidates)), (4, vec!["checkout", "cherry-pick"]));
+        assert!(candidates[0].description.is_some());
+
+        let line = "ls | git commit --am";
+        let (start, candidates) = completer.complete(line, line.len());
+        assert_eq!((start, texts(&candidates)), (16, vec!["--amend"]));
+
+        let (_, candidates) = completer.complete("kubectl get po", 14);
+        assert_eq!(texts(&candidates), ["pods"]);
+    }
+
+    #[test]
+    fn test_user_specs_merge_with_builtin_ones() {
+        let dir = std::env::temp_dir().join(format!("shell-t-completions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("git.toml"), "[subcommands.chekpoint]\ndescription = \"Save work\"\n").unwrap();
+        fs::write(dir.join("deploy.toml"), "flags = [\"--prod\", \"--dry-run\"]\n").unwrap();
+        fs::write(dir.join("broken.toml"), "flags = 3\n").unwrap();
+
+        let mut completer = Completer::new(&Config::default(), Arc::new(ShellState::new()), history());
+        completer.user_dir = Some(dir.clone());
+        let (_, candidates) = completer.complete("git che", 7);
+        assert_eq!(texts(&candidates), ["checkout", "chekpoint", "cherry-pick"]);
+        let (_, candidates) = completer.complete("deploy --", 9);
+        assert_eq!(texts(&candidates), ["--dry-run", "--prod"]);
+        assert!(completer.spec("broken").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_help_flags() {
+        let help = "Usage: ls [OPTION]... [FILE]...
+  -a, --all                  do not ignore entries starting with .
+      --block-size=SIZE      with -l, scale sizes by SIZE
+      --color[=WHEN]         color the output; see --sort
+  -1\tlist one file per line
+Report bugs to -nobody-
+";
+        assert_eq!(parse_help_flags(help), ["-a", "--all", "--block-size", "--color", "-1"]);
+        assert_eq!(strip_overstrike("-\x08--\x08-a\x08al\x08ll\x08l"), "--all");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_help_flags_for_commands_without_specs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("shell-t-help-flags-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("tool");
+        fs::write(&tool, "#!/bin/sh\necho '  -v, --verbose   say more'\necho '  --version       print it' >&2\n").unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        config.sandbox.allow_network = true;
+        config.completion.cache_dir = Some(dir.join("cache"));
+        let line = format!("{} --ver", tool.display());
+        let (_, candidates) = Completer::new(&config, Arc::new(ShellState::new()), history()).complete(&line, line.len());
+        assert!(candidates.is_empty(), "help flags are off by default");
+
+        config.completion.help_flags = true;
+        let (_, candidates) = Completer::new(&config, Arc::new(ShellState::new()), history()).complete(&line, line.len());
+        assert_eq!(texts(&candidates), ["--verbose", "--version"]);
+        // A new completer reads the flags back from the disk cache, while
+        // the tool's modification time is unchanged
+        let modified = fs::metadata(&tool).unwrap().modified().unwrap();
+        fs::write(&tool, "#!/bin/sh\n").unwrap();
+        fs::File::options().write(true).open(&tool).unwrap().set_modified(modified).unwrap();
+        let (_, candidates) = Completer::new(&config, Arc::new(ShellState::new()), history()).complete(&line, line.len());
+        assert_eq!(candidates.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_command_names_from_builtins_and_path() {
+        let dir = std::env::temp_dir().join(format!("shell-t-complete-commands-{}", std::process::id()));
+        let mut config = Config::default();
+        config.completion.cache_dir = Some(dir.clone());
+        let completer = Completer::new(&config, Arc::new(ShellState::new()), history());
+
+        let (start, candidates) = completer.complete("ls | hist", 9);
+        assert_eq!(start, 5);
+        assert!(["hist", "history"].iter().all(|name| texts(&candidates).contains(name)));
+        // The index is saved for the next session
+        assert!(dir.join("path-index.json").exists());
+        let sh = texts(&completer.complete("s", 1).1).contains(&"sh");
+        assert_eq!(sh, which::which("sh").is_ok());
+        assert!(completer.rehash() > 0 || std::env::var_os("PATH").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_variables_complete_with_values() {
+        let state = Arc::new(ShellState::new());
+        state.set_var("SHELL_T_TEST_GREETING", "hello\tthere");
+        let completer = Completer::new(&Config::default(), Arc::clone(&state), history());
+
+        let (start, candidates) = completer.complete("echo $SHELL_T_TEST_G", 20);
+        assert_eq!((start, texts(&candidates)), (5, vec!["$SHELL_T_TEST_GREETING"]));
+        assert_eq!(candidates[0].description.as_deref(), Some("hello there"));
+        assert!(!candidates[0].is_final());
+        assert_eq!(candidates[0].insertion(), "$SHELL_T_TEST_GREETING");
+
+        let line = "cd \"${SHELL_T_TEST_GR";
+        let (start, candidates) = completer.complete(line, line.chars().count());
+        assert_eq!((start, texts(&candidates)), (4, vec!["${SHELL_T_TEST_GREETING}"]));
+        assert!(texts(&completer.complete("ls $", 4).1).contains(&"$PATH"));
+        assert_eq!(variable_prefix("echo \\$HO"), None);
+        assert_eq!(variable_prefix("echo $1"), None);
+    }
+
+    #[test]
+    fn test_history_tokens_when_nothing_else_matches() {
+        let history = history();
+        let here = env::current_dir().unwrap();
+        let entry = |command: &str, cwd: &Path| Entry { cwd: Some(cwd.to_path_buf()), ..Entry::new(command) };
+        history.add(entry("scp zz-release.tgz host:/srv", &here));
+        for n in 0..6 {
+            history.add(entry(&format!("cat zz-notes-{}.md", n), Path::new("/elsewhere")));
+        }
+        history.add(entry("tar czf zz-archive.tgz zz-notes-5.md | wc", Path::new("/elsewhere")));
+        let completer = Completer::new(&Config::default(), Arc::new(ShellState::new()), Arc::clone(&history));
+
+        // Newest first, but a command from this directory ranks as more recent
+        let (_, candidates) = completer.complete("vim zz-", 7);
+        assert_eq!(texts(&candidates)[..4], ["zz-archive.tgz", "zz-notes-5.md", "zz-release.tgz", "zz-notes-4.md"]);
+        assert_eq!(candidates.len(), 8);
+        let (_, candidates) = completer.complete("vim zz-r", 8);
+        assert_eq!(texts(&candidates), ["zz-release.tgz"]);
+        // Only when nothing else matches, and not for an empty word
+        assert!(completer.complete("vim ", 4).1.iter().all(|c| !c.text.starts_with("zz-")));
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.yml", "deploy.yml"));
+        assert!(!wildcard_match("*.yml", "deploy.yaml"));
+        assert!(wildcard_match("v?.[0-9]*", "v1.2-beta"));
+        assert!(!wildcard_match("[!a-c]*", "beta"));
+        assert!(wildcard_match("[]x]", "]"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rules_override_specs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("shell-t-complete-rules-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["app.yml", "db.yml", "notes.txt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let script = dir.join("candidates");
+        fs::write(&script, "#!/bin/sh\necho \"$1:$2:$3\"\necho \"$COMP_LINE\"\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        config.sandbox.allow_network = true;
+        config.completion.commands.insert("deploy".to_string(), CompletionRule::Words(vec!["staging".to_string(), "prod".to_string(), "preview".to_string()]));
+        let completer = Completer::new(&config, Arc::new(ShellState::new()), history());
+        let (_, candidates) = completer.complete("deploy pr", 9);
+        assert_eq!(texts(&candidates), ["preview", "prod"]);
+
+        completer.set_rule("git", CompletionRule::Glob(format!("{}/*.yml", dir.display())));
+        let line = format!("git {}/", dir.display());
+        let (_, candidates) = completer.complete(&line, line.chars().count());
+        assert_eq!(texts(&candidates), [format!("{}/app.yml", dir.display()), format!("{}/db.yml", dir.display())]);
+
+        completer.set_rule("git", CompletionRule::Command(script.display().to_string()));
+        let (_, candidates) = completer.complete("git push or", 11);
+        assert_eq!(texts(&candidates), ["git push or", "git:or:push"]);
+
+        // A function only answers once no rule does
+        let function = |command: &str, word: &str, _: &[String]| (command == "git").then(|| vec![format!("{}-from-lua", word)]);
+        assert_eq!(texts(&completer.complete_with("git x", 5, Some(&function)).1), ["git x", "git:x:git"]);
+        assert!(completer.remove_rule("git"));
+        assert_eq!(texts(&completer.complete_with("git x", 5, Some(&function)).1), ["x-from-lua"]);
+        assert_eq!(completer.rules().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_words_and_files() {
+        assert_eq!(split_words("echo 'a b' c\\ d e"), (vec!["echo".to_string(), "a b".to_string(), "c d".to_string()], 16, "e".to_string()));
+        assert_eq!(escape("my file(1).txt"), "my\\ file\\(1\\).txt");
+        assert_eq!(split_words(&escape("a b")), (Vec::new(), 0, "a b".to_string()));
+        assert_eq!(split_words("a && b "), (vec!["b".to_string()], 7, String::new()));
+
+        let dir = std::env::temp_dir().join(format!("shell-t-complete-files-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("setup.py"), "").unwrap();
+        fs::write(dir.join(".secret"), "").unwrap();
+        let word = format!("{}/s", dir.display());
+        let mut candidates = complete_files(&word);
+        candidates.sort_by(|a, b| a.text.cmp(&b.text));
+        assert_eq!(texts(&candidates), [format!("{}/setup.py", dir.display()), format!("{}/src/", dir.display())]);
+        assert!(!candidates[1].is_final());
+        assert_eq!(common_prefix(&candidates), word);
+        assert_eq!(texts(&complete_files(&format!("{}/.", dir.display()))), [format!("{}/.secret", dir.display())]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
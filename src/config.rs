@@ -1,19 +1,45 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::warnings::{WarningCategory, WarningLevel, WarningManager};
 
 /// Main configuration structure
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub security: SecurityConfig,
     pub limits: ResourceLimits,
     pub ui: UiConfig,
     pub interpreters: InterpreterConfig,
+    pub retry: RetryConfig,
+    pub warnings: WarningConfig,
+    pub logging: LoggingConfig,
+    pub telemetry: TelemetryConfig,
+    pub lua: LuaConfig,
+    pub plugins: PluginConfig,
+    pub sandbox: SandboxConfig,
+    pub source: SourceConfig,
+    pub parse_cache: ParseCacheConfig,
+    pub jobs: JobsConfig,
+    pub history: HistoryConfig,
+    pub completion: CompletionConfig,
+    pub ssh: SshConfig,
+    pub fetch: FetchConfig,
+    pub schedule: ScheduleConfig,
+    pub server: ServerConfig,
+    pub trash: TrashConfig,
+    pub glob: GlobConfig,
+    pub expansion: ExpansionConfig,
+    pub output: OutputConfig,
 }
 
 /// Security configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SecurityConfig {
     pub enable_logging: bool,
     pub enable_auditing: bool,
@@ -23,46 +49,369 @@ pub struct SecurityConfig {
     pub blocked_commands: HashSet<String>,
     pub validate_paths: bool,
     pub sanitize_input: bool,
+    /// Commands with individual run stats; less recently run ones are
+    /// counted together past this
+    pub max_tracked_commands: usize,
+    /// Rate-limit keys remembered; idle ones are forgotten first past this
+    pub max_rate_limit_keys: usize,
+    /// Runs of any one command allowed per minute; 0 turns the limit off
+    pub max_runs_per_minute: usize,
+    /// Run `$(...)` and splice in its output; off, it is refused
+    pub command_substitution: bool,
+    /// Default for `set -C`: `>` refuses to replace an existing file
+    pub noclobber: bool,
 }
 
 /// Resource limits
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ResourceLimits {
     pub max_background_processes: usize,
     pub max_pipeline_length: usize,
+    /// Seconds a foreground command may run before it is stopped (0 for no
+    /// limit)
     pub command_timeout: u64,
+    /// `command_timeout` for particular programs, by name; 0 exempts one
+    pub command_timeouts: HashMap<String, u64>,
+    /// Seconds between the SIGTERM sent to a command at its timeout and the
+    /// SIGKILL if it is still running
+    pub timeout_grace_secs: u64,
+    /// Address space each command may use, in MB (0 for no limit)
     pub max_memory_mb: usize,
+    /// CPU seconds each command may use (0 for no limit)
+    pub max_cpu_secs: u64,
+    /// Largest file a command may write, in MB (0 for no limit)
+    pub max_file_size_mb: u64,
+    /// Files each command may have open at once (0 leaves the shell's limit)
+    pub max_open_files: u64,
     pub max_arg_length: usize,
+    /// Iterations allowed per loop before it is aborted (0 for no limit)
+    pub max_loop_iterations: usize,
+}
+
+/// Retry policy for transient failures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub enabled: bool,
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+/// Warning output configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WarningConfig {
+    pub min_level: String,
+    pub suppressed: HashSet<String>,
+}
+
+/// Logging configuration for the `tracing` facade
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub modules: HashMap<String, String>,
+    pub file: Option<String>,
+}
+
+/// Local, opt-in error telemetry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    pub file: Option<String>,
+    pub max_recent: usize,
+}
+
+/// Embedded Lua scripting (requires the `lua` feature)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LuaConfig {
+    pub enabled: bool,
+    pub memory_limit_mb: usize,
+    /// VM instructions allowed per call into Lua
+    pub instruction_limit: u64,
+    /// Expose `shell.getenv` to scripts
+    pub allow_env: bool,
+}
+
+/// WebAssembly plugins (requires the `wasm` feature)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginConfig {
+    pub enabled: bool,
+    /// Fuel (roughly, wasm instructions) allowed per plugin call
+    pub fuel: u64,
+    pub max_memory_mb: usize,
+}
+
+/// Restrictions for `.sh` scripts run through `interpreters.sh_path`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Without this, scripts run in an empty network namespace (Linux only)
+    pub allow_network: bool,
+    /// Environment variables passed through; everything else is dropped
+    pub pass_env: Vec<String>,
+}
+
+/// Remote scripts run with `source URL sha256=HASH`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceConfig {
+    /// Run remote scripts that have no `sha256=` pin
+    pub allow_unpinned: bool,
+    pub timeout_secs: u64,
+    pub max_size_kb: u64,
+    /// Where verified downloads are kept; defaults to the user cache directory
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// On-disk cache of parsed scripts and startup files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParseCacheConfig {
+    pub enabled: bool,
+    /// Defaults to `parse` in the user cache directory
+    pub dir: Option<PathBuf>,
+}
+
+/// Background jobs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// Keep background output in a per-job buffer (`jobs -o %N`) instead of
+    /// passing it through to the terminal
+    pub buffer_output: bool,
+    /// Size of each job's buffer; the oldest output is dropped beyond it
+    pub buffer_kb: usize,
+}
+
+/// Command history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Save history to a file shared by all sessions; off keeps it in memory
+    pub enabled: bool,
+    /// Defaults to `history` in the user data directory
+    pub file: Option<PathBuf>,
+    /// Entries kept; the file is compacted back to this many as it grows
+    pub max_entries: usize,
+    /// Don't record a command that repeats the previous one
+    pub ignore_dups: bool,
+    /// Keep only the newest copy of a repeated command
+    pub erase_dups: bool,
+    /// Don't record commands typed with a leading space
+    pub ignore_space: bool,
+    /// Expand `!!`, `!N`, `!prefix` and `^old^new` in typed lines
+    pub expansion: bool,
+    /// Show an expanded line and ask before running it
+    pub verify_expansion: bool,
+}
+
+/// Tab completion at the interactive prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompletionConfig {
+    /// Complete flags of commands without a spec by running `cmd --help`
+    /// (or reading its man page) under the `[sandbox]` restrictions
+    pub help_flags: bool,
+    /// How long `cmd --help` or a completion command may run before it is killed
+    pub timeout_ms: u64,
+    /// Where parsed flag lists are kept; defaults to `completions` in the user cache directory
+    pub cache_dir: Option<PathBuf>,
+    /// Where candidates come from for particular commands, as set by `complete`
+    pub commands: BTreeMap<String, CompletionRule>,
+}
+
+/// Remote commands run with `ssh-run`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SshConfig {
+    /// Client used to connect
+    pub program: String,
+    /// Hosts `ssh-run` may connect to, as wildcard patterns such as
+    /// `*.internal`; empty allows any
+    pub allowed_hosts: Vec<String>,
+    pub connect_timeout_secs: u64,
+}
+
+/// HTTP requests made with the `fetch` builtin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FetchConfig {
+    /// Hosts `fetch` may contact, as wildcard patterns such as
+    /// `*.example.com`; empty allows none
+    pub allowed_hosts: Vec<String>,
+    pub timeout_secs: u64,
+    /// Larger responses are refused
+    pub max_size_kb: u64,
+}
+
+/// Commands run on a schedule with the `schedule` builtin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// Run due commands while an interactive session is open; `shell-t
+    /// --scheduler` runs them regardless
+    pub enabled: bool,
+}
+
+/// Requests served over a Unix socket with `shell-t --serve`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Clients connected at once; more are turned away
+    pub max_connections: usize,
+    /// Commands still running after this are killed
+    pub timeout_secs: u64,
+    /// Output past this, on stdout and stderr each, is dropped
+    pub max_output_kb: u64,
+    /// Variables a request may set, where a name ending in `_` allows every
+    /// one starting with it; requests setting any other are refused
+    pub allowed_env: Vec<String>,
+}
+
+/// Safe delete: a blocked `rm` moving its targets to the trash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// Handle `rm` with the trash while `security.blocked_commands` refuses it
+    pub safe_delete: bool,
+    /// Entries deleted longer ago than this are purged
+    pub max_age_days: u64,
+    /// The oldest entries are purged while the trash is larger than this
+    pub max_size_mb: u64,
+}
+
+/// Filename expansion of unquoted `*`, `?`, `[...]` and `**`; the defaults
+/// for `set -o nullglob`, `set -o failglob` and `set -o dotglob`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GlobConfig {
+    /// A pattern matching nothing is removed instead of passed on as typed
+    pub nullglob: bool,
+    /// A pattern matching nothing is an error and the command doesn't run;
+    /// takes precedence over `nullglob`
+    pub failglob: bool,
+    /// Wildcards match names starting with `.`, as if the pattern did
+    pub dotglob: bool,
+}
+
+/// Word splitting of unquoted `$VAR` and `$(...)` results
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExpansionConfig {
+    /// They stay one word instead of splitting on `$IFS`; the default for
+    /// `set -o nosplit`
+    pub nosplit: bool,
+}
+
+/// Output of external commands passed through the shell a line at a time on
+/// its way to the terminal, instead of the command writing to it directly
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// Stream output through the shell; the rest of this section needs it
+    pub stream: bool,
+    /// A command writing more than this to the terminal, on stdout or on
+    /// stderr, is stopped; 0 for no limit
+    pub max_kb: u64,
+    /// Regular expressions; text matching one is shown as `[REDACTED]`
+    pub redact: Vec<String>,
+    /// Each line starts with the time it was written, `[14:03:07] `
+    pub timestamps: bool,
+    /// Every line shown is also appended to this file
+    pub record_file: Option<PathBuf>,
+}
+
+/// Source of completions for a command's arguments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompletionRule {
+    /// Fixed words
+    Words(Vec<String>),
+    /// Files matching a pattern such as `*.yml` or `deploy/*.toml`
+    Glob(String),
+    /// A command printing candidates one per line; it gets the command name,
+    /// the word being completed and the word before it as arguments
+    Command(String),
 }
 
 /// UI configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct UiConfig {
     pub enable_colors: bool,
     pub prompt_color: String,
     pub show_timestamps: bool,
     pub enable_completion: bool,
+    /// The prompt shows a failed command's exit status, e.g. `[exit 1]`
+    pub show_exit_status: bool,
+    /// Identical errors closer together than this are collapsed
+    pub repeat_window_ms: u64,
+    /// How `jobs`, `history`, `which`, `type`, `config` and `security stats`
+    /// print results when not given `--output`
+    pub builtin_output: OutputMode,
+}
+
+/// Output form of builtins with structured results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// JSON when stdout is not a terminal, text otherwise
+    Auto,
+    #[default]
+    Text,
+    Json,
 }
 
 /// Interpreter configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct InterpreterConfig {
     pub python_path: String,
     pub ruby_path: String,
     pub node_path: String,
+    /// Runner for `.rs` scripts: `rust-script`, or `cargo` for `cargo -Zscript`
+    pub rust_path: String,
+    /// Toolchain for `.go` scripts, run as `go run`
+    pub go_path: String,
+    /// POSIX shell for `.sh` scripts, which run under `[sandbox]`
+    pub sh_path: String,
     pub enable_scripts: bool,
+    pub enable_rust: bool,
+    pub enable_go: bool,
     pub allowed_extensions: HashSet<String>,
+    /// Interpreters for further script extensions, e.g. `pl = "perl -w"`; the
+    /// extension must also be in `allowed_extensions`
+    pub extensions: HashMap<String, String>,
+    /// Keep interpreters running between scripts to skip startup cost
+    pub persistent: bool,
+    /// Prefer a project's `.venv`, Poetry, rbenv or nvm interpreter over the
+    /// paths above, in `trusted_projects` only
+    pub detect_project_envs: bool,
+    /// Directories whose project environments may be used; anywhere else a
+    /// checkout could choose which binary runs
+    pub trusted_projects: Vec<PathBuf>,
+    /// Run JavaScript in the built-in engine instead of `node_path` (requires the `js` feature)
+    pub embedded_js: bool,
+    pub limits: InterpreterLimits,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            security: SecurityConfig::default(),
-            limits: ResourceLimits::default(),
-            ui: UiConfig::default(),
-            interpreters: InterpreterConfig::default(),
-        }
-    }
+/// Limits on interpreter processes started for scripts and inline code,
+/// separate from the general `[limits]`; 0 turns a limit off
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InterpreterLimits {
+    /// Wall-clock seconds before the interpreter is killed
+    pub timeout_secs: u64,
+    /// Address space cap (Unix only)
+    pub max_memory_mb: u64,
+    /// Bytes of stdout passed on before the interpreter is killed
+    pub max_output_kb: u64,
 }
 
 impl Default for SecurityConfig {
@@ -86,6 +435,11 @@ impl Default for SecurityConfig {
             blocked_commands,
             validate_paths: true,
             sanitize_input: true,
+            max_tracked_commands: 500,
+            max_rate_limit_keys: 1000,
+            max_runs_per_minute: 0,
+            command_substitution: true,
+            noclobber: false,
         }
     }
 }
@@ -96,8 +450,186 @@ impl Default for ResourceLimits {
             max_background_processes: 10,
             max_pipeline_length: 10,
             command_timeout: 300, // 5 minutes
-            max_memory_mb: 512,
+            command_timeouts: HashMap::new(),
+            timeout_grace_secs: 5,
+            max_memory_mb: 0,
+            max_cpu_secs: 0,
+            max_file_size_mb: 0,
+            max_open_files: 0,
             max_arg_length: 1024,
+            max_loop_iterations: 100_000,
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 3,
+            base_delay_ms: 50,
+            max_delay_ms: 1000,
+        }
+    }
+}
+
+impl Default for WarningConfig {
+    fn default() -> Self {
+        Self {
+            min_level: "notice".to_string(),
+            suppressed: HashSet::new(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "error".to_string(),
+            modules: HashMap::new(),
+            file: None,
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file: None,
+            max_recent: 50,
+        }
+    }
+}
+
+impl Default for LuaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            memory_limit_mb: 16,
+            instruction_limit: 10_000_000,
+            allow_env: false,
+        }
+    }
+}
+
+impl Default for PluginConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fuel: 100_000_000,
+            max_memory_mb: 64,
+        }
+    }
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            allow_network: false,
+            pass_env: ["PATH", "HOME", "USER", "LANG", "LC_ALL", "TERM", "TMPDIR"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        Self {
+            allow_unpinned: false,
+            timeout_secs: 30,
+            max_size_kb: 1024,
+            cache_dir: None,
+        }
+    }
+}
+
+impl Default for ParseCacheConfig {
+    fn default() -> Self {
+        Self { enabled: true, dir: None }
+    }
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self { buffer_output: true, buffer_kb: 64 }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            file: None,
+            max_entries: 10000,
+            ignore_dups: true,
+            erase_dups: false,
+            ignore_space: true,
+            expansion: true,
+            verify_expansion: false,
+        }
+    }
+}
+
+impl Default for CompletionConfig {
+    fn default() -> Self {
+        Self {
+            help_flags: false,
+            timeout_ms: 2000,
+            cache_dir: None,
+            commands: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            program: "ssh".to_string(),
+            allowed_hosts: Vec::new(),
+            connect_timeout_secs: 10,
+        }
+    }
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: Vec::new(),
+            timeout_secs: 30,
+            max_size_kb: 10240,
+        }
+    }
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            safe_delete: false,
+            max_age_days: 30,
+            max_size_mb: 1024,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 16,
+            timeout_secs: 60,
+            max_output_kb: 1024,
+            allowed_env: ["LANG", "LANGUAGE", "LC_", "TZ", "TERM", "COLUMNS", "LINES", "NO_COLOR"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
         }
     }
 }
@@ -109,6 +641,9 @@ impl Default for UiConfig {
             prompt_color: "green".to_string(),
             show_timestamps: false,
             enable_completion: true,
+            show_exit_status: true,
+            repeat_window_ms: 2000,
+            builtin_output: OutputMode::Text,
         }
     }
 }
@@ -116,7 +651,7 @@ impl Default for UiConfig {
 impl Default for InterpreterConfig {
     fn default() -> Self {
         let mut allowed_extensions = HashSet::new();
-        for ext in ["py", "rb", "js", "sh"] {
+        for ext in ["py", "rb", "js", "sh", "rs", "go"] {
             allowed_extensions.insert(ext.to_string());
         }
 
@@ -124,18 +659,41 @@ impl Default for InterpreterConfig {
             python_path: "python3".to_string(),
             ruby_path: "ruby".to_string(),
             node_path: "node".to_string(),
+            rust_path: "rust-script".to_string(),
+            go_path: "go".to_string(),
+            sh_path: "/bin/sh".to_string(),
             enable_scripts: true,
+            enable_rust: true,
+            enable_go: true,
             allowed_extensions,
+            extensions: HashMap::new(),
+            persistent: false,
+            detect_project_envs: false,
+            trusted_projects: Vec::new(),
+            embedded_js: true,
+            limits: InterpreterLimits::default(),
+        }
+    }
+}
+
+impl Default for InterpreterLimits {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 300,
+            max_memory_mb: 1024,
+            max_output_kb: 0,
         }
     }
 }
 
 impl Config {
-    /// Load configuration from file and environment variables
+    /// Load configuration from file and environment variables. Only the
+    /// user's own config file is read, never one in the current directory,
+    /// which could replace `[security]` for whoever runs the shell there.
     pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
         let mut config = Self::default();
 
-        if let Ok(config_str) = fs::read_to_string("shell-t.toml") {
+        if let Some(config_str) = Self::file().and_then(|p| fs::read_to_string(p).ok()) {
             config = Self::parse_toml(&config_str)?;
         }
 
@@ -144,9 +702,19 @@ impl Config {
         Ok(config)
     }
 
+    /// The config file `load` reads: `$SHELL_T_CONFIG` if set (empty for
+    /// none), else `config.toml` in the config directory
+    pub fn file() -> Option<PathBuf> {
+        match env::var_os("SHELL_T_CONFIG") {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => config_dir().map(|d| d.join("config.toml")),
+        }
+    }
+
     /// Parse TOML configuration
-    fn parse_toml(_content: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(Self::default())
+    fn parse_toml(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(content)?)
     }
 
     /// Load configuration from environment variables
@@ -161,6 +729,12 @@ impl Config {
             }
         }
 
+        if let Ok(val) = env::var("SHELL_T_MAX_RUNS_PER_MINUTE") {
+            if let Ok(runs) = val.parse() {
+                self.security.max_runs_per_minute = runs;
+            }
+        }
+
         if let Ok(val) = env::var("SHELL_T_PYTHON_PATH") {
             self.interpreters.python_path = val;
         }
@@ -173,17 +747,112 @@ impl Config {
             self.interpreters.node_path = val;
         }
 
+        if let Ok(val) = env::var("SHELL_T_RUST_PATH") {
+            self.interpreters.rust_path = val;
+        }
+
+        if let Ok(val) = env::var("SHELL_T_GO_PATH") {
+            self.interpreters.go_path = val;
+        }
+
+        if let Ok(val) = env::var("SHELL_T_SH_PATH") {
+            self.interpreters.sh_path = val;
+        }
+
+        if let Ok(val) = env::var("SHELL_T_PERSISTENT_INTERPRETERS") {
+            self.interpreters.persistent = val.parse().unwrap_or(false);
+        }
+
+        if let Ok(val) = env::var("SHELL_T_PROJECT_ENVS") {
+            self.interpreters.detect_project_envs = val.parse().unwrap_or(false);
+        }
+
+        if let Ok(val) = env::var("SHELL_T_EMBEDDED_JS") {
+            self.interpreters.embedded_js = val.parse().unwrap_or(true);
+        }
+
+        if let Ok(val) = env::var("SHELL_T_INTERPRETER_TIMEOUT") {
+            if let Ok(secs) = val.parse() {
+                self.interpreters.limits.timeout_secs = secs;
+            }
+        }
+
+        if let Ok(val) = env::var("SHELL_T_INTERPRETER_MEMORY_MB") {
+            if let Ok(mb) = val.parse() {
+                self.interpreters.limits.max_memory_mb = mb;
+            }
+        }
+
+        if let Ok(val) = env::var("SHELL_T_INTERPRETER_MAX_OUTPUT_KB") {
+            if let Ok(kb) = val.parse() {
+                self.interpreters.limits.max_output_kb = kb;
+            }
+        }
+
+        if let Ok(val) = env::var("SHELL_T_PARSE_CACHE") {
+            self.parse_cache.enabled = val.parse().unwrap_or(true);
+        }
+
+        if let Ok(val) = env::var("SHELL_T_BUFFER_JOB_OUTPUT") {
+            self.jobs.buffer_output = val.parse().unwrap_or(true);
+        }
+
+        if let Ok(val) = env::var("SHELL_T_HISTORY_FILE") {
+            self.history.file = Some(PathBuf::from(val));
+        }
+
+        if let Ok(val) = env::var("SHELL_T_OUTPUT") {
+            match val.as_str() {
+                "auto" => self.ui.builtin_output = OutputMode::Auto,
+                "text" => self.ui.builtin_output = OutputMode::Text,
+                "json" => self.ui.builtin_output = OutputMode::Json,
+                _ => {}
+            }
+        }
+
         if let Ok(val) = env::var("SHELL_T_ENABLE_COLORS") {
             self.ui.enable_colors = val.parse().unwrap_or(true);
         }
+
+        if let Ok(val) = env::var("SHELL_T_LOG") {
+            self.logging.level = val;
+        }
+
+        if let Ok(val) = env::var("SHELL_T_LOG_FILE") {
+            self.logging.file = Some(val);
+        }
+
+        if let Ok(val) = env::var("SHELL_T_TELEMETRY") {
+            self.telemetry.enabled = val.parse().unwrap_or(false);
+        }
+
+        if let Ok(val) = env::var("SHELL_T_NO_WARN") {
+            self.warnings.suppressed.extend(
+                val.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()),
+            );
+        }
+
+        if let Ok(val) = env::var("SHELL_T_RETRY") {
+            self.retry.enabled = val.parse().unwrap_or(true);
+        }
+
+        if let Ok(val) = env::var("SHELL_T_RETRY_ATTEMPTS") {
+            if let Ok(attempts) = val.parse() {
+                self.retry.max_attempts = attempts;
+            }
+        }
     }
 
-    /// Validate the configuration
+    /// Validate the configuration, rejecting settings the shell cannot run with
     pub fn validate(&self) -> Result<(), String> {
         if self.security.max_command_length == 0 {
             return Err("Max command length must be greater than 0".to_string());
         }
 
+        if self.security.max_tracked_commands == 0 || self.security.max_rate_limit_keys == 0 {
+            return Err("Security bookkeeping caps must be greater than 0".to_string());
+        }
+
         if self.limits.max_background_processes == 0 {
             return Err("Max background processes must be greater than 0".to_string());
         }
@@ -192,19 +861,75 @@ impl Config {
             return Err("Max pipeline length must be greater than 0".to_string());
         }
 
-        if !Path::new(&self.interpreters.python_path).exists() {
-            eprintln!("Warning: Python interpreter not found at {}", self.interpreters.python_path);
+        if self.history.max_entries == 0 {
+            return Err("History max entries must be greater than 0".to_string());
+        }
+
+        if self.completion.timeout_ms == 0 {
+            return Err("Completion help timeout must be greater than 0".to_string());
+        }
+
+        if self.ssh.connect_timeout_secs == 0 {
+            return Err("SSH connect timeout must be greater than 0".to_string());
+        }
+
+        if self.fetch.timeout_secs == 0 {
+            return Err("Fetch timeout must be greater than 0".to_string());
+        }
+
+        if self.server.max_connections == 0 || self.server.timeout_secs == 0 {
+            return Err("Server connection limit and timeout must be greater than 0".to_string());
+        }
+
+        for pattern in &self.output.redact {
+            if let Err(e) = regex::bytes::Regex::new(pattern) {
+                return Err(format!("Invalid output.redact pattern {:?}: {}", pattern, e));
+            }
         }
 
         Ok(())
     }
 
+    /// Report configured interpreters that can't be found: as a notice in
+    /// an interactive session, where someone can act on it, and only to the
+    /// log in `-c` and batch runs
+    pub fn check_interpreters(&self, warnings: &WarningManager, interactive: bool) {
+        for path in [&self.interpreters.python_path, &self.interpreters.ruby_path, &self.interpreters.node_path] {
+            if Path::new(path).exists() || which::which(path).is_ok() {
+                continue;
+            }
+            tracing::debug!(path = path.as_str(), "interpreter not found");
+            if interactive {
+                warnings.warn_once(
+                    WarningLevel::Notice,
+                    WarningCategory::Interpreter,
+                    &format!("interpreter not found: {}", path),
+                );
+            }
+        }
+    }
+
     /// Save configuration to file
     pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
         Ok(())
     }
 }
 
+/// Directory holding user configuration (`~/.config/shell-t` on Linux)
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("shell-t"))
+}
+
+/// Directory holding persistent shell data (`~/.local/share/shell-t` on Linux)
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("shell-t"))
+}
+
+/// Directory holding disposable cached data (`~/.cache/shell-t` on Linux)
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("shell-t"))
+}
+
 /// Configuration validation functions
 pub mod validation {
     use super::*;
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::process::Command;
+
+/// Container engine whose CLI runs commands for `in`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    Docker,
+    Podman,
+}
+
+impl Runtime {
+    /// Program invoked for `exec`, checked against the command policy
+    pub fn program(self) -> &'static str {
+        match self {
+            Runtime::Docker => "docker",
+            Runtime::Podman => "podman",
+        }
+    }
+}
+
+/// A running container commands are sent into, written `docker:NAME` or
+/// `podman:NAME`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    pub runtime: Runtime,
+    pub name: String,
+}
+
+impl Container {
+    /// Parse a `RUNTIME:NAME` target
+    pub fn parse(target: &str) -> Result<Self, String> {
+        let (runtime, name) = target
+            .split_once(':')
+            .ok_or_else(|| format!("{}: expected docker:NAME or podman:NAME", target))?;
+        let runtime = match runtime {
+            "docker" => Runtime::Docker,
+            "podman" => Runtime::Podman,
+            _ => return Err(format!("{}: unknown container runtime", runtime)),
+        };
+        // The engines' own rule for names; IDs fit it too
+        let valid = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if !valid {
+            return Err(format!("{}: invalid container name", name));
+        }
+        Ok(Self { runtime, name: name.to_string() })
+    }
+
+    /// `exec` invocation running `program` in the container with `env` set.
+    /// A terminal is only allocated for commands that own one, since it
+    /// merges stderr into stdout.
+    pub fn command(&self, program: &str, args: &[String], env: &[(String, String)], tty: bool) -> Command {
+        let mut command = Command::new(self.runtime.program());
+        command.arg("exec").arg(if tty { "-it" } else { "-i" });
+        for (name, value) in env {
+            command.arg("-e").arg(format!("{}={}", name, value));
+        }
+        command.arg(&self.name).arg(program).args(args);
+        command
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.runtime.program(), self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_command() {
+        let container = Container::parse("podman:web_1").unwrap();
+        assert_eq!(container.runtime, Runtime::Podman);
+        assert_eq!(container.to_string(), "podman:web_1");
+        assert!(Container::parse("web").is_err());
+        assert!(Container::parse("lxc:web").is_err());
+        assert!(Container::parse("docker:-e").is_err());
+        assert!(Container::parse("docker:").is_err());
+
+        let command = container.command("ls", &["-l".to_string()], &[("LANG".to_string(), "C".to_string())], false);
+        assert_eq!(command.get_program(), "podman");
+        let args: Vec<_> = command.get_args().map(|arg| arg.to_string_lossy().to_string()).collect();
+        assert_eq!(args, ["exec", "-i", "-e", "LANG=C", "web_1", "ls", "-l"]);
+    }
+}
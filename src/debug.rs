@@ -0,0 +1,179 @@
+use std::collections::BTreeSet;
+use std::io::BufRead;
+
+use crate::expand;
+use crate::state::ShellState;
+use crate::ui::UiManager;
+
+const HELP: &str = "\
+  step, s          Run this statement and stop at the next one (also Enter)
+  continue, c      Run until the next breakpoint
+  break N, b N     Stop before line N; without N, list breakpoints
+  delete N, d N    Remove the breakpoint on line N
+  print NAME, p    Show a variable, parameter ($1, $#) or environment variable
+  vars             List shell variables
+  list, l          Show the current statement
+  skip             Skip this statement
+  rerun, r         Run the previous statement again
+  quit, q          Stop the script";
+
+/// What the script runner should do with the statement the debugger stopped at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Run,
+    Skip,
+    /// Run the previous statement (its first line and text) again, then stop here again
+    Rerun(usize, String),
+    Quit,
+}
+
+/// Step debugger for `shell-t --debug script.sht`.
+///
+/// Statements are the chunks a script runs at a time: one line, or a whole
+/// compound command. The debugger stops before the first statement, then
+/// before each one while stepping or whenever a breakpoint line falls inside it.
+pub struct Debugger {
+    input: Box<dyn BufRead>,
+    breakpoints: BTreeSet<usize>,
+    stepping: bool,
+    /// First line and text of the statement run last, for `rerun`
+    previous: Option<(usize, String)>,
+}
+
+impl Debugger {
+    /// Create a debugger reading commands from `input`
+    pub fn new(input: Box<dyn BufRead>) -> Self {
+        Self { input, breakpoints: BTreeSet::new(), stepping: true, previous: None }
+    }
+
+    /// Whether to stop before the statement starting at `line`
+    pub fn should_stop(&self, line: usize, statement: &str) -> bool {
+        let end = line + statement.lines().count().max(1);
+        self.stepping || self.breakpoints.range(line..end).next().is_some()
+    }
+
+    /// Remember the statement that just ran
+    pub fn ran(&mut self, line: usize, statement: &str) {
+        self.previous = Some((line, statement.to_string()));
+    }
+
+    /// Show where the script stopped and read commands until one resumes it
+    pub fn prompt(&mut self, ui: &UiManager, location: &str, line: usize, statement: &str, state: &ShellState) -> Action {
+        let _ = ui.display_debug_stop(location, line, statement);
+        loop {
+            let _ = ui.display_language_prompt("debug", false);
+            let mut input = String::new();
+            // Without a terminal to ask, let the script finish
+            if !matches!(self.input.read_line(&mut input), Ok(n) if n > 0) {
+                println!();
+                self.stepping = false;
+                self.breakpoints.clear();
+                return Action::Run;
+            }
+
+            let mut words = input.split_whitespace();
+            let command = words.next().unwrap_or("step");
+            let arg = words.next();
+            match command {
+                "step" | "s" => {
+                    self.stepping = true;
+                    return Action::Run;
+                }
+                "continue" | "c" => {
+                    self.stepping = false;
+                    return Action::Run;
+                }
+                "skip" => {
+                    self.stepping = true;
+                    return Action::Skip;
+                }
+                "rerun" | "r" => match &self.previous {
+                    Some((line, statement)) => return Action::Rerun(*line, statement.clone()),
+                    None => println!("no statement has run yet"),
+                },
+                "quit" | "q" => return Action::Quit,
+                "break" | "b" => match arg.map(str::parse::<usize>) {
+                    None if self.breakpoints.is_empty() => println!("no breakpoints"),
+                    None => {
+                        let lines: Vec<String> = self.breakpoints.iter().map(usize::to_string).collect();
+                        println!("breakpoints: {}", lines.join(", "));
+                    }
+                    Some(Ok(n)) if n > 0 => {
+                        self.breakpoints.insert(n);
+                        println!("breakpoint at line {}", n);
+                    }
+                    Some(_) => println!("break: expected a line number"),
+                },
+                "delete" | "d" => match arg.map(str::parse::<usize>) {
+                    Some(Ok(n)) if self.breakpoints.remove(&n) => println!("deleted breakpoint at line {}", n),
+                    Some(Ok(n)) => println!("no breakpoint at line {}", n),
+                    _ => println!("delete: expected a line number"),
+                },
+                "print" | "p" => match arg {
+                    Some(name) => {
+                        let name = name.trim_start_matches('$');
+                        println!("{}={}", name, expand::value(name, state).unwrap_or_default());
+                    }
+                    None => println!("print: expected a variable name"),
+                },
+                "vars" => {
+                    for (name, value) in state.vars() {
+                        println!("{}={}", name, value);
+                    }
+                }
+                "list" | "l" => {
+                    let _ = ui.display_debug_stop(location, line, statement);
+                }
+                "help" | "h" => println!("{}", HELP),
+                _ => println!("unknown debugger command: {} (try help)", command),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::io::Cursor;
+
+    fn debugger(input: &str) -> Debugger {
+        Debugger::new(Box::new(Cursor::new(input.to_string())))
+    }
+
+    fn prompt(debugger: &mut Debugger, state: &ShellState) -> Action {
+        debugger.prompt(&UiManager::new(Config::default()), "test.sht:1", 1, "echo hi\n", state)
+    }
+
+    #[test]
+    fn test_breakpoints_and_continue() {
+        let state = ShellState::new();
+        let mut debugger = debugger("b 3\nbogus\nc\n");
+        assert!(debugger.should_stop(1, "true\n"));
+        assert_eq!(prompt(&mut debugger, &state), Action::Run);
+
+        assert!(!debugger.should_stop(2, "true\n"));
+        assert!(debugger.should_stop(2, "if true\nthen true\nfi\n"));
+        assert!(debugger.should_stop(3, "true\n"));
+    }
+
+    #[test]
+    fn test_skip_rerun_and_quit() {
+        let state = ShellState::new();
+        let mut debugger = debugger("r\nskip\nrerun\nq\n");
+        // Nothing has run yet, so `r` is refused and `skip` is read next
+        assert_eq!(prompt(&mut debugger, &state), Action::Skip);
+
+        debugger.ran(4, "echo again\n");
+        assert_eq!(prompt(&mut debugger, &state), Action::Rerun(4, "echo again\n".to_string()));
+        assert_eq!(prompt(&mut debugger, &state), Action::Quit);
+    }
+
+    #[test]
+    fn test_end_of_input_detaches() {
+        let state = ShellState::new();
+        let mut debugger = debugger("b 5\n");
+        assert_eq!(prompt(&mut debugger, &state), Action::Run);
+        assert!(!debugger.should_stop(5, "true\n"));
+    }
+}
@@ -0,0 +1,421 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crossterm::cursor::{MoveDown, MoveToColumn, MoveUp};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::QueueableCommand;
+
+use crate::completions::{self, Candidate, Completer, Function};
+use crate::history::History;
+use crate::picker;
+use crate::ui;
+
+/// Text being edited and the cursor position in it, in chars
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    /// A line holding `text`, with the cursor at its end
+    pub fn new(text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        Self { cursor: chars.len(), chars }
+    }
+
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    /// Replace the text from char `start` to the cursor with `text`
+    pub fn replace(&mut self, start: usize, text: &str) {
+        self.chars.splice(start..self.cursor, text.chars());
+        self.cursor = start + text.chars().count();
+    }
+
+    /// Delete the char before the cursor (Backspace)
+    pub fn delete_back(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    /// Delete the char under the cursor (Delete)
+    pub fn delete_forward(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    /// Delete the word before the cursor (Ctrl-W)
+    pub fn delete_word_back(&mut self) {
+        let end = self.cursor;
+        self.word_left();
+        self.chars.drain(self.cursor..end);
+    }
+
+    /// Delete from the start of the line to the cursor (Ctrl-U)
+    pub fn delete_to_start(&mut self) {
+        self.chars.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    /// Delete from the cursor to the end of the line (Ctrl-K)
+    pub fn delete_to_end(&mut self) {
+        self.chars.truncate(self.cursor);
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.chars.len());
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.chars.len();
+    }
+
+    /// Move to the start of the previous word
+    pub fn word_left(&mut self) {
+        while self.cursor > 0 && self.chars[self.cursor - 1].is_whitespace() {
+            self.cursor -= 1;
+        }
+        while self.cursor > 0 && !self.chars[self.cursor - 1].is_whitespace() {
+            self.cursor -= 1;
+        }
+    }
+
+    /// Move past the end of the next word
+    pub fn word_right(&mut self) {
+        let len = self.chars.len();
+        while self.cursor < len && self.chars[self.cursor].is_whitespace() {
+            self.cursor += 1;
+        }
+        while self.cursor < len && !self.chars[self.cursor].is_whitespace() {
+            self.cursor += 1;
+        }
+    }
+}
+
+/// What Up and Ctrl-R recall in a tab's pane: the history from before the
+/// pane opened, then the commands typed in it, so panes don't see each
+/// other's commands
+#[derive(Debug, Clone, Default)]
+pub struct Recall {
+    /// Seconds since the epoch
+    pub before: i64,
+    pub typed: Vec<String>,
+}
+
+/// Leaves raw mode however reading a line ends
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Line editor for the interactive prompt: cursor movement, emacs-style
+/// kill keys, Up/Down through history, Ctrl-R for the fuzzy history picker
+/// and Tab completion
+pub struct LineEditor {
+    history: Arc<History>,
+    /// Tab completion, unless turned off with `ui.enable_completion`
+    completer: Option<Arc<Completer>>,
+    /// Row of the cursor below the prompt's row, for redrawing wrapped lines
+    cursor_row: usize,
+    /// Limits recall to a pane's commands, in multiplexer mode
+    recall: Option<Recall>,
+}
+
+impl LineEditor {
+    pub fn new(history: Arc<History>, completer: Option<Arc<Completer>>) -> Self {
+        Self { history, completer, cursor_row: 0, recall: None }
+    }
+
+    /// Recall only what `recall` allows, or the whole history with `None`
+    pub fn set_recall(&mut self, recall: Option<Recall>) {
+        self.recall = recall;
+    }
+
+    /// Read a line typed after a prompt `prompt_width` columns wide, starting
+    /// with `initial` in the buffer; Tab tries `function` after any `complete`
+    /// rule. Returns `None` for Ctrl-D on an empty line; Ctrl-C abandons the
+    /// line and returns it empty.
+    pub fn read_line(&mut self, prompt_width: usize, initial: &str, function: Option<Function>) -> io::Result<Option<String>> {
+        let _raw = RawMode::enable()?;
+        let mut line = Line::new(initial);
+        // History is loaded on the first Up, newest last
+        let mut commands: Option<Vec<String>> = None;
+        let mut browsing: Option<(usize, String)> = None;
+        self.cursor_row = 0;
+        self.redraw(prompt_width, &line)?;
+
+        loop {
+            let key = match event::read()? {
+                Event::Key(key @ KeyEvent { kind: KeyEventKind::Press, .. }) => key,
+                Event::Resize(..) => {
+                    self.redraw(prompt_width, &line)?;
+                    continue;
+                }
+                _ => continue,
+            };
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            let alt = key.modifiers.contains(KeyModifiers::ALT);
+            // Windows reports AltGr as Ctrl+Alt; what it types is plain text
+            let (ctrl, alt) = if cfg!(windows) && ctrl && alt { (false, false) } else { (ctrl, alt) };
+            let code = match key.code {
+                KeyCode::Char('p') if ctrl => KeyCode::Up,
+                KeyCode::Char('n') if ctrl => KeyCode::Down,
+                code => code,
+            };
+            match code {
+                KeyCode::Enter => break,
+                KeyCode::Char('j' | 'm') if ctrl => break,
+                KeyCode::Char('c') if ctrl => {
+                    self.finish(prompt_width, &line, "^C")?;
+                    return Ok(Some(String::new()));
+                }
+                KeyCode::Char('d') if ctrl && line.is_empty() => return Ok(None),
+                KeyCode::Char('d') if ctrl => line.delete_forward(),
+                KeyCode::Char('r') if ctrl => {
+                    let commands = commands.get_or_insert_with(|| self.commands());
+                    let newest_first: Vec<String> = commands.iter().rev().cloned().collect();
+                    if let Some(picked) = picker::pick(&newest_first, &line.text())? {
+                        line = Line::new(&picked);
+                    }
+                    // The picker ran on the alternate screen and left raw mode
+                    terminal::enable_raw_mode()?;
+                }
+                KeyCode::Up | KeyCode::Down => {
+                    let commands = commands.get_or_insert_with(|| self.commands());
+                    let index = browsing.as_ref().map_or(commands.len(), |(index, _)| *index);
+                    let index = match code {
+                        KeyCode::Up => index.saturating_sub(1),
+                        _ => (index + 1).min(commands.len()),
+                    };
+                    let draft = browsing.take().map_or_else(|| line.text(), |(_, draft)| draft);
+                    line = Line::new(commands.get(index).unwrap_or(&draft));
+                    browsing = Some((index, draft));
+                }
+                KeyCode::Tab => {
+                    if let Some(candidates) = self.complete(&mut line, function) {
+                        self.redraw(prompt_width, &line)?;
+                        self.show_candidates(prompt_width, &line, &candidates)?;
+                        continue;
+                    }
+                }
+                KeyCode::Left => line.left(),
+                KeyCode::Char('b') if ctrl => line.left(),
+                KeyCode::Right => line.right(),
+                KeyCode::Char('f') if ctrl => line.right(),
+                KeyCode::Char('b') if alt => line.word_left(),
+                KeyCode::Char('f') if alt => line.word_right(),
+                KeyCode::Home => line.home(),
+                KeyCode::Char('a') if ctrl => line.home(),
+                KeyCode::End => line.end(),
+                KeyCode::Char('e') if ctrl => line.end(),
+                KeyCode::Backspace => line.delete_back(),
+                KeyCode::Char('h') if ctrl => line.delete_back(),
+                KeyCode::Delete => line.delete_forward(),
+                KeyCode::Char('w') if ctrl => line.delete_word_back(),
+                KeyCode::Char('u') if ctrl => line.delete_to_start(),
+                KeyCode::Char('k') if ctrl => line.delete_to_end(),
+                // A pasted CRLF arrives as Enter and then a stray `\n`
+                KeyCode::Char(c) if !ctrl && !alt && !c.is_control() => line.insert(c),
+                _ => continue,
+            }
+            self.redraw(prompt_width, &line)?;
+        }
+        self.finish(prompt_width, &line, "")?;
+        Ok(Some(line.text()))
+    }
+
+    /// Commands in history, oldest first
+    fn commands(&self) -> Vec<String> {
+        let entries = self.history.entries().into_iter();
+        let mut commands: Vec<String> = match &self.recall {
+            Some(recall) => entries
+                .filter(|entry| entry.time.is_none_or(|time| time < recall.before))
+                .map(|entry| entry.command)
+                .chain(recall.typed.iter().cloned())
+                .collect(),
+            None => entries.map(|entry| entry.command).collect(),
+        };
+        commands.dedup();
+        commands
+    }
+
+    /// Complete the word before the cursor: a single candidate replaces it,
+    /// several extend it to their common prefix. Returns the candidates to list
+    /// when that changes nothing.
+    fn complete(&self, line: &mut Line, function: Option<Function>) -> Option<Vec<Candidate>> {
+        let (start, candidates) = self.completer.as_ref()?.complete_with(&line.text(), line.cursor, function);
+        match candidates.as_slice() {
+            [] => None,
+            [only] => {
+                let mut text = only.insertion();
+                if only.is_final() {
+                    text.push(' ');
+                }
+                line.replace(start, &text);
+                None
+            }
+            _ => {
+                let common = Candidate { text: completions::common_prefix(&candidates), ..candidates[0].clone() };
+                let prefix = common.insertion();
+                let typed: String = line.chars[start..line.cursor].iter().collect();
+                if prefix.chars().count() > typed.chars().count() {
+                    line.replace(start, &prefix);
+                    return None;
+                }
+                Some(candidates)
+            }
+        }
+    }
+
+    /// List `candidates` below the line, then put the cursor back; the next
+    /// redraw clears the list
+    fn show_candidates(&mut self, prompt_width: usize, line: &Line, candidates: &[Candidate]) -> io::Result<()> {
+        let (width, height) = ui::terminal_size();
+        // Files in one directory show by name, like other shells
+        let common = completions::common_prefix(candidates);
+        let dir = common
+            .rfind(std::path::is_separator)
+            .map(|slash| slash + 1)
+            .filter(|&dir| candidates.iter().all(|candidate| candidate.text.len() > dir))
+            .unwrap_or(0);
+        let names: Vec<String> = candidates.iter().map(|candidate| candidate.text[dir..].to_string()).collect();
+
+        let mut rows: Vec<String> = if candidates.iter().any(|candidate| candidate.description.is_some()) {
+            let column = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+            names
+                .iter()
+                .zip(candidates)
+                .map(|(name, candidate)| match &candidate.description {
+                    Some(description) => format!("{:column$}  -- {}", name, description),
+                    None => name.clone(),
+                })
+                .collect()
+        } else {
+            let column = names.iter().map(|name| name.chars().count()).max().unwrap_or(0) + 2;
+            let columns = (width / column).max(1);
+            let count = names.len().div_ceil(columns);
+            // Column-major, like ls
+            (0..count)
+                .map(|row| {
+                    (row..names.len()).step_by(count).map(|index| format!("{:column$}", names[index])).collect::<String>().trim_end().to_string()
+                })
+                .collect()
+        };
+        let room = height.saturating_sub(2).max(1);
+        if rows.len() > room {
+            let hidden = rows.len() - room + 1;
+            rows.truncate(room - 1);
+            rows.push(format!("... {} more rows", hidden));
+        }
+
+        let mut out = io::stdout();
+        let end_row = (prompt_width + line.chars.len()) / width;
+        if end_row > self.cursor_row {
+            out.queue(MoveDown((end_row - self.cursor_row) as u16))?;
+        }
+        for row in &rows {
+            let shown: String = row.chars().map(|c| if c.is_control() { ' ' } else { c }).take(width.saturating_sub(1)).collect();
+            out.queue(Print("\r\n"))?.queue(Print(shown))?;
+        }
+        out.queue(MoveUp((rows.len() + end_row - self.cursor_row) as u16))?;
+        out.queue(MoveToColumn(((prompt_width + line.cursor) % width) as u16))?;
+        out.flush()
+    }
+
+    /// Redraw the line after the prompt and place the cursor
+    fn redraw(&mut self, prompt_width: usize, line: &Line) -> io::Result<()> {
+        let (width, _) = ui::terminal_size();
+        let mut out = io::stdout();
+        if self.cursor_row > 0 {
+            out.queue(MoveUp(self.cursor_row as u16))?;
+        }
+        out.queue(MoveToColumn((prompt_width % width) as u16))?.queue(Clear(ClearType::FromCursorDown))?;
+
+        // Tabs and newlines would break the column arithmetic
+        let shown: String = line.chars.iter().map(|&c| if c.is_control() { ' ' } else { c }).collect();
+        out.queue(Print(&shown))?;
+        let end = prompt_width + line.chars.len();
+        // At the right margin the terminal waits to wrap; make it wrap now
+        if end.is_multiple_of(width) && !line.is_empty() {
+            out.queue(Print("\r\n"))?;
+        }
+
+        let target = prompt_width + line.cursor;
+        let (end_row, target_row) = (end / width, target / width);
+        if end_row > target_row {
+            out.queue(MoveUp((end_row - target_row) as u16))?;
+        }
+        out.queue(MoveToColumn((target % width) as u16))?;
+        self.cursor_row = target_row;
+        out.flush()
+    }
+
+    /// Move below the finished line, after showing `marker` at its end
+    fn finish(&mut self, prompt_width: usize, line: &Line, marker: &str) -> io::Result<()> {
+        let mut end = line.clone();
+        end.end();
+        self.redraw(prompt_width, &end)?;
+        let mut out = io::stdout();
+        out.queue(Print(marker))?.queue(Print("\r\n"))?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_editing() {
+        let mut line = Line::new("git commit -m wip");
+        line.word_left();
+        line.delete_word_back();
+        assert_eq!((line.text().as_str(), line.cursor), ("git commit wip", 11));
+        line.home();
+        line.word_right();
+        line.insert('!');
+        line.delete_to_end();
+        assert_eq!(line.text(), "git!");
+        line.left();
+        line.delete_back();
+        line.delete_forward();
+        assert_eq!(line.text(), "gi");
+        line.delete_to_start();
+        line.delete_back();
+        assert!(line.is_empty());
+    }
+}
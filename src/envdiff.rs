@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::state::ShellState;
+
+/// Exported and shell variables and the working directory at one moment,
+/// as taken by `envdiff snapshot`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub env: BTreeMap<String, String>,
+    pub vars: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+}
+
+/// A variable that differs between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// Exported, rather than a shell variable
+    pub exported: bool,
+    pub name: String,
+    /// `None` where it was unset
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl Snapshot {
+    /// The state of the shell now
+    pub fn take(state: &ShellState) -> Self {
+        Self {
+            env: env::vars_os()
+                .map(|(name, value)| (name.to_string_lossy().to_string(), value.to_string_lossy().to_string()))
+                .collect(),
+            vars: state.vars().into_iter().collect(),
+            cwd: env::current_dir().ok(),
+        }
+    }
+
+    /// Variables that differ in `now`, exported ones first, each by name
+    pub fn changes(&self, now: &Snapshot) -> Vec<Change> {
+        let mut changes = diff(true, &self.env, &now.env);
+        changes.extend(diff(false, &self.vars, &now.vars));
+        changes
+    }
+}
+
+/// Undo the exported ones of `changes`, setting variables back to their
+/// earlier values and unsetting those exported since; returns how many
+pub fn revert(changes: &[Change], state: &ShellState) -> usize {
+    let exported: Vec<&Change> = changes.iter().filter(|change| change.exported).collect();
+    for change in &exported {
+        match &change.before {
+            Some(value) => {
+                state.unset_var(&change.name);
+                env::set_var(&change.name, value);
+            }
+            None => env::remove_var(&change.name),
+        }
+    }
+    exported.len()
+}
+
+fn diff(exported: bool, before: &BTreeMap<String, String>, after: &BTreeMap<String, String>) -> Vec<Change> {
+    let mut names: Vec<&String> = before.keys().chain(after.keys()).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter(|name| before.get(*name) != after.get(*name))
+        .map(|name| Change {
+            exported,
+            name: name.clone(),
+            before: before.get(name).cloned(),
+            after: after.get(name).cloned(),
+        })
+        .collect()
+}
+
+/// `+ export NAME=VALUE`, `- NAME=OLD` or `~ NAME=OLD -> NEW`
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let export = if self.exported { "export " } else { "" };
+        match (&self.before, &self.after) {
+            (None, Some(value)) => write!(f, "+ {}{}={}", export, self.name, value),
+            (Some(value), None) => write!(f, "- {}{}={}", export, self.name, value),
+            (Some(before), Some(after)) => write!(f, "~ {}{}={} -> {}", export, self.name, before, after),
+            (None, None) => write!(f, "  {}{}", export, self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_changes_are_listed_by_scope_and_name() {
+        let before = Snapshot { env: vars(&[("PATH", "/bin"), ("OLD", "1")]), vars: vars(&[("x", "1")]), cwd: None };
+        let after = Snapshot {
+            env: vars(&[("PATH", "/opt/bin:/bin"), ("NEW", "yes")]),
+            vars: vars(&[("x", "1"), ("y", "2")]),
+            cwd: None,
+        };
+        let lines: Vec<String> = before.changes(&after).iter().map(ToString::to_string).collect();
+        assert_eq!(lines, ["+ export NEW=yes", "- export OLD=1", "~ export PATH=/bin -> /opt/bin:/bin", "+ y=2"]);
+        assert!(after.changes(&after).is_empty());
+    }
+
+    #[test]
+    fn test_revert_undoes_exported_changes_only() {
+        let state = ShellState::new();
+        env::set_var("SHELL_T_ENVDIFF_KEPT", "after");
+        env::set_var("SHELL_T_ENVDIFF_ADDED", "1");
+        state.set_var("shell_only", "1");
+        let before = Snapshot { env: vars(&[("SHELL_T_ENVDIFF_KEPT", "before")]), ..Snapshot::default() };
+        let after = Snapshot {
+            env: vars(&[("SHELL_T_ENVDIFF_KEPT", "after"), ("SHELL_T_ENVDIFF_ADDED", "1")]),
+            vars: vars(&[("shell_only", "1")]),
+            cwd: None,
+        };
+
+        assert_eq!(revert(&before.changes(&after), &state), 2);
+        assert_eq!(env::var("SHELL_T_ENVDIFF_KEPT").as_deref(), Ok("before"));
+        assert!(env::var_os("SHELL_T_ENVDIFF_ADDED").is_none());
+        assert_eq!(state.var("shell_only").as_deref(), Some("1"));
+        env::remove_var("SHELL_T_ENVDIFF_KEPT");
+    }
+}
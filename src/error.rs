@@ -1,95 +1,570 @@
+use std::backtrace::{Backtrace, BacktraceStatus};
 use std::fmt;
 use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
 
 /// Custom error type for Shell-T operations
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum ShellError {
-    Io(io::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Command execution failed: {0}")]
     CommandExecution(String),
+    #[error("Failed to execute {program}: {source}")]
+    Spawn {
+        program: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Parse error: {0}")]
     Parse(String),
+    #[error("Security error: {0}")]
     SecurityViolation(String),
+    #[error("Security error: {0}")]
+    Security(#[source] SecurityError),
+    #[error("Configuration error: {0}")]
     Config(String),
+    #[error("File system error: {0}")]
     FileSystem(String),
+    #[error("File system error: cannot open {path}: {source}")]
+    Redirect {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Process error: {0}")]
     Process(String),
+    #[error("Process error: wait for {program} failed: {source}")]
+    Wait {
+        program: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{}", describe_context(.0, .1))]
+    Context(#[source] Box<ShellError>, ErrorContext),
+    /// A transient failure that may succeed if attempted again
+    #[error(transparent)]
+    Retryable(Box<ShellError>),
+}
+
+/// Stage of command processing in which an error occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorStage {
+    Parse,
+    Validate,
+    Spawn,
+    Wait,
+}
+
+/// Byte range into the source line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Where an error happened: the input line, the offending span and the stage
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    pub stage: ErrorStage,
+    pub source: Option<String>,
+    pub span: Option<Span>,
+    pub token: Option<String>,
+    /// Captured only when `RUST_BACKTRACE`/`RUST_LIB_BACKTRACE` is set
+    pub backtrace: Arc<Backtrace>,
 }
 
 /// Security-specific error types
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum SecurityError {
+    #[error("Path traversal attempt detected: {0}")]
     PathTraversal(String),
+    #[error("Dangerous command blocked: {0}")]
     DangerousCommand(String),
+    #[error("Invalid input: {0}")]
     InvalidInput(String),
+    #[error("Permission denied: {0}")]
     PermissionDenied(String),
+    #[error("Resource limit exceeded: {0}")]
     ResourceLimitExceeded(String),
+    #[error("Rate limit exceeded: {0}")]
+    RateLimited(String),
 }
 
-impl fmt::Display for ShellError {
+fn describe_context(err: &ShellError, ctx: &ErrorContext) -> String {
+    match &ctx.token {
+        Some(token) => format!("{} (during {}, at '{}')", err, ctx.stage, token),
+        None => format!("{} (during {})", err, ctx.stage),
+    }
+}
+
+impl fmt::Display for ErrorStage {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ShellError::Io(err) => write!(f, "I/O error: {}", err),
-            ShellError::CommandExecution(msg) => write!(f, "Command execution failed: {}", msg),
-            ShellError::Parse(msg) => write!(f, "Parse error: {}", msg),
-            ShellError::SecurityViolation(err) => write!(f, "Security error: {}", err),
-            ShellError::Config(msg) => write!(f, "Configuration error: {}", msg),
-            ShellError::FileSystem(msg) => write!(f, "File system error: {}", msg),
-            ShellError::Process(msg) => write!(f, "Process error: {}", msg),
+            ErrorStage::Parse => write!(f, "parse"),
+            ErrorStage::Validate => write!(f, "validate"),
+            ErrorStage::Spawn => write!(f, "spawn"),
+            ErrorStage::Wait => write!(f, "wait"),
         }
     }
 }
 
-impl fmt::Display for SecurityError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            SecurityError::PathTraversal(path) => {
-                write!(f, "Path traversal attempt detected: {}", path)
-            }
-            SecurityError::DangerousCommand(cmd) => {
-                write!(f, "Dangerous command blocked: {}", cmd)
-            }
-            SecurityError::InvalidInput(input) => {
-                write!(f, "Invalid input: {}", input)
-            }
-            SecurityError::PermissionDenied(resource) => {
-                write!(f, "Permission denied: {}", resource)
-            }
-            SecurityError::ResourceLimitExceeded(limit) => {
-                write!(f, "Resource limit exceeded: {}", limit)
+impl Span {
+    /// Create a new span covering `start..end`
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+impl ErrorContext {
+    /// Create a context for the given stage
+    pub fn new(stage: ErrorStage) -> Self {
+        Self {
+            stage,
+            source: None,
+            span: None,
+            token: None,
+            backtrace: Arc::new(Backtrace::capture()),
+        }
+    }
+
+    /// Attach the offending span
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Attach the offending token; its span is resolved once the source is known
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Attach the source line, locating the token in it if no span is set.
+    /// A source already attached is kept, as the span points into it.
+    pub fn with_source(mut self, source: &str) -> Self {
+        if self.source.is_some() {
+            return self;
+        }
+        if self.span.is_none() {
+            if let Some(token) = self.token.as_deref().filter(|t| !t.is_empty()) {
+                self.span = source
+                    .find(token)
+                    .map(|start| Span::new(start, start + token.len()));
             }
         }
+        self.source = Some(source.to_string());
+        self
     }
 }
 
-impl std::error::Error for ShellError {}
-impl std::error::Error for SecurityError {}
+impl ShellError {
+    /// Wrap this error with context, keeping any existing (more specific) context
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            ShellError::Context(..) => self,
+            err => ShellError::Context(Box::new(err), context),
+        }
+    }
+
+    /// Attach the source line to this error's context, if it has one
+    pub fn with_source(self, source: &str) -> Self {
+        match self {
+            ShellError::Context(err, ctx) => ShellError::Context(err, ctx.with_source(source)),
+            ShellError::Retryable(err) => ShellError::Retryable(Box::new(err.with_source(source))),
+            err => err,
+        }
+    }
+
+    /// Set the span of this error's token to `span`, unless it has one
+    pub fn locate(self, span: Option<Span>) -> Self {
+        match self {
+            ShellError::Context(err, mut ctx) => {
+                if ctx.span.is_none() && ctx.token.is_some() {
+                    ctx.span = span;
+                }
+                ShellError::Context(err, ctx)
+            }
+            ShellError::Retryable(err) => ShellError::Retryable(Box::new(err.locate(span))),
+            err => err,
+        }
+    }
+
+    /// Whether the REPL cannot sensibly continue after this error
+    pub fn is_fatal(&self) -> bool {
+        match self.root() {
+            ShellError::Io(err) => matches!(
+                err.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::OutOfMemory
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether the REPL can report this error and carry on
+    pub fn is_recoverable(&self) -> bool {
+        !self.is_fatal()
+    }
+
+    /// Stable catalog code for this error, e.g. `ST0302`; see `catalog`
+    pub fn code(&self) -> &'static str {
+        match self.root() {
+            ShellError::Io(_) => "ST0001",
+            ShellError::Parse(_) => "ST0101",
+            ShellError::SecurityViolation(_) => "ST0201",
+            ShellError::Security(err) => err.code(),
+            ShellError::CommandExecution(_) => "ST0301",
+            ShellError::Spawn { .. } => "ST0302",
+            ShellError::Process(_) => "ST0303",
+            ShellError::Wait { .. } => "ST0304",
+            ShellError::FileSystem(_) => "ST0401",
+            ShellError::Redirect { .. } => "ST0402",
+            ShellError::Config(_) => "ST0501",
+            ShellError::Context(..) | ShellError::Retryable(_) => "ST0000",
+        }
+    }
+
+    /// Exit status a command failing with this error reports (as in POSIX shells)
+    pub fn exit_status(&self) -> i32 {
+        match self.root() {
+            ShellError::Parse(_) => 2,
+            ShellError::Spawn { source, .. } if source.kind() == io::ErrorKind::NotFound => 127,
+            ShellError::Spawn { .. } | ShellError::SecurityViolation(_) | ShellError::Security(_) => 126,
+            _ => 1,
+        }
+    }
+
+    /// Whether retrying the failed operation may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ShellError::Retryable(_) => true,
+            ShellError::Context(err, _) => err.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// The backtrace captured with this error's context, if backtraces are enabled
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.context()
+            .map(|ctx| ctx.backtrace.as_ref())
+            .filter(|bt| bt.status() == BacktraceStatus::Captured)
+    }
+
+    /// The context attached to this error, if any
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            ShellError::Context(_, ctx) => Some(ctx),
+            ShellError::Retryable(err) => err.context(),
+            _ => None,
+        }
+    }
+
+    /// The underlying error without any context or retry marker
+    pub fn root(&self) -> &ShellError {
+        match self {
+            ShellError::Context(err, _) | ShellError::Retryable(err) => err.root(),
+            err => err,
+        }
+    }
+}
 
-impl From<io::Error> for ShellError {
-    fn from(err: io::Error) -> Self {
-        ShellError::Io(err)
+impl SecurityError {
+    /// Stable catalog code for this error
+    pub fn code(&self) -> &'static str {
+        match self {
+            SecurityError::PathTraversal(_) => "ST0202",
+            SecurityError::DangerousCommand(_) => "ST0203",
+            SecurityError::InvalidInput(_) => "ST0204",
+            SecurityError::PermissionDenied(_) => "ST0205",
+            SecurityError::ResourceLimitExceeded(_) => "ST0206",
+            SecurityError::RateLimited(_) => "ST0207",
+        }
     }
 }
 
 impl From<SecurityError> for ShellError {
     fn from(err: SecurityError) -> Self {
-        ShellError::SecurityViolation(err.to_string())
+        ShellError::Security(err)
     }
 }
 
 /// Result type alias for Shell operations
 pub type ShellResult<T> = Result<T, ShellError>;
 
+/// Extension trait for attaching context to results
+pub trait ResultExt<T> {
+    /// Attach a stage and offending token to the error, if any
+    fn stage(self, stage: ErrorStage, token: &str) -> ShellResult<T>;
+}
+
+impl<T> ResultExt<T> for ShellResult<T> {
+    fn stage(self, stage: ErrorStage, token: &str) -> ShellResult<T> {
+        self.map_err(|e| e.with_context(ErrorContext::new(stage).with_token(token)))
+    }
+}
+
+/// Outcome of observing a message with a `RepeatFilter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// A different message; carries how often the previous one was suppressed
+    New { previous_repeats: usize },
+    /// Same as the previous message within the window; should be suppressed
+    Repeated,
+}
+
+/// Collapses runs of identical messages into "repeated N times" summaries
+#[derive(Debug)]
+pub struct RepeatFilter {
+    window: Duration,
+    last: Option<(String, Instant)>,
+    repeats: usize,
+}
+
+impl RepeatFilter {
+    /// Create a filter treating messages closer together than `window` as a run
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last: None,
+            repeats: 0,
+        }
+    }
+
+    /// Record a message and decide whether it should be shown
+    pub fn observe(&mut self, key: &str) -> Repeat {
+        let now = Instant::now();
+        if let Some((last, seen)) = &mut self.last {
+            if last == key && now.duration_since(*seen) < self.window {
+                *seen = now;
+                self.repeats += 1;
+                return Repeat::Repeated;
+            }
+        }
+
+        let previous_repeats = self.flush();
+        self.last = Some((key.to_string(), now));
+        Repeat::New { previous_repeats }
+    }
+
+    /// End the current run, returning how many repeats were suppressed
+    pub fn flush(&mut self) -> usize {
+        self.last = None;
+        std::mem::take(&mut self.repeats)
+    }
+}
+
+/// Long-form descriptions of error codes, shown by the `explain` builtin
+pub mod catalog {
+    /// A documented error code
+    #[derive(Debug)]
+    pub struct CatalogEntry {
+        pub code: &'static str,
+        pub title: &'static str,
+        pub description: &'static str,
+        pub causes: &'static [&'static str],
+        pub config: &'static [&'static str],
+    }
+
+    pub const ENTRIES: &[CatalogEntry] = &[
+        CatalogEntry {
+            code: "ST0001",
+            title: "I/O error",
+            description: "Reading input or writing output on the terminal failed.",
+            causes: &["the terminal was closed or detached", "stdout is a pipe whose reader exited"],
+            config: &[],
+        },
+        CatalogEntry {
+            code: "ST0101",
+            title: "Parse error",
+            description: "The command line could not be split into commands. The offending part of the \
+                          input is underlined below the error.",
+            causes: &[
+                "a redirection operator (<, >, >>) without a file name",
+                "a pipe with no command on one side",
+            ],
+            config: &[],
+        },
+        CatalogEntry {
+            code: "ST0201",
+            title: "Security policy violation",
+            description: "The command or one of its arguments was rejected by the security policy \
+                          before anything was executed.",
+            causes: &[
+                "the command is not in the whitelist",
+                "the command is blacklisted",
+                "an argument contains '../' or exceeds the length limit",
+            ],
+            config: &["security.allowed_commands", "security.blocked_commands", "limits.max_arg_length"],
+        },
+        CatalogEntry {
+            code: "ST0202",
+            title: "Path traversal",
+            description: "A path tried to escape the allowed directories.",
+            causes: &["a path containing '..'", "an absolute path outside the allowed prefixes"],
+            config: &["security.validate_paths"],
+        },
+        CatalogEntry {
+            code: "ST0203",
+            title: "Dangerous command",
+            description: "The command matched a pattern considered dangerous and was blocked.",
+            causes: &["shell metacharacters in arguments", "a blacklisted command"],
+            config: &["security.blocked_commands"],
+        },
+        CatalogEntry {
+            code: "ST0204",
+            title: "Invalid input",
+            description: "The input was malformed or too large to be processed safely.",
+            causes: &[
+                "null bytes in the input",
+                "too many or too long arguments",
+                "a remote script whose content doesn't match its sha256= pin",
+            ],
+            config: &["security.max_command_length", "security.max_arg_count"],
+        },
+        CatalogEntry {
+            code: "ST0205",
+            title: "Permission denied",
+            description: "The shell refused an operation it is not permitted to perform.",
+            causes: &[
+                "running shell-t with elevated privileges",
+                "sourcing a remote script without a sha256= pin",
+            ],
+            config: &["source.allow_unpinned"],
+        },
+        CatalogEntry {
+            code: "ST0206",
+            title: "Resource limit exceeded",
+            description: "A configured resource limit was reached.",
+            causes: &[
+                "too many background processes",
+                "a pipeline with too many stages",
+                "a command exceeded its timeout",
+                "a loop ran more iterations than allowed",
+                "a remote script larger than allowed",
+                "an interpreter-run script exceeded its time, memory or output limit",
+                "a command wrote more streamed output than allowed",
+            ],
+            config: &[
+                "limits.max_background_processes",
+                "limits.max_pipeline_length",
+                "limits.command_timeout",
+                "limits.command_timeouts",
+                "limits.max_loop_iterations",
+                "source.max_size_kb",
+                "interpreters.limits.timeout_secs",
+                "interpreters.limits.max_memory_mb",
+                "interpreters.limits.max_output_kb",
+                "output.max_kb",
+            ],
+        },
+        CatalogEntry {
+            code: "ST0207",
+            title: "Rate limit exceeded",
+            description: "The same command was run more often in the last minute than \
+                          security.max_runs_per_minute allows. It is not retried; run it again later.",
+            causes: &["a loop or history replay running one command many times"],
+            config: &["security.max_runs_per_minute"],
+        },
+        CatalogEntry {
+            code: "ST0301",
+            title: "Command execution failed",
+            description: "The command could not be run.",
+            causes: &["the command or interpreter failed to start"],
+            config: &[],
+        },
+        CatalogEntry {
+            code: "ST0302",
+            title: "Spawn failed",
+            description: "The operating system refused to start the process.",
+            causes: &[
+                "the program is not installed or not on PATH",
+                "the file is not executable",
+                "the system is temporarily out of processes (retried automatically)",
+            ],
+            config: &["interpreters.python_path", "interpreters.ruby_path", "interpreters.node_path", "retry.max_attempts"],
+        },
+        CatalogEntry {
+            code: "ST0303",
+            title: "Process error",
+            description: "Managing a running process or pipeline failed.",
+            causes: &["the pipeline is longer than allowed"],
+            config: &["limits.max_pipeline_length"],
+        },
+        CatalogEntry {
+            code: "ST0304",
+            title: "Wait failed",
+            description: "The shell could not collect the exit status of a child process.",
+            causes: &["the process was already reaped", "the wait was interrupted"],
+            config: &[],
+        },
+        CatalogEntry {
+            code: "ST0401",
+            title: "File system error",
+            description: "A file system operation failed.",
+            causes: &["the file does not exist", "missing permissions"],
+            config: &[],
+        },
+        CatalogEntry {
+            code: "ST0402",
+            title: "Redirection failed",
+            description: "A file named in a redirection could not be opened.",
+            causes: &[
+                "the input file for '<' does not exist",
+                "the directory for a '>' target does not exist or is not writable",
+            ],
+            config: &[],
+        },
+        CatalogEntry {
+            code: "ST0501",
+            title: "Configuration error",
+            description: "The configuration file or a runtime setting is invalid.",
+            causes: &[
+                "a syntax error in config.toml",
+                "an unknown value such as a log level",
+                "an interpreter whose version doesn't match the one pinned in .shell-t.toml",
+            ],
+            config: &["~/.config/shell-t/config.toml", ".shell-t.toml"],
+        },
+    ];
+
+    /// Look up a code, case-insensitively
+    pub fn lookup(code: &str) -> Option<&'static CatalogEntry> {
+        ENTRIES.iter().find(|entry| entry.code.eq_ignore_ascii_case(code))
+    }
+
+    /// Render an entry for display
+    pub fn render(entry: &CatalogEntry) -> String {
+        let mut text = format!("{}: {}\n\n{}\n", entry.code, entry.title, entry.description);
+        if !entry.causes.is_empty() {
+            text.push_str("\nCommon causes:\n");
+            for cause in entry.causes {
+                text.push_str(&format!("  - {}\n", cause));
+            }
+        }
+        if !entry.config.is_empty() {
+            text.push_str("\nRelated configuration:\n");
+            for knob in entry.config {
+                text.push_str(&format!("  - {}\n", knob));
+            }
+        }
+        text.trim_end().to_string()
+    }
+}
+
 /// Security validation functions
 pub mod security {
     use super::{SecurityError, ShellResult};
-    use std::path::Path;
 
     /// Validate that a path doesn't contain path traversal attempts
     pub fn validate_path(path: &str) -> ShellResult<()> {
-        let path_obj = Path::new(path);
-
-        if path.contains("..") || path.contains("../") || path.starts_with('/') {
-            if path.starts_with('/') && !is_allowed_absolute_path(path) {
-                return Err(SecurityError::PathTraversal(path.to_string()).into());
-            }
+        if path.starts_with('/') && !is_allowed_absolute_path(path) {
+            return Err(SecurityError::PathTraversal(path.to_string()).into());
         }
 
         if path.contains('\0') {
@@ -146,22 +621,39 @@ pub mod security {
 pub mod logging {
     use std::fs::OpenOptions;
     use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::time::Duration;
     use chrono::Utc;
 
-    /// Log a security event
+    use super::{Repeat, RepeatFilter};
+
+    /// Identical consecutive events within this window are collapsed
+    const EVENT_REPEAT_WINDOW: Duration = Duration::from_secs(5);
+
+    static SECURITY_REPEATS: Mutex<Option<RepeatFilter>> = Mutex::new(None);
+
+    /// Log a security event, collapsing bursts of identical events
     pub fn log_security_event(event: &str, details: &str) {
-        let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S");
-        let log_entry = format!("[{}] SECURITY: {} - {}\n", timestamp, event, details);
+        let key = format!("{} - {}", event, details);
+        let outcome = SECURITY_REPEATS
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| RepeatFilter::new(EVENT_REPEAT_WINDOW))
+            .observe(&key);
 
-        if let Ok(mut file) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open("shell-t-security.log")
-        {
-            let _ = file.write_all(log_entry.as_bytes());
-        }
+        let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S");
+        let log_entry = match outcome {
+            Repeat::Repeated => return,
+            Repeat::New { previous_repeats: 0 } => format!("[{}] SECURITY: {}\n", timestamp, key),
+            Repeat::New { previous_repeats } => format!(
+                "[{}] SECURITY: last event repeated {} times\n[{}] SECURITY: {}\n",
+                timestamp, previous_repeats, timestamp, key
+            ),
+        };
 
-        eprintln!("Security event: {} - {}", event, details);
+        append(&log_path("shell-t-security.log"), &log_entry);
+        tracing::warn!(event, details, "security event");
     }
 
     /// Log a command execution for audit purposes
@@ -169,12 +661,71 @@ pub mod logging {
         let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S");
         let log_entry = format!("[{}] AUDIT: User '{}' executed: {}\n", timestamp, user, command);
 
+        append(&log_path("shell-t-audit.log"), &log_entry);
+    }
+
+    /// Log files live in the data directory when one is available
+    fn log_path(name: &str) -> PathBuf {
+        match crate::config::data_dir() {
+            Some(dir) if std::fs::create_dir_all(&dir).is_ok() => dir.join(name),
+            _ => PathBuf::from(name),
+        }
+    }
+
+    fn append(path: &PathBuf, entry: &str) {
         if let Ok(mut file) = OpenOptions::new()
             .create(true)
             .append(true)
-            .open("shell-t-audit.log")
+            .open(path)
         {
-            let _ = file.write_all(log_entry.as_bytes());
+            let _ = file.write_all(entry.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_code_is_documented() {
+        let errors = vec![
+            ShellError::Io(io::Error::other("x")),
+            ShellError::Parse("x".to_string()),
+            ShellError::SecurityViolation("x".to_string()),
+            ShellError::Spawn { program: "x".to_string(), source: io::Error::other("x") },
+            ShellError::Redirect { path: "x".to_string(), source: io::Error::other("x") },
+            ShellError::Wait { program: "x".to_string(), source: io::Error::other("x") },
+            ShellError::Config("x".to_string()),
+            SecurityError::RateLimited("x".to_string()).into(),
+            SecurityError::PathTraversal("x".to_string()).into(),
+        ];
+        for err in errors {
+            assert!(catalog::lookup(err.code()).is_some(), "{} is not documented", err.code());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_repeat_filter_collapses_runs() {
+        let mut filter = RepeatFilter::new(Duration::from_secs(60));
+        assert_eq!(filter.observe("blocked"), Repeat::New { previous_repeats: 0 });
+        assert_eq!(filter.observe("blocked"), Repeat::Repeated);
+        assert_eq!(filter.observe("blocked"), Repeat::Repeated);
+        assert_eq!(filter.observe("other"), Repeat::New { previous_repeats: 2 });
+        assert_eq!(filter.flush(), 0);
+    }
+
+    #[test]
+    fn test_repeat_filter_window_expires() {
+        let mut filter = RepeatFilter::new(Duration::ZERO);
+        assert_eq!(filter.observe("blocked"), Repeat::New { previous_repeats: 0 });
+        assert_eq!(filter.observe("blocked"), Repeat::New { previous_repeats: 0 });
+    }
+
+    #[test]
+    fn test_code_looks_through_context() {
+        let err = ShellError::Parse("x".to_string()).with_context(ErrorContext::new(ErrorStage::Parse));
+        assert_eq!(err.code(), "ST0101");
+        assert!(catalog::lookup("st0101").is_some());
+    }
+}
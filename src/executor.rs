@@ -1,124 +1,855 @@
-use std::process::{Command, Stdio};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
 use std::sync::Arc;
-use std::time::Instant;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::config::Config;
-use crate::error::{ShellError, ShellResult};
-use crate::parser::Command as ParsedCommand;
+use crate::error::{self, ErrorContext, ErrorStage, ResultExt, Span, SecurityError, ShellError, ShellResult};
+use crate::inline::{InlineBlock, Language};
+use crate::jobs::{self, JobManager, JobOutput, Waited};
+use crate::limits::{self, Budget, Exceeded, Rlimits};
+use crate::output::OutputFilter;
+use crate::persistent::{self, InterpreterPool, Request, Stream};
+use crate::project;
+use crate::records;
+use crate::sandbox;
+use crate::parser::{Command as ParsedCommand, FdRedirect, RedirectMode};
 use crate::security::SecurityManager;
+use crate::state::ShellState;
+
+/// Commands built into POSIX shells, exempt from the whitelist when found in `.sh` scripts
+const SH_BUILTINS: &[&str] = &[
+    ":", "[", "cd", "echo", "exit", "export", "false", "printf", "read", "return", "set", "shift", "test",
+    "true", "umask", "unset", "wait",
+];
+
+/// Builtins that run commands the static scan of a `.sh` script can't see;
+/// scripts using them are refused while `security.allowed_commands` is set
+const SH_DYNAMIC_BUILTINS: &[&str] = &[".", "eval", "exec", "source", "trap"];
+
+/// Extensions with a built-in interpreter mapping; `[interpreters.extensions]`
+/// adds more
+const SCRIPT_EXTENSIONS: &[&str] = &["py", "rb", "js", "sh", "rs", "go"];
 
 /// Command execution engine
 pub struct CommandExecutor {
     security: Arc<SecurityManager>,
     config: Config,
+    state: Arc<ShellState>,
+    jobs: Arc<JobManager>,
+    interpreters: InterpreterPool,
+    /// Filter for output bound for the terminal, under `output.stream`
+    filter: Option<Arc<OutputFilter>>,
 }
 
 impl CommandExecutor {
     /// Create a new command executor
-    pub fn new(security: Arc<SecurityManager>, config: Config) -> Self {
-        Self { security, config }
+    pub fn new(security: Arc<SecurityManager>, config: Config, state: Arc<ShellState>, jobs: Arc<JobManager>) -> Self {
+        let filter = OutputFilter::new(&config.output);
+        let rlimits = Rlimits::new(&config.limits).with_memory(config.interpreters.limits.max_memory_mb);
+        let interpreters = InterpreterPool::with_rlimits(rlimits).with_jobs(jobs.clone());
+        Self { security, config, state, jobs, interpreters, filter }
+    }
+
+    /// Start and wait on jobs in `jobs` from now on, as when switching tabs
+    pub fn set_jobs(&mut self, jobs: Arc<JobManager>) {
+        self.jobs = jobs;
     }
 
-    /// Execute a pipeline of commands
-    pub fn execute_pipeline(&self, commands: &[ParsedCommand]) -> ShellResult<()> {
+    /// Execute a pipeline of commands, returning the exit status of each one
+    /// that ran
+    pub fn execute_pipeline(&self, commands: &[ParsedCommand]) -> ShellResult<Vec<i32>> {
+        self.execute(commands, None)
+    }
+
+    /// The exit status of a whole pipeline whose commands ended with
+    /// `statuses`, recorded for `$PIPESTATUS`: the last one's, or under
+    /// `set -o pipefail` the last non-zero one's
+    pub fn finish_pipeline(&self, statuses: Vec<i32>) -> i32 {
+        let status = pipeline_status(&statuses, self.state.options().pipefail);
+        self.state.set_pipe_status(statuses);
+        status
+    }
+
+    /// Execute a pipeline for `$(...)`, returning the exit status of the last
+    /// command and what it wrote to stdout. It runs in the foreground even if
+    /// it ends in `&`.
+    pub fn capture_pipeline(&self, commands: &[ParsedCommand]) -> ShellResult<(i32, String)> {
+        let (mut reader, writer) = io::pipe()?;
+        let collector = thread::spawn(move || {
+            let mut text = Vec::new();
+            let _ = reader.read_to_end(&mut text);
+            String::from_utf8_lossy(&text).to_string()
+        });
+        let statuses = self.execute(commands, Some(writer));
+        // Every copy of the writer is gone by now, so the collector sees the end
+        let text = collector.join().unwrap_or_default();
+        Ok((pipeline_status(&statuses?, self.state.options().pipefail), text))
+    }
+
+    /// Run `commands`, the last one writing to `capture` if given rather
+    /// than the terminal
+    fn execute(&self, commands: &[ParsedCommand], mut capture: Option<io::PipeWriter>) -> ShellResult<Vec<i32>> {
         if commands.is_empty() {
-            return Ok(());
+            return Ok(vec![0]);
         }
 
         if commands.len() > self.config.limits.max_pipeline_length {
-            return Err(ShellError::Process("Pipeline too long".to_string()));
+            return Err(ShellError::Process("Pipeline too long".to_string())
+                .with_context(ErrorContext::new(ErrorStage::Validate)));
         }
 
-        let mut children = Vec::new();
-        let mut prev_stdout = None;
+        for cmd in commands.iter().filter(|cmd| !cmd.program.is_empty()) {
+            self.check_rate_limit(&cmd.program)?;
+        }
 
-        for (i, cmd) in commands.iter().enumerate() {
-            if cmd.program.is_empty() {
-                continue;
+        let container = self.state.container();
+        if let Some(container) = &container {
+            self.validate_command(container.runtime.program()).stage(ErrorStage::Validate, &container.to_string())?;
+        }
+
+        // Persistent and embedded interpreters run locally, not in a container
+        if let (None, None, [cmd]) = (&container, &capture, commands) {
+            if let Some(status) = self.try_persistent_script(cmd)? {
+                return Ok(vec![status]);
+            }
+            #[cfg(feature = "js")]
+            if let Some(status) = self.try_embedded_js(cmd)? {
+                return Ok(vec![status]);
             }
+        }
+
+        let background = capture.is_none() && commands.last().is_some_and(|c| c.background);
+        let job_control = self.jobs.job_control();
+        if background && self.jobs.active() >= self.config.limits.max_background_processes {
+            return Err(SecurityError::ResourceLimitExceeded("Maximum background processes reached".to_string()).into());
+        }
 
-            let (actual_cmd, actual_args) = self.resolve_command(&cmd.program, &cmd.args)?;
+        let options = self.state.options();
+        let typed = commands.len() > 1 && options.jsonpipes;
+        let noclobber = options.noclobber;
+        let script_limits = &self.config.interpreters.limits;
+        // Background output is kept for `jobs -o` rather than mixed into the prompt
+        let output = (background && self.config.jobs.buffer_output)
+            .then(|| JobOutput::new(self.config.jobs.buffer_kb * 1024));
 
-            self.validate_command(&actual_cmd)?;
-            self.validate_args(&actual_args)?;
+        // Every stage's command and fds are set up before any is spawned, so
+        // no stage waits on the one before it
+        let running: Vec<&ParsedCommand> = commands.iter().filter(|cmd| !cmd.program.is_empty()).collect();
+        let mut pending = Vec::with_capacity(running.len());
+        let mut next_stdin: Option<Stdio> = None;
+        for (i, cmd) in running.iter().enumerate() {
+            // Errors about a word point at it by position, not at another
+            // word that reads the same
+            let at_program = |e: ShellError| e.locate(cmd.span_of(0));
+            let (actual_cmd, actual_args) = self.resolve_command(&cmd.program, &cmd.args).map_err(at_program)?;
+            tracing::debug!(program = %actual_cmd, args = ?actual_args, stage = i, "preparing command");
 
-            let mut command = Command::new(&actual_cmd);
-            command.args(&actual_args);
+            self.validate_command(&actual_cmd).stage(ErrorStage::Validate, &cmd.program).map_err(at_program)?;
+            for (n, arg) in actual_args.iter().enumerate() {
+                // The typed words end the arguments, after any interpreter flags
+                let word = (n + 1 + cmd.args.len()).checked_sub(actual_args.len());
+                self.validate_arg(arg).map_err(|e| e.locate(word.and_then(|word| cmd.span_of(word))))?;
+            }
+            self.validate_env(&cmd.env)?;
+            let language = Language::from_path(&cmd.program);
+            let actual_cmd = match language {
+                Some(language) => self.interpreter_for(language, actual_cmd)?,
+                None => actual_cmd,
+            };
+            let actual_args = match language {
+                Some(language) if typed => records::adapt(language, &actual_args),
+                _ => actual_args,
+            };
 
-            if let Some(prev) = prev_stdout.take() {
+            let mut command = match &container {
+                Some(container) => {
+                    if self.config.security.enable_auditing {
+                        let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+                        let line = std::iter::once(&actual_cmd).chain(&actual_args).cloned().collect::<Vec<_>>().join(" ");
+                        error::logging::log_command_execution(&format!("{} (in {})", line, container), &user);
+                    }
+                    let tty = running.len() == 1
+                        && !background
+                        && cmd.input_redirect.is_none()
+                        && cmd.here_doc.is_none()
+                        && cmd.output_redirect.is_none()
+                        && capture.is_none()
+                        && io::stdin().is_terminal()
+                        && io::stdout().is_terminal();
+                    container.command(&actual_cmd, &actual_args, &cmd.env, tty)
+                }
+                None => {
+                    // `PATH=... cmd` searches its own PATH
+                    let found = match cmd.env.iter().any(|(name, _)| name == "PATH") {
+                        true => None,
+                        false => self.state.find_command(&actual_cmd),
+                    };
+                    let mut command = match found {
+                        Some(file) => found_command(file, &actual_cmd),
+                        None => Command::new(&actual_cmd),
+                    };
+                    command.args(&actual_args).envs(cmd.env.iter().cloned());
+                    command
+                }
+            };
+            let sandboxed = cmd.program.ends_with(".sh");
+            if sandboxed {
+                self.sandbox_script(&cmd.program, &mut command)?;
+            }
+            let interpreted = self.script_extension(&cmd.program).is_some();
+            let rlimits = Rlimits::new(&self.config.limits);
+            limits::restrict(&mut command, match interpreted {
+                true => rlimits.with_memory(script_limits.max_memory_mb),
+                false => rlimits,
+            });
+
+            let piped = next_stdin.take();
+            if let Some(text) = &cmd.here_doc {
+                command.stdin(feed(text.clone())?);
+            } else if let Some(prev) = piped {
                 command.stdin(prev);
             } else if let Some(ref input_file) = cmd.input_redirect {
                 match std::fs::File::open(input_file) {
                     Ok(file) => { command.stdin(file); }
                     Err(e) => {
-                        return Err(ShellError::FileSystem(format!("Error opening input file {}: {}", input_file, e)));
+                        return Err(ShellError::Redirect { path: input_file.clone(), source: e })
+                            .stage(ErrorStage::Spawn, input_file);
                     }
                 }
             }
 
-            if i < commands.len() - 1 {
+            let last = i == running.len() - 1;
+            let output_file = match (last, &cmd.output_redirect) {
+                (true, Some(output_file)) => Some(open_redirect(output_file, cmd.append, noclobber && !cmd.clobber)?),
+                _ => None,
+            };
+            // Unlike stdout, every stage's stderr can be redirected
+            let stderr_file = match &cmd.stderr_redirect {
+                Some(stderr_file) => Some(open_redirect(stderr_file, cmd.stderr_append, noclobber)?),
+                None => None,
+            };
+            // The pipe to the next stage, or to the collector of `$(...)`
+            let next_stdout = if last {
+                capture.take().filter(|_| output_file.is_none())
+            } else {
+                let (reader, writer) = io::pipe()?;
+                next_stdin = Some(reader.into());
+                Some(writer)
+            };
+            // Output for the terminal goes through the filter under `output.stream`
+            let streamed = self.filter.is_some() && output.is_none();
+            let stdout_streamed = streamed && last && output_file.is_none() && next_stdout.is_none();
+            // `2>&1` and `&>`: stderr shares stdout's file, pipe or terminal;
+            // when stdout is captured or streamed, stderr is merged into it below
+            let stderr_target: Option<Stdio> = match (cmd.stderr_to_stdout, &output_file, &next_stdout) {
+                (false, ..) => stderr_file.map(Stdio::from),
+                (true, Some(file), _) => Some(file.try_clone()?.into()),
+                (true, None, Some(writer)) => Some(writer.try_clone()?.into()),
+                (true, None, None) if output.is_none() && !stdout_streamed => Some(io::stdout().into()),
+                (true, None, None) => None,
+            };
+
+            // Capped and streamed output passes through a relay thread that
+            // counts it
+            let script_cap = (interpreted && script_limits.max_output_kb > 0)
+                .then_some((script_limits.max_output_kb * 1024, Exceeded::Output));
+            let mut relays = Vec::new();
+            if script_cap.is_some() || stdout_streamed {
+                let (reader, writer) = io::pipe()?;
+                if stdout_streamed && cmd.stderr_to_stdout {
+                    command.stderr(writer.try_clone()?);
+                }
+                command.stdout(writer);
+                let sink: Box<dyn io::Write + Send> = match (output_file, next_stdout) {
+                    (Some(file), _) => Box::new(file),
+                    (None, Some(writer)) => Box::new(writer),
+                    (None, None) => match &output {
+                        Some(output) => Box::new(output.clone()),
+                        None => self.to_terminal(io::stdout()),
+                    },
+                };
+                let cap = script_cap.into_iter().chain(self.stream_cap(stdout_streamed)).min_by_key(|(bytes, _)| *bytes);
+                relays.push(Relay { source: reader, sink, cap });
+            } else if let Some(writer) = next_stdout {
+                command.stdout(writer);
+            } else if let Some(file) = output_file {
+                command.stdout(file);
+            } else if output.is_some() {
                 command.stdout(Stdio::piped());
-            } else if let Some(ref output_file) = cmd.output_redirect {
-                match if cmd.append {
-                    std::fs::OpenOptions::new().create(true).append(true).open(output_file)
-                } else {
-                    std::fs::File::create(output_file)
-                } {
-                    Ok(file) => { command.stdout(file); }
-                    Err(e) => {
-                        return Err(ShellError::FileSystem(format!("Error opening output file {}: {}", output_file, e)));
+            }
+            if let Some(target) = stderr_target {
+                command.stderr(target);
+            } else if output.is_some() {
+                command.stderr(Stdio::piped());
+            } else if streamed && !cmd.stderr_to_stdout {
+                let (reader, writer) = io::pipe()?;
+                command.stderr(writer);
+                relays.push(Relay { source: reader, sink: self.to_terminal(io::stderr()), cap: self.stream_cap(true) });
+            }
+            if !cmd.fd_redirects.is_empty() {
+                redirect_fds(&mut command, &cmd.fd_redirects, noclobber)?;
+            }
+
+            pending.push(Pending {
+                command,
+                program: cmd.program.clone(),
+                span: cmd.spans.first().copied(),
+                actual_cmd,
+                sandboxed,
+                interpreted,
+                relays,
+                stderr_to_stdout: cmd.stderr_to_stdout,
+            });
+        }
+
+        // Background pipelines, and all of them under job control, get their
+        // own process group, led by the first stage; the rest are spawned
+        // together once it exists
+        let grouped = background || job_control;
+        let start_time = Instant::now();
+        let mut children = Vec::with_capacity(pending.len());
+        // Held while a foreground pipeline owns the terminal
+        let mut terminal = None;
+        if let Some(first) = pending.first_mut() {
+            if grouped {
+                jobs::set_process_group(&mut first.command, None, job_control && !background);
+            }
+            let leader = match self.with_retry(|| spawn_command(&mut first.command)) {
+                Ok(child) => child,
+                Err(e) => {
+                    if !background {
+                        // The child may have taken the terminal before its exec failed
+                        self.jobs.reclaim_terminal();
                     }
+                    return Err(first.spawn_error(e));
                 }
+            };
+            if !background {
+                terminal = self.jobs.foreground(leader.id());
             }
+            let leader_pid = leader.id();
+            children.push(leader);
 
-            let start_time = Instant::now();
+            let rest = &mut pending[1..];
+            if grouped {
+                for stage in rest.iter_mut() {
+                    jobs::set_process_group(&mut stage.command, Some(leader_pid), false);
+                }
+            }
+            let spawned: Vec<ShellResult<Child>> = thread::scope(|scope| {
+                let handles: Vec<_> = rest
+                    .iter_mut()
+                    .map(|stage| scope.spawn(|| self.with_retry(|| spawn_command(&mut stage.command))))
+                    .collect();
+                handles.into_iter().map(|handle| handle.join().expect("spawning thread panicked")).collect()
+            });
+            for (stage, child) in rest.iter().zip(spawned) {
+                children.push(child.map_err(|e| stage.spawn_error(e))?);
+            }
+        }
+        let execution_time = start_time.elapsed();
 
-            match command.spawn() {
-                Ok(mut child) => {
-                    if i < commands.len() - 1 {
-                        prev_stdout = child.stdout.take();
-                    }
-                    children.push(child);
+        let mut stages = Vec::with_capacity(children.len());
+        for (stage, mut child) in pending.into_iter().zip(children) {
+            tracing::info!(program = %stage.actual_cmd, pid = child.id(), "spawned process");
+            let last = stages.len() == running.len() - 1;
+            let relays = stage
+                .relays
+                .into_iter()
+                .map(|relay| {
+                    let bytes = relay.cap.map_or(u64::MAX, |(bytes, _)| bytes);
+                    (limits::relay_output(relay.source, relay.sink, bytes, child.id()), relay.cap.map(|(_, limit)| limit))
+                })
+                .collect();
+            if let (true, Some(output), Some(stdout)) = (last, &output, child.stdout.take()) {
+                output.capture(stdout);
+            }
+            if let (Some(output), Some(stderr)) = (&output, child.stderr.take()) {
+                match stage.stderr_to_stdout {
+                    true => output.capture(stderr),
+                    false => output.stderr().capture(stderr),
+                }
+            }
+            let deadline = self
+                .time_limit(&stage.program, stage.interpreted)
+                .map(|(secs, limit)| (start_time + Duration::from_secs(secs), limit));
+            stages.push(Stage { child, program: stage.program, deadline, relays });
+            self.security.record_command(&stage.actual_cmd, execution_time);
+        }
+
+        let grace = Duration::from_secs(self.config.limits.timeout_grace_secs);
+        if background {
+            let deadlines = stages.iter().map(|stage| stage.deadline.map(|(deadline, _)| deadline)).collect();
+            let children: Vec<Child> = stages.into_iter().map(|stage| stage.child).collect();
+            let pid = children.last().map_or(0, Child::id);
+            self.state.set_last_background(pid);
+            let id = self.jobs.add(describe(commands), children, output);
+            jobs::watch_deadlines(&self.jobs, id, deadlines, grace);
+            eprintln!("[{}] {}", id, pid);
+            return Ok(vec![0]);
+        }
 
-                    let execution_time = start_time.elapsed();
-                    self.security.record_command(&actual_cmd, execution_time);
+        let mut waiting: Vec<_> =
+            stages.iter_mut().map(|stage| (&mut stage.child, stage.deadline.map(|(deadline, _)| deadline))).collect();
+        let waited = match self.jobs.wait_pipeline(&mut waiting, grace) {
+            Ok(waited) => waited,
+            Err((i, e)) => {
+                let program = &stages[i].program;
+                return Err(ShellError::Wait { program: program.clone(), source: e }).stage(ErrorStage::Wait, program);
+            }
+        };
+        let stopped = waited.contains(&Waited::Stopped);
+        let mut statuses = Vec::with_capacity(stages.len());
+        let mut exceeded = None;
+        for (stage, waited) in stages.iter_mut().zip(waited) {
+            match waited {
+                Waited::Exited(code) => statuses.push(code),
+                Waited::TimedOut(code) => {
+                    statuses.push(code);
+                    if let Some((_, limit)) = stage.deadline {
+                        exceeded.get_or_insert((stage.program.clone(), limit));
+                    }
                 }
-                Err(e) => {
-                    return Err(ShellError::CommandExecution(format!("Failed to execute {}: {}", actual_cmd, e)));
+                Waited::Stopped => continue,
+            }
+            for (relay, limit) in stage.relays.drain(..) {
+                if let (true, Some(limit)) = (limits::hit_output_cap(relay), limit) {
+                    exceeded.get_or_insert((stage.program.clone(), limit));
                 }
             }
         }
+        drop(terminal);
+
+        // Ctrl-Z: the pipeline carries on as a stopped job
+        if stopped {
+            let children = stages.into_iter().map(|stage| stage.child).collect();
+            let job = self.jobs.add_stopped(describe(commands), children);
+            eprintln!("\n{}", job.line(false));
+            return Ok(vec![jobs::STOPPED_STATUS]);
+        }
+        if let Some((program, limit)) = exceeded {
+            return Err(limit.error(&program, script_limits)).stage(ErrorStage::Wait, &program);
+        }
+
+        Ok(statuses)
+    }
+
+    /// Run inline code with the configured interpreter, returning its exit status
+    pub fn execute_inline(&self, block: &InlineBlock) -> ShellResult<i32> {
+        let interpreter = block.language.interpreter(&self.config.interpreters);
+        if !self.config.interpreters.enable_scripts {
+            return Err(ShellError::SecurityViolation("script execution is disabled".to_string()))
+                .stage(ErrorStage::Validate, interpreter);
+        }
+        if block.code.len() > self.config.security.max_command_length {
+            return Err(SecurityError::InvalidInput("inline code too long".to_string()).into());
+        }
+        self.validate_command(interpreter).stage(ErrorStage::Validate, interpreter)?;
+        self.check_rate_limit(interpreter)?;
+
+        #[cfg(feature = "js")]
+        if block.language == Language::Node && self.config.interpreters.embedded_js {
+            return self.run_embedded_js(&block.code, "<inline>", &[], true);
+        }
+
+        let interpreter = &self.interpreter_for(block.language, interpreter.to_string())?;
+
+        if self.config.interpreters.persistent && InterpreterPool::supports(block.language) {
+            let request = Request::new(block.code.clone(), "<inline>", &[])?;
+            return self.run_persistent(block.language, interpreter, &request, &mut persistent::relay);
+        }
+
+        tracing::debug!(interpreter, bytes = block.code.len(), "running inline code");
+        let script_limits = &self.config.interpreters.limits;
+        let mut command = Command::new(interpreter);
+        command.arg(block.language.eval_flag()).arg(&block.code);
+        limits::restrict(&mut command, Rlimits::new(&self.config.limits).with_memory(script_limits.max_memory_mb));
+        if script_limits.max_output_kb > 0 {
+            command.stdout(Stdio::piped());
+        }
+
+        let start_time = Instant::now();
+        let mut child = self
+            .with_retry(|| spawn_command(&mut command))
+            .stage(ErrorStage::Spawn, interpreter)?;
+        self.security.record_command(interpreter, start_time.elapsed());
+        let output_cap = script_limits.max_output_kb * 1024;
+        let relay = child
+            .stdout
+            .take()
+            .map(|stdout| limits::relay_output(stdout, Box::new(io::stdout()), output_cap, child.id()));
+        let deadline = (script_limits.timeout_secs > 0).then(|| start_time + Duration::from_secs(script_limits.timeout_secs));
+
+        let exit = match limits::wait_until(&mut child, deadline) {
+            Ok(exit) => exit,
+            Err(e) => {
+                return Err(ShellError::Wait { program: interpreter.to_string(), source: e })
+                    .stage(ErrorStage::Wait, interpreter)
+            }
+        };
+        let limit = match exit {
+            None => Exceeded::Timeout,
+            Some(_) if relay.is_some_and(limits::hit_output_cap) => Exceeded::Output,
+            Some(exit) => return Ok(exit_code(exit)),
+        };
+        Err(limit.error(interpreter, script_limits)).stage(ErrorStage::Wait, interpreter)
+    }
+
+    /// Run one sub-REPL entry on the persistent interpreter for `language`, with
+    /// shell variables visible read-only
+    pub fn execute_repl(&self, language: Language, code: &str, vars: BTreeMap<String, String>) -> ShellResult<i32> {
+        self.execute_repl_with(language, code, vars, &mut persistent::relay)
+    }
+
+    /// Like `execute_repl`, passing output to `output` instead of the terminal
+    pub fn execute_repl_with(
+        &self,
+        language: Language,
+        code: &str,
+        vars: BTreeMap<String, String>,
+        output: &mut dyn FnMut(Stream, &[u8]) -> io::Result<()>,
+    ) -> ShellResult<i32> {
+        let interpreter = language.interpreter(&self.config.interpreters);
+        if !self.config.interpreters.enable_scripts {
+            return Err(ShellError::SecurityViolation("script execution is disabled".to_string()))
+                .stage(ErrorStage::Validate, interpreter);
+        }
+        if code.len() > self.config.security.max_command_length {
+            return Err(SecurityError::InvalidInput("input too long".to_string()).into());
+        }
+        self.validate_command(interpreter).stage(ErrorStage::Validate, interpreter)?;
+        self.check_rate_limit(interpreter)?;
+        let interpreter = self.interpreter_for(language, interpreter.to_string())?;
+
+        let request = Request::interactive(code.to_string(), vars)?;
+        self.run_persistent(language, &interpreter, &request, output)
+    }
+
+    /// Run a lone `script.py` on a persistent interpreter, if enabled; pipelines,
+    /// redirections and background jobs need a real process and return None
+    fn try_persistent_script(&self, cmd: &ParsedCommand) -> ShellResult<Option<i32>> {
+        if !self.config.interpreters.persistent
+            || cmd.background
+            || cmd.input_redirect.is_some()
+            || cmd.output_redirect.is_some()
+            || cmd.stderr_redirect.is_some()
+            || cmd.stderr_to_stdout
+            || cmd.here_doc.is_some()
+            || !cmd.fd_redirects.is_empty()
+            || !cmd.env.is_empty()
+        {
+            return Ok(None);
+        }
+        let language = match cmd.program.rsplit_once('.') {
+            Some((_, "py")) => Language::Python,
+            _ => return Ok(None),
+        };
+
+        let (interpreter, args) = self.resolve_command(&cmd.program, &cmd.args)?;
+        self.validate_command(&interpreter).stage(ErrorStage::Validate, &cmd.program)?;
+        self.validate_args(&args)?;
+        let interpreter = self.interpreter_for(language, interpreter)?;
+
+        let code = std::fs::read_to_string(&cmd.program)
+            .map_err(|e| ShellError::Redirect { path: cmd.program.clone(), source: e })
+            .stage(ErrorStage::Spawn, &cmd.program)?;
+        let request = Request::new(code, &cmd.program, &cmd.args)?;
+        self.run_persistent(language, &interpreter, &request, &mut persistent::relay).map(Some)
+    }
+
+    /// Run a lone `script.js` in the embedded engine, if enabled; like
+    /// persistent scripts, anything needing a real process returns None
+    #[cfg(feature = "js")]
+    fn try_embedded_js(&self, cmd: &ParsedCommand) -> ShellResult<Option<i32>> {
+        if !self.config.interpreters.embedded_js
+            || !cmd.program.ends_with(".js")
+            || cmd.background
+            || cmd.input_redirect.is_some()
+            || cmd.output_redirect.is_some()
+            || cmd.stderr_redirect.is_some()
+            || cmd.stderr_to_stdout
+            || cmd.here_doc.is_some()
+            || !cmd.fd_redirects.is_empty()
+            || !cmd.env.is_empty()
+        {
+            return Ok(None);
+        }
+
+        let (interpreter, args) = self.resolve_command(&cmd.program, &cmd.args)?;
+        self.validate_command(&interpreter).stage(ErrorStage::Validate, &cmd.program)?;
+        self.validate_args(&args)?;
+
+        let code = std::fs::read_to_string(&cmd.program)
+            .map_err(|e| ShellError::Redirect { path: cmd.program.clone(), source: e })
+            .stage(ErrorStage::Spawn, &cmd.program)?;
+        self.run_embedded_js(&code, &cmd.program, &cmd.args, false).map(Some)
+    }
+
+    #[cfg(feature = "js")]
+    fn run_embedded_js(&self, code: &str, file: &str, args: &[String], echo: bool) -> ShellResult<i32> {
+        tracing::debug!(file, bytes = code.len(), "running javascript in embedded engine");
+        let start_time = Instant::now();
+        let (budget, timeout) = self.budget(file, start_time);
+        let result = crate::js::run(code, file, args, budget, echo);
+        self.security.record_command("node", start_time.elapsed());
+        match result {
+            Err(ShellError::Io(e)) => match self.over_budget(file, &e, timeout) {
+                Some(limit) => Err(limit).stage(ErrorStage::Wait, file),
+                None => Err(ShellError::Io(e)).stage(ErrorStage::Spawn, file),
+            },
+            result => result.stage(ErrorStage::Spawn, file),
+        }
+    }
+
+    fn run_persistent(
+        &self,
+        language: Language,
+        interpreter: &str,
+        request: &Request,
+        output: &mut dyn FnMut(Stream, &[u8]) -> io::Result<()>,
+    ) -> ShellResult<i32> {
+        tracing::debug!(interpreter, file = %request.file, "running on persistent interpreter");
+        let start_time = Instant::now();
+        let (budget, timeout) = self.budget(&request.file, start_time);
+        let result = self.interpreters.run_with(language, interpreter, request, budget, output);
+        self.security.record_command(interpreter, start_time.elapsed());
+        match result {
+            Ok(status) => Ok(status),
+            Err(e) => match self.over_budget(&request.file, &e, timeout) {
+                Some(limit) => Err(limit).stage(ErrorStage::Wait, &request.file),
+                None => Err(ShellError::Spawn { program: interpreter.to_string(), source: e })
+                    .stage(ErrorStage::Spawn, interpreter),
+            },
+        }
+    }
+
+    /// Deadline and output cap for running `file` on a persistent interpreter
+    /// or the embedded engine, from its time limit and
+    /// `interpreters.limits.max_output_kb`, with the limit setting the deadline
+    fn budget(&self, file: &str, start_time: Instant) -> (Budget, Option<Exceeded>) {
+        let limit = self.time_limit(file, true);
+        let kb = self.config.interpreters.limits.max_output_kb;
+        let budget = Budget {
+            deadline: limit.map(|(secs, _)| start_time + Duration::from_secs(secs)),
+            max_output: (kb > 0).then_some(kb * 1024),
+        };
+        (budget, limit.map(|(_, limit)| limit))
+    }
+
+    /// The limit error for `file` when its run failed with `e` because it
+    /// went over its budget
+    fn over_budget(&self, file: &str, e: &io::Error, timeout: Option<Exceeded>) -> Option<ShellError> {
+        let limit = match e.kind() {
+            io::ErrorKind::TimedOut => timeout?,
+            io::ErrorKind::FileTooLarge => Exceeded::Output,
+            _ => return None,
+        };
+        Some(limit.error(file, &self.config.interpreters.limits))
+    }
+
+    /// Audit and policy-check the commands a `.sh` script appears to run, then
+    /// restrict its process as configured under `[sandbox]`
+    fn sandbox_script(&self, script: &str, command: &mut Command) -> ShellResult<()> {
+        let source = std::fs::read_to_string(script)
+            .map_err(|e| ShellError::Redirect { path: script.to_string(), source: e })
+            .stage(ErrorStage::Spawn, script)?;
+        let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+
+        for name in sandbox::script_commands(&source) {
+            if self.config.security.enable_auditing {
+                error::logging::log_command_execution(&format!("{} (from {})", name, script), &user);
+            }
+            let dynamic = SH_DYNAMIC_BUILTINS.contains(&name.as_str()) || sandbox::is_dynamic(&name);
+            if dynamic && !self.config.security.allowed_commands.is_empty() {
+                return Err(ShellError::SecurityViolation(format!(
+                    "{}: `{}` runs commands that can't be checked against the whitelist",
+                    script, name
+                )))
+                .stage(ErrorStage::Validate, script);
+            }
+            if !SH_BUILTINS.contains(&name.as_str()) {
+                self.validate_command(&name).stage(ErrorStage::Validate, script)?;
+            }
+        }
+
+        sandbox::restrict(command, &self.config.sandbox)
+            .map_err(|e| ShellError::Spawn { program: script.to_string(), source: e })
+            .stage(ErrorStage::Spawn, script)
+    }
+
+    /// Interpreter to run for `language`: the project-local one if detected, else
+    /// `configured`, checked against any version pinned in `.shell-t.toml`. A
+    /// project interpreter must pass the command policy by its own path.
+    fn interpreter_for(&self, language: Language, configured: String) -> ShellResult<String> {
+        let interpreter = match self.project_interpreter(language) {
+            Some(interpreter) => {
+                self.validate_command(&interpreter).stage(ErrorStage::Validate, &interpreter)?;
+                interpreter
+            }
+            None => configured,
+        };
+        let dir = std::env::current_dir()?;
+        project::check_pin(&dir, language, &interpreter)
+            .map_err(ShellError::Config)
+            .stage(ErrorStage::Validate, &interpreter)?;
+        Ok(interpreter)
+    }
+
+    /// Project-local interpreter standing in for the configured one, if
+    /// detection is on and the project is trusted
+    fn project_interpreter(&self, language: Language) -> Option<String> {
+        let interpreters = &self.config.interpreters;
+        if !interpreters.detect_project_envs {
+            return None;
+        }
+        let env = project::detect(&std::env::current_dir().ok()?, language, &interpreters.trusted_projects)?;
+        tracing::debug!(interpreter = %env.interpreter.display(), kind = ?env.kind, "using project interpreter");
+        Some(env.interpreter.display().to_string())
+    }
 
-        if !commands.last().map_or(false, |c| c.background) {
-            for mut child in children {
-                if let Err(e) = child.wait() {
-                    return Err(ShellError::Process(format!("Process wait error: {}", e)));
+    /// Stop the persistent interpreters, before the shell exits
+    pub fn shutdown(&self) {
+        self.interpreters.shutdown();
+    }
+
+    /// The file `cmd` sends its output to with `>` or `>>`, opened under the
+    /// noclobber policy, for a builtin that writes it itself
+    pub fn open_output(&self, cmd: &ParsedCommand) -> ShellResult<Option<std::fs::File>> {
+        let noclobber = self.state.options().noclobber && !cmd.clobber;
+        cmd.output_redirect.as_deref().map(|path| open_redirect(path, cmd.append, noclobber)).transpose()
+    }
+
+    /// Seconds `program` may run, in the foreground or background, and the
+    /// limit setting them: its entry in `limits.command_timeouts`, else
+    /// `limits.command_timeout`, or for a script
+    /// `interpreters.limits.timeout_secs` if that is sooner. `None` when
+    /// nothing limits it.
+    fn time_limit(&self, program: &str, interpreted: bool) -> Option<(u64, Exceeded)> {
+        let limits = &self.config.limits;
+        let name = Path::new(program).file_name().map_or(program.into(), |name| name.to_string_lossy());
+        let secs = limits.command_timeouts.get(name.as_ref()).copied().unwrap_or(limits.command_timeout);
+        let command = (secs > 0).then_some((secs, Exceeded::CommandTimeout(secs)));
+        let script_secs = self.config.interpreters.limits.timeout_secs;
+        let script = (interpreted && script_secs > 0).then_some((script_secs, Exceeded::Timeout));
+        script.into_iter().chain(command).min_by_key(|(secs, _)| *secs)
+    }
+
+    /// `sink`, through the output filter when output is streamed
+    fn to_terminal(&self, sink: impl io::Write + Send + 'static) -> Box<dyn io::Write + Send> {
+        match &self.filter {
+            Some(filter) => filter.wrap(Box::new(sink)),
+            None => Box::new(sink),
+        }
+    }
+
+    /// Bytes of streamed output allowed by `output.max_kb`, if `streamed`
+    fn stream_cap(&self, streamed: bool) -> Option<(u64, Exceeded)> {
+        let kb = self.config.output.max_kb;
+        (streamed && kb > 0).then_some((kb * 1024, Exceeded::StreamOutput(kb)))
+    }
+
+    /// Run `op`, retrying retryable failures with jittered exponential backoff
+    fn with_retry<T>(&self, mut op: impl FnMut() -> ShellResult<T>) -> ShellResult<T> {
+        let policy = &self.config.retry;
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(e) if policy.enabled && e.is_retryable() && attempt < policy.max_attempts => {
+                    let delay = self.backoff_delay(attempt);
+                    tracing::warn!(error = %e, attempt, ?delay, "retrying after transient failure");
+                    std::thread::sleep(delay);
+                    attempt += 1;
                 }
+                result => return result,
             }
         }
+    }
 
-        Ok(())
+    /// Delay before the given retry attempt (1-based), with up to 50% jitter
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let policy = &self.config.retry;
+        let exp = policy.base_delay_ms.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        let capped = exp.min(policy.max_delay_ms);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u64)
+            .unwrap_or(0);
+        let jitter = if capped > 1 { nanos % (capped / 2 + 1) } else { 0 };
+        Duration::from_millis(capped - jitter)
     }
 
-    /// Resolve command name to actual executable
+    /// Resolve command name to actual executable. Scripts are mapped to their
+    /// interpreter by extension, from `[interpreters]`
     fn resolve_command(&self, program: &str, args: &[String]) -> ShellResult<(String, Vec<String>)> {
-        if program.ends_with(".py") {
-            Ok(("python3".to_string(), vec![program.to_string()].into_iter().chain(args.iter().cloned()).collect()))
-        } else if program.ends_with(".rb") {
-            Ok(("ruby".to_string(), vec![program.to_string()].into_iter().chain(args.iter().cloned()).collect()))
-        } else if program.ends_with(".js") {
-            Ok(("node".to_string(), vec![program.to_string()].into_iter().chain(args.iter().cloned()).collect()))
-        } else {
-            Ok((program.to_string(), args.to_vec()))
+        let Some(ext) = self.script_extension(program) else {
+            return Ok((program.to_string(), args.to_vec()));
+        };
+        let interpreters = &self.config.interpreters;
+        if !interpreters.enable_scripts {
+            return Err(ShellError::SecurityViolation("script execution is disabled".to_string()))
+                .stage(ErrorStage::Validate, program);
+        }
+        if !interpreters.allowed_extensions.iter().any(|allowed| allowed.trim_start_matches('.') == ext) {
+            return Err(ShellError::SecurityViolation(format!(
+                ".{} scripts are not allowed (interpreters.allowed_extensions)",
+                ext
+            )))
+            .stage(ErrorStage::Validate, program);
+        }
+
+        let script = std::iter::once(program.to_string()).chain(args.iter().cloned());
+        match ext {
+            "py" => Ok((Language::Python.interpreter(interpreters).to_string(), script.collect())),
+            "rb" => Ok((Language::Ruby.interpreter(interpreters).to_string(), script.collect())),
+            "js" => Ok((Language::Node.interpreter(interpreters).to_string(), script.collect())),
+            "sh" => Ok((interpreters.sh_path.clone(), script.collect())),
+            "rs" => {
+                if !interpreters.enable_rust {
+                    return Err(ShellError::SecurityViolation("Rust scripts are disabled".to_string()))
+                        .stage(ErrorStage::Validate, program);
+                }
+                let toolchain = &interpreters.rust_path;
+                let is_cargo = Path::new(toolchain).file_stem().is_some_and(|stem| stem == "cargo");
+                let flags = if is_cargo { vec!["-Zscript".to_string()] } else { Vec::new() };
+                Ok((toolchain.clone(), flags.into_iter().chain(script).collect()))
+            }
+            "go" => {
+                if !interpreters.enable_go {
+                    return Err(ShellError::SecurityViolation("Go scripts are disabled".to_string()))
+                        .stage(ErrorStage::Validate, program);
+                }
+                Ok((interpreters.go_path.clone(), std::iter::once("run".to_string()).chain(script).collect()))
+            }
+            _ => {
+                // User-defined: the interpreter, any flags, then the script
+                let runner = interpreters.extensions.get(ext).map(String::as_str).unwrap_or_default();
+                let mut words = runner.split_whitespace().map(str::to_string);
+                let interpreter = words
+                    .next()
+                    .ok_or_else(|| ShellError::Config(format!("interpreters.extensions.{} is empty", ext)))
+                    .stage(ErrorStage::Validate, program)?;
+                Ok((interpreter, words.chain(script).collect()))
+            }
         }
     }
 
+    /// Extension of `program` when it names a script run through an interpreter
+    /// or toolchain: a built-in one or an `[interpreters.extensions]` entry
+    fn script_extension<'a>(&self, program: &'a str) -> Option<&'a str> {
+        let ext = Path::new(program).extension()?.to_str()?;
+        (SCRIPT_EXTENSIONS.contains(&ext) || self.config.interpreters.extensions.contains_key(ext)).then_some(ext)
+    }
+
     /// Validate a command against security policies
     fn validate_command(&self, command: &str) -> ShellResult<()> {
-        if !self.config.security.allowed_commands.is_empty() {
-            if !self.config.security.allowed_commands.contains(command) {
-                return Err(ShellError::SecurityViolation(format!("Command not in whitelist: {}", command)));
-            }
+        tracing::trace!(command, "validating command");
+        if !self.config.security.allowed_commands.is_empty()
+            && !self.config.security.allowed_commands.contains(command) {
+            return Err(ShellError::SecurityViolation(format!("Command not in whitelist: {}", command)));
         }
 
         if self.config.security.blocked_commands.contains(command) {
@@ -128,21 +859,245 @@ impl CommandExecutor {
         Ok(())
     }
 
+    /// Count a run of `program` against `security.max_runs_per_minute`
+    fn check_rate_limit(&self, program: &str) -> ShellResult<()> {
+        self.security
+            .check_rate_limit(&format!("cmd:{}", program), &self.config)
+            .stage(ErrorStage::Validate, program)
+    }
+
+    /// Refuse `NAME=VALUE` prefixes that change how programs are loaded
+    fn validate_env(&self, env: &[(String, String)]) -> ShellResult<()> {
+        for (name, _) in env {
+            if name.starts_with("LD_") || name.starts_with("DYLD_") {
+                return Err(ShellError::SecurityViolation(format!("Variable may not be set for a command: {}", name)))
+                    .stage(ErrorStage::Validate, &format!("{}=", name));
+            }
+        }
+        Ok(())
+    }
+
     /// Validate command arguments
     fn validate_args(&self, args: &[String]) -> ShellResult<()> {
-        for arg in args {
-            if arg.contains("../") || arg.contains("..\\") {
-                return Err(ShellError::SecurityViolation("Path traversal detected".to_string()));
-            }
+        args.iter().try_for_each(|arg| self.validate_arg(arg))
+    }
 
-            if arg.len() > self.config.limits.max_arg_length {
-                return Err(ShellError::SecurityViolation("Argument too long".to_string()));
-            }
+    /// Validate one argument
+    fn validate_arg(&self, arg: &str) -> ShellResult<()> {
+        if arg.contains("../") || arg.contains("..\\") {
+            return Err(ShellError::SecurityViolation("Path traversal detected".to_string()))
+                .stage(ErrorStage::Validate, arg);
+        }
+
+        if arg.len() > self.config.limits.max_arg_length {
+            return Err(ShellError::SecurityViolation("Argument too long".to_string()))
+                .stage(ErrorStage::Validate, arg);
         }
         Ok(())
     }
 }
 
+/// Convert a child's exit status to a shell status code (128+N for signal N)
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Exit status of a pipeline whose commands ended with `statuses`: the last
+/// command's, or with `pipefail` the last one that failed
+pub fn pipeline_status(statuses: &[i32], pipefail: bool) -> i32 {
+    match pipefail {
+        true => statuses.iter().rev().find(|status| **status != 0),
+        false => statuses.last(),
+    }
+    .copied()
+    .unwrap_or(0)
+}
+
+/// A command running `file`, found on `PATH` for `name`; the program still
+/// sees itself run as `name`
+fn found_command(file: PathBuf, name: &str) -> Command {
+    let mut command = Command::new(file);
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::arg0(&mut command, name);
+    #[cfg(not(unix))]
+    let _ = name;
+    command
+}
+
+/// Command text of a pipeline, as `jobs` lists it
+fn describe(commands: &[ParsedCommand]) -> String {
+    commands
+        .iter()
+        .map(|cmd| std::iter::once(&cmd.program).chain(&cmd.args).cloned().collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// Open the target of `>`/`2>`, or `>>`/`2>>` when `append` is set. With
+/// `noclobber`, an existing regular file is refused rather than emptied.
+fn open_redirect(path: &str, append: bool, noclobber: bool) -> ShellResult<std::fs::File> {
+    let file = if append {
+        std::fs::OpenOptions::new().create(true).append(true).open(path)
+    } else if noclobber {
+        // Devices like /dev/null may still be written to
+        match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => {
+                Err(io::Error::new(io::ErrorKind::AlreadyExists, "cannot overwrite existing file (noclobber)"))
+            }
+            Ok(_) => std::fs::OpenOptions::new().write(true).open(path),
+            Err(_) => std::fs::OpenOptions::new().write(true).create_new(true).open(path),
+        }
+    } else {
+        std::fs::File::create(path)
+    };
+    file.map_err(|e| ShellError::Redirect { path: path.to_string(), source: e }).stage(ErrorStage::Spawn, path)
+}
+
+/// Set up `redirects` in the child once its stdin, stdout and stderr are
+/// in place, so `>&2` sends stdout wherever stderr goes by then
+#[cfg(unix)]
+fn redirect_fds(command: &mut Command, redirects: &[FdRedirect], noclobber: bool) -> ShellResult<()> {
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::os::unix::process::CommandExt;
+
+    // Files are opened here, where errors can be reported; the child only
+    // copies descriptors
+    let mut files: Vec<OwnedFd> = Vec::new();
+    let mut steps = Vec::with_capacity(redirects.len());
+    for redirect in redirects {
+        let file = match redirect.mode {
+            RedirectMode::Read => Some(
+                std::fs::File::open(&redirect.target)
+                    .map_err(|e| ShellError::Redirect { path: redirect.target.clone(), source: e })
+                    .stage(ErrorStage::Spawn, &redirect.target)?,
+            ),
+            RedirectMode::Write | RedirectMode::Append => {
+                Some(open_redirect(&redirect.target, redirect.mode == RedirectMode::Append, noclobber)?)
+            }
+            RedirectMode::Duplicate | RedirectMode::Close => None,
+        };
+        let source = match (file, redirect.mode) {
+            (Some(file), _) => {
+                let fd = file.as_raw_fd();
+                files.push(file.into());
+                Some(fd)
+            }
+            (None, RedirectMode::Duplicate) => Some(redirect.target.parse().map_err(|_| {
+                ShellError::Parse(format!("bad file descriptor: {}", redirect.target))
+            })?),
+            (None, _) => None,
+        };
+        steps.push((redirect.fd, source));
+    }
+    // SAFETY: the closure only makes async-signal-safe calls
+    unsafe {
+        command.pre_exec(move || {
+            // The files stay open until the child has copied them
+            let _ = &files;
+            for &(fd, source) in &steps {
+                match source {
+                    Some(source) if source != fd => {
+                        if libc::dup2(source, fd) < 0 {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        libc::close(fd);
+                    }
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn redirect_fds(_command: &mut Command, _redirects: &[FdRedirect], _noclobber: bool) -> ShellResult<()> {
+    Err(ShellError::Process("redirecting other file descriptors needs Unix".to_string()))
+}
+
+/// A pipe `text` is written into on a thread, to be a stage's stdin; the
+/// writer stops early if the stage exits without reading it all
+fn feed(text: String) -> ShellResult<Stdio> {
+    let (reader, mut writer) = io::pipe()?;
+    thread::spawn(move || writer.write_all(text.as_bytes()));
+    Ok(reader.into())
+}
+
+/// A pipeline stage set up but not yet spawned
+struct Pending {
+    command: Command,
+    /// The program as typed, for errors
+    program: String,
+    span: Option<Span>,
+    /// The program actually run, after aliases and interpreters
+    actual_cmd: String,
+    sandboxed: bool,
+    interpreted: bool,
+    relays: Vec<Relay>,
+    /// Stderr is merged into stdout when that is captured
+    stderr_to_stdout: bool,
+}
+
+impl Pending {
+    fn spawn_error(&self, e: ShellError) -> ShellError {
+        let e = match e {
+            ShellError::Spawn { source, .. } if self.sandboxed && source.kind() == io::ErrorKind::PermissionDenied => {
+                ShellError::from(SecurityError::PermissionDenied(format!(
+                    "cannot isolate the network for {} ({}); set sandbox.allow_network = true to run it anyway",
+                    self.program, source
+                )))
+            }
+            e => e,
+        };
+        let mut context = ErrorContext::new(ErrorStage::Spawn).with_token(&self.program);
+        context.span = self.span;
+        e.with_context(context)
+    }
+}
+
+/// A started pipeline stage
+struct Stage {
+    child: Child,
+    program: String,
+    /// When the stage is stopped for running too long, and the limit that set it
+    deadline: Option<(Instant, Exceeded)>,
+    /// Threads passing on capped or streamed output; each yields whether
+    /// it hit its cap, the limit named by the second
+    relays: Vec<(JoinHandle<io::Result<bool>>, Option<Exceeded>)>,
+}
+
+/// Output of a stage to be passed on by a relay thread once it is spawned
+struct Relay {
+    source: io::PipeReader,
+    sink: Box<dyn io::Write + Send>,
+    /// Bytes passed on before the stage is stopped, and the limit setting them
+    cap: Option<(u64, Exceeded)>,
+}
+
+/// Spawn a command, marking transient failures (EAGAIN, EINTR) as retryable
+fn spawn_command(command: &mut Command) -> ShellResult<Child> {
+    command.spawn().map_err(|e| {
+        let transient = matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted);
+        let program = command.get_program().to_string_lossy().into_owned();
+        let err = ShellError::Spawn { program, source: e };
+        if transient {
+            ShellError::Retryable(Box::new(err))
+        } else {
+            err
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,7 +1108,7 @@ mod tests {
     fn create_test_executor() -> CommandExecutor {
         let security = Arc::new(SecurityManager::new());
         let config = Config::default();
-        CommandExecutor::new(security, config)
+        CommandExecutor::new(security, config, Arc::new(ShellState::new()), Arc::new(JobManager::new()))
     }
 
     fn create_test_command(program: &str, args: Vec<&str>) -> ParsedCommand {
@@ -163,7 +1118,16 @@ mod tests {
             input_redirect: None,
             output_redirect: None,
             append: false,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
             background: false,
+            spans: Vec::new(),
         }
     }
 
@@ -171,7 +1135,7 @@ mod tests {
     fn test_executor_creation() {
         let executor = create_test_executor();
         // Test passes if executor is created successfully
-        assert!(true);
+        assert_eq!(executor.config.limits.max_pipeline_length, 10);
     }
 
     #[test]
@@ -192,6 +1156,36 @@ mod tests {
         assert_eq!(args, vec!["script.py", "arg1"]);
     }
 
+    #[test]
+    fn test_resolve_command_rust() {
+        let executor = create_test_executor();
+
+        let (cmd, args) = executor.resolve_command("hello.rs", &["arg1".to_string()]).unwrap();
+        assert_eq!(cmd, "rust-script");
+        assert_eq!(args, vec!["hello.rs", "arg1"]);
+
+        let mut config = Config::default();
+        config.interpreters.rust_path = "/usr/bin/cargo".to_string();
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+        let (cmd, args) = executor.resolve_command("hello.rs", &[]).unwrap();
+        assert_eq!(cmd, "/usr/bin/cargo");
+        assert_eq!(args, vec!["-Zscript", "hello.rs"]);
+    }
+
+    #[test]
+    fn test_resolve_command_go() {
+        let executor = create_test_executor();
+
+        let (cmd, args) = executor.resolve_command("tool.go", &["arg1".to_string()]).unwrap();
+        assert_eq!(cmd, "go");
+        assert_eq!(args, vec!["run", "tool.go", "arg1"]);
+
+        let mut config = Config::default();
+        config.interpreters.enable_go = false;
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+        assert!(executor.resolve_command("tool.go", &[]).is_err());
+    }
+
     #[test]
     fn test_resolve_command_ruby() {
         let executor = create_test_executor();
@@ -210,13 +1204,46 @@ mod tests {
         assert_eq!(args, vec!["script.js", "arg1"]);
     }
 
+    #[test]
+    fn test_resolve_command_uses_interpreter_config() {
+        let mut config = Config::default();
+        config.interpreters.python_path = "/opt/python/bin/python3.12".to_string();
+        config.interpreters.extensions.insert("pl".to_string(), "perl -w".to_string());
+        config.interpreters.allowed_extensions.insert("pl".to_string());
+        config.interpreters.allowed_extensions.remove("rb");
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+
+        let (cmd, args) = executor.resolve_command("script.py", &[]).unwrap();
+        assert_eq!(cmd, "/opt/python/bin/python3.12");
+        assert_eq!(args, vec!["script.py"]);
+
+        let (cmd, args) = executor.resolve_command("report.pl", &["arg1".to_string()]).unwrap();
+        assert_eq!(cmd, "perl");
+        assert_eq!(args, vec!["-w", "report.pl", "arg1"]);
+
+        let err = executor.resolve_command("script.rb", &[]).unwrap_err();
+        assert!(err.to_string().contains("allowed_extensions"));
+        // Unknown extensions are ordinary commands
+        assert_eq!(executor.resolve_command("notes.txt", &[]).unwrap().0, "notes.txt");
+    }
+
+    #[test]
+    fn test_resolve_command_scripts_disabled() {
+        let mut config = Config::default();
+        config.interpreters.enable_scripts = false;
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+
+        assert!(executor.resolve_command("script.sh", &[]).is_err());
+        assert!(executor.resolve_command("ls", &[]).is_ok());
+    }
+
     #[test]
     fn test_validate_command_whitelist_allowed() {
         let mut config = Config::default();
-        config.security.command_whitelist = Some(vec!["ls".to_string(), "pwd".to_string()]);
+        config.security.allowed_commands = ["ls", "pwd"].iter().map(|s| s.to_string()).collect();
 
         let security = Arc::new(SecurityManager::new());
-        let executor = CommandExecutor::new(security, config);
+        let executor = CommandExecutor::new(security, config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
 
         assert!(executor.validate_command("ls").is_ok());
         assert!(executor.validate_command("pwd").is_ok());
@@ -225,22 +1252,78 @@ mod tests {
     #[test]
     fn test_validate_command_whitelist_denied() {
         let mut config = Config::default();
-        config.security.command_whitelist = Some(vec!["ls".to_string(), "pwd".to_string()]);
+        config.security.allowed_commands = ["ls", "pwd"].iter().map(|s| s.to_string()).collect();
 
         let security = Arc::new(SecurityManager::new());
-        let executor = CommandExecutor::new(security, config);
+        let executor = CommandExecutor::new(security, config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
 
         assert!(executor.validate_command("rm").is_err());
         assert!(executor.validate_command("sudo").is_err());
     }
 
+    #[test]
+    fn test_sandbox_script_refuses_dynamic_builtins_under_whitelist() {
+        let dir = std::env::temp_dir().join(format!("shell-t-sh-builtins-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let plain = dir.join("plain.sh");
+        let dynamic = dir.join("dynamic.sh");
+        std::fs::write(&plain, "cd /tmp\nls -l\n").unwrap();
+        std::fs::write(&dynamic, "cmd=rm\neval \"$cmd -rf x\"\n").unwrap();
+
+        let mut config = Config::default();
+        config.security.allowed_commands = ["ls".to_string()].into_iter().collect();
+        let security = Arc::new(SecurityManager::new());
+        let executor = CommandExecutor::new(security, config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+        let mut command = Command::new("sh");
+        assert!(executor.sandbox_script(plain.to_str().unwrap(), &mut command).is_ok());
+        assert!(executor.sandbox_script(dynamic.to_str().unwrap(), &mut command).is_err());
+        // Commands named by a variable or a substitution can't be checked either
+        for source in ["c=rm; $c -rf x\n", "$(echo rm) x\n", "`echo rm` x\n"] {
+            std::fs::write(&plain, source).unwrap();
+            let err = executor.sandbox_script(plain.to_str().unwrap(), &mut command).unwrap_err();
+            assert!(err.to_string().contains("can't be checked"), "{}", source);
+        }
+        std::fs::write(&plain, "x=$(ls) y=`ls`\nls $x\n").unwrap();
+        assert!(executor.sandbox_script(plain.to_str().unwrap(), &mut command).is_ok());
+
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        let security = Arc::new(SecurityManager::new());
+        let executor = CommandExecutor::new(security, config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+        assert!(executor.sandbox_script(dynamic.to_str().unwrap(), &mut command).is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rate_limit_refuses_without_retrying() {
+        let mut config = Config::default();
+        config.security.max_runs_per_minute = 2;
+        config.retry.enabled = true;
+        let security = Arc::new(SecurityManager::new());
+        let executor = CommandExecutor::new(security, config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+        let commands = vec![create_test_command("pwd", vec![])];
+        let mut runs = 0;
+        for _ in 0..3 {
+            if executor.execute_pipeline(&commands).is_ok() {
+                runs += 1;
+            }
+        }
+        assert_eq!(runs, 2);
+        let err = executor.execute_pipeline(&commands).unwrap_err();
+        assert_eq!(err.code(), "ST0207");
+        assert!(!err.is_retryable());
+        // Other commands have a count of their own
+        assert!(executor.execute_pipeline(&[create_test_command("ls", vec![])]).is_ok());
+    }
+
     #[test]
     fn test_validate_command_blacklist() {
         let mut config = Config::default();
-        config.security.command_blacklist = Some(vec!["rm".to_string(), "sudo".to_string()]);
+        config.security.allowed_commands.clear();
+        config.security.blocked_commands = ["rm", "sudo"].iter().map(|s| s.to_string()).collect();
 
         let security = Arc::new(SecurityManager::new());
-        let executor = CommandExecutor::new(security, config);
+        let executor = CommandExecutor::new(security, config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
 
         assert!(executor.validate_command("ls").is_ok());
         assert!(executor.validate_command("rm").is_err());
@@ -255,6 +1338,131 @@ mod tests {
         assert!(executor.validate_args(&args).is_err());
     }
 
+    #[test]
+    fn test_validate_error_context() {
+        let executor = create_test_executor();
+        let commands = vec![create_test_command("cat", vec!["../secret"])];
+
+        let err = executor.execute_pipeline(&commands).unwrap_err().with_source("cat ../secret");
+        let ctx = err.context().expect("validation errors carry context");
+        assert_eq!(ctx.stage, ErrorStage::Validate);
+        assert_eq!(ctx.span, Some(crate::error::Span::new(4, 13)));
+    }
+
+    #[test]
+    fn test_validate_error_points_at_stage_word() {
+        let executor = create_test_executor();
+        let line = "ls sudo | sudo ls";
+        let commands = crate::parser::parse_command(line).unwrap();
+
+        let err = executor.execute_pipeline(&commands).unwrap_err().with_source(line);
+        assert_eq!(err.context().and_then(|ctx| ctx.span), Some(crate::error::Span::new(10, 14)));
+
+        // A refused argument reading the same as the program points at the argument
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+        let line = "../ls ../ls";
+        let commands = crate::parser::parse_command(line).unwrap();
+        let err = executor.execute_pipeline(&commands).unwrap_err().with_source(line);
+        assert_eq!(err.context().and_then(|ctx| ctx.span), Some(crate::error::Span::new(6, 11)));
+    }
+
+    #[test]
+    fn test_spawn_error_preserves_io_source() {
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+        let commands = vec![create_test_command("shell-t-no-such-command", vec![])];
+
+        let err = executor.execute_pipeline(&commands).unwrap_err();
+        assert!(matches!(err.root(), ShellError::Spawn { .. }));
+        let source = std::error::Error::source(err.root()).expect("spawn errors keep their cause");
+        assert!(source.downcast_ref::<io::Error>().is_some());
+    }
+
+    #[test]
+    fn test_env_prefix_reaches_only_the_command() {
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+
+        let commands = crate::parser::parse_command("SHELL_T_GREETING='hi there' printenv SHELL_T_GREETING").unwrap();
+        assert_eq!(executor.capture_pipeline(&commands).unwrap(), (0, "hi there\n".to_string()));
+        assert!(std::env::var_os("SHELL_T_GREETING").is_none());
+
+        let commands = crate::parser::parse_command("LD_PRELOAD=x.so ls").unwrap();
+        assert!(executor.execute_pipeline(&commands).is_err());
+    }
+
+    #[test]
+    fn test_command_timeout_stops_foreground_commands() {
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        config.limits.command_timeouts.insert("sleep".to_string(), 1);
+        config.limits.command_timeouts.insert("make".to_string(), 0);
+        config.limits.timeout_grace_secs = 0;
+        config.interpreters.limits.timeout_secs = 60;
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+        assert_eq!(executor.time_limit("/bin/sleep", false), Some((1, Exceeded::CommandTimeout(1))));
+        assert_eq!(executor.time_limit("make", false), None);
+        assert_eq!(executor.time_limit("build.py", true), Some((60, Exceeded::Timeout)));
+        assert_eq!(executor.time_limit("ls", true), Some((60, Exceeded::Timeout)));
+
+        let start = Instant::now();
+        let err = executor.execute_pipeline(&[create_test_command("sleep", vec!["5"])]).unwrap_err();
+        assert!(err.to_string().contains("sleep ran longer than 1s (limits.command_timeout)"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_streamed_output_is_filtered_and_capped() {
+        let record = std::env::temp_dir().join(format!("shell-t-streamed-{}.log", std::process::id()));
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        config.output.stream = true;
+        config.output.max_kb = 1;
+        config.output.redact = vec!["token=\\S+".to_string()];
+        config.output.record_file = Some(record.clone());
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+
+        let script = "echo login token=abc; echo warn token=def >&2";
+        assert_eq!(executor.execute_pipeline(&[create_test_command("sh", vec!["-c", script])]).unwrap(), [0]);
+        let mut merged = create_test_command("sh", vec!["-c", "echo both token=ghi >&2"]);
+        merged.stderr_to_stdout = true;
+        assert_eq!(executor.execute_pipeline(&[merged]).unwrap(), [0]);
+        let recorded = std::fs::read_to_string(&record).unwrap();
+        let mut lines: Vec<&str> = recorded.lines().collect();
+        lines.sort();
+        assert_eq!(lines, ["both [REDACTED]", "login [REDACTED]", "warn [REDACTED]"]);
+
+        // Output captured by `$(...)` isn't for the terminal, so isn't streamed
+        let (_, captured) = executor.capture_pipeline(&[create_test_command("sh", vec!["-c", "echo token=jkl"])]).unwrap();
+        assert_eq!(captured, "token=jkl\n");
+
+        let err = executor.execute_pipeline(&[create_test_command("sh", vec!["-c", "printf '%1100s' ''"])]).unwrap_err();
+        assert!(err.to_string().contains("sh wrote more than 1 KB (output.max_kb)"), "{}", err);
+        std::fs::remove_file(record).unwrap();
+    }
+
+    #[test]
+    fn test_pipefail_and_pipe_status() {
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        let state = Arc::new(ShellState::new());
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, state.clone(), Arc::new(JobManager::new()));
+        let commands = crate::parser::parse_command("ls /nonexistent-shell-t-dir 2> /dev/null | cat").unwrap();
+
+        let statuses = executor.execute_pipeline(&commands).unwrap();
+        assert_eq!((statuses[0], statuses[1]), (2, 0));
+        assert_eq!(executor.finish_pipeline(statuses), 0);
+        assert_eq!(state.pipe_status(), [2, 0]);
+
+        state.update_options(|options| options.pipefail = true);
+        assert_eq!(executor.capture_pipeline(&commands).unwrap().0, 2);
+        assert_eq!((pipeline_status(&[1, 3, 0], true), pipeline_status(&[], true)), (3, 0));
+    }
+
     #[test]
     fn test_validate_args_too_long() {
         let executor = create_test_executor();
@@ -272,6 +1480,46 @@ mod tests {
         assert!(executor.validate_args(&args).is_ok());
     }
 
+    #[test]
+    fn test_with_retry_recovers_from_transient_errors() {
+        let mut config = Config::default();
+        config.retry.base_delay_ms = 1;
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
+
+        let mut calls = 0;
+        let result = executor.with_retry(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(ShellError::Retryable(Box::new(ShellError::Process("busy".to_string()))))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_with_retry_gives_up_on_permanent_errors() {
+        let executor = create_test_executor();
+
+        let mut calls = 0;
+        let result: ShellResult<()> = executor.with_retry(|| {
+            calls += 1;
+            Err(ShellError::Process("broken".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_backoff_delay_is_capped() {
+        let executor = create_test_executor();
+        let max = Duration::from_millis(executor.config.retry.max_delay_ms);
+        for attempt in 1..20 {
+            assert!(executor.backoff_delay(attempt) <= max);
+        }
+    }
+
     #[test]
     fn test_execute_pipeline_empty() {
         let executor = create_test_executor();
@@ -287,7 +1535,7 @@ mod tests {
         config.limits.max_pipeline_length = 2;
 
         let security = Arc::new(SecurityManager::new());
-        let executor = CommandExecutor::new(security, config);
+        let executor = CommandExecutor::new(security, config, Arc::new(ShellState::new()), Arc::new(JobManager::new()));
 
         let commands = vec![
             create_test_command("ls", vec![]),
@@ -0,0 +1,730 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::completions::wildcard_match;
+use crate::error::{ErrorContext, ErrorStage, ShellError, ShellResult};
+use crate::parser::{Command, Quoting};
+use crate::script::is_name;
+use crate::state::{ShellOptions, ShellState};
+
+/// Expand `$NAME` and `${NAME}` outside single quotes, looking in shell
+/// variables first and then the environment; unset names expand to nothing.
+///
+/// Positional parameters (`$0`, `$1`, `${10}`, `$#`, `$@`, `$*`) come from the
+/// running script. A quoted `"$@"` keeps each argument a separate word.
+///
+/// Values are taken literally: quotes, `$` and operators in them are quoted
+/// so they don't act. Unquoted, they are split into words on `$IFS` (see
+/// `field_separators`) and their wildcards still expand, except in the value
+/// of a `NAME=VALUE` assignment, which stays one word.
+pub fn expand_variables(input: &str, state: &ShellState) -> String {
+    let ifs = field_separators(state);
+    expand_with(input, |name, quoted| match name {
+        "@" if quoted => Some(state.params().iter().map(|arg| literal(arg, true)).collect::<Vec<_>>().join("\" \"")),
+        // `"$*"` joins the arguments with the first character of `$IFS`
+        "*" if quoted => {
+            let separator = ifs.as_deref().map_or(" ", |ifs| &ifs[..ifs.chars().next().map_or(0, char::len_utf8)]);
+            Some(literal(&state.params().join(separator), true))
+        }
+        _ => value(name, state).map(|value| match quoted {
+            true => literal(&value, true),
+            false => unquoted(&value, ifs.as_deref()),
+        }),
+    })
+}
+
+/// What unquoted expansions are split on: `$IFS`, by default space, tab and
+/// newline. `None` under `set -o nosplit`, where each stays one word.
+pub fn field_separators(state: &ShellState) -> Option<String> {
+    if state.options().nosplit {
+        return None;
+    }
+    Some(state.var("IFS").unwrap_or_else(|| " \t\n".to_string()))
+}
+
+/// Split `value` into fields on `ifs`: runs of its whitespace separate
+/// fields and are dropped at either end, while each other character ends a
+/// field, even an empty one. With no `ifs`, a value is one field unless it
+/// is empty.
+pub fn split_fields(value: &str, ifs: Option<&str>) -> Vec<String> {
+    let Some(ifs) = ifs else {
+        return if value.is_empty() { Vec::new() } else { vec![value.to_string()] };
+    };
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_field = false;
+    // Just ended a field on whitespace, which a following `:` belongs to
+    let mut after_space = false;
+    for ch in value.chars() {
+        if !ifs.contains(ch) {
+            field.push(ch);
+            in_field = true;
+            after_space = false;
+        } else if ch.is_whitespace() {
+            if in_field {
+                fields.push(std::mem::take(&mut field));
+                in_field = false;
+                after_space = true;
+            }
+        } else {
+            if in_field || !after_space {
+                fields.push(std::mem::take(&mut field));
+            }
+            in_field = false;
+            after_space = false;
+        }
+    }
+    if in_field {
+        fields.push(field);
+    }
+    fields
+}
+
+/// An unquoted expansion of `value` as the line's words: one per field
+fn unquoted(value: &str, ifs: Option<&str>) -> String {
+    let fields = split_fields(value, ifs);
+    fields
+        .iter()
+        .map(|field| if field.is_empty() { "''".to_string() } else { literal(field, false) })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The value of variable or parameter `name`, as set. `NAME[N]` is element
+/// `N` of an array, and `NAME[@]` all of them; a plain variable is an array
+/// of its one value.
+pub fn value(name: &str, state: &ShellState) -> Option<String> {
+    if let Some((array, index)) = element(name) {
+        let items = match array {
+            "PIPESTATUS" => state.pipe_status().iter().map(ToString::to_string).collect(),
+            _ => value(array, state).into_iter().collect::<Vec<_>>(),
+        };
+        return match index {
+            "@" | "*" => Some(items.join(" ")),
+            _ => items.into_iter().nth(index.parse().ok()?),
+        };
+    }
+    match name {
+        // Statuses of the last pipeline's commands, as one list
+        "PIPESTATUS" => Some(state.pipe_status().iter().map(ToString::to_string).collect::<Vec<_>>().join(" ")),
+        _ if is_name(name) => state.var(name).or_else(|| env::var(name).ok()),
+        _ => state.param(name),
+    }
+}
+
+/// `value` with the characters that would otherwise act once it is in the
+/// line quoted, inside double quotes when `in_double`; outside them, it
+/// stays one word
+fn literal(value: &str, in_double: bool) -> String {
+    let mut output = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match (ch, in_double) {
+            ('\\', _) => output.push_str("\\\\"),
+            // Close the double quotes around it, then reopen them
+            ('"' | '$', true) => output.push_str(&format!("\"'{}'\"", ch)),
+            ('\'', false) => output.push_str("\"'\""),
+            ('"' | '$' | '|' | '&' | '<' | '>' | '(' | ')' | '`', false) => output.push_str(&format!("'{}'", ch)),
+            (c, false) if c.is_whitespace() => output.push_str(&format!("'{}'", ch)),
+            _ => output.push(ch),
+        }
+    }
+    output
+}
+
+/// The array and index of a `NAME[N]`, `NAME[@]` or `NAME[*]` element
+fn element(name: &str) -> Option<(&str, &str)> {
+    let (array, index) = name.strip_suffix(']')?.split_once('[')?;
+    let valid = matches!(index, "@" | "*") || (!index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()));
+    (is_name(array) && valid).then_some((array, index))
+}
+
+/// Digits or one of `#`, `@`, `*`, `?`, `$`, `!`: a positional or special
+/// parameter
+fn is_param(name: &str) -> bool {
+    matches!(name, "#" | "@" | "*" | "?" | "$" | "!") || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Follows the unquoted characters of a line to tell which words are
+/// assignments: `NAME=VALUE` words before a command's program, whose values
+/// are neither split into fields nor globbed
+#[derive(Debug)]
+struct Assignments {
+    /// Every word of the current command so far has been an assignment
+    prefix: bool,
+    in_word: bool,
+    /// The current word is an assignment
+    current: bool,
+}
+
+impl Default for Assignments {
+    fn default() -> Self {
+        Self { prefix: true, in_word: false, current: false }
+    }
+}
+
+impl Assignments {
+    /// Move past the unquoted character that starts `rest`
+    fn step(&mut self, rest: &str) {
+        let Some(ch) = rest.chars().next() else {
+            return;
+        };
+        let separator = ch == '\n' || ";|&()".contains(ch);
+        if ch.is_whitespace() || separator {
+            if self.in_word {
+                self.prefix &= self.current;
+                self.in_word = false;
+                self.current = false;
+            }
+            self.prefix |= separator;
+        } else if !self.in_word {
+            self.in_word = true;
+            self.current = self.prefix && rest.split_once('=').is_some_and(|(name, _)| is_name(name));
+        }
+    }
+
+    /// Whether the word being read is an assignment
+    fn in_value(&self) -> bool {
+        self.current
+    }
+}
+
+/// Expand variables using `lookup(name, in_double_quotes)` to resolve names.
+/// In an assignment's value, names are looked up as in double quotes and the
+/// value is double-quoted, so it stays one word.
+pub fn expand_with(input: &str, lookup: impl Fn(&str, bool) -> Option<String>) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.char_indices().peekable();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut assignments = Assignments::default();
+
+    while let Some((idx, ch)) = chars.next() {
+        if !in_single && !in_double {
+            assignments.step(&input[idx..]);
+        }
+        let expand = |name: &str| match (in_double, assignments.in_value()) {
+            // `x=$@` joins the arguments, like `x="$*"`
+            (false, true) => format!("\"{}\"", lookup(if name == "@" { "*" } else { name }, true).unwrap_or_default()),
+            _ => lookup(name, in_double).unwrap_or_default(),
+        };
+        match ch {
+            // `$'...'`: escapes decoded, then single-quoted like any other word
+            '$' if !in_single && !in_double && input[idx + 1..].starts_with('\'') => {
+                if let Some((text, len)) = ansi_c_quoted(&input[idx + 2..]) {
+                    output.push_str(&single_quote(&text));
+                    let end = idx + 2 + len;
+                    while chars.next_if(|(i, _)| *i < end).is_some() {}
+                    continue;
+                }
+            }
+            // `\$HOME`, `"\""`: the escaped character is left for the parser
+            '\\' if !in_single => {
+                output.push(ch);
+                if let Some((_, next)) = chars.next() {
+                    output.push(next);
+                }
+                continue;
+            }
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '$' if !in_single => {
+                let rest = &input[idx + 1..];
+                if let Some(braced) = rest.strip_prefix('{') {
+                    if let Some(end) = braced.find('}') {
+                        let name = &braced[..end];
+                        if is_name(name) || is_param(name) || element(name).is_some() {
+                            output.push_str(&expand(name));
+                            // Skip `{NAME}`
+                            for _ in 0..name.chars().count() + 2 {
+                                chars.next();
+                            }
+                            continue;
+                        }
+                    }
+                } else if let Some(param) = rest.chars().next().filter(|c| c.is_ascii_digit() || "#@*?$!".contains(*c)) {
+                    // Single-character parameter: `$1x` is `$1` then `x`
+                    output.push_str(&expand(&param.to_string()));
+                    chars.next();
+                    continue;
+                } else {
+                    let len = rest
+                        .char_indices()
+                        .find(|(i, c)| !(c.is_ascii_alphanumeric() || *c == '_') || (*i == 0 && c.is_ascii_digit()))
+                        .map_or(rest.len(), |(i, _)| i);
+                    if len > 0 {
+                        output.push_str(&expand(&rest[..len]));
+                        for _ in 0..len {
+                            chars.next();
+                        }
+                        continue;
+                    }
+                }
+            }
+            _ => {}
+        }
+        output.push(ch);
+    }
+
+    output
+}
+
+/// The text of a `$'...'` word whose body starts `body`, with `\n`, `\t`,
+/// `\xHH`, `\NNN` (octal), `\uHHHH` and the other C escapes decoded, and
+/// the bytes it takes up to and including the closing quote. A NUL ends the
+/// text, as in bash. `None` if the quote isn't closed.
+fn ansi_c_quoted(body: &str) -> Option<(String, usize)> {
+    let mut text = String::new();
+    let mut ended = false;
+    let mut chars = body.char_indices().peekable();
+    while let Some((idx, ch)) = chars.next() {
+        let decoded = match ch {
+            '\'' => return Some((text, idx + 1)),
+            '\\' => match chars.next().map(|(_, escape)| escape) {
+                Some('n') => Some('\n'),
+                Some('t') => Some('\t'),
+                Some('r') => Some('\r'),
+                Some('a') => Some('\x07'),
+                Some('b') => Some('\x08'),
+                Some('e' | 'E') => Some('\x1b'),
+                Some('f') => Some('\x0c'),
+                Some('v') => Some('\x0b'),
+                Some(c @ ('\\' | '\'' | '"' | '?')) => Some(c),
+                Some(radix @ ('x' | 'u' | 'U' | '0'..='7')) => {
+                    let (base, max) = match radix {
+                        'x' => (16, 2),
+                        'u' => (16, 4),
+                        'U' => (16, 8),
+                        _ => (8, 2),
+                    };
+                    let mut value = radix.to_digit(8).filter(|_| base == 8);
+                    for _ in 0..max {
+                        match chars.peek().and_then(|(_, c)| c.to_digit(base)) {
+                            Some(digit) => {
+                                value = Some(value.unwrap_or(0) * base + digit);
+                                chars.next();
+                            }
+                            None => break,
+                        }
+                    }
+                    match value {
+                        Some(value) => char::from_u32(value),
+                        // `\x` with no digits stays as typed
+                        None => {
+                            text.push('\\');
+                            Some(radix)
+                        }
+                    }
+                }
+                Some(other) => {
+                    text.push('\\');
+                    Some(other)
+                }
+                None => return None,
+            },
+            c => Some(c),
+        };
+        match decoded {
+            Some('\0') => ended = true,
+            Some(c) if !ended => text.push(c),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Replace each `$(COMMAND)` outside single quotes with what `run` prints
+/// for it, less trailing newlines. The output is quoted into the line so it
+/// can only ever be words: split into fields on `ifs`, or one word inside
+/// double quotes or an assignment's value.
+pub fn substitute_commands(
+    input: &str,
+    ifs: Option<&str>,
+    mut run: impl FnMut(&str) -> ShellResult<String>,
+) -> ShellResult<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut quoting = Quoting::default();
+    let mut assignments = Assignments::default();
+    let mut pos = 0;
+
+    while let Some(ch) = input[pos..].chars().next() {
+        let quote = quoting.quote();
+        let escaped = quoting.escaped();
+        if quote.is_none() {
+            assignments.step(&input[pos..]);
+        }
+        quoting.step(ch);
+        pos += ch.len_utf8();
+        if ch != '$' || escaped || quote == Some('\'') || !input[pos..].starts_with('(') {
+            output.push(ch);
+            continue;
+        }
+
+        // Find the `)` closing it
+        let start = pos + 1;
+        quoting.step('(');
+        let mut end = start;
+        for inner in input[start..].chars() {
+            quoting.step(inner);
+            if !quoting.in_substitution() {
+                break;
+            }
+            end += inner.len_utf8();
+        }
+        if quoting.in_substitution() {
+            return Err(ShellError::Parse("unterminated $(".to_string()));
+        }
+        let text = run(&input[start..end])?;
+        let text = text.trim_end_matches('\n');
+        if quote == Some('"') {
+            output.push('"');
+            output.push_str(&single_quote(text));
+            output.push('"');
+        } else if assignments.in_value() {
+            output.push_str(&single_quote(text));
+        } else {
+            output.push_str(&unquoted(text, ifs));
+        }
+        pos = end + 1;
+    }
+    Ok(output)
+}
+
+/// `text` as one single-quoted word
+fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\"'\"'"))
+}
+
+/// Replace arguments with unquoted wildcards by the paths they match, in
+/// order. One matching nothing is kept as typed, or with `nullglob` dropped;
+/// with `failglob` it is an error.
+pub fn expand_globs(mut commands: Vec<Command>, options: &ShellOptions) -> ShellResult<Vec<Command>> {
+    if options.noglob {
+        return Ok(commands);
+    }
+    for command in &mut commands {
+        // From the last, so earlier indices stay valid as arguments multiply
+        for (index, pattern) in std::mem::take(&mut command.globs).into_iter().rev() {
+            let matches = glob(&pattern, options.dotglob);
+            // The program's span comes first, so a word's is one further on
+            let span = command.spans.get(index + 1).copied();
+            if !matches.is_empty() {
+                if let Some(span) = span {
+                    command.spans.splice(index + 1..=index + 1, vec![span; matches.len()]);
+                }
+                command.args.splice(index..=index, matches);
+            } else if options.failglob {
+                let mut context = ErrorContext::new(ErrorStage::Parse).with_token(&command.args[index]);
+                context.span = span;
+                return Err(ShellError::Parse(format!("no match: {}", command.args[index])).with_context(context));
+            } else if options.nullglob {
+                if span.is_some() {
+                    command.spans.remove(index + 1);
+                }
+                command.args.remove(index);
+            }
+        }
+    }
+    Ok(commands)
+}
+
+/// Paths matching `pattern`, sorted. Each `/`-separated component is matched
+/// against one directory's entries; a `**` component matches any number of
+/// directories, or as the last one everything below. Names starting with `.`
+/// only match a component that starts with `.` too, unless `dotglob`.
+pub fn glob(pattern: &str, dotglob: bool) -> Vec<String> {
+    let (root, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => ("/", rest),
+        None => ("", pattern),
+    };
+    let components: Vec<&str> = rest.split('/').filter(|component| !component.is_empty()).collect();
+    let dirs_only = pattern.ends_with('/');
+
+    let mut paths = vec![root.to_string()];
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        if *component == "**" {
+            paths = paths
+                .iter()
+                .flat_map(|prefix| {
+                    let below = descendants(prefix, dotglob);
+                    let dirs = below.iter().filter(|(_, dir)| *dir).map(|(path, _)| format!("{}/", path));
+                    match (last, dirs_only) {
+                        (true, false) => below.iter().map(|(path, _)| path.clone()).collect::<Vec<_>>(),
+                        (true, true) => dirs.collect(),
+                        // Zero directories, then each one below
+                        (false, _) => std::iter::once(prefix.clone()).chain(dirs).collect(),
+                    }
+                })
+                .collect();
+            continue;
+        }
+        let mut next = Vec::new();
+        for prefix in &paths {
+            if !component.contains(['*', '?', '[']) {
+                next.push(format!("{}{}", prefix, component));
+                continue;
+            }
+            let dir = if prefix.is_empty() { Path::new(".") } else { Path::new(prefix) };
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| (dotglob || !name.starts_with('.') || component.starts_with('.')) && wildcard_match(component, name))
+                .collect();
+            names.sort();
+            next.extend(names.into_iter().map(|name| format!("{}{}", prefix, name)));
+        }
+        paths = next
+            .into_iter()
+            .filter(|path| if last && !dirs_only { fs::symlink_metadata(path).is_ok() } else { Path::new(path).is_dir() })
+            .map(|path| if last && !dirs_only { path } else { format!("{}/", path) })
+            .collect();
+    }
+    // Matches from different depths come out interleaved
+    if components.contains(&"**") {
+        paths.sort();
+    }
+    paths
+}
+
+/// Everything below directory `prefix` (`""` for the working directory),
+/// each directory's entries sorted and followed by what is inside it, with
+/// whether each is a directory. Symlinks to directories aren't followed;
+/// hidden names are skipped unless `dotglob`.
+fn descendants(prefix: &str, dotglob: bool) -> Vec<(String, bool)> {
+    let dir = if prefix.is_empty() { Path::new(".") } else { Path::new(prefix) };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<(String, bool)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let is_dir = entry.file_type().ok()?.is_dir();
+            Some((entry.file_name().into_string().ok()?, is_dir))
+        })
+        .filter(|(name, _)| dotglob || !name.starts_with('.'))
+        .collect();
+    names.sort();
+    let mut found = Vec::new();
+    for (name, is_dir) in names {
+        let path = format!("{}{}", prefix, name);
+        found.push((path.clone(), is_dir));
+        if is_dir {
+            found.extend(descendants(&format!("{}/", path), dotglob));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str, _quoted: bool) -> Option<String> {
+        match name {
+            "x" => Some("5".to_string()),
+            "dir" => Some("/tmp".to_string()),
+            "1" => Some("one".to_string()),
+            "10" => Some("ten".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand_simple_and_braced() {
+        assert_eq!(expand_with("echo $x ${dir}/a $x$x", lookup), "echo 5 /tmp/a 55");
+    }
+
+    #[test]
+    fn test_expand_respects_single_quotes() {
+        assert_eq!(expand_with("echo '$x' \"$x\"", lookup), "echo '$x' \"5\"");
+    }
+
+    #[test]
+    fn test_expand_unset_and_literal_dollar() {
+        assert_eq!(expand_with("echo $missing. $ $2x ${", lookup), "echo . $ x ${");
+    }
+
+    #[test]
+    fn test_expand_ansi_c_quoting() {
+        assert_eq!(expand_with("echo $'line1\\nline2\\t tab' \"$'x'\" '$'", lookup), "echo 'line1\nline2\t tab' \"$'x'\" '$'");
+        assert_eq!(expand_with("echo $'\\x41\\101\\u00e9 it\\'s\\\\ \\q \\xz'", lookup), "echo 'AAé it'\"'\"'s\\ \\q \\xz'");
+        assert_eq!(expand_with("echo $'a\\0b' $'$x'", lookup), "echo 'a' '$x'");
+        assert_eq!(expand_with("echo $'open", lookup), "echo $'open");
+
+        let commands = crate::parser::parse_command(&expand_with("printf $'a b\\n' $'it\\'s'", lookup)).unwrap();
+        assert_eq!(commands[0].args, ["a b\n", "it's"]);
+    }
+
+    const IFS: Option<&str> = Some(" \t\n");
+
+    #[test]
+    fn test_split_fields_on_ifs() {
+        assert_eq!(split_fields("  a b\t\nc  ", IFS), ["a", "b", "c"]);
+        assert_eq!(split_fields("a:b::c:", Some(":")), ["a", "b", "", "c"]);
+        assert_eq!(split_fields(":a : b", Some(" :")), ["", "a", "b"]);
+        assert_eq!(split_fields("a b", Some("")), ["a b"]);
+        assert_eq!(split_fields("a b", None), ["a b"]);
+        assert!(split_fields("", None).is_empty() && split_fields("   ", IFS).is_empty());
+    }
+
+    #[test]
+    fn test_expand_splits_unquoted_values_on_ifs() {
+        let state = ShellState::new();
+        state.set_var("path", "/bin:/usr/local bin::");
+        state.set_var("list", "a\tb  c");
+        let words = |line: &str| crate::parser::parse_command(&expand_variables(line, &state)).unwrap()[0].args.clone();
+        assert_eq!(words("echo $list \"$list\""), ["a", "b", "c", "a\tb  c"]);
+        assert_eq!(words("echo $path"), ["/bin:/usr/local", "bin::"]);
+
+        state.set_var("IFS", ":");
+        assert_eq!(words("echo $path"), ["/bin", "/usr/local bin", ""]);
+        state.set_positional("x", vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(words("echo \"$*\""), ["a:b"]);
+
+        state.update_options(|options| options.nosplit = true);
+        assert_eq!(words("echo $list $missing"), ["a\tb  c"]);
+    }
+
+    #[test]
+    fn test_expand_keeps_assignment_values_whole() {
+        let state = ShellState::new();
+        state.set_var("y", "c *");
+        assert_eq!(expand_variables("z=$y", &state), "z=\"c *\"");
+        let commands = crate::parser::parse_command(&expand_variables("a=1 z=${y} env $y", &state)).unwrap();
+        assert_eq!(commands[0].env, [("a".to_string(), "1".to_string()), ("z".to_string(), "c *".to_string())]);
+        assert_eq!((commands[0].args.as_slice(), commands[0].globs.len()), (["c".to_string(), "*".to_string()].as_slice(), 1));
+        // Past the program, and after a word that isn't one, it is an ordinary word
+        let words = |line: &str| crate::parser::parse_command(&expand_variables(line, &state)).unwrap()[0].args.clone();
+        assert_eq!(words("echo z=$y"), ["z=c", "*"]);
+        assert_eq!(words("x z=$y"), ["z=c", "*"]);
+        assert_eq!(expand_variables("ls; z=$y | w=$y", &state), "ls; z=\"c *\" | w=\"c *\"");
+    }
+
+    #[test]
+    fn test_substitute_commands_quotes_output() {
+        let mut ran = Vec::new();
+        let mut run = |command: &str| {
+            ran.push(command.to_string());
+            Ok(match command {
+                "date +%s" => "1700000000\n".to_string(),
+                "cat notes" => "it's > x | y\n\n".to_string(),
+                _ => String::new(),
+            })
+        };
+        let line = "echo log-$(date +%s).txt \"$(cat notes)\" $(cat notes) '$(date +%s)' $(echo \"a)\" $(b))";
+        assert_eq!(
+            substitute_commands(line, IFS, &mut run).unwrap(),
+            "echo log-1700000000.txt \"\"'it'\"'\"'s > x | y'\"\" it\"'\"s '>' x '|' y '$(date +%s)' "
+        );
+
+        let line = "echo \"$(cat notes)\" $(cat notes)";
+        let words = crate::parser::parse_command(&substitute_commands(line, IFS, &mut run).unwrap()).unwrap();
+        assert_eq!(words[0].args, ["it's > x | y", "it's", ">", "x", "|", "y"]);
+        assert!(words[0].output_redirect.is_none());
+        let words = crate::parser::parse_command(&substitute_commands("echo $(cat notes)", None, &mut run).unwrap()).unwrap();
+        assert_eq!(words[0].args, ["it's > x | y"]);
+        assert!(substitute_commands("echo $(date", IFS, &mut run).is_err());
+        // An assignment's value stays one word
+        let words = crate::parser::parse_command(&substitute_commands("x=$(cat notes) env $(date +%s)", IFS, &mut run).unwrap()).unwrap();
+        assert_eq!(words[0].env, [("x".to_string(), "it's > x | y".to_string())]);
+        assert_eq!(ran, ["date +%s", "cat notes", "cat notes", "echo \"a)\" $(b)", "cat notes", "cat notes", "cat notes", "cat notes", "date +%s"]);
+    }
+
+    #[test]
+    fn test_expand_globs() {
+        let root = env::temp_dir().join(format!("shell-t-glob-{}", std::process::id()));
+        for file in ["src/main.rs", "src/lib.rs", "src/a*b.rs", "src/.hidden.rs", "src/bin/tool.rs", "docs/guide.md"] {
+            fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+            fs::write(root.join(file), "").unwrap();
+        }
+        let dir = root.display().to_string();
+        assert_eq!(glob(&format!("{}/src/*.rs", dir), false), [format!("{}/src/a*b.rs", dir), format!("{}/src/lib.rs", dir), format!("{}/src/main.rs", dir)]);
+        assert_eq!(glob(&format!("{}/*/", dir), false), [format!("{}/docs/", dir), format!("{}/src/", dir)]);
+        assert_eq!(glob(&format!("{}/*/guide.md", dir), false), [format!("{}/docs/guide.md", dir)]);
+        assert_eq!(glob(&format!("{}/src/.h*", dir), false), [format!("{}/src/.hidden.rs", dir)]);
+        assert!(glob(&format!("{}/src/*.py", dir), false).is_empty());
+        assert_eq!(glob(&format!("{}/src/.*", dir), false), glob(&format!("{}/src/.h*", dir), true));
+        assert_eq!(glob(&format!("{}/src/*", dir), true).len(), 5);
+
+        // `**` is any number of directories, or as the last part everything below
+        assert_eq!(glob(&format!("{}/**/*.rs", dir), false)[..2], [format!("{}/src/a*b.rs", dir), format!("{}/src/bin/tool.rs", dir)]);
+        assert_eq!(glob(&format!("{}/**/*.rs", dir), true).len(), 5);
+        assert_eq!(glob(&format!("{}/src/**", dir), false), ["a*b.rs", "bin", "bin/tool.rs", "lib.rs", "main.rs"].map(|name| format!("{}/src/{}", dir, name)));
+        assert_eq!(glob(&format!("{}/**/", dir), false), ["docs/", "src/", "src/bin/"].map(|name| format!("{}/{}", dir, name)));
+
+        // Quoted wildcards match only themselves
+        let line = format!("ls {dir}/src/\"a*\"* '*.md' {dir}/*.py");
+        let mut options = ShellOptions::default();
+        let commands = expand_globs(crate::parser::parse_command(&line).unwrap(), &options).unwrap();
+        assert_eq!(commands[0].args, [format!("{}/src/a*b.rs", dir), "*.md".to_string(), format!("{}/*.py", dir)]);
+        options.nullglob = true;
+        assert_eq!(expand_globs(crate::parser::parse_command(&line).unwrap(), &options).unwrap()[0].args.len(), 2);
+        options.failglob = true;
+        assert!(expand_globs(crate::parser::parse_command(&line).unwrap(), &options).is_err());
+        options.noglob = true;
+        assert_eq!(expand_globs(crate::parser::parse_command(&line).unwrap(), &options).unwrap()[0].args[2], format!("{}/*.py", dir));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_expand_positional_params() {
+        assert_eq!(expand_with("echo $1x $10 ${10}", lookup), "echo onex one0 ten");
+
+        let state = ShellState::new();
+        state.set_positional("build.sht", vec!["a b".to_string(), "c".to_string()]);
+        assert_eq!(expand_variables("$0 $# $* $@", &state), "build.sht 2 a b c a b c");
+        assert_eq!(expand_variables("\"$@\" '$1'", &state), "\"a b\" \"c\" '$1'");
+
+        state.set_last_status(2);
+        assert_eq!(expand_variables("echo $? ${?}x \"$!\" '$$'", &state), "echo 2 2x \"\" '$$'");
+    }
+
+    #[test]
+    fn test_expand_array_elements() {
+        let state = ShellState::new();
+        state.set_pipe_status(vec![2, 0, 1]);
+        state.set_var("x", "one");
+        assert_eq!(
+            expand_variables("${PIPESTATUS[0]} ${PIPESTATUS[2]} \"${PIPESTATUS[@]}\" $PIPESTATUS ${PIPESTATUS[3]}.", &state),
+            "2 1 \"2 0 1\" 2 0 1 ."
+        );
+        assert_eq!(expand_variables("${x[0]} ${x[1]}. ${x[*]}", &state), "one . one");
+        // Not an element, so not expanded
+        assert_eq!(expand_variables("${x[a]} ${x[]} $x[0]", &state), "${x[a]} ${x[]} one[0]");
+    }
+
+    #[test]
+    fn test_expand_keeps_quotes_in_values_literal() {
+        let state = ShellState::new();
+        state.set_var("said", "he said \"it's $(date)\" > x");
+        state.set_var("files", "*.rs src/*.md");
+        let words = |line: &str| crate::parser::parse_command(&expand_variables(line, &state)).unwrap()[0].args.clone();
+        assert_eq!(words("echo \"$said\""), ["he said \"it's $(date)\" > x"]);
+        assert_eq!(words("echo $said"), ["he", "said", "\"it's", "$(date)\"", ">", "x"]);
+        assert_eq!(words("echo '$said'x\"${said}\""), ["$saidxhe said \"it's $(date)\" > x"]);
+        let commands = crate::parser::parse_command(&expand_variables("ls $files", &state)).unwrap();
+        assert_eq!(commands[0].args, ["*.rs", "src/*.md"]);
+        assert_eq!(commands[0].globs.len(), 2);
+
+        state.set_var("path", "C:\\a b\\$x");
+        assert_eq!(words("echo $path \"$path\""), ["C:\\a", "b\\$x", "C:\\a b\\$x"]);
+    }
+
+    #[test]
+    fn test_expand_leaves_escaped_characters() {
+        let lookup = |name: &str, _| (name == "HOME").then(|| "/root".to_string());
+        assert_eq!(expand_with(r#"echo \$HOME "\$HOME" "a\"$HOME" '\' $HOME"#, lookup), r#"echo \$HOME "\$HOME" "a\"/root" '\' /root"#);
+        assert_eq!(expand_with(r"echo \\$HOME", lookup), r"echo \\/root");
+
+        let mut ran = Vec::new();
+        let line = substitute_commands(r#"echo \$(date) "\$(date)" \\$(date)"#, None, |command| {
+            ran.push(command.to_string());
+            Ok("now".to_string())
+        })
+        .unwrap();
+        assert_eq!((line.as_str(), ran.len()), (r#"echo \$(date) "\$(date)" \\now"#, 1));
+    }
+}
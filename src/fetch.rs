@@ -0,0 +1,243 @@
+use std::fs;
+use std::io::Read;
+use std::time::Duration;
+
+use crate::completions::wildcard_match;
+use crate::config::{Config, FetchConfig};
+use crate::error::{SecurityError, ShellError, ShellResult};
+use crate::remote;
+use crate::security::validation;
+
+/// An HTTP request made by the `fetch` builtin
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    /// `-d DATA`, or `-d @FILE` for a file's contents
+    pub data: Option<String>,
+    /// `-o FILE`; the body goes to stdout without it
+    pub output: Option<String>,
+    /// `--sha256 HASH` the body must match before it is written anywhere
+    pub sha256: Option<String>,
+}
+
+/// Status and body of a response
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub status_text: String,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Parse `fetch` arguments:
+    /// `[-X METHOD] [-H 'NAME: VALUE']... [-d DATA|@FILE] [-o FILE] [--sha256 HASH] URL`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let usage = "fetch: usage: fetch [-X METHOD] [-H 'NAME: VALUE']... [-d DATA|@FILE] [-o FILE] [--sha256 HASH] URL";
+        let mut request = Self {
+            method: String::new(),
+            url: String::new(),
+            headers: Vec::new(),
+            data: None,
+            output: None,
+            sha256: None,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().cloned().ok_or_else(|| usage.to_string());
+            match arg.as_str() {
+                "-X" => request.method = value()?.to_ascii_uppercase(),
+                "-H" => {
+                    let header = value()?;
+                    let (name, content) = header
+                        .split_once(':')
+                        .filter(|(name, _)| !name.trim().is_empty())
+                        .ok_or_else(|| format!("fetch: {}: expected NAME: VALUE", header))?;
+                    request.headers.push((name.trim().to_string(), content.trim().to_string()));
+                }
+                "-d" => request.data = Some(value()?),
+                "-o" => request.output = Some(value()?),
+                "--sha256" => request.sha256 = Some(value()?.to_ascii_lowercase()),
+                _ if arg.starts_with('-') || !request.url.is_empty() => return Err(usage.to_string()),
+                _ => request.url = arg.clone(),
+            }
+        }
+        if request.url.is_empty() {
+            return Err(usage.to_string());
+        }
+        if request.method.is_empty() {
+            request.method = if request.data.is_some() { "POST" } else { "GET" }.to_string();
+        }
+        Ok(request)
+    }
+
+    /// Refuse `fetch` itself unless the command policy allows it, URLs
+    /// outside `fetch.allowed_hosts` (all of them while it is empty) and
+    /// files the path policy refuses
+    pub fn check(&self, config: &Config) -> ShellResult<()> {
+        let security = &config.security;
+        if !security.allowed_commands.is_empty() && !security.allowed_commands.contains("fetch") {
+            return Err(ShellError::SecurityViolation("Command not in whitelist: fetch".to_string()));
+        }
+        if security.blocked_commands.contains("fetch") {
+            return Err(ShellError::SecurityViolation("Command blacklisted: fetch".to_string()));
+        }
+        let host = host(&self.url)
+            .ok_or_else(|| ShellError::from(SecurityError::InvalidInput(format!("Not an http(s) URL: {}", self.url))))?;
+        let allowed = &config.fetch.allowed_hosts;
+        if allowed.is_empty() {
+            return Err(ShellError::SecurityViolation("fetch.allowed_hosts is empty; list the hosts fetch may contact".to_string()));
+        }
+        if !allowed.iter().any(|pattern| wildcard_match(pattern, &host)) {
+            return Err(ShellError::SecurityViolation(format!("Host not in fetch.allowed_hosts: {}", host)));
+        }
+        if let Some(file) = self.data.as_deref().and_then(|data| data.strip_prefix('@')) {
+            validation::validate_file_path(file, config)?;
+        }
+        if let Some(file) = &self.output {
+            validation::validate_file_path(file, config)?;
+        }
+        Ok(())
+    }
+
+    /// Send the request. HTTP error statuses are responses too; only
+    /// connection failures and oversized bodies are errors. Redirects are
+    /// returned as they are rather than followed, since the host they point
+    /// at was never checked against `fetch.allowed_hosts`.
+    pub fn send(&self, config: &FetchConfig) -> ShellResult<Response> {
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(config.timeout_secs)).redirects(0).build();
+        let mut request = agent.request(&self.method, &self.url);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        let result = match &self.data {
+            Some(data) => {
+                let body = match data.strip_prefix('@') {
+                    Some(file) => fs::read(file).map_err(|e| ShellError::Redirect { path: file.to_string(), source: e })?,
+                    None => data.clone().into_bytes(),
+                };
+                request.send_bytes(&body)
+            }
+            None => request.call(),
+        };
+        let response = match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => return Err(ShellError::CommandExecution(format!("fetch: {}: {}", self.url, e))),
+        };
+
+        let (status, status_text) = (response.status(), response.status_text().to_string());
+        let limit = config.max_size_kb * 1024;
+        let mut body = Vec::new();
+        response.into_reader().take(limit + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > limit {
+            return Err(SecurityError::ResourceLimitExceeded(format!(
+                "response from {} is larger than {} KB",
+                self.url, config.max_size_kb
+            ))
+            .into());
+        }
+        if let Some(pin) = &self.sha256 {
+            let actual = remote::sha256_hex(&body);
+            if actual != *pin {
+                return Err(SecurityError::PermissionDenied(format!(
+                    "checksum mismatch for {}: expected {}, got {}",
+                    self.url, pin, actual
+                ))
+                .into());
+            }
+        }
+        Ok(Response { status, status_text, body })
+    }
+}
+
+/// Host part of an http(s) URL, parsed the way the HTTP client parses it
+/// so the host checked is the host connected to
+fn host(url: &str) -> Option<String> {
+    let url = url::Url::parse(url).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    match url.host()? {
+        url::Host::Domain(domain) => Some(domain.to_string()),
+        url::Host::Ipv4(address) => Some(address.to_string()),
+        url::Host::Ipv6(address) => Some(address.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_and_check() {
+        let request = Request::parse(&args("-H X-Token:abc -d name=x -o out.json https://api.example.com:8443/v1?q")).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.headers, [("X-Token".to_string(), "abc".to_string())]);
+        assert_eq!(request.output.as_deref(), Some("out.json"));
+        assert!(Request::parse(&args("-X")).is_err());
+        assert!(Request::parse(&args("-H novalue https://a")).is_err());
+        assert!(Request::parse(&args("https://a https://b")).is_err());
+
+        assert_eq!(host("https://user@api.example.com:8443/v1?q").as_deref(), Some("api.example.com"));
+        assert_eq!(host("http://[::1]:80/").as_deref(), Some("::1"));
+        assert_eq!(host("ftp://example.com"), None);
+        // A backslash ends the authority, so the host is the one before it
+        assert_eq!(host("http://evil.com\\@good.example.com/").as_deref(), Some("evil.com"));
+
+        // Under a whitelist, fetch must be on it, and no host is allowed until listed
+        let mut config = Config::default();
+        config.fetch.allowed_hosts = vec!["*".to_string()];
+        assert!(request.check(&config).is_err());
+        config.security.allowed_commands.insert("fetch".to_string());
+        assert!(request.check(&config).is_ok());
+        config.fetch.allowed_hosts.clear();
+        assert!(request.check(&config).is_err());
+        config.fetch.allowed_hosts = vec!["*.internal".to_string()];
+        assert!(request.check(&config).is_err());
+        config.fetch.allowed_hosts = vec!["*.example.com".to_string()];
+        assert!(request.check(&config).is_ok());
+        let escaping = Request { output: Some("../out".to_string()), ..request };
+        assert!(escaping.check(&config).is_err());
+        let smuggled = Request::parse(&args("http://evil.com\\@api.example.com/")).unwrap();
+        assert!(smuggled.check(&config).is_err());
+    }
+
+    #[test]
+    fn test_send_reports_status_and_verifies_checksum() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/items", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for (i, stream) in listener.incoming().take(4).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let (status, body) = match i {
+                    1 => ("404 Not Found", "missing"),
+                    3 => ("302 Found\r\nLocation: http://evil.example.com/", ""),
+                    _ => ("200 OK", "hello"),
+                };
+                let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+            }
+        });
+
+        let config = FetchConfig::default();
+        let request = Request::parse(&[url]).unwrap();
+        let response = request.send(&config).unwrap();
+        assert_eq!((response.status, response.body.as_slice()), (200, &b"hello"[..]));
+        let response = request.send(&config).unwrap();
+        assert_eq!((response.status, response.status_text.as_str()), (404, "Not Found"));
+
+        let pinned = Request { sha256: Some("0".repeat(64)), ..request.clone() };
+        assert!(pinned.send(&config).is_err());
+        // Redirects come back as responses instead of being followed
+        assert_eq!(request.send(&config).unwrap().status, 302);
+    }
+}
@@ -0,0 +1,698 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, HistoryConfig};
+
+/// One recorded command, stored in the history file as a JSON line.
+/// Lines written by older versions or other shells lack the metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub command: String,
+    /// Unix time the command started, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Working directory the command started in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// Exit status of the line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<i32>,
+}
+
+impl Entry {
+    /// An entry with no metadata
+    pub fn new(command: &str) -> Self {
+        Self { command: command.to_string(), time: None, duration_ms: None, cwd: None, status: None }
+    }
+
+    /// Parse a line of the history file; lines that are not JSON are taken
+    /// as a plain command, as written by other shells
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            return None;
+        }
+        serde_json::from_str(line).ok().or_else(|| Some(Self::new(line)))
+    }
+
+    /// Whether the command ran and exited with a non-zero status
+    pub fn failed(&self) -> bool {
+        self.status.is_some_and(|status| status != 0)
+    }
+
+    /// Listing line for `history`, numbered `number`; `verbose` adds when,
+    /// for how long and where the command ran, and how it exited
+    pub fn line(&self, number: usize, verbose: bool) -> String {
+        if !verbose {
+            return format!("{:5}  {}", number, self.command);
+        }
+        let time = self
+            .time
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string());
+        let duration = self.duration_ms.map(|ms| match ms {
+            0..1000 => format!("{}ms", ms),
+            _ => format!("{:.1}s", ms as f64 / 1000.0),
+        });
+        format!(
+            "{:5}  {:19}  {:>7}  {:>3}  {}  {}",
+            number,
+            time.as_deref().unwrap_or("-"),
+            duration.as_deref().unwrap_or("-"),
+            self.status.map_or("-".to_string(), |status| status.to_string()),
+            self.cwd.as_ref().map_or("-".into(), |cwd| cwd.display().to_string()),
+            self.command
+        )
+    }
+}
+
+/// What this session knows of the history file
+#[derive(Debug, Default)]
+struct Store {
+    entries: Vec<Entry>,
+    /// Bytes of the file already read into `entries`
+    offset: u64,
+    /// Identity of the file `offset` refers to; a compaction replaces the file
+    file_id: Option<(u64, u64)>,
+}
+
+/// Command history shared by concurrent sessions.
+///
+/// Each command is appended to the history file as soon as it is entered,
+/// under an exclusive advisory lock. Before appending, lines that other
+/// sessions wrote since the last read are merged in, so every session sees
+/// the others' commands and none overwrites them on exit. When the file grows
+/// well past `max_entries` it is rewritten with only the newest entries.
+#[derive(Debug)]
+pub struct History {
+    path: Option<PathBuf>,
+    config: HistoryConfig,
+    store: Mutex<Store>,
+}
+
+impl History {
+    /// History kept in memory only, for this session
+    pub fn new(config: &HistoryConfig) -> Self {
+        Self { path: None, config: config.clone(), store: Mutex::default() }
+    }
+
+    /// History backed by the file `[history]` names. The file is not touched
+    /// until the history is first read or written.
+    pub fn open(config: &HistoryConfig) -> Self {
+        let path = match config.enabled {
+            true => config.file.clone().or_else(|| config::data_dir().map(|dir| dir.join("history"))),
+            false => None,
+        };
+        Self { path, ..Self::new(config) }
+    }
+
+    /// Record a command as typed. With `ignore_space`, a command typed with a
+    /// leading space is left out; with `ignore_dups`, so is one repeating the
+    /// newest entry of any session.
+    pub fn add(&self, mut entry: Entry) {
+        if self.config.ignore_space && entry.command.starts_with(' ') {
+            return;
+        }
+        entry.command = entry.command.trim().to_string();
+        if entry.command.is_empty() {
+            return;
+        }
+
+        let mut store = self.store.lock().unwrap();
+        let file = self.lock_and_merge(&mut store);
+        if self.config.ignore_dups && store.entries.last().is_some_and(|last| last.command == entry.command) {
+            return;
+        }
+        self.record(&mut store, file, vec![entry]);
+    }
+
+    /// Add entries converted from another shell's history, after the current
+    /// ones and as they are; returns how many were added
+    pub fn import(&self, entries: Vec<Entry>) -> usize {
+        let entries: Vec<Entry> = entries.into_iter().filter(|entry| !entry.command.trim().is_empty()).collect();
+        let count = entries.len();
+        let mut store = self.store.lock().unwrap();
+        let file = self.lock_and_merge(&mut store);
+        self.record(&mut store, file, entries);
+        count
+    }
+
+    /// Lock the history file, if there is one, and merge what other sessions
+    /// added. `None` when there is no file or it can't be read.
+    fn lock_and_merge(&self, store: &mut Store) -> Option<File> {
+        let path = self.path.as_deref()?;
+        let locked = lock(path).and_then(|mut file| merge(&mut file, store).map(|_| file));
+        locked.map_err(|e| tracing::debug!(path = %path.display(), error = %e, "could not read history")).ok()
+    }
+
+    /// Add `entries` to the store, appending them to the locked `file` if any
+    fn record(&self, store: &mut Store, file: Option<File>, entries: Vec<Entry>) {
+        match (&self.path, file) {
+            (Some(path), Some(mut file)) => {
+                if let Err(e) = self.append(path, &mut file, store, entries) {
+                    tracing::debug!(path = %path.display(), error = %e, "could not write history");
+                }
+            }
+            _ => store.entries.extend(entries),
+        }
+        let excess = store.entries.len().saturating_sub(self.compact_at());
+        store.entries.drain(..excess);
+    }
+
+    /// The newest `max_entries` commands, oldest first, including those
+    /// other sessions have recorded so far
+    pub fn entries(&self) -> Vec<Entry> {
+        let mut store = self.store.lock().unwrap();
+        self.lock_and_merge(&mut store);
+        self.visible(&store.entries)
+    }
+
+    /// The newest `max_entries` of `entries`; with `erase_dups`, only the
+    /// newest copy of each command counts
+    fn visible(&self, entries: &[Entry]) -> Vec<Entry> {
+        let mut kept: Vec<&Entry> = if self.config.erase_dups {
+            let mut seen = HashSet::new();
+            let mut unique: Vec<&Entry> = entries.iter().rev().filter(|entry| seen.insert(&entry.command)).collect();
+            unique.reverse();
+            unique
+        } else {
+            entries.iter().collect()
+        };
+        let skip = kept.len().saturating_sub(self.config.max_entries);
+        kept.drain(..skip);
+        kept.into_iter().cloned().collect()
+    }
+
+    /// Number of entries the file may hold before it is compacted
+    fn compact_at(&self) -> usize {
+        self.config.max_entries + self.config.max_entries / 2
+    }
+
+    /// Append `entries` to the locked, freshly merged file, compacting it
+    /// once it has grown too long
+    fn append(&self, path: &Path, file: &mut File, store: &mut Store, entries: Vec<Entry>) -> io::Result<()> {
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        store.entries.extend(entries);
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(lines.as_bytes())?;
+        store.offset += lines.len() as u64;
+
+        if store.entries.len() > self.compact_at() {
+            let kept = self.visible(&store.entries);
+            let (offset, file_id) = compact(path, &kept)?;
+            store.offset = offset;
+            store.file_id = file_id;
+            store.entries = kept;
+        }
+        Ok(())
+    }
+}
+
+/// Characters ending a `!prefix` event designator
+const DESIGNATOR_END: &[char] = &[';', '|', '&', '(', ')', '<', '>', '"', '\''];
+
+/// Apply bash-style history expansion to `line`, with `entries` numbered from
+/// 1 as `history` lists them. Returns `None` when the line has nothing to
+/// expand.
+///
+/// `!!` is the previous command, `!N` entry N, `!-N` the Nth previous,
+/// `!prefix` the newest command starting with `prefix` and `!?text?` the
+/// newest containing `text`. A line starting `^old^new` repeats the previous
+/// command with the first `old` replaced. `!` is left alone in single quotes,
+/// after a backslash or `$`, and before a blank, `=` or `(`.
+pub fn expand(line: &str, entries: &[Entry]) -> Result<Option<String>, String> {
+    if let Some(rest) = line.strip_prefix('^') {
+        let (old, new) = rest.split_once('^').ok_or_else(|| format!("{}: bad substitution", line))?;
+        let new = new.strip_suffix('^').unwrap_or(new);
+        let previous = &entries.last().ok_or_else(|| "!!: event not found".to_string())?.command;
+        if old.is_empty() || !previous.contains(old) {
+            return Err(format!("^{}^{}: substitution failed", old, new));
+        }
+        return Ok(Some(previous.replacen(old, new, 1)));
+    }
+
+    let mut expanded = String::with_capacity(line.len());
+    let mut changed = false;
+    let mut in_single = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\'' => in_single = !in_single,
+            '\\' if !in_single => {
+                expanded.push(c);
+                if let Some((_, escaped)) = chars.next() {
+                    expanded.push(escaped);
+                }
+                continue;
+            }
+            '!' if !in_single && !line[..index].ends_with('$') => {
+                let rest = &line[index + 1..];
+                if !rest.starts_with(|c: char| c.is_whitespace() || c == '=' || c == '(') && !rest.is_empty() {
+                    let (designator, command) = event(rest, entries)?;
+                    expanded.push_str(&command.command);
+                    for _ in 0..designator.chars().count() {
+                        chars.next();
+                    }
+                    changed = true;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        expanded.push(c);
+    }
+    Ok(changed.then_some(expanded))
+}
+
+/// Resolve the event designator at the start of `rest` (after the `!`),
+/// returning the designator text and the entry it names
+fn event<'a>(rest: &'a str, entries: &'a [Entry]) -> Result<(&'a str, &'a Entry), String> {
+    let not_found = |designator: &str| format!("!{}: event not found", designator);
+    if rest.starts_with('!') {
+        return entries.last().map(|entry| (&rest[..1], entry)).ok_or_else(|| not_found("!"));
+    }
+    if let Some(search) = rest.strip_prefix('?') {
+        let (text, designator) = match search.find('?') {
+            Some(end) => (&search[..end], &rest[..end + 2]),
+            None => (search, rest),
+        };
+        let entry = entries.iter().rev().find(|entry| entry.command.contains(text));
+        return entry.map(|entry| (designator, entry)).ok_or_else(|| not_found(designator));
+    }
+
+    let end = rest.find(|c: char| c.is_whitespace() || DESIGNATOR_END.contains(&c)).unwrap_or(rest.len());
+    let designator = &rest[..end];
+    let digits = designator.strip_prefix('-').unwrap_or(designator);
+    let entry = if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        let n: usize = digits.parse().map_err(|_| not_found(designator))?;
+        let index = match designator.starts_with('-') {
+            true => entries.len().checked_sub(n),
+            false => n.checked_sub(1),
+        };
+        index.and_then(|index| entries.get(index))
+    } else {
+        entries.iter().rev().find(|entry| entry.command.starts_with(designator))
+    };
+    entry.map(|entry| (designator, entry)).ok_or_else(|| not_found(designator))
+}
+
+/// zsh's escape byte in history files: it precedes bytes zsh uses
+/// internally, XORed with 0x20
+const ZSH_META: u8 = 0x83;
+
+/// Whether zsh escapes `byte` in its history file
+fn zsh_meta(byte: u8) -> bool {
+    byte == 0 || (ZSH_META..=0xa2).contains(&byte)
+}
+
+/// History file formats `history import` and `history export` convert between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One command per line; with `HISTTIMEFORMAT` set, each after a `#SECONDS` line
+    Bash,
+    /// zsh, plain or with `EXTENDED_HISTORY` `: START:ELAPSED;command` lines
+    Zsh,
+    /// shell-t's own history file, one JSON entry per line
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bash" => Some(Format::Bash),
+            "zsh" => Some(Format::Zsh),
+            "json" | "shell-t" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    /// Guess the format of a history file from its contents
+    pub fn detect(data: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(data);
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        if lines.clone().next().is_some_and(|line| serde_json::from_str::<Entry>(line).is_ok()) {
+            Format::Json
+        } else if lines.any(|line| zsh_extended(line).is_some()) {
+            Format::Zsh
+        } else {
+            Format::Bash
+        }
+    }
+}
+
+/// Split a zsh `: START:ELAPSED;command` line
+fn zsh_extended(line: &str) -> Option<(i64, u64, &str)> {
+    let (stamp, command) = line.strip_prefix(": ")?.split_once(';')?;
+    let (start, elapsed) = stamp.split_once(':')?;
+    Some((start.trim().parse().ok()?, elapsed.trim().parse().ok()?, command))
+}
+
+/// Read the entries of a history file written by another shell
+pub fn import(data: &[u8], format: Format) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    match format {
+        Format::Json => entries.extend(String::from_utf8_lossy(data).lines().filter_map(Entry::parse)),
+        Format::Bash => {
+            let text = String::from_utf8_lossy(data);
+            let timestamped = text.lines().next().is_some_and(|line| bash_timestamp(line).is_some());
+            for line in text.lines() {
+                match bash_timestamp(line) {
+                    Some(time) => entries.push(Entry { time: Some(time), ..Entry::new("") }),
+                    // With timestamps, a command runs until the next one
+                    None if timestamped => match entries.last_mut() {
+                        Some(entry) if !entry.command.is_empty() => {
+                            entry.command.push('\n');
+                            entry.command.push_str(line);
+                        }
+                        Some(entry) => entry.command = line.to_string(),
+                        None => entries.push(Entry::new(line)),
+                    },
+                    None => entries.push(Entry::new(line)),
+                }
+            }
+        }
+        Format::Zsh => {
+            let mut bytes = Vec::with_capacity(data.len());
+            let mut iter = data.iter();
+            while let Some(&byte) = iter.next() {
+                match byte {
+                    ZSH_META => bytes.extend(iter.next().map(|next| next ^ 0x20)),
+                    _ => bytes.push(byte),
+                }
+            }
+            let text = String::from_utf8_lossy(&bytes);
+            let mut pending: Option<Entry> = None;
+            for line in text.lines() {
+                let entry = match pending.take() {
+                    Some(mut entry) => {
+                        entry.command.push('\n');
+                        entry.command.push_str(line);
+                        entry
+                    }
+                    None => match zsh_extended(line) {
+                        Some((start, elapsed, command)) => Entry {
+                            time: Some(start),
+                            duration_ms: Some(elapsed * 1000),
+                            ..Entry::new(command)
+                        },
+                        None => Entry::new(line),
+                    },
+                };
+                // A trailing backslash continues the command on the next line
+                match entry.command.strip_suffix('\\') {
+                    Some(command) => pending = Some(Entry { command: command.to_string(), ..entry }),
+                    None => entries.push(entry),
+                }
+            }
+            entries.extend(pending);
+        }
+    }
+    entries.retain(|entry| !entry.command.trim().is_empty());
+    entries
+}
+
+/// `#SECONDS` timestamp line of a bash history file
+fn bash_timestamp(line: &str) -> Option<i64> {
+    let digits = line.strip_prefix('#')?;
+    (!digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())).then(|| digits.parse().ok())?
+}
+
+/// Write entries as a history file another shell reads
+pub fn export(entries: &[Entry], format: Format) -> Vec<u8> {
+    let mut out = Vec::new();
+    for entry in entries {
+        match format {
+            Format::Json => {
+                out.extend(serde_json::to_vec(entry).unwrap_or_default());
+                out.push(b'\n');
+            }
+            Format::Bash => {
+                if let Some(time) = entry.time {
+                    out.extend(format!("#{}\n", time).bytes());
+                }
+                out.extend(entry.command.bytes());
+                out.push(b'\n');
+            }
+            Format::Zsh => {
+                let command = entry.command.replace('\n', "\\\n");
+                let line = match entry.time {
+                    Some(time) => format!(": {}:{};{}\n", time, entry.duration_ms.unwrap_or(0) / 1000, command),
+                    None => format!("{}\n", command),
+                };
+                for byte in line.bytes() {
+                    match zsh_meta(byte) {
+                        true => out.extend([ZSH_META, byte ^ 0x20]),
+                        false => out.push(byte),
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Open the history file and take an exclusive lock on it. A compaction in
+/// another session may replace the file while we wait; then lock the new one.
+fn lock(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    loop {
+        let file = private().read(true).append(true).open(path)?;
+        file.lock()?;
+        let locked = file.metadata().ok().map(|metadata| file_id(&metadata));
+        if fs::metadata(path).is_ok_and(|current| Some(file_id(&current)) == locked) {
+            return Ok(file);
+        }
+    }
+}
+
+/// Options creating a file only the user can read: history holds whatever was typed
+fn private() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+/// Read the lines appended to `file` since the last merge into `store`
+fn merge(file: &mut File, store: &mut Store) -> io::Result<()> {
+    let metadata = file.metadata()?;
+    let id = file_id(&metadata);
+    // Compacted or truncated by someone else: start over
+    if store.file_id.is_some_and(|known| Some(known) != id) || metadata.len() < store.offset {
+        tracing::debug!("history file was replaced; reloading");
+        store.entries.clear();
+        store.offset = 0;
+    }
+    store.file_id = id;
+
+    file.seek(SeekFrom::Start(store.offset))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    // Only whole lines; a writer that died mid-line leaves the rest for later
+    let complete = data.iter().rposition(|&byte| byte == b'\n').map_or(0, |end| end + 1);
+    store.entries.extend(String::from_utf8_lossy(&data[..complete]).lines().filter_map(Entry::parse));
+    store.offset += complete as u64;
+    Ok(())
+}
+
+/// Replace the file with `entries` in a single rename, returning its new
+/// length and identity
+fn compact(path: &Path, entries: &[Entry]) -> io::Result<(u64, Option<(u64, u64)>)> {
+    let mut data = String::new();
+    for entry in entries {
+        data.push_str(&serde_json::to_string(entry)?);
+        data.push('\n');
+    }
+    let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+    private().write(true).truncate(true).open(&partial)?.write_all(data.as_bytes())?;
+    fs::rename(&partial, path)?;
+    let id = fs::metadata(path).ok().and_then(|metadata| file_id(&metadata));
+    Ok((data.len() as u64, id))
+}
+
+/// Device and inode of a file, to notice it being replaced
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(name: &str, max_entries: usize) -> HistoryConfig {
+        let dir = std::env::temp_dir().join(format!("shell-t-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        HistoryConfig { file: Some(dir.join("history")), max_entries, ..HistoryConfig::default() }
+    }
+
+    fn commands(history: &History) -> Vec<String> {
+        history.entries().into_iter().map(|entry| entry.command).collect()
+    }
+
+    #[test]
+    fn test_sessions_share_history() {
+        let config = test_config("shared", 100);
+        let first = History::open(&config);
+        let second = History::open(&config);
+        first.add(Entry::new("echo one"));
+        second.add(Entry::new("echo two"));
+        first.add(Entry::new("echo 'three\nlines'"));
+        assert_eq!(commands(&first), ["echo one", "echo two", "echo 'three\nlines'"]);
+        assert_eq!(commands(&second), commands(&first));
+        // A new session starts with everything recorded so far
+        assert_eq!(commands(&History::open(&config)).len(), 3);
+
+        let _ = fs::remove_dir_all(config.file.unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn test_compaction_keeps_newest_entries() {
+        let config = test_config("compact", 4);
+        let path = config.file.clone().unwrap();
+        let first = History::open(&config);
+        let second = History::open(&config);
+        second.add(Entry::new("from second"));
+        for n in 0..6 {
+            first.add(Entry::new(&format!("cmd {}", n)));
+        }
+        // Compacted down to max_entries once it passed 6 lines
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+        assert_eq!(commands(&first), ["cmd 2", "cmd 3", "cmd 4", "cmd 5"]);
+        // The other session notices the file was replaced
+        second.add(Entry::new("after"));
+        assert_eq!(commands(&second), ["cmd 3", "cmd 4", "cmd 5", "after"]);
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_ignored_and_duplicate_commands() {
+        let config = test_config("dups", 100);
+        let first = History::open(&config);
+        let second = History::open(&config);
+        first.add(Entry::new("make"));
+        second.add(Entry::new("make"));
+        first.add(Entry::new(" export TOKEN=secret"));
+        first.add(Entry::new("ls"));
+        first.add(Entry::new("make  "));
+        assert_eq!(commands(&first), ["make", "ls", "make"]);
+
+        let erasing = History::open(&HistoryConfig { erase_dups: true, ignore_space: false, ..config.clone() });
+        erasing.add(Entry::new(" ls"));
+        assert_eq!(commands(&erasing), ["make", "ls"]);
+
+        let _ = fs::remove_dir_all(config.file.unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn test_metadata_round_trips() {
+        let config = test_config("metadata", 100);
+        let entry = Entry {
+            command: "make test".to_string(),
+            time: Some(1_700_000_000),
+            duration_ms: Some(1500),
+            cwd: Some(PathBuf::from("/src/app")),
+            status: Some(2),
+        };
+        History::open(&config).add(entry.clone());
+        let entries = History::open(&config).entries();
+        assert_eq!(entries, [entry]);
+        assert!(entries[0].failed());
+        assert!(entries[0].line(1, true).ends_with("1.5s    2  /src/app  make test"));
+        assert_eq!(entries[0].line(1, false), "    1  make test");
+        assert_eq!(Entry::new("ls").line(2, true), "    2  -                          -    -  -  ls");
+
+        let _ = fs::remove_dir_all(config.file.unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn test_expand_event_designators() {
+        let entries: Vec<Entry> = ["make build", "git status", "echo hi there"].into_iter().map(Entry::new).collect();
+        let expand = |line: &str| expand(line, &entries);
+        assert_eq!(expand("sudo !!"), Ok(Some("sudo echo hi there".to_string())));
+        assert_eq!(expand("!1 && !-2"), Ok(Some("make build && git status".to_string())));
+        assert_eq!(expand("!git; !?hi?"), Ok(Some("git status; echo hi there".to_string())));
+        assert_eq!(expand("^hi^bye"), Ok(Some("echo bye there".to_string())));
+        // Nothing to expand
+        assert_eq!(expand("[ ! -f x ] && [ a != b ] && echo '!!' \\!!"), Ok(None));
+        assert_eq!(expand("echo hi!"), Ok(None));
+        assert_eq!(expand("kill \"$!\""), Ok(None));
+        assert_eq!(expand("!nope"), Err("!nope: event not found".to_string()));
+        assert_eq!(expand("!9"), Err("!9: event not found".to_string()));
+        assert_eq!(expand("^zz^y"), Err("^zz^y: substitution failed".to_string()));
+    }
+
+    #[test]
+    fn test_import_bash_and_zsh() {
+        let bash = b"ls -l\ncd /tmp\n";
+        assert_eq!(Format::detect(bash), Format::Bash);
+        assert_eq!(import(bash, Format::Bash), [Entry::new("ls -l"), Entry::new("cd /tmp")]);
+
+        let stamped = import(b"#1700000000\nfor f in *; do\n  echo $f\ndone\n#1700000060\npwd\n", Format::Bash);
+        assert_eq!(stamped.len(), 2);
+        assert_eq!(stamped[0].command, "for f in *; do\n  echo $f\ndone");
+        assert_eq!((stamped[0].time, stamped[1].time), (Some(1_700_000_000), Some(1_700_000_060)));
+
+        // "voil\xc3\xa0": zsh escapes the 0xa0 byte
+        let zsh = b": 1700000000:3;make\n: 1700000010:0;echo a\\\nb\n: 1700000020:0;echo voil\xc3\x83\x80\n";
+        assert_eq!(Format::detect(zsh), Format::Zsh);
+        let entries = import(zsh, Format::Zsh);
+        let commands: Vec<&str> = entries.iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(commands, ["make", "echo a\nb", "echo voil\u{e0}"]);
+        assert_eq!((entries[0].time, entries[0].duration_ms), (Some(1_700_000_000), Some(3000)));
+    }
+
+    #[test]
+    fn test_export_round_trips() {
+        let entries = vec![
+            Entry { time: Some(1_700_000_000), duration_ms: Some(2500), ..Entry::new("echo voil\u{e0}") },
+            Entry { time: Some(1_700_000_100), ..Entry::new("if true\nthen ls\nfi") },
+        ];
+        for format in [Format::Bash, Format::Zsh, Format::Json] {
+            let data = export(&entries, format);
+            assert_eq!(Format::detect(&data), format);
+            let back = import(&data, format);
+            let commands: Vec<&str> = back.iter().map(|entry| entry.command.as_str()).collect();
+            assert_eq!(commands, ["echo voil\u{e0}", "if true\nthen ls\nfi"], "{:?}", format);
+            assert_eq!(back[1].time, Some(1_700_000_100));
+        }
+        assert!(export(&entries, Format::Zsh).starts_with(b": 1700000000:2;echo voil\xc3\x83\x80\n"));
+    }
+
+    #[test]
+    fn test_plain_lines_and_disabled_history() {
+        let config = test_config("plain", 10);
+        let path = config.file.clone().unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "ls -l\n{\"command\":\"pwd\"}\npartial").unwrap();
+        assert_eq!(commands(&History::open(&config)), ["ls -l", "pwd"]);
+
+        let disabled = History::open(&HistoryConfig { enabled: false, ..config });
+        disabled.add(Entry::new("echo private"));
+        assert_eq!(commands(&disabled), ["echo private"]);
+        assert!(!fs::read_to_string(&path).unwrap().contains("private"));
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
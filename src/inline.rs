@@ -0,0 +1,129 @@
+use crate::config::InterpreterConfig;
+
+/// Marker opening and closing a fenced block
+const FENCE: &str = "```";
+
+/// A language that inline code can be routed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Language {
+    Python,
+    Ruby,
+    Node,
+}
+
+impl Language {
+    /// Look up a language by the tag used before `>` or after a fence
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "py" | "python" => Some(Language::Python),
+            "rb" | "ruby" => Some(Language::Ruby),
+            "js" | "node" => Some(Language::Node),
+            _ => None,
+        }
+    }
+
+    /// Name used in configuration, e.g. `python = "3.11"`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Python => "python",
+            Language::Ruby => "ruby",
+            Language::Node => "node",
+        }
+    }
+
+    /// Language of a script file, by extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path.rsplit_once('.')?.1 {
+            "py" => Some(Language::Python),
+            "rb" => Some(Language::Ruby),
+            "js" => Some(Language::Node),
+            _ => None,
+        }
+    }
+
+    /// Interpreter configured for this language
+    pub fn interpreter<'a>(&self, config: &'a InterpreterConfig) -> &'a str {
+        match self {
+            Language::Python => &config.python_path,
+            Language::Ruby => &config.ruby_path,
+            Language::Node => &config.node_path,
+        }
+    }
+
+    /// Interpreter flag that evaluates its argument as a program
+    pub fn eval_flag(&self) -> &'static str {
+        match self {
+            Language::Python => "-c",
+            Language::Ruby | Language::Node => "-e",
+        }
+    }
+}
+
+/// Code to hand to an interpreter instead of the shell parser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineBlock {
+    pub language: Language,
+    pub code: String,
+}
+
+/// Recognise `py> CODE` or a complete fenced block
+pub fn parse(input: &str) -> Option<InlineBlock> {
+    if let Some(rest) = input.strip_prefix(FENCE) {
+        let (tag, body) = rest.split_once('\n')?;
+        let language = Language::from_tag(tag.trim())?;
+        let code = body.trim_end().strip_suffix(FENCE)?;
+        return Some(InlineBlock { language, code: code.to_string() });
+    }
+
+    let (tag, code) = input.split_once('>')?;
+    let language = Language::from_tag(tag)?;
+    Some(InlineBlock { language, code: code.trim().to_string() })
+}
+
+/// Whether `input` is a fenced block still waiting for its closing fence
+pub fn is_incomplete(input: &str) -> bool {
+    match input.strip_prefix(FENCE) {
+        Some(rest) => {
+            let mut lines = rest.lines();
+            let tag = lines.next().unwrap_or("").trim();
+            Language::from_tag(tag).is_some() && !lines.any(|line| line.trim() == FENCE)
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prompt_form() {
+        let block = parse("py> print(sum(range(10)))").unwrap();
+        assert_eq!(block.language, Language::Python);
+        assert_eq!(block.code, "print(sum(range(10)))");
+
+        // Unknown tags are ordinary redirections
+        assert!(parse("echo> out.txt").is_none());
+    }
+
+    #[test]
+    fn test_language_from_path() {
+        assert_eq!(Language::from_path("tools/report.rb"), Some(Language::Ruby));
+        assert_eq!(Language::from_path("main.rs"), None);
+        assert_eq!(Language::from_path("Makefile"), None);
+    }
+
+    #[test]
+    fn test_parse_fenced_block_keeps_indentation() {
+        let input = "```python\nfor i in range(2):\n    print(i)\n```";
+        assert!(!is_incomplete(input));
+        let block = parse(input).unwrap();
+        assert_eq!(block.code, "for i in range(2):\n    print(i)\n");
+    }
+
+    #[test]
+    fn test_unclosed_fence_is_incomplete() {
+        assert!(is_incomplete("```rb\nputs 1\n"));
+        assert!(!is_incomplete("```text\nnot code\n"));
+    }
+}
@@ -0,0 +1,1013 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::executor::exit_code;
+use crate::persistent::Stream;
+use crate::signals;
+
+/// Finished jobs whose buffered output is kept for `jobs -o`
+const KEPT_OUTPUTS: usize = 10;
+
+/// How often `fg` checks a job's processes and the interrupt flag
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Longest a foreground wait sleeps between checks while SIGCHLD wakes it
+const EVENT_WAIT_LIMIT: Duration = Duration::from_secs(1);
+
+/// Exit status reported for a pipeline stopped with Ctrl-Z
+#[cfg(unix)]
+pub const STOPPED_STATUS: i32 = 128 + libc::SIGTSTP;
+#[cfg(not(unix))]
+pub const STOPPED_STATUS: i32 = 148;
+
+/// Where a job is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    /// Every process has exited; the status is the last stage's
+    Done(i32),
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobState::Running => write!(f, "Running"),
+            JobState::Stopped => write!(f, "Stopped"),
+            JobState::Done(0) => write!(f, "Done"),
+            #[cfg(unix)]
+            JobState::Done(status) if *status == 128 + libc::SIGTERM => write!(f, "Terminated"),
+            #[cfg(unix)]
+            JobState::Done(status) if *status == 128 + libc::SIGKILL => write!(f, "Killed"),
+            #[cfg(unix)]
+            JobState::Done(status) if *status == 128 + libc::SIGINT => write!(f, "Interrupt"),
+            JobState::Done(status) => write!(f, "Exit {}", status),
+        }
+    }
+}
+
+/// A job as seen by `jobs`, `fg`, `bg`, `kill` and the prompt
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub id: usize,
+    /// Process group of the pipeline; the pid of its first stage
+    pub pgid: u32,
+    pub pids: Vec<u32>,
+    pub command: String,
+    pub state: JobState,
+    pub started: Instant,
+    /// Bytes of buffered output waiting to be read with `jobs -o`
+    pub buffered: usize,
+    /// `+` for the current job (the `%%` default), `-` for the previous one
+    pub marker: char,
+}
+
+impl JobInfo {
+    /// The job as `jobs` lists it, with its process group when `long`
+    pub fn line(&self, long: bool) -> String {
+        let pgid = if long { format!("{} ", self.pgid) } else { String::new() };
+        let background = if self.state == JobState::Running { " &" } else { "" };
+        format!("[{}]{}  {}{:<24}{}{}", self.id, self.marker, pgid, self.state.to_string(), self.command, background)
+    }
+}
+
+/// Output a background job wrote, up to a fixed size
+#[derive(Debug, Default)]
+struct Ring {
+    data: VecDeque<u8>,
+    capacity: usize,
+    /// Bytes dropped from the front to stay within `capacity`
+    dropped: usize,
+    /// Set once the job is brought to the foreground; output then goes
+    /// straight to the terminal
+    live: bool,
+}
+
+/// Where a background job's stdout or stderr goes: a buffer shared by both
+/// streams, replayed with `jobs -o`
+#[derive(Debug, Clone)]
+pub struct JobOutput {
+    ring: Arc<Mutex<Ring>>,
+    stream: Stream,
+}
+
+impl JobOutput {
+    /// A buffer keeping the last `capacity` bytes, written to as stdout
+    pub fn new(capacity: usize) -> Self {
+        let ring = Ring { capacity, ..Ring::default() };
+        Self { ring: Arc::new(Mutex::new(ring)), stream: Stream::Stdout }
+    }
+
+    /// A handle on the same buffer for the job's stderr
+    pub fn stderr(&self) -> Self {
+        Self { ring: Arc::clone(&self.ring), stream: Stream::Stderr }
+    }
+
+    /// Copy `source` into the buffer on a thread until it closes
+    pub fn capture(&self, mut source: impl Read + Send + 'static) {
+        let mut sink = self.clone();
+        thread::spawn(move || io::copy(&mut source, &mut sink));
+    }
+
+    /// What has been kept so far, with a note if older output was dropped
+    pub fn contents(&self) -> String {
+        let ring = self.ring.lock().unwrap();
+        let (front, back) = ring.data.as_slices();
+        let text = String::from_utf8_lossy(&[front, back].concat()).into_owned();
+        match ring.dropped {
+            0 => text,
+            dropped => format!("[{} earlier bytes dropped]\n{}", dropped, text),
+        }
+    }
+
+    /// Bytes currently kept
+    pub fn len(&self) -> usize {
+        self.ring.lock().unwrap().data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Print what was kept and pass further output straight through
+    fn go_live(&self) {
+        let mut ring = self.ring.lock().unwrap();
+        let (front, back) = ring.data.as_slices();
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(front).and_then(|_| stdout.write_all(back)).and_then(|_| stdout.flush());
+        ring.data.clear();
+        ring.live = true;
+    }
+}
+
+impl Write for JobOutput {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut ring = self.ring.lock().unwrap();
+        if ring.live {
+            drop(ring);
+            return match self.stream {
+                Stream::Stdout => io::stdout().write_all(data).and_then(|_| io::stdout().flush()),
+                Stream::Stderr => io::stderr().write_all(data),
+            }
+            .map(|_| data.len());
+        }
+        ring.data.extend(data);
+        let excess = ring.data.len().saturating_sub(ring.capacity);
+        ring.data.drain(..excess);
+        ring.dropped += excess;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A started pipeline and its processes
+struct Job {
+    command: String,
+    /// Buffered output, unless it goes to the terminal
+    output: Option<JobOutput>,
+    pgid: u32,
+    children: Vec<Child>,
+    /// Exit status of each stage, once reaped
+    statuses: Vec<Option<i32>>,
+    state: JobState,
+    started: Instant,
+    /// When each stage must have exited, as for a foreground one
+    deadlines: Vec<Option<Instant>>,
+    /// When each stage sent SIGTERM at its deadline is killed outright
+    kill_at: Vec<Option<Instant>>,
+    grace: Duration,
+}
+
+impl Job {
+    /// Collect stages that have exited without blocking
+    fn poll(&mut self) {
+        if matches!(self.state, JobState::Done(_)) {
+            return;
+        }
+        for (child, status) in self.children.iter_mut().zip(&mut self.statuses) {
+            if status.is_some() {
+                continue;
+            }
+            match check(child) {
+                Change::Exited(code) => *status = Some(code),
+                Change::Stopped => self.state = JobState::Stopped,
+                Change::Continued => self.state = JobState::Running,
+                Change::None => {}
+            }
+        }
+        if self.statuses.iter().all(Option::is_some) {
+            self.state = JobState::Done(self.statuses.last().copied().flatten().unwrap_or(0));
+        }
+    }
+
+    /// Stop the stages that are past their deadlines, returning when the next
+    /// one falls due
+    fn enforce_deadlines(&mut self, now: Instant) -> Option<Instant> {
+        self.poll();
+        if self.deadlines.is_empty() || matches!(self.state, JobState::Done(_)) {
+            return None;
+        }
+        let mut next = None;
+        for (i, child) in self.children.iter_mut().enumerate() {
+            if self.statuses[i].is_some() {
+                continue;
+            }
+            match (self.kill_at[i], self.deadlines[i]) {
+                (Some(kill_at), _) if now >= kill_at => {
+                    // Collected by the next poll, like any other exit
+                    let _ = child.kill();
+                    continue;
+                }
+                (None, Some(deadline)) if now >= deadline => {
+                    tracing::info!(pid = child.id(), command = %self.command, "background job passed its deadline");
+                    if terminate(child).is_ok() {
+                        self.kill_at[i] = Some(now + self.grace);
+                    }
+                }
+                _ => {}
+            }
+            let due = self.kill_at[i].or(self.deadlines[i]);
+            next = next.into_iter().chain(due).min();
+        }
+        next
+    }
+}
+
+#[derive(Default)]
+struct Table {
+    jobs: BTreeMap<usize, Job>,
+    /// Job ids, most recently started or resumed last
+    recent: Vec<usize>,
+    /// Output of the last few finished jobs, oldest first
+    finished: VecDeque<(usize, JobOutput)>,
+}
+
+impl Table {
+    fn touch(&mut self, id: usize) {
+        self.recent.retain(|&other| other != id);
+        self.recent.push(id);
+    }
+
+    fn info(&self, id: usize, job: &Job) -> JobInfo {
+        let marker = match self.recent.iter().rev().position(|&other| other == id) {
+            Some(0) => '+',
+            Some(1) => '-',
+            _ => ' ',
+        };
+        JobInfo {
+            id,
+            pgid: job.pgid,
+            pids: job.children.iter().map(Child::id).collect(),
+            command: job.command.clone(),
+            state: job.state,
+            started: job.started,
+            buffered: job.output.as_ref().map_or(0, JobOutput::len),
+            marker,
+        }
+    }
+}
+
+/// Background and stopped jobs of a session.
+///
+/// The executor adds pipelines started with `&`; the builtins list, resume and
+/// signal them; the prompt shows how many are active. All of them read this one
+/// table, shared through an `Arc`.
+#[derive(Default)]
+pub struct JobManager {
+    table: Mutex<Table>,
+    /// The shell's own process group, once job control is on
+    shell_pgid: OnceLock<u32>,
+    events: ChildEvents,
+    /// Whether a thread is enforcing background jobs' deadlines
+    watching: AtomicBool,
+    /// Servers the shell keeps running in the background, such as persistent
+    /// interpreters, by pid
+    servers: Mutex<BTreeMap<u32, String>>,
+}
+
+/// Counts SIGCHLDs seen by the reaper, so foreground waits sleep until some
+/// child changes state instead of polling each process in turn
+#[derive(Default)]
+struct ChildEvents {
+    count: Mutex<u64>,
+    changed: Condvar,
+    /// Whether the reaper is running; waits poll without it
+    reaping: AtomicBool,
+}
+
+impl ChildEvents {
+    fn current(&self) -> u64 {
+        *self.count.lock().unwrap()
+    }
+
+    fn notify(&self) {
+        *self.count.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+
+    /// Sleep until an event after `seen`, or for at most `timeout`
+    fn wait(&self, seen: u64, timeout: Duration) {
+        let limit = if self.reaping.load(Ordering::Relaxed) { EVENT_WAIT_LIMIT } else { POLL_INTERVAL };
+        let count = self.count.lock().unwrap();
+        let _ = self.changed.wait_timeout_while(count, timeout.min(limit), |count| *count == seen);
+    }
+}
+
+/// How waiting for a foreground process ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waited {
+    Exited(i32),
+    /// Stopped at its deadline, ending with this status
+    TimedOut(i32),
+    /// Stopped by Ctrl-Z (or another stop signal)
+    Stopped,
+}
+
+/// Hands the terminal back to the shell when a foreground job is done with it
+pub struct Foreground<'a> {
+    jobs: &'a JobManager,
+}
+
+impl Drop for Foreground<'_> {
+    fn drop(&mut self) {
+        self.jobs.reclaim_terminal();
+    }
+}
+
+impl JobManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turn on job control for an interactive shell: put the shell in its own
+    /// process group and make that the terminal's foreground group, so
+    /// pipelines can be given the terminal and stopped with Ctrl-Z.
+    /// Returns false when stdin is not a terminal.
+    #[cfg(unix)]
+    pub fn enable_job_control(&self) -> bool {
+        // SAFETY: plain libc calls on the shell's own process and stdin
+        let pgid = unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return false;
+            }
+            // Fails harmlessly if the shell already leads its group or session
+            libc::setpgid(0, 0);
+            libc::getpgrp() as u32
+        };
+        signals::catch_job_control();
+        give_terminal(pgid);
+        let _ = self.shell_pgid.set(pgid);
+        true
+    }
+
+    #[cfg(not(unix))]
+    pub fn enable_job_control(&self) -> bool {
+        false
+    }
+
+    /// Whether foreground pipelines get their own process group and the terminal
+    pub fn job_control(&self) -> bool {
+        self.shell_pgid.get().is_some()
+    }
+
+    /// Give the terminal to the process group `pgid` until the returned guard is dropped
+    pub fn foreground(&self, pgid: u32) -> Option<Foreground<'_>> {
+        self.job_control().then(|| {
+            give_terminal(pgid);
+            Foreground { jobs: self }
+        })
+    }
+
+    /// Make the shell the terminal's foreground process group again
+    pub fn reclaim_terminal(&self) {
+        if let Some(&pgid) = self.shell_pgid.get() {
+            give_terminal(pgid);
+        }
+    }
+
+    /// Wait for the processes of a foreground pipeline together, each until it
+    /// exits or passes its deadline, so a late stage's deadline holds while an
+    /// earlier one still runs. A stage past its deadline gets SIGTERM, then
+    /// SIGKILL if it is still running `grace` later. With job control, a stop
+    /// of any stage (Ctrl-Z) ends the wait and every unfinished stage is
+    /// reported `Stopped`. Errors carry the index of the stage they came from.
+    pub fn wait_pipeline(
+        &self,
+        stages: &mut [(&mut Child, Option<Instant>)],
+        grace: Duration,
+    ) -> Result<Vec<Waited>, (usize, io::Error)> {
+        let job_control = self.job_control();
+        let mut results: Vec<Option<Waited>> = vec![None; stages.len()];
+        // When each stage sent SIGTERM is killed outright
+        let mut kill_at: Vec<Option<Instant>> = vec![None; stages.len()];
+        loop {
+            // Read before checking, so a SIGCHLD arriving mid-check still wakes the wait
+            let seen = self.events.current();
+            let mut stopped = false;
+            for (i, (child, deadline)) in stages.iter_mut().enumerate() {
+                if results[i].is_some() {
+                    continue;
+                }
+                let change = if job_control {
+                    check(child)
+                } else {
+                    match child.try_wait() {
+                        Ok(Some(exit)) => Change::Exited(exit_code(exit)),
+                        Ok(None) => Change::None,
+                        Err(e) => return Err((i, e)),
+                    }
+                };
+                match change {
+                    Change::Exited(code) if kill_at[i].is_some() => results[i] = Some(Waited::TimedOut(code)),
+                    Change::Exited(code) => results[i] = Some(Waited::Exited(code)),
+                    Change::Stopped => stopped = true,
+                    Change::Continued | Change::None => {}
+                }
+                if results[i].is_some() {
+                    continue;
+                }
+                let now = Instant::now();
+                match kill_at[i] {
+                    Some(kill_at) if now >= kill_at => {
+                        let exit = child.kill().and_then(|_| child.wait()).map_err(|e| (i, e))?;
+                        results[i] = Some(Waited::TimedOut(exit_code(exit)));
+                    }
+                    None if deadline.is_some_and(|deadline| now >= deadline) => {
+                        terminate(child).map_err(|e| (i, e))?;
+                        kill_at[i] = Some(now + grace);
+                    }
+                    _ => {}
+                }
+            }
+            if stopped {
+                return Ok(results.into_iter().map(|result| result.unwrap_or(Waited::Stopped)).collect());
+            }
+            if results.iter().all(Option::is_some) {
+                return Ok(results.into_iter().flatten().collect());
+            }
+            let next_deadline = stages
+                .iter()
+                .zip(&results)
+                .zip(&kill_at)
+                .filter(|((_, result), _)| result.is_none())
+                .filter_map(|(((_, deadline), _), kill_at)| kill_at.or(*deadline))
+                .min();
+            let timeout = next_deadline.map_or(EVENT_WAIT_LIMIT, |deadline| deadline.saturating_duration_since(Instant::now()));
+            self.events.wait(seen, timeout);
+        }
+    }
+
+    /// Track the processes of a pipeline started in the background, returning its job id
+    pub fn add(&self, command: String, children: Vec<Child>, output: Option<JobOutput>) -> usize {
+        let mut table = self.table.lock().unwrap();
+        let id = table.jobs.keys().next_back().map_or(1, |last| last + 1);
+        let job = Job {
+            command,
+            output,
+            pgid: children.first().map_or(0, Child::id),
+            statuses: vec![None; children.len()],
+            children,
+            state: JobState::Running,
+            started: Instant::now(),
+            deadlines: Vec::new(),
+            kill_at: Vec::new(),
+            grace: Duration::ZERO,
+        };
+        table.finished.retain(|(other, _)| *other != id);
+        table.jobs.insert(id, job);
+        table.touch(id);
+        id
+    }
+
+    /// Stop background stages past their deadlines, returning the next one
+    /// due. With none left, the watching thread is done.
+    fn enforce_deadlines(&self) -> Option<Instant> {
+        let mut table = self.table.lock().unwrap();
+        let now = Instant::now();
+        let next = table.jobs.values_mut().filter_map(|job| job.enforce_deadlines(now)).min();
+        if next.is_none() {
+            // Cleared under the lock, so a job limited after this starts a new thread
+            self.watching.store(false, Ordering::SeqCst);
+        }
+        next
+    }
+
+    /// Track a foreground pipeline that was stopped, returning the new job
+    pub fn add_stopped(&self, command: String, children: Vec<Child>) -> JobInfo {
+        let id = self.add(command, children, None);
+        let mut table = self.table.lock().unwrap();
+        if let Some(job) = table.jobs.get_mut(&id) {
+            job.state = JobState::Stopped;
+        }
+        let table = &*table;
+        table.info(id, &table.jobs[&id])
+    }
+
+    /// All jobs, after collecting any that finished
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut table = self.table.lock().unwrap();
+        table.jobs.values_mut().for_each(Job::poll);
+        let table = &*table;
+        table.jobs.iter().map(|(&id, job)| table.info(id, job)).collect()
+    }
+
+    /// One job, after collecting it if it finished
+    pub fn get(&self, id: usize) -> Option<JobInfo> {
+        let mut table = self.table.lock().unwrap();
+        let job = table.jobs.get_mut(&id)?;
+        job.poll();
+        let table = &*table;
+        table.jobs.get(&id).map(|job| table.info(id, job))
+    }
+
+    /// Number of jobs still running or stopped
+    pub fn active(&self) -> usize {
+        self.list().iter().filter(|job| !matches!(job.state, JobState::Done(_))).count()
+    }
+
+    /// Record a server the shell started, so `jobs` lists it
+    pub fn add_server(&self, pid: u32, command: String) {
+        self.servers.lock().unwrap().insert(pid, command);
+    }
+
+    /// Forget a server once it has stopped
+    pub fn remove_server(&self, pid: u32) {
+        self.servers.lock().unwrap().remove(&pid);
+    }
+
+    /// Servers still running, as `(pid, command)`
+    pub fn servers(&self) -> Vec<(u32, String)> {
+        self.servers.lock().unwrap().iter().map(|(&pid, command)| (pid, command.clone())).collect()
+    }
+
+    /// Forget jobs reported as finished
+    pub fn forget(&self, finished: &[JobInfo]) {
+        let mut table = self.table.lock().unwrap();
+        for job in finished.iter().filter(|job| matches!(job.state, JobState::Done(_))) {
+            let output = table.jobs.remove(&job.id).and_then(|job| job.output);
+            table.recent.retain(|&id| id != job.id);
+            // The output stays readable for a while after the job is gone
+            if let Some(output) = output {
+                table.finished.push_back((job.id, output));
+                if table.finished.len() > KEPT_OUTPUTS {
+                    table.finished.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Buffered output of the job `spec` names, still running or recently
+    /// finished; the current or last finished job by default
+    pub fn output(&self, spec: Option<&str>) -> Result<String, String> {
+        let found = self.resolve(spec);
+        let table = self.table.lock().unwrap();
+        if let Some(job) = found.as_ref().ok().and_then(|id| table.jobs.get(id)) {
+            return match &job.output {
+                Some(output) => Ok(output.contents()),
+                None => Err(format!("{}: output was not buffered", spec.unwrap_or("%%"))),
+            };
+        }
+        let pattern = spec.unwrap_or("%%").trim_start_matches('%');
+        let kept = match pattern {
+            "" | "%" | "+" => table.finished.back(),
+            _ => pattern.parse().ok().and_then(|id: usize| table.finished.iter().rev().find(|(other, _)| *other == id)),
+        };
+        match (kept, found) {
+            (Some((_, output)), _) => Ok(output.contents()),
+            (None, Err(e)) => Err(e),
+            (None, Ok(_)) => Err(format!("{}: no such job", spec.unwrap_or("%%"))),
+        }
+    }
+
+    /// Collect every job whose processes have all exited, so none linger as zombies
+    pub fn reap(&self) {
+        self.table.lock().unwrap().jobs.values_mut().for_each(Job::poll);
+    }
+
+    /// Jobs that finished since the last call, for `[1]+ Done ...` notices;
+    /// they are forgotten once returned
+    pub fn take_finished(&self) -> Vec<JobInfo> {
+        let mut table = self.table.lock().unwrap();
+        table.jobs.values_mut().for_each(Job::poll);
+        let finished: Vec<JobInfo> = table
+            .jobs
+            .iter()
+            .filter(|(_, job)| matches!(job.state, JobState::Done(_)))
+            .map(|(&id, job)| table.info(id, job))
+            .collect();
+        drop(table);
+        self.forget(&finished);
+        finished
+    }
+
+    /// Find the job a spec names: `%N` or `N`, `%%`/`%+`/`%` for the current
+    /// job, `%-` for the previous one, `%name` for a command starting with
+    /// `name` and `%?text` for one containing `text`
+    pub fn resolve(&self, spec: Option<&str>) -> Result<usize, String> {
+        let table = self.table.lock().unwrap();
+        let spec = spec.unwrap_or("%%");
+        let pattern = spec.strip_prefix('%').unwrap_or(spec);
+        let found = match pattern {
+            "" | "%" | "+" => table.recent.last().copied(),
+            "-" => table.recent.iter().rev().nth(1).copied(),
+            _ if pattern.chars().all(|c| c.is_ascii_digit()) => {
+                pattern.parse().ok().filter(|id| table.jobs.contains_key(id))
+            }
+            _ => {
+                let matches: Vec<usize> = table
+                    .jobs
+                    .iter()
+                    .filter(|(_, job)| match pattern.strip_prefix('?') {
+                        Some(text) => job.command.contains(text),
+                        None => job.command.starts_with(pattern),
+                    })
+                    .map(|(&id, _)| id)
+                    .collect();
+                match matches[..] {
+                    [id] => Some(id),
+                    [] => None,
+                    _ => return Err(format!("{}: ambiguous job spec", spec)),
+                }
+            }
+        };
+        found.ok_or_else(|| {
+            if spec == "%%" {
+                "no current job".to_string()
+            } else {
+                format!("{}: no such job", spec)
+            }
+        })
+    }
+
+    /// Send `signal` to every process of a job
+    pub fn signal(&self, id: usize, signal: i32) -> io::Result<()> {
+        let table = self.table.lock().unwrap();
+        let job = table.jobs.get(&id).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        signal_group(job.pgid, signal)
+    }
+
+    /// Let a stopped job carry on in the background
+    pub fn resume(&self, id: usize) -> io::Result<()> {
+        let mut table = self.table.lock().unwrap();
+        let job = table.jobs.get_mut(&id).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        #[cfg(unix)]
+        signal_group(job.pgid, libc::SIGCONT)?;
+        job.state = JobState::Running;
+        table.touch(id);
+        Ok(())
+    }
+
+    /// Bring a job to the foreground: give it the terminal, resume it and wait
+    /// for it to finish, passing on Ctrl-C (seen through `interrupted`) to its
+    /// processes. Returns the job's exit status; the job is no longer tracked
+    /// afterwards unless it was stopped again.
+    pub fn wait(&self, id: usize, interrupted: impl Fn() -> bool) -> io::Result<i32> {
+        // Waiting happens outside the lock so the table stays readable
+        let mut job = {
+            let mut table = self.table.lock().unwrap();
+            let job = table.jobs.remove(&id).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+            table.recent.retain(|&other| other != id);
+            job
+        };
+        if let Some(output) = &job.output {
+            output.go_live();
+        }
+        let terminal = self.foreground(job.pgid);
+        #[cfg(unix)]
+        signal_group(job.pgid, libc::SIGCONT)?;
+        job.state = JobState::Running;
+
+        let status = loop {
+            job.poll();
+            match job.state {
+                JobState::Done(status) => break status,
+                JobState::Stopped => break STOPPED_STATUS,
+                JobState::Running => {}
+            }
+            if interrupted() {
+                #[cfg(unix)]
+                signal_group(job.pgid, libc::SIGINT)?;
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        drop(terminal);
+
+        if job.state == JobState::Stopped {
+            let mut table = self.table.lock().unwrap();
+            table.jobs.insert(id, job);
+            table.touch(id);
+        }
+        Ok(status)
+    }
+}
+
+/// What happened to a process since it was last checked
+enum Change {
+    Exited(i32),
+    Stopped,
+    Continued,
+    None,
+}
+
+/// Check a process without blocking. Exits are collected through `Child` so
+/// it keeps the status; stops and continues are read with `waitid`.
+fn check(child: &mut Child) -> Change {
+    match child.try_wait() {
+        Ok(Some(exit)) => return Change::Exited(exit_code(exit)),
+        Ok(None) => {}
+        Err(_) => return Change::None,
+    }
+    #[cfg(unix)]
+    {
+        // SAFETY: siginfo_t is plain data, valid when zeroed, and filled in by waitid
+        let (result, info) = unsafe {
+            let mut info: libc::siginfo_t = std::mem::zeroed();
+            let flags = libc::WSTOPPED | libc::WCONTINUED | libc::WNOHANG;
+            (libc::waitid(libc::P_PID, child.id() as libc::id_t, &mut info, flags), info)
+        };
+        // With WNOHANG and nothing to report, the zeroed info is left as is
+        if result == 0 && info.si_signo == libc::SIGCHLD {
+            return if info.si_code == libc::CLD_CONTINUED { Change::Continued } else { Change::Stopped };
+        }
+    }
+    Change::None
+}
+
+/// Put the process `command` starts into the process group led by `leader`,
+/// or a new group it leads. A new group given `take_terminal` makes itself
+/// the terminal's foreground group before exec, so the program never reads
+/// the terminal from the background.
+#[cfg(unix)]
+pub fn set_process_group(command: &mut Command, leader: Option<u32>, take_terminal: bool) {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(leader.map_or(0, |pid| pid as i32));
+    if take_terminal && leader.is_none() {
+        // SAFETY: the closure only makes async-signal-safe calls
+        unsafe {
+            command.pre_exec(|| {
+                give_terminal(libc::getpgrp() as u32);
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn set_process_group(_command: &mut Command, _leader: Option<u32>, _take_terminal: bool) {}
+
+/// Make `pgid` the foreground process group of the terminal on stdin
+#[cfg(unix)]
+fn give_terminal(pgid: u32) {
+    // SAFETY: signal sets are plain data; SIGTTOU is blocked so a caller in a
+    // background group isn't stopped by the call
+    unsafe {
+        let mut block: libc::sigset_t = std::mem::zeroed();
+        let mut previous: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut block);
+        libc::sigaddset(&mut block, libc::SIGTTOU);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &block, &mut previous);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid as libc::pid_t);
+        libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+fn give_terminal(_pgid: u32) {}
+
+/// Reap background jobs as their processes exit (on SIGCHLD), rather than
+/// only when `jobs` or the prompt next looks at them, and wake foreground waits
+#[cfg(unix)]
+pub fn start_reaper(jobs: &Arc<JobManager>) {
+    let mut signals = match signal_hook::iterator::Signals::new([signal_hook::consts::SIGCHLD]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to install SIGCHLD handler");
+            return;
+        }
+    };
+    jobs.events.reaping.store(true, Ordering::Relaxed);
+    let jobs = Arc::downgrade(jobs);
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            match jobs.upgrade() {
+                Some(jobs) => {
+                    jobs.reap();
+                    jobs.events.notify();
+                }
+                None => break,
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn start_reaper(_jobs: &Arc<JobManager>) {}
+
+/// Hold the stages of job `id` to `deadlines`, stopping those still
+/// running at theirs as `wait_pipeline` would in the foreground: SIGTERM,
+/// then SIGKILL `grace` later. A thread enforces them while any are left.
+pub fn watch_deadlines(jobs: &Arc<JobManager>, id: usize, deadlines: Vec<Option<Instant>>, grace: Duration) {
+    if deadlines.iter().all(Option::is_none) {
+        return;
+    }
+    if let Some(job) = jobs.table.lock().unwrap().jobs.get_mut(&id) {
+        job.kill_at = vec![None; deadlines.len()];
+        job.deadlines = deadlines;
+        job.grace = grace;
+    }
+    if jobs.watching.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let jobs = Arc::downgrade(jobs);
+    thread::spawn(move || {
+        while let Some(next) = jobs.upgrade().and_then(|jobs| jobs.enforce_deadlines()) {
+            thread::sleep(next.saturating_duration_since(Instant::now()).max(POLL_INTERVAL));
+        }
+    });
+}
+
+/// Send `signal` to the process group `pgid`
+#[cfg(unix)]
+fn signal_group(pgid: u32, signal: i32) -> io::Result<()> {
+    // SAFETY: sending a signal has no memory-safety requirements
+    if unsafe { libc::kill(-(pgid as libc::pid_t), signal) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn signal_group(_pgid: u32, _signal: i32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "job signals need a Unix system"))
+}
+
+/// Ask `child` to exit with SIGTERM; elsewhere it is killed
+#[cfg(unix)]
+fn terminate(child: &mut Child) -> io::Result<()> {
+    // SAFETY: sending a signal has no memory-safety requirements
+    if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn terminate(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    fn spawn(program: &str, args: &[&str]) -> Child {
+        Command::new(program).args(args).process_group(0).spawn().unwrap()
+    }
+
+    #[test]
+    fn test_wait_pipeline_keeps_each_stage_deadline() {
+        let jobs = JobManager::new();
+        let (mut first, mut second) = (spawn("sleep", &["0.3"]), spawn("sleep", &["5"]));
+        let start = Instant::now();
+        let waited = jobs
+            .wait_pipeline(&mut [(&mut first, None), (&mut second, Some(start + Duration::from_millis(100)))], Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(waited, vec![Waited::Exited(0), Waited::TimedOut(128 + libc::SIGTERM)]);
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // One that ignores SIGTERM is killed once the grace period is over
+        let mut stubborn = spawn("sh", &["-c", "trap '' TERM; exec sleep 5"]);
+        let start = Instant::now();
+        let waited = jobs.wait_pipeline(&mut [(&mut stubborn, Some(start + Duration::from_millis(100)))], Duration::from_millis(200));
+        assert_eq!(waited.unwrap(), vec![Waited::TimedOut(128 + libc::SIGKILL)]);
+        assert!(start.elapsed() >= Duration::from_millis(300) && start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_background_jobs_stop_at_deadlines() {
+        let jobs = Arc::new(JobManager::new());
+        let start = Instant::now();
+        let stubborn = spawn("sh", &["-c", "trap '' TERM; exec sleep 5"]);
+        let id = jobs.add("sleep".to_string(), vec![spawn("sleep", &["0.2"]), spawn("sleep", &["5"]), stubborn], None);
+        let deadline = Some(start + Duration::from_millis(100));
+        watch_deadlines(&jobs, id, vec![None, deadline, deadline], Duration::from_millis(200));
+        let untimed = jobs.add("sleep".to_string(), vec![spawn("sleep", &["0.5"])], None);
+
+        while jobs.active() > 0 && start.elapsed() < Duration::from_secs(3) {
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(300) && start.elapsed() < Duration::from_secs(2));
+        assert_eq!(jobs.get(id).unwrap().state, JobState::Done(128 + libc::SIGKILL));
+        assert_eq!(jobs.get(untimed).unwrap().state, JobState::Done(0));
+        assert!(!jobs.watching.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_resolve_job_specs() {
+        let jobs = JobManager::new();
+        assert_eq!(jobs.resolve(None), Err("no current job".to_string()));
+
+        let first = jobs.add("sleep 5".to_string(), vec![spawn("sleep", &["5"])], None);
+        let second = jobs.add("make docs".to_string(), vec![spawn("sleep", &["5"])], None);
+        assert_eq!(jobs.resolve(None), Ok(second));
+        assert_eq!(jobs.resolve(Some("%-")), Ok(first));
+        assert_eq!(jobs.resolve(Some("%1")), Ok(first));
+        assert_eq!(jobs.resolve(Some("2")), Ok(second));
+        assert_eq!(jobs.resolve(Some("%mak")), Ok(second));
+        assert_eq!(jobs.resolve(Some("%?5")), Ok(first));
+        assert!(jobs.resolve(Some("%3")).is_err());
+
+        let markers: Vec<char> = jobs.list().iter().map(|job| job.marker).collect();
+        assert_eq!(markers, vec!['-', '+']);
+        for id in [first, second] {
+            jobs.signal(id, libc::SIGKILL).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_jobs_finish_and_are_removed() {
+        let jobs = JobManager::new();
+        let id = jobs.add("false".to_string(), vec![spawn("true", &[]), spawn("false", &[])], None);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while jobs.get(id).unwrap().state == JobState::Running && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert_eq!(jobs.get(id).unwrap().state, JobState::Done(1));
+        assert_eq!(jobs.active(), 0);
+
+        let finished = jobs.take_finished();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].line(false), "[1]+  Exit 1                  false");
+        assert!(jobs.list().is_empty());
+        assert!(jobs.take_finished().is_empty());
+    }
+
+    #[test]
+    fn test_job_output_keeps_the_tail() {
+        let output = JobOutput::new(8);
+        output.clone().write_all(b"hello ").unwrap();
+        output.stderr().write_all(b"world\n").unwrap();
+        assert_eq!(output.len(), 8);
+        assert_eq!(output.contents(), "[4 earlier bytes dropped]\no world\n");
+    }
+
+    #[test]
+    fn test_output_readable_after_job_finishes() {
+        let jobs = JobManager::new();
+        let output = JobOutput::new(1024);
+        let mut child = Command::new("echo").arg("built").stdout(std::process::Stdio::piped()).spawn().unwrap();
+        output.capture(child.stdout.take().unwrap());
+        let id = jobs.add("echo built".to_string(), vec![child], Some(output));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while jobs.take_finished().is_empty() && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        // The copy thread may still be draining the pipe
+        while jobs.output(None).is_ok_and(|text| text.is_empty()) && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert_eq!(jobs.output(Some(&format!("%{}", id))), Ok("built\n".to_string()));
+        assert_eq!(jobs.output(None), Ok("built\n".to_string()));
+        assert!(jobs.output(Some("%7")).is_err());
+    }
+
+    #[test]
+    fn test_stopped_and_resumed_jobs() {
+        let jobs = JobManager::new();
+        let id = jobs.add("sleep 5".to_string(), vec![spawn("sleep", &["5"])], None);
+        jobs.signal(id, libc::SIGSTOP).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while jobs.get(id).unwrap().state != JobState::Stopped && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert_eq!(jobs.active(), 1);
+        assert!(jobs.get(id).unwrap().line(false).contains("Stopped"));
+
+        jobs.resume(id).unwrap();
+        assert_eq!(jobs.get(id).unwrap().state, JobState::Running);
+        jobs.signal(id, libc::SIGKILL).unwrap();
+    }
+
+    #[test]
+    fn test_wait_brings_job_to_foreground() {
+        let jobs = JobManager::new();
+        let id = jobs.add("sleep 5".to_string(), vec![spawn("sleep", &["5"])], None);
+        // An interrupt stops the job like Ctrl-C would
+        let status = jobs.wait(id, || true).unwrap();
+        assert_eq!(status, 128 + libc::SIGINT);
+        assert!(jobs.get(id).is_none());
+    }
+}
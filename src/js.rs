@@ -0,0 +1,180 @@
+use std::env;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use rquickjs::{CatchResultExt, CaughtError, Context, Ctx, Function, Object, Runtime, Value};
+
+use crate::error::{ShellError, ShellResult};
+use crate::limits::Budget;
+
+/// Memory available to one embedded run
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Globals installed before user code: `console`, `print` and a small `process`.
+/// `process.exit` throws a marker object that [`run`] turns into a status.
+const PRELUDE: &str = r#"
+globalThis.__shell_t_format = (v) =>
+    typeof v === "string" ? v
+    : typeof v === "function" ? `[Function ${v.name || "anonymous"}]`
+    : v !== null && typeof v === "object" && !(v instanceof Error) ? JSON.stringify(v)
+    : String(v);
+const __line = (args) => args.map(__shell_t_format).join(" ") + "\n";
+globalThis.console = {
+    log: (...a) => __shell_t_write(1, __line(a)),
+    info: (...a) => __shell_t_write(1, __line(a)),
+    warn: (...a) => __shell_t_write(2, __line(a)),
+    error: (...a) => __shell_t_write(2, __line(a)),
+};
+globalThis.print = console.log;
+process.exit = (code) => { throw { __shell_t_exit: code === undefined ? 0 : code | 0 }; };
+"#;
+
+/// Run JavaScript in an embedded QuickJS engine instead of spawning Node.
+///
+/// Each run gets a fresh runtime with no module loader and no file or network
+/// access; only `console`, `print` and `process.{argv,env,exit}` are provided.
+/// When `echo` is set, a non-undefined completion value is printed, so
+/// `js> 1 + 2` behaves like a REPL. Code still running at the budget's
+/// deadline, or printing past its output cap, is interrupted and the run
+/// fails as [`Budget`] describes.
+pub fn run(code: &str, file: &str, args: &[String], budget: Budget, echo: bool) -> ShellResult<i32> {
+    let runtime = Runtime::new().map_err(js_error)?;
+    runtime.set_memory_limit(MEMORY_LIMIT);
+    let output = Arc::new(Output { left: AtomicU64::new(budget.max_output.unwrap_or(u64::MAX)), ..Output::default() });
+    let timed_out = Arc::new(AtomicBool::new(false));
+    {
+        let (output, timed_out) = (Arc::clone(&output), Arc::clone(&timed_out));
+        runtime.set_interrupt_handler(Some(Box::new(move || {
+            if budget.deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                timed_out.store(true, Ordering::Relaxed);
+            }
+            timed_out.load(Ordering::Relaxed) || output.capped.load(Ordering::Relaxed)
+        })));
+    }
+    let context = Context::full(&runtime).map_err(js_error)?;
+
+    let status = context.with(|ctx| {
+        install_globals(&ctx, file, args, &output).map_err(js_error)?;
+        let result = ctx.eval::<Value, _>(code).catch(&ctx);
+        match result {
+            Ok(value) => {
+                if echo && !value.is_undefined() {
+                    let format: Function = ctx.globals().get("__shell_t_format").map_err(js_error)?;
+                    let text: String = format.call((value,)).map_err(js_error)?;
+                    println!("{}", text);
+                }
+                Ok(0)
+            }
+            Err(CaughtError::Value(value)) => {
+                let exit = value
+                    .as_object()
+                    .and_then(|object| object.get::<_, Option<i32>>("__shell_t_exit").ok().flatten());
+                match exit {
+                    Some(status) => Ok(status),
+                    None => {
+                        eprintln!("{}: uncaught {:?}", file, value);
+                        Ok(1)
+                    }
+                }
+            }
+            Err(CaughtError::Exception(exception)) => {
+                eprintln!("{}: {}", file, exception);
+                Ok(1)
+            }
+            Err(CaughtError::Error(e)) => Err(js_error(e)),
+        }
+    });
+    if timed_out.load(Ordering::Relaxed) {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, format!("{} ran past its deadline", file)).into());
+    }
+    if output.capped.load(Ordering::Relaxed) {
+        return Err(Budget::output_exceeded().into());
+    }
+    status
+}
+
+/// Output a run may still write, and whether it has written past that
+#[derive(Default)]
+struct Output {
+    left: AtomicU64,
+    capped: AtomicBool,
+}
+
+fn install_globals(ctx: &Ctx<'_>, file: &str, args: &[String], output: &Arc<Output>) -> rquickjs::Result<()> {
+    let globals = ctx.globals();
+    let output = Arc::clone(output);
+    globals.set(
+        "__shell_t_write",
+        Function::new(ctx.clone(), move |fd: i32, text: String| {
+            let left = output.left.load(Ordering::Relaxed);
+            let allowed = left.min(text.len() as u64);
+            output.left.store(left - allowed, Ordering::Relaxed);
+            if allowed < text.len() as u64 {
+                output.capped.store(true, Ordering::Relaxed);
+            }
+            let text = &text.as_bytes()[..allowed as usize];
+            // A closed stdout (e.g. `| head`) shouldn't abort the script
+            let _ = if fd == 2 { io::stderr().write_all(text) } else { io::stdout().write_all(text) };
+        })?,
+    )?;
+
+    let process = Object::new(ctx.clone())?;
+    let argv: Vec<String> = ["shell-t".to_string(), file.to_string()].into_iter().chain(args.iter().cloned()).collect();
+    process.set("argv", argv)?;
+    let environment = Object::new(ctx.clone())?;
+    for (name, value) in env::vars() {
+        environment.set(name, value)?;
+    }
+    process.set("env", environment)?;
+    globals.set("process", process)?;
+
+    ctx.eval::<(), _>(PRELUDE)
+}
+
+fn js_error(e: rquickjs::Error) -> ShellError {
+    ShellError::CommandExecution(format!("js: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn eval(code: &str) -> ShellResult<i32> {
+        run(code, "<inline>", &[], Budget { deadline: Some(Instant::now() + Duration::from_secs(5)), max_output: None }, false)
+    }
+
+    #[test]
+    fn test_exit_status() {
+        assert_eq!(eval("1 + 2").unwrap(), 0);
+        assert_eq!(eval("process.exit(3)").unwrap(), 3);
+        assert_eq!(eval("throw new Error('boom')").unwrap(), 1);
+        assert_eq!(eval("process.argv.length === 2 ? 0 : process.exit(4)").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_no_module_or_file_access() {
+        assert_eq!(eval("require('fs')").unwrap(), 1);
+        assert_eq!(eval("typeof std === 'undefined' ? 0 : process.exit(5)").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_timeout_interrupts_runaway_code() {
+        let budget = Budget { deadline: Some(Instant::now() + Duration::from_millis(50)), max_output: None };
+        match run("while (true) {}", "<inline>", &[], budget, false) {
+            Err(ShellError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_output_cap_stops_the_run() {
+        let budget = Budget { deadline: None, max_output: Some(0) };
+        match run("while (true) { console.log('spam') }", "<inline>", &[], budget, false) {
+            Err(ShellError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::FileTooLarge),
+            other => panic!("expected the output cap, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::{self, BufRead, Write};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::error::{self, ShellError};
+use crate::executor::CommandExecutor;
+use crate::inline::Language;
+use crate::persistent::{InterpreterPool, Stream};
+use crate::state::ShellState;
+
+/// Version of the request/response shapes below, reported by `kernel_info`
+const PROTOCOL_VERSION: &str = "1";
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Code rejected by the security policy or failing to run; `data.code` has the catalog code
+const EXECUTION_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct ExecuteParams {
+    language: String,
+    code: String,
+}
+
+/// A failed call, sent back as a JSON-RPC error object
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), data: None }
+    }
+}
+
+impl From<ShellError> for RpcError {
+    fn from(e: ShellError) -> Self {
+        Self {
+            code: EXECUTION_ERROR,
+            message: e.to_string(),
+            data: Some(json!({ "code": e.code(), "status": e.exit_status() })),
+        }
+    }
+}
+
+/// Serve the kernel protocol: one JSON-RPC 2.0 request per line on `input`,
+/// one response per line on `output`, until `shutdown` or end of input.
+///
+/// `execute` runs code on the persistent interpreter sessions, sharing their
+/// state with `lang` and `py>`, and goes through the same command policy and
+/// audit log as the interactive shell. Notifications (requests without an
+/// `id`) run but get no response.
+pub fn serve(
+    config: &Config,
+    executor: &CommandExecutor,
+    state: &ShellState,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (id, result, shutdown) = match serde_json::from_str::<RpcRequest>(&line) {
+            Ok(request) if request.jsonrpc == "2.0" => {
+                let shutdown = request.method == "shutdown";
+                let result = call(config, executor, state, &request.method, request.params);
+                match request.id {
+                    Some(id) => (id, result, shutdown),
+                    None if shutdown => break,
+                    None => continue,
+                }
+            }
+            Ok(_) => (Value::Null, Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")), false),
+            Err(e) => (Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())), false),
+        };
+
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => {
+                let mut error = json!({ "code": e.code, "message": e.message });
+                if let Some(data) = e.data {
+                    error["data"] = data;
+                }
+                json!({ "jsonrpc": "2.0", "id": id, "error": error })
+            }
+        };
+        writeln!(output, "{}", response)?;
+        output.flush()?;
+        if shutdown {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Dispatch one method call
+fn call(config: &Config, executor: &CommandExecutor, state: &ShellState, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "kernel_info" => {
+            let languages: Vec<&str> = [Language::Python, Language::Ruby, Language::Node]
+                .into_iter()
+                .filter(|language| InterpreterPool::supports(*language))
+                .map(|language| language.name())
+                .collect();
+            Ok(json!({
+                "implementation": "shell-t",
+                "version": env!("CARGO_PKG_VERSION"),
+                "protocol_version": PROTOCOL_VERSION,
+                "languages": languages,
+            }))
+        }
+        "execute" => {
+            let params: ExecuteParams =
+                serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            execute(config, executor, state, &params)
+        }
+        "shutdown" => Ok(Value::Null),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method: {}", method))),
+    }
+}
+
+/// Run code on a persistent session, returning its status and captured output
+fn execute(config: &Config, executor: &CommandExecutor, state: &ShellState, params: &ExecuteParams) -> Result<Value, RpcError> {
+    let language = Language::from_tag(&params.language)
+        .filter(|language| InterpreterPool::supports(*language))
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("no interpreter session for: {}", params.language)))?;
+
+    if config.security.enable_auditing {
+        let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+        let interpreter = language.interpreter(&config.interpreters);
+        error::logging::log_command_execution(&format!("{} (kernel)", interpreter), &user);
+    }
+
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    let vars = state.vars().into_iter().collect();
+    let result = executor.execute_repl_with(language, &params.code, vars, &mut |stream, data| {
+        match stream {
+            Stream::Stdout => stdout.extend_from_slice(data),
+            Stream::Stderr => stderr.extend_from_slice(data),
+        }
+        Ok(())
+    });
+
+    match result {
+        Ok(status) => {
+            state.set_last_status(status);
+            Ok(json!({
+                "status": status,
+                "stdout": String::from_utf8_lossy(&stdout),
+                "stderr": String::from_utf8_lossy(&stderr),
+            }))
+        }
+        Err(e) => {
+            if config.security.enable_logging && e.code().starts_with("ST02") {
+                error::logging::log_security_event("blocked", &format!("{} (kernel)", e.root()));
+            }
+            state.set_last_status(e.exit_status());
+            Err(e.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::JobManager;
+    use crate::security::SecurityManager;
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    /// Feed `requests` to a kernel and parse each response line
+    fn session(config: Config, requests: &str) -> Vec<Value> {
+        let state = Arc::new(ShellState::new());
+        let jobs = Arc::new(JobManager::new());
+        let executor = CommandExecutor::new(Arc::new(SecurityManager::new()), config.clone(), Arc::clone(&state), jobs);
+        let mut output = Vec::new();
+        serve(&config, &executor, &state, Cursor::new(requests.to_string()), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_protocol_errors_and_shutdown() {
+        let responses = session(
+            Config::default(),
+            concat!(
+                "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"kernel_info\"}\n",
+                "not json\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"bogus\"}\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":3,\"method\":\"execute\",\"params\":{\"language\":\"cobol\",\"code\":\"\"}}\n",
+                "{\"jsonrpc\":\"2.0\",\"method\":\"kernel_info\"}\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":4,\"method\":\"shutdown\"}\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":5,\"method\":\"kernel_info\"}\n",
+            ),
+        );
+        assert_eq!(responses.len(), 5);
+        assert_eq!(responses[0]["result"]["languages"], json!(["python"]));
+        assert_eq!(responses[1]["error"]["code"], PARSE_ERROR);
+        assert_eq!(responses[2]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(responses[3]["error"]["code"], INVALID_PARAMS);
+        assert_eq!(responses[4]["id"], 4);
+    }
+
+    #[test]
+    fn test_execute_follows_security_policy() {
+        let mut config = Config::default();
+        config.security.allowed_commands = ["ls".to_string()].into_iter().collect();
+        let responses = session(
+            config,
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"execute\",\"params\":{\"language\":\"python\",\"code\":\"1\"}}\n",
+        );
+        assert_eq!(responses[0]["error"]["code"], EXECUTION_ERROR);
+        assert_eq!(responses[0]["error"]["data"]["code"], "ST0201");
+    }
+
+    #[test]
+    fn test_execute_captures_output_and_keeps_state() {
+        if which::which("python3").is_err() {
+            return;
+        }
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        let responses = session(
+            config,
+            concat!(
+                "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"execute\",\"params\":{\"language\":\"python\",\"code\":\"x = 20\"}}\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"execute\",\"params\":{\"language\":\"python\",\"code\":\"print(x + 1)\\nx * 2\"}}\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":3,\"method\":\"execute\",\"params\":{\"language\":\"python\",\"code\":\"raise SystemExit(3)\"}}\n",
+            ),
+        );
+        assert_eq!(responses[0]["result"]["status"], 0);
+        assert_eq!(responses[1]["result"]["stdout"], "21\n40\n");
+        assert_eq!(responses[2]["result"]["status"], 3);
+    }
+}
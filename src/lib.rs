@@ -0,0 +1,521 @@
+//! shell-t as a library: the parser, expansion, executor, security policy and
+//! builtins behind the `shell-t` binary, for programs that embed it as a
+//! restricted command runner through [`Shell`].
+//!
+//! ```no_run
+//! use shell_t::{Config, Shell};
+//!
+//! let mut shell = Shell::new(Config::default());
+//! let status = shell.eval("ls -l | grep src").unwrap();
+//! ```
+
+pub mod parser;
+pub mod security;
+pub mod builtins;
+pub mod executor;
+pub mod ui;
+pub mod config;
+pub mod error;
+pub mod cli;
+pub mod warnings;
+pub mod logging;
+pub mod telemetry;
+pub mod state;
+pub mod session;
+pub mod script;
+pub mod expand;
+pub mod signals;
+pub mod inline;
+pub mod persistent;
+pub mod project;
+pub mod records;
+pub mod sandbox;
+pub mod debug;
+pub mod remote;
+pub mod parse_cache;
+pub mod kernel;
+pub mod limits;
+pub mod jobs;
+pub mod history;
+pub mod picker;
+pub mod editor;
+pub mod completions;
+pub mod bookmarks;
+pub mod ssh;
+pub mod container;
+pub mod fetch;
+pub mod schedule;
+pub mod server;
+pub mod trash;
+pub mod envdiff;
+pub mod tabs;
+pub mod path_index;
+pub mod output;
+#[cfg(feature = "js")]
+pub mod js;
+#[cfg(feature = "lua")]
+pub mod lua;
+#[cfg(feature = "wasm")]
+pub mod plugins;
+mod shell;
+
+pub use config::Config;
+pub use error::{ShellError, ShellResult};
+pub use shell::Shell;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use session::execute_commands;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn create_test_managers() -> (builtins::BuiltinManager, executor::CommandExecutor) {
+        let mut config = config::Config::default();
+        config.security.allowed_commands.insert("echo".to_string());
+        let security = Arc::new(security::SecurityManager::new());
+        let state = Arc::new(state::ShellState::new());
+        let jobs = Arc::new(jobs::JobManager::new());
+        let history = Arc::new(history::History::new(&config.history));
+        let builtin_manager = builtins::BuiltinManager::new(
+            Arc::clone(&security),
+            config.clone(),
+            Arc::clone(&state),
+            Arc::clone(&jobs),
+            Arc::clone(&history),
+            Arc::new(completions::Completer::new(&config, Arc::clone(&state), Arc::clone(&history))),
+        );
+        let executor = executor::CommandExecutor::new(security, config, state, jobs);
+        (builtin_manager, executor)
+    }
+
+    #[test]
+    fn test_execute_commands_empty() {
+        let commands = Vec::new();
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_execute_commands_builtin_cd() {
+        let original_dir = std::env::current_dir().unwrap();
+
+        let commands = vec![parser::Command {
+            program: "cd".to_string(),
+            args: vec!["/tmp".to_string()],
+            input_redirect: None,
+            output_redirect: None,
+            append: false,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
+            background: false,
+            spans: Vec::new(),
+        }];
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+        let tmp = std::env::current_dir().unwrap();
+        assert_eq!(std::env::var("OLDPWD").unwrap(), original_dir.display().to_string());
+        assert_eq!(std::env::var("PWD").unwrap(), tmp.display().to_string());
+
+        // `cd -` goes back, and back again
+        let back = parser::parse_command("cd -").unwrap();
+        assert_eq!(execute_commands(&back, &builtin_manager, &executor).unwrap(), 0);
+        assert_eq!(std::env::current_dir().unwrap(), original_dir);
+        assert_eq!(std::env::var("OLDPWD").unwrap(), tmp.display().to_string());
+        assert_eq!(execute_commands(&back, &builtin_manager, &executor).unwrap(), 0);
+        assert_eq!(std::env::current_dir().unwrap(), tmp);
+
+        // Change back to original directory
+        std::env::set_current_dir(original_dir).unwrap();
+    }
+
+    #[test]
+    fn test_execute_commands_builtin_pwd() {
+        let commands = vec![parser::Command {
+            program: "pwd".to_string(),
+            args: vec![],
+            input_redirect: None,
+            output_redirect: None,
+            append: false,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
+            background: false,
+            spans: Vec::new(),
+        }];
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_execute_commands_external() {
+        let commands = vec![parser::Command {
+            program: "echo".to_string(),
+            args: vec!["test".to_string()],
+            input_redirect: None,
+            output_redirect: None,
+            append: false,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
+            background: false,
+            spans: Vec::new(),
+        }];
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_execute_commands_with_input_redirection() {
+        // Create a temporary test file
+        let test_content = "Hello, World!";
+        fs::write("test_input.txt", test_content).unwrap();
+
+        let commands = vec![parser::Command {
+            program: "cat".to_string(),
+            args: vec![],
+            input_redirect: Some("test_input.txt".to_string()),
+            output_redirect: None,
+            append: false,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
+            background: false,
+            spans: Vec::new(),
+        }];
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+
+        // Clean up
+        fs::remove_file("test_input.txt").unwrap();
+    }
+
+    #[test]
+    fn test_execute_commands_with_output_redirection() {
+        let commands = vec![parser::Command {
+            program: "echo".to_string(),
+            args: vec!["test output".to_string()],
+            input_redirect: None,
+            output_redirect: Some("test_output.txt".to_string()),
+            append: false,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
+            background: false,
+            spans: Vec::new(),
+        }];
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+
+        // Verify file was created
+        assert!(Path::new("test_output.txt").exists());
+
+        // Clean up
+        fs::remove_file("test_output.txt").unwrap();
+    }
+
+    #[test]
+    fn test_execute_commands_with_append_redirection() {
+        // Create initial file
+        fs::write("test_append.txt", "initial content\n").unwrap();
+
+        let commands = vec![parser::Command {
+            program: "echo".to_string(),
+            args: vec!["appended content".to_string()],
+            input_redirect: None,
+            output_redirect: Some("test_append.txt".to_string()),
+            append: true,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
+            background: false,
+            spans: Vec::new(),
+        }];
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+
+        // Verify content was appended
+        let content = fs::read_to_string("test_append.txt").unwrap();
+        assert!(content.contains("initial content"));
+        assert!(content.contains("appended content"));
+
+        // Clean up
+        fs::remove_file("test_append.txt").unwrap();
+    }
+
+    #[test]
+    fn test_execute_commands_with_stderr_redirection() {
+        fs::write("test_stderr.txt", "earlier\n").unwrap();
+
+        let commands = parser::parse_command("ls /nonexistent-shell-t-dir 2>> test_stderr.txt").unwrap();
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert_ne!(result.unwrap(), 0);
+
+        // The error went to the file, after what was there
+        let content = fs::read_to_string("test_stderr.txt").unwrap();
+        assert!(content.starts_with("earlier\n") && content.contains("nonexistent-shell-t-dir"));
+
+        fs::remove_file("test_stderr.txt").unwrap();
+    }
+
+    #[test]
+    fn test_execute_commands_with_fd_redirection() {
+        let dir = std::env::temp_dir().join(format!("shell-t-fds-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (out, err) = (dir.join("out.txt"), dir.join("err.txt"));
+
+        // stdout joins stderr in its file; stderr is then closed
+        let line = format!("ls / /nonexistent-shell-t-dir 3> {} 1>&3 2>&3 3>&-", err.display());
+        let commands = parser::parse_command(&line).unwrap();
+        let (builtin_manager, executor) = create_test_managers();
+        assert_ne!(execute_commands(&commands, &builtin_manager, &executor).unwrap(), 0);
+        let content = fs::read_to_string(&err).unwrap();
+        assert!(content.contains("nonexistent-shell-t-dir") && content.contains("tmp"));
+
+        let line = format!("cat 4< {} 0<&4 > {}", err.display(), out.display());
+        let commands = parser::parse_command(&line).unwrap();
+        assert_eq!(execute_commands(&commands, &builtin_manager, &executor).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), content);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_execute_commands_pipes_builtins() {
+        let dir = std::env::temp_dir().join(format!("shell-t-builtin-pipe-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (out, err) = (dir.join("out.txt"), dir.join("err.txt"));
+        let (builtin_manager, executor) = create_test_managers();
+
+        // A builtin's output is the next stage's input
+        let line = format!("pwd | wc -l > {}", out.display());
+        let commands = parser::parse_command(&line).unwrap();
+        assert_eq!(execute_commands(&commands, &builtin_manager, &executor).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap().trim(), "1");
+
+        // One at the end ignores its input and writes its own redirect
+        let line = format!("ls /nonexistent-shell-t-dir 2> {} | pwd > {}", err.display(), out.display());
+        let commands = parser::parse_command(&line).unwrap();
+        assert_eq!(execute_commands(&commands, &builtin_manager, &executor).unwrap(), 0);
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), format!("{}\n", cwd.display()));
+        assert!(fs::read_to_string(&err).unwrap().contains("nonexistent-shell-t-dir"));
+
+        let commands = parser::parse_command(&format!("type ls > {}", out.display())).unwrap();
+        assert_eq!(execute_commands(&commands, &builtin_manager, &executor).unwrap(), 0);
+        assert!(fs::read_to_string(&out).unwrap().contains("ls"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_execute_commands_pipes_stderr() {
+        let out = std::env::temp_dir().join(format!("shell-t-stderr-pipe-{}.txt", std::process::id()));
+        let line = format!("ls /nonexistent-shell-t-dir |& grep -c nonexistent > {}", out.display());
+        let commands = parser::parse_command(&line).unwrap();
+        let (builtin_manager, executor) = create_test_managers();
+        assert_eq!(execute_commands(&commands, &builtin_manager, &executor).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "1\n");
+        fs::remove_file(out).unwrap();
+    }
+
+    #[test]
+    fn test_execute_commands_multiple_commands() {
+        let commands = vec![
+            parser::Command {
+                program: "echo".to_string(),
+                args: vec!["first".to_string()],
+                input_redirect: None,
+                output_redirect: None,
+                append: false,
+                clobber: false,
+                stderr_redirect: None,
+                stderr_append: false,
+                stderr_to_stdout: false,
+                here_doc: None,
+                globs: Vec::new(),
+                fd_redirects: Vec::new(),
+                env: Vec::new(),
+                background: false,
+                spans: Vec::new(),
+            },
+            parser::Command {
+                program: "echo".to_string(),
+                args: vec!["second".to_string()],
+                input_redirect: None,
+                output_redirect: None,
+                append: false,
+                clobber: false,
+                stderr_redirect: None,
+                stderr_append: false,
+                stderr_to_stdout: false,
+                here_doc: None,
+                globs: Vec::new(),
+                fd_redirects: Vec::new(),
+                env: Vec::new(),
+                background: false,
+                spans: Vec::new(),
+            },
+        ];
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_execute_commands_skip_empty_program() {
+        let commands = vec![
+            parser::Command {
+                program: "".to_string(),
+                args: vec![],
+                input_redirect: None,
+                output_redirect: None,
+                append: false,
+                clobber: false,
+                stderr_redirect: None,
+                stderr_append: false,
+                stderr_to_stdout: false,
+                here_doc: None,
+                globs: Vec::new(),
+                fd_redirects: Vec::new(),
+                env: Vec::new(),
+                background: false,
+                spans: Vec::new(),
+            },
+            parser::Command {
+                program: "echo".to_string(),
+                args: vec!["test".to_string()],
+                input_redirect: None,
+                output_redirect: None,
+                append: false,
+                clobber: false,
+                stderr_redirect: None,
+                stderr_append: false,
+                stderr_to_stdout: false,
+                here_doc: None,
+                globs: Vec::new(),
+                fd_redirects: Vec::new(),
+                env: Vec::new(),
+                background: false,
+                spans: Vec::new(),
+            },
+        ];
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_error_recoverability() {
+        let interrupted = error::ShellError::Io(io::Error::new(io::ErrorKind::Interrupted, "resize"));
+        assert!(interrupted.is_recoverable());
+
+        let closed = error::ShellError::Io(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
+        assert!(closed.is_fatal());
+
+        let blocked = error::ShellError::SecurityViolation("blocked".to_string());
+        assert!(blocked.is_recoverable());
+    }
+
+    #[test]
+    fn test_parser_integration() {
+        // Test that parser and executor work together
+        let input = "echo hello world";
+        let commands = parser::parse_command(input).unwrap();
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_parser_integration_with_redirection() {
+        let input = "echo test > output.txt";
+        let commands = parser::parse_command(input).unwrap();
+
+        let (builtin_manager, executor) = create_test_managers();
+        let result = execute_commands(&commands, &builtin_manager, &executor);
+        assert!(result.is_ok());
+
+        // Verify file was created
+        assert!(Path::new("output.txt").exists());
+
+        // Clean up
+        fs::remove_file("output.txt").unwrap();
+    }
+
+    #[test]
+    fn test_structured_builtin_output() {
+        let (builtin_manager, _) = create_test_managers();
+        let run = |command: &str, args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            match builtin_manager.execute_builtin(command, &args).unwrap() {
+                Some(builtins::BuiltinResult::Success(Some(output))) => Ok(output),
+                other => Err(format!("{:?}", other)),
+            }
+        };
+
+        // Text unless asked for, even though stdout isn't a terminal here
+        assert!(matches!(
+            builtin_manager.execute_builtin("type", &["cd".to_string()]).unwrap(),
+            Some(builtins::BuiltinResult::Info(text)) if text == "cd is a shell builtin"
+        ));
+        let value: serde_json::Value = serde_json::from_str(&run("type", &["--output", "json", "cd"]).unwrap()).unwrap();
+        assert_eq!(value, serde_json::json!({ "name": "cd", "type": "builtin" }));
+        let value: serde_json::Value = serde_json::from_str(&run("config", &["ssh", "--output=json"]).unwrap()).unwrap();
+        assert_eq!(value["program"], "ssh");
+        assert!(run("config", &["--output", "text", "ssh"]).unwrap().contains("program = \"ssh\""));
+        let value: serde_json::Value = serde_json::from_str(&run("security", &["stats", "--output", "json"]).unwrap()).unwrap();
+        assert_eq!(value["commands"], serde_json::json!([]));
+        assert!(run("jobs", &["--output", "xml"]).is_err());
+        assert!(run("config", &["nope"]).is_err());
+    }
+}
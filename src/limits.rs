@@ -0,0 +1,303 @@
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::config::{InterpreterLimits, ResourceLimits};
+use crate::error::{SecurityError, ShellError};
+
+/// Which of the `[interpreters.limits]`, `limits.command_timeout` or
+/// `output.max_kb` a process hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exceeded {
+    Timeout,
+    Output,
+    /// Ran for longer than these seconds, its command timeout
+    CommandTimeout(u64),
+    /// Wrote more than this many KB of streamed output
+    StreamOutput(u64),
+}
+
+impl Exceeded {
+    /// Error reported for `program` being stopped at this limit
+    pub fn error(self, program: &str, limits: &InterpreterLimits) -> ShellError {
+        let message = match self {
+            Exceeded::Timeout => {
+                format!("{} ran longer than {}s (interpreters.limits.timeout_secs)", program, limits.timeout_secs)
+            }
+            Exceeded::Output => {
+                format!("{} wrote more than {} KB (interpreters.limits.max_output_kb)", program, limits.max_output_kb)
+            }
+            Exceeded::CommandTimeout(secs) => format!("{} ran longer than {}s (limits.command_timeout)", program, secs),
+            Exceeded::StreamOutput(kb) => format!("{} wrote more than {} KB (output.max_kb)", program, kb),
+        };
+        SecurityError::ResourceLimitExceeded(message).into()
+    }
+}
+
+/// A deadline and output cap for a run on a persistent interpreter or in the
+/// embedded engine, which has no process of its own to wait on. A run stopped
+/// at its deadline fails with `TimedOut`, one stopped at its cap with
+/// `FileTooLarge`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    pub deadline: Option<Instant>,
+    /// Bytes of output passed on before the run is stopped
+    pub max_output: Option<u64>,
+}
+
+impl Budget {
+    /// Error for a run stopped at its output cap
+    pub fn output_exceeded() -> io::Error {
+        io::Error::new(io::ErrorKind::FileTooLarge, "output cap reached")
+    }
+}
+
+/// How often a child with a deadline is polled
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Limits set with setrlimit on a process the shell starts, from `[limits]`
+/// and for scripts `[interpreters.limits]`; 0 leaves one as the shell has it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rlimits {
+    pub memory_mb: u64,
+    pub cpu_secs: u64,
+    pub file_size_mb: u64,
+    pub open_files: u64,
+}
+
+impl Rlimits {
+    pub fn new(limits: &ResourceLimits) -> Self {
+        Self {
+            memory_mb: limits.max_memory_mb as u64,
+            cpu_secs: limits.max_cpu_secs,
+            file_size_mb: limits.max_file_size_mb,
+            open_files: limits.max_open_files,
+        }
+    }
+
+    /// These limits, with memory capped at `memory_mb` if that is tighter
+    pub fn with_memory(self, memory_mb: u64) -> Self {
+        let memory_mb = match (self.memory_mb, memory_mb) {
+            (0, mb) | (mb, 0) => mb,
+            (ours, theirs) => ours.min(theirs),
+        };
+        Self { memory_mb, ..self }
+    }
+}
+
+/// Apply `limits` to the process `command` starts, never above the shell's
+/// own hard limits. Past its CPU time a process gets SIGXCPU, and SIGKILL a
+/// second later. (Unix only)
+#[cfg(unix)]
+pub fn restrict(command: &mut Command, limits: Rlimits) {
+    use std::os::unix::process::CommandExt;
+
+    const MB: u64 = 1024 * 1024;
+    if limits == Rlimits::default() {
+        return;
+    }
+    // Each resource with its soft limit and how far above that the hard one is
+    let wanted = [
+        (libc::RLIMIT_AS, limits.memory_mb.saturating_mul(MB), 0),
+        (libc::RLIMIT_CPU, limits.cpu_secs, 1),
+        (libc::RLIMIT_FSIZE, limits.file_size_mb.saturating_mul(MB), 0),
+        (libc::RLIMIT_NOFILE, limits.open_files, 0),
+    ];
+    // SAFETY: the closure only calls getrlimit and setrlimit, which are
+    // async-signal-safe
+    unsafe {
+        command.pre_exec(move || {
+            for (resource, soft, slack) in wanted {
+                if soft == 0 {
+                    continue;
+                }
+                let mut current = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+                if libc::getrlimit(resource, &mut current) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let hard = (soft.saturating_add(slack) as libc::rlim_t).min(current.rlim_max);
+                let limit = libc::rlimit { rlim_cur: (soft as libc::rlim_t).min(hard), rlim_max: hard };
+                if libc::setrlimit(resource, &limit) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub fn restrict(_command: &mut Command, _limits: Rlimits) {}
+
+/// Copy a child's output to `sink` on a thread. Once more than `limit` bytes
+/// have been produced, the excess is dropped, the process `pid` is killed and
+/// the thread returns `true`.
+pub fn relay_output(
+    mut source: impl Read + Send + 'static,
+    mut sink: Box<dyn Write + Send>,
+    limit: u64,
+    pid: u32,
+) -> JoinHandle<io::Result<bool>> {
+    thread::spawn(move || {
+        let mut buffer = [0; 8192];
+        let mut written = 0u64;
+        loop {
+            let n = match source.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let allowed = (limit - written).min(n as u64) as usize;
+            // A reader that went away (`| head`) is not an error here
+            if sink.write_all(&buffer[..allowed]).and_then(|_| sink.flush()).is_err() {
+                return Ok(false);
+            }
+            written += allowed as u64;
+            if allowed < n {
+                kill(pid);
+                return Ok(true);
+            }
+        }
+    })
+}
+
+/// Wait for a relay thread, returning whether it stopped at its cap
+pub fn hit_output_cap(relay: JoinHandle<io::Result<bool>>) -> bool {
+    matches!(relay.join(), Ok(Ok(true)))
+}
+
+/// Wait for `child`, killing it if it is still running at `deadline`.
+/// Returns `None` when the child was killed for running too long.
+pub fn wait_until(child: &mut Child, deadline: Option<Instant>) -> io::Result<Option<ExitStatus>> {
+    let Some(deadline) = deadline else {
+        return child.wait().map(Some);
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Kill the process `pid` with SIGKILL (Unix only)
+#[cfg(unix)]
+pub fn kill(pid: u32) {
+    // SAFETY: sending a signal has no memory-safety requirements
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn kill(_pid: u32) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+    use std::sync::{Arc, Mutex};
+
+    /// A sink tests can read back after the relay finishes
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_wait_until_kills_at_deadline() {
+        let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+        let start = Instant::now();
+        let status = wait_until(&mut child, Some(start + Duration::from_millis(100))).unwrap();
+        assert!(status.is_none());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let mut child = Command::new("true").spawn().unwrap();
+        assert!(wait_until(&mut child, Some(Instant::now() + Duration::from_secs(5))).unwrap().unwrap().success());
+    }
+
+    #[test]
+    fn test_relay_output_truncates_and_kills() {
+        let mut child = Command::new("yes").stdout(Stdio::piped()).spawn().unwrap();
+        let sink = Shared::default();
+        let relay = relay_output(child.stdout.take().unwrap(), Box::new(sink.clone()), 10, child.id());
+        assert!(hit_output_cap(relay));
+        child.wait().unwrap();
+        assert_eq!(&*sink.0.lock().unwrap(), b"y\ny\ny\ny\ny\n");
+    }
+
+    /// Soft and hard memory, CPU, file size and open file limits of a child
+    /// started under `limits`, as read with getrlimit in the child itself
+    #[cfg(unix)]
+    fn applied(limits: Rlimits) -> [(libc::rlim_t, libc::rlim_t); 4] {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::process::CommandExt;
+
+        let (mut reader, writer) = io::pipe().unwrap();
+        let fd = writer.as_raw_fd();
+        let mut command = Command::new("true");
+        restrict(&mut command, limits);
+        // SAFETY: runs after restrict's hook and only calls getrlimit and
+        // write, which are async-signal-safe, on a stack buffer
+        unsafe {
+            command.pre_exec(move || {
+                let mut values: [libc::rlim_t; 8] = [0; 8];
+                let resources = [libc::RLIMIT_AS, libc::RLIMIT_CPU, libc::RLIMIT_FSIZE, libc::RLIMIT_NOFILE];
+                for (i, resource) in resources.into_iter().enumerate() {
+                    let mut current = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+                    if libc::getrlimit(resource, &mut current) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    values[2 * i] = current.rlim_cur;
+                    values[2 * i + 1] = current.rlim_max;
+                }
+                libc::write(fd, values.as_ptr().cast(), std::mem::size_of_val(&values));
+                Ok(())
+            });
+        }
+        assert!(command.status().unwrap().success());
+        drop(writer);
+
+        const SIZE: usize = std::mem::size_of::<libc::rlim_t>();
+        let mut bytes = [0; 8 * SIZE];
+        reader.read_exact(&mut bytes).unwrap();
+        let value = |i: usize| libc::rlim_t::from_ne_bytes(bytes[SIZE * i..SIZE * (i + 1)].try_into().unwrap());
+        std::array::from_fn(|i| (value(2 * i), value(2 * i + 1)))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restrict_memory() {
+        const MB: libc::rlim_t = 1024 * 1024;
+        assert_eq!(applied(Rlimits { memory_mb: 64, ..Rlimits::default() })[0], (64 * MB, 64 * MB));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restrict_sets_rlimits_within_hard_limits() {
+        const MB: libc::rlim_t = 1024 * 1024;
+        let limits = Rlimits { memory_mb: 1024, cpu_secs: 5, file_size_mb: 1, open_files: 64 }.with_memory(256);
+        assert_eq!((limits.memory_mb, Rlimits::default().with_memory(64).memory_mb), (256, 64));
+        assert_eq!(applied(limits), [(256 * MB, 256 * MB), (5, 6), (MB, MB), (64, 64)]);
+
+        // More than the shell may have is held to what it has
+        let mut command = Command::new("true");
+        restrict(&mut command, Rlimits { open_files: u64::MAX / 2, ..Rlimits::default() });
+        assert!(command.status().unwrap().success());
+    }
+}
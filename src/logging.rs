@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::sync::{Mutex, OnceLock};
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use crate::config::LoggingConfig;
+use crate::error::{ShellError, ShellResult};
+
+/// Crate prefix used for per-module filter directives
+const CRATE_TARGET: &str = "shell_t";
+
+/// Levels accepted by `log-level`
+const LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+/// Current filter state, kept so single directives can be changed live
+struct LogState {
+    level: String,
+    modules: BTreeMap<String, String>,
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+static STATE: OnceLock<Mutex<LogState>> = OnceLock::new();
+
+/// Install the global tracing subscriber from `[logging]`
+pub fn init(config: &LoggingConfig) -> ShellResult<()> {
+    let level = normalize_level(&config.level)?;
+    let mut modules = BTreeMap::new();
+    for (module, module_level) in &config.modules {
+        modules.insert(module.clone(), normalize_level(module_level)?);
+    }
+
+    let filter = EnvFilter::try_new(directives(&level, &modules))
+        .map_err(|e| ShellError::Config(format!("invalid logging filter: {}", e)))?;
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let (file_layer, stderr_layer) = match &config.file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            (Some(fmt::layer().with_ansi(false).with_writer(Mutex::new(file))), None)
+        }
+        None => (None, Some(fmt::layer().with_writer(std::io::stderr))),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(file_layer)
+        .with(stderr_layer)
+        .try_init()
+        .map_err(|e| ShellError::Config(format!("logging already initialized: {}", e)))?;
+
+    let _ = STATE.set(Mutex::new(LogState { level, modules, handle }));
+    Ok(())
+}
+
+/// Change verbosity live: `debug` sets the default, `executor=trace` one module
+pub fn set_level(spec: &str) -> ShellResult<()> {
+    let state = STATE
+        .get()
+        .ok_or_else(|| ShellError::Config("logging is not initialized".to_string()))?;
+    let mut state = state.lock().unwrap();
+
+    match spec.split_once('=') {
+        Some((module, level)) => {
+            let level = normalize_level(level)?;
+            state.modules.insert(module.trim().to_string(), level);
+        }
+        None => state.level = normalize_level(spec)?,
+    }
+
+    let filter = EnvFilter::try_new(directives(&state.level, &state.modules))
+        .map_err(|e| ShellError::Config(format!("invalid logging filter: {}", e)))?;
+    state
+        .handle
+        .reload(filter)
+        .map_err(|e| ShellError::Config(format!("failed to change log level: {}", e)))
+}
+
+/// Describe the active levels, e.g. `warn (executor=debug)`
+pub fn describe() -> String {
+    match STATE.get() {
+        Some(state) => {
+            let state = state.lock().unwrap();
+            if state.modules.is_empty() {
+                state.level.clone()
+            } else {
+                let modules: Vec<String> = state.modules.iter().map(|(m, l)| format!("{}={}", m, l)).collect();
+                format!("{} ({})", state.level, modules.join(", "))
+            }
+        }
+        None => "off".to_string(),
+    }
+}
+
+fn normalize_level(level: &str) -> ShellResult<String> {
+    let level = level.trim().to_ascii_lowercase();
+    if LEVELS.contains(&level.as_str()) {
+        Ok(level)
+    } else {
+        Err(ShellError::Config(format!(
+            "unknown log level '{}', expected one of: {}",
+            level,
+            LEVELS.join(", ")
+        )))
+    }
+}
+
+/// Build an EnvFilter directive string, scoping module names to this crate
+fn directives(level: &str, modules: &BTreeMap<String, String>) -> String {
+    let mut parts = vec![level.to_string()];
+    for (module, module_level) in modules {
+        if module.contains("::") {
+            parts.push(format!("{}={}", module, module_level));
+        } else {
+            parts.push(format!("{}::{}={}", CRATE_TARGET, module, module_level));
+        }
+    }
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directives_scope_modules_to_crate() {
+        let mut modules = BTreeMap::new();
+        modules.insert("executor".to_string(), "debug".to_string());
+        modules.insert("tokio::process".to_string(), "trace".to_string());
+
+        assert_eq!(
+            directives("warn", &modules),
+            "warn,shell_t::executor=debug,tokio::process=trace"
+        );
+    }
+
+    #[test]
+    fn test_normalize_level() {
+        assert_eq!(normalize_level(" DEBUG ").unwrap(), "debug");
+        assert!(normalize_level("loud").is_err());
+    }
+}
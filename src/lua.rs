@@ -0,0 +1,230 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use mlua::{Function, HookTriggers, Lua, LuaOptions, StdLib, Table, Variadic};
+
+use crate::builtins::BuiltinCommand;
+use crate::config::LuaConfig;
+use crate::error::{ShellError, ShellResult};
+
+/// Registry tables holding functions registered by user scripts
+const BUILTINS: &str = "shell_t_builtins";
+const PROMPT_SEGMENTS: &str = "shell_t_prompt_segments";
+const COMPLETIONS: &str = "shell_t_completions";
+
+/// Instructions between checks of the per-call budget
+const HOOK_INTERVAL: u32 = 1000;
+
+/// Lua runtime for user builtins, prompt segments and completion functions.
+///
+/// Scripts get a restricted standard library: no `io`, `os`, `package` or
+/// `debug`, so they cannot touch files or spawn processes. The only bridge
+/// to the shell is the `shell` table.
+pub struct LuaRuntime {
+    lua: Lua,
+    /// Instructions left for the current call
+    budget: Rc<Cell<u64>>,
+    instruction_limit: u64,
+}
+
+impl LuaRuntime {
+    /// Create a sandboxed runtime with the `shell` API installed
+    pub fn new(config: &LuaConfig) -> ShellResult<Self> {
+        let libs = StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8 | StdLib::COROUTINE;
+        let lua = Lua::new_with(libs, LuaOptions::default()).map_err(lua_error)?;
+        lua.set_memory_limit(config.memory_limit_mb * 1024 * 1024).map_err(lua_error)?;
+
+        let budget = Rc::new(Cell::new(config.instruction_limit));
+        let hook_budget = Rc::clone(&budget);
+        lua.set_hook(HookTriggers::new().every_nth_instruction(HOOK_INTERVAL), move |_, _| {
+            let left = hook_budget.get().saturating_sub(u64::from(HOOK_INTERVAL));
+            hook_budget.set(left);
+            if left == 0 {
+                return Err(mlua::Error::RuntimeError("instruction limit exceeded".to_string()));
+            }
+            Ok(())
+        });
+
+        let runtime = Self { lua, budget, instruction_limit: config.instruction_limit };
+        runtime.install_api(config).map_err(lua_error)?;
+        Ok(runtime)
+    }
+
+    /// Load every `*.lua` file in `dir`, in name order
+    pub fn load_dir(&self, dir: &Path) -> ShellResult<usize> {
+        let mut files: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+                .collect(),
+            Err(_) => return Ok(0),
+        };
+        files.sort();
+
+        for path in &files {
+            let source = fs::read_to_string(path)?;
+            self.exec(&source, &path.display().to_string())?;
+            tracing::debug!(path = %path.display(), "loaded lua script");
+        }
+        Ok(files.len())
+    }
+
+    /// Run a chunk of Lua source
+    pub fn exec(&self, source: &str, name: &str) -> ShellResult<()> {
+        self.budget.set(self.instruction_limit);
+        self.lua.load(source).set_name(name).exec().map_err(lua_error)
+    }
+
+    /// Whether a Lua builtin called `name` is registered
+    pub fn has_builtin(&self, name: &str) -> bool {
+        self.registry(BUILTINS)
+            .and_then(|table| table.contains_key(name))
+            .unwrap_or(false)
+    }
+
+    /// Call a Lua builtin; it may return output text and an exit status
+    pub fn call_builtin(&self, name: &str, args: &[String]) -> ShellResult<(Option<String>, i32)> {
+        let function: Function = self.registry(BUILTINS).and_then(|t| t.get(name)).map_err(lua_error)?;
+        self.budget.set(self.instruction_limit);
+        let (output, status): (Option<String>, Option<i32>) =
+            function.call(Variadic::from_iter(args.iter().cloned())).map_err(lua_error)?;
+        Ok((output, status.unwrap_or(0)))
+    }
+
+    /// Render the registered prompt segments, skipping any that fail
+    pub fn prompt_segments(&self) -> Vec<String> {
+        let Ok(segments) = self.registry(PROMPT_SEGMENTS) else {
+            return Vec::new();
+        };
+        segments
+            .sequence_values::<Function>()
+            .filter_map(|function| {
+                self.budget.set(self.instruction_limit);
+                match function.and_then(|f| f.call::<_, Option<String>>(())) {
+                    Ok(text) => text.filter(|t| !t.is_empty()),
+                    Err(e) => {
+                        tracing::warn!(error = %e, "prompt segment failed");
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Candidates from the completion function registered for `command`, or
+    /// `None` when there is none
+    pub fn complete(&self, command: &str, word: &str, args: &[String]) -> ShellResult<Option<Vec<String>>> {
+        let function: Option<Function> =
+            self.registry(COMPLETIONS).and_then(|t| t.get(command)).map_err(lua_error)?;
+        let Some(function) = function else {
+            return Ok(None);
+        };
+        self.budget.set(self.instruction_limit);
+        function.call((word, args.to_vec())).map(Some).map_err(lua_error)
+    }
+
+    fn registry(&self, name: &str) -> mlua::Result<Table<'_>> {
+        self.lua.named_registry_value(name)
+    }
+
+    /// Install the `shell` table: `builtin`, `prompt_segment`, `complete`, `getenv`
+    fn install_api(&self, config: &LuaConfig) -> mlua::Result<()> {
+        let lua = &self.lua;
+        for name in [BUILTINS, PROMPT_SEGMENTS, COMPLETIONS] {
+            lua.set_named_registry_value(name, lua.create_table()?)?;
+        }
+
+        let shell = lua.create_table()?;
+        shell.set(
+            "builtin",
+            lua.create_function(|lua, (name, function): (String, Function)| {
+                if BuiltinCommand::is_builtin(&name) {
+                    return Err(mlua::Error::RuntimeError(format!("cannot override builtin '{}'", name)));
+                }
+                lua.named_registry_value::<Table>(BUILTINS)?.set(name, function)
+            })?,
+        )?;
+        shell.set(
+            "prompt_segment",
+            lua.create_function(|lua, function: Function| {
+                lua.named_registry_value::<Table>(PROMPT_SEGMENTS)?.push(function)
+            })?,
+        )?;
+        shell.set(
+            "complete",
+            lua.create_function(|lua, (command, function): (String, Function)| {
+                lua.named_registry_value::<Table>(COMPLETIONS)?.set(command, function)
+            })?,
+        )?;
+        if config.allow_env {
+            shell.set(
+                "getenv",
+                lua.create_function(|_, name: String| Ok(std::env::var(name).ok()))?,
+            )?;
+        }
+        lua.globals().set("shell", shell)
+    }
+}
+
+fn lua_error(e: mlua::Error) -> ShellError {
+    ShellError::CommandExecution(format!("lua: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime() -> LuaRuntime {
+        LuaRuntime::new(&LuaConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn test_builtin_registration_and_call() {
+        let lua = runtime();
+        lua.exec(
+            r#"shell.builtin("greet", function(name) return "hello " .. name, 3 end)"#,
+            "test",
+        )
+        .unwrap();
+
+        assert!(lua.has_builtin("greet"));
+        assert_eq!(lua.call_builtin("greet", &["t".to_string()]).unwrap(), (Some("hello t".to_string()), 3));
+        assert!(lua.exec(r#"shell.builtin("cd", function() end)"#, "test").is_err());
+    }
+
+    #[test]
+    fn test_restricted_stdlib() {
+        let lua = runtime();
+        assert!(lua.exec("os.execute('true')", "test").is_err());
+        assert!(lua.exec("io.open('/etc/passwd')", "test").is_err());
+        assert!(lua.exec("require('os')", "test").is_err());
+    }
+
+    #[test]
+    fn test_instruction_limit_stops_runaway_scripts() {
+        let lua = LuaRuntime::new(&LuaConfig { instruction_limit: 10_000, ..LuaConfig::default() }).unwrap();
+        assert!(lua.exec("while true do end", "test").is_err());
+        // The budget resets for the next call
+        assert!(lua.exec("local x = 1", "test").is_ok());
+    }
+
+    #[test]
+    fn test_prompt_segments_and_completion() {
+        let lua = runtime();
+        lua.exec(
+            r#"
+            shell.prompt_segment(function() return "[dev]" end)
+            shell.prompt_segment(function() error("broken") end)
+            shell.complete("git", function(word) return { word .. "status", word .. "stash" } end)
+            "#,
+            "test",
+        )
+        .unwrap();
+
+        assert_eq!(lua.prompt_segments(), vec!["[dev]"]);
+        assert_eq!(lua.complete("git", "s", &[]).unwrap(), Some(vec!["sstatus".to_string(), "sstash".to_string()]));
+        assert!(lua.complete("ls", "", &[]).unwrap().is_none());
+    }
+}
@@ -1,340 +1,122 @@
-use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
-use crossterm::ExecutableCommand;
-use std::io::{self, Write};
-use std::process::Command;
-use std::sync::Arc;
-
-mod parser;
-mod security;
-mod builtins;
-mod executor;
-mod ui;
-mod config;
-mod error;
-
-use error::ShellResult;
-
-fn main() -> ShellResult<()> {
-    println!("Shell-T - Secure Multi-Language Terminal");
-    println!("Type 'exit' to quit\n");
-
-    // Initialize configuration
-    let config = config::Config::default();
-
-    // Initialize security manager
-    let security = Arc::new(security::SecurityManager::new());
-
-    // Initialize managers
-    let builtin_manager = builtins::BuiltinManager::new(Arc::clone(&security), config.clone());
-    let executor = executor::CommandExecutor::new(Arc::clone(&security), config.clone());
-    let ui_manager = ui::UiManager::new(config.clone());
-
-    loop {
-        // Display prompt using UI manager
-        if let Err(e) = ui_manager.display_prompt() {
-            eprintln!("UI error: {}", e);
-            break;
-        }
-
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let input = input.trim();
-
-        if input.is_empty() {
-            continue;
+use std::io::{self, IsTerminal};
+
+use shell_t::{cli, config, debug, jobs, logging, schedule, server, session, signals, telemetry, ui, warnings};
+
+fn main() {
+    let mut args = std::env::args();
+    // login(1) starts login shells with a leading dash, e.g. "-shell-t"
+    let dash_login = args.next().is_some_and(|argv0| argv0.starts_with('-'));
+    let options = match cli::CliOptions::parse(args) {
+        Ok(mut options) => {
+            options.login |= dash_login;
+            options
         }
-
-        if input == "exit" {
-            println!("Goodbye!");
-            break;
+        Err(e) => {
+            eprintln!("shell-t: {}", e);
+            std::process::exit(2);
         }
+    };
 
-        match parser::parse_command(input) {
-            Ok(commands) => {
-                if let Err(e) = execute_commands(&commands, &builtin_manager, &executor) {
-                    eprintln!("Error: {}", e);
-                }
-            }
-            Err(e) => {
-                eprintln!("Parse error: {}", e);
-            }
-        }
-    }
-
-    Ok(())
-}
-
-fn execute_commands(
-    commands: &[parser::Command],
-    builtin_manager: &builtins::BuiltinManager,
-    executor: &executor::CommandExecutor,
-) -> ShellResult<()> {
-    if commands.is_empty() {
-        return Ok(());
-    }
-
-    // Handle single command (no pipeline)
-    if commands.len() == 1 {
-        let cmd = &commands[0];
-        if cmd.program.is_empty() {
-            return Ok(());
-        }
-
-        // Try builtin commands first
-        if let Some(result) = builtin_manager.execute_builtin(&cmd.program, &cmd.args)? {
-            match result {
-                builtins::BuiltinResult::Success(msg) => {
-                    if let Some(msg) = msg {
-                        println!("{}", msg);
-                    }
-                }
-                builtins::BuiltinResult::Error(msg) => {
-                    eprintln!("{}", msg);
-                }
-                builtins::BuiltinResult::Info(msg) => {
-                    println!("{}", msg);
-                }
-                builtins::BuiltinResult::Warning(msg) => {
-                    eprintln!("Warning: {}", msg);
-                }
-                builtins::BuiltinResult::Exit => {
-                    std::process::exit(0);
-                }
-            }
-            return Ok(());
-        }
-
-        // Not a builtin, execute as external command
-        return executor.execute_pipeline(commands);
-    }
-
-    // Handle pipeline
-    executor.execute_pipeline(commands)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use std::path::Path;
-    use std::sync::Arc;
-
-    fn create_test_managers() -> (builtins::BuiltinManager, executor::CommandExecutor) {
-        let config = config::Config::default();
-        let security = Arc::new(security::SecurityManager::new());
-        let builtin_manager = builtins::BuiltinManager::new(Arc::clone(&security), config.clone());
-        let executor = executor::CommandExecutor::new(security, config);
-        (builtin_manager, executor)
-    }
-
-    #[test]
-    fn test_execute_commands_empty() {
-        let commands = Vec::new();
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
+    // Initialize configuration
+    let (mut config, load_error) = match config::Config::load() {
+        Ok(config) => (config, None),
+        Err(e) => (config::Config::default(), Some(e.to_string())),
+    };
+    config.warnings.suppressed.extend(options.no_warn.iter().cloned());
+    if options.no_cache {
+        config.parse_cache.enabled = false;
     }
 
-    #[test]
-    fn test_execute_commands_builtin_cd() {
-        let original_dir = std::env::current_dir().unwrap();
-
-        let commands = vec![parser::Command {
-            program: "cd".to_string(),
-            args: vec!["/tmp".to_string()],
-            input_redirect: None,
-            output_redirect: None,
-            append: false,
-            background: false,
-        }];
-
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
-
-        // Change back to original directory
-        std::env::set_current_dir(original_dir).unwrap();
+    // Initialize warnings before anything can raise one
+    warnings::init(config.clone());
+    if let Some(e) = load_error {
+        warnings::warn(
+            warnings::WarningLevel::Warning,
+            warnings::WarningCategory::Config,
+            &format!("failed to load configuration, using defaults: {}", e),
+        );
     }
-
-    #[test]
-    fn test_execute_commands_builtin_pwd() {
-        let commands = vec![parser::Command {
-            program: "pwd".to_string(),
-            args: vec![],
-            input_redirect: None,
-            output_redirect: None,
-            append: false,
-            background: false,
-        }];
-
-        let result = execute_commands(&commands);
-        assert!(result.is_ok());
+    telemetry::init(&config.telemetry);
+    if let Err(e) = logging::init(&config.logging) {
+        warnings::warn(warnings::WarningLevel::Warning, warnings::WarningCategory::Config, &e.to_string());
     }
-
-    #[test]
-    fn test_execute_commands_external() {
-        let commands = vec![parser::Command {
-            program: "echo".to_string(),
-            args: vec!["test".to_string()],
-            input_redirect: None,
-            output_redirect: None,
-            append: false,
-            background: false,
-        }];
-
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
+    for category in &options.no_warn {
+        if warnings::WarningCategory::from_name(category).is_none() {
+            warnings::warn(
+                warnings::WarningLevel::Warning,
+                warnings::WarningCategory::Config,
+                &format!("unknown warning category: {}", category),
+            );
+        }
     }
-
-    #[test]
-    fn test_execute_commands_with_input_redirection() {
-        // Create a temporary test file
-        let test_content = "Hello, World!";
-        fs::write("test_input.txt", test_content).unwrap();
-
-        let commands = vec![parser::Command {
-            program: "cat".to_string(),
-            args: vec![],
-            input_redirect: Some("test_input.txt".to_string()),
-            output_redirect: None,
-            append: false,
-            background: false,
-        }];
-
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
-
-        // Clean up
-        fs::remove_file("test_input.txt").unwrap();
+    if let Err(e) = config.validate() {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(2);
     }
-
-    #[test]
-    fn test_execute_commands_with_output_redirection() {
-        let commands = vec![parser::Command {
-            program: "echo".to_string(),
-            args: vec!["test output".to_string()],
-            input_redirect: None,
-            output_redirect: Some("test_output.txt".to_string()),
-            append: false,
-            background: false,
-        }];
-
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
-
-        // Verify file was created
-        assert!(Path::new("test_output.txt").exists());
-
-        // Clean up
-        fs::remove_file("test_output.txt").unwrap();
+    let interactive =
+        options.command.is_none() && options.script.is_none() && !options.kernel && io::stdin().is_terminal();
+    config.check_interpreters(warnings::global(), interactive);
+
+    signals::init();
+    if let Some(path) = &options.serve {
+        let result = server::Server::new(config).and_then(|server| server.serve(path));
+        if let Err(e) = result {
+            eprintln!("shell-t: --serve {}: {}", path.display(), e);
+        }
+        std::process::exit(1);
     }
-
-    #[test]
-    fn test_execute_commands_with_append_redirection() {
-        // Create initial file
-        fs::write("test_append.txt", "initial content\n").unwrap();
-
-        let commands = vec![parser::Command {
-            program: "echo".to_string(),
-            args: vec!["appended content".to_string()],
-            input_redirect: None,
-            output_redirect: Some("test_append.txt".to_string()),
-            append: true,
-            background: false,
-        }];
-
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
-
-        // Verify content was appended
-        let content = fs::read_to_string("test_append.txt").unwrap();
-        assert!(content.contains("initial content"));
-        assert!(content.contains("appended content"));
-
-        // Clean up
-        fs::remove_file("test_append.txt").unwrap();
+    let run_schedule = config.schedule.enabled;
+    let mut session = session::Session::new(config);
+    jobs::start_reaper(session.jobs());
+    if options.scheduler {
+        schedule::run();
+        std::process::exit(1);
     }
-
-    #[test]
-    fn test_execute_commands_multiple_commands() {
-        let commands = vec![
-            parser::Command {
-                program: "echo".to_string(),
-                args: vec!["first".to_string()],
-                input_redirect: None,
-                output_redirect: None,
-                append: false,
-                background: false,
-            },
-            parser::Command {
-                program: "echo".to_string(),
-                args: vec!["second".to_string()],
-                input_redirect: None,
-                output_redirect: None,
-                append: false,
-                background: false,
-            },
-        ];
-
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
+    if interactive && run_schedule {
+        schedule::start();
     }
+    session.source_startup_files(options.login, interactive);
 
-    #[test]
-    fn test_execute_commands_skip_empty_program() {
-        let commands = vec![
-            parser::Command {
-                program: "".to_string(),
-                args: vec![],
-                input_redirect: None,
-                output_redirect: None,
-                append: false,
-                background: false,
-            },
-            parser::Command {
-                program: "echo".to_string(),
-                args: vec!["test".to_string()],
-                input_redirect: None,
-                output_redirect: None,
-                append: false,
-                background: false,
-            },
-        ];
-
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
+    if options.debug {
+        session.attach_debugger(debug::Debugger::new(Box::new(io::stdin().lock())));
     }
 
-    #[test]
-    fn test_parser_integration() {
-        // Test that parser and executor work together
-        let input = "echo hello world";
-        let commands = parser::parse_command(input).unwrap();
-
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
+    // As in `sh -c CMD NAME ARGS...`, the first argument after a command string is `$0`
+    match (&options.command, &options.script) {
+        (Some(_), _) => {
+            let mut args = options.script_args.clone();
+            let name = if args.is_empty() { String::new() } else { args.remove(0) };
+            session.set_positional(&name, args);
+        }
+        (None, Some(path)) => session.set_positional(&path.display().to_string(), options.script_args.clone()),
+        (None, None) => {}
     }
 
-    #[test]
-    fn test_parser_integration_with_redirection() {
-        let input = "echo test > output.txt";
-        let commands = parser::parse_command(input).unwrap();
-
-        let (builtin_manager, executor) = create_test_managers();
-        let result = execute_commands(&commands, &builtin_manager, &executor);
-        assert!(result.is_ok());
-
-        // Verify file was created
-        assert!(Path::new("output.txt").exists());
+    let status = match (&options.command, &options.script) {
+        _ if options.kernel => session.run_kernel(),
+        (Some(command), _) => session.run_command(command),
+        (None, Some(path)) => match session.run_script(path) {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("shell-t: {}: {}", path.display(), e);
+                127
+            }
+        },
+        (None, None) if !interactive => match session.run_batch() {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("shell-t: {}", e);
+                1
+            }
+        },
+        (None, None) => {
+            ui::prepare_terminal();
+            println!("Shell-T - Secure Multi-Language Terminal");
+            println!("Type 'exit' to quit\n");
+            session.repl()
+        }
+    };
 
-        // Clean up
-        fs::remove_file("output.txt").unwrap();
-    }
+    session.shutdown();
+    std::process::exit(status)
 }
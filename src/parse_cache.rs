@@ -0,0 +1,216 @@
+use std::fs;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ParseCacheConfig};
+use crate::error::ShellResult;
+use crate::inline;
+use crate::parser::{self, SplitError};
+use crate::remote::sha256_hex;
+use crate::script::{self, Node};
+
+/// Version of the cached form of statements. Bump it whenever `Statement`
+/// or `script::Node` changes, so entries written in the old form are reparsed
+/// rather than misread.
+const PARSE_FORMAT: u32 = 1;
+
+/// One complete statement of a script: a line, a whole compound command or a
+/// fenced block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Statement {
+    /// Line the statement starts on, for error reports and breakpoints
+    pub line: usize,
+    pub text: String,
+    /// Parsed form; `None` for fenced blocks and text that fails to parse,
+    /// which are handled (and their errors reported) when run
+    pub nodes: Option<Vec<Node>>,
+}
+
+impl Statement {
+    /// Parse `text`, starting on `line`
+    pub fn new(line: usize, text: String) -> Self {
+        let nodes = match inline::parse(&text) {
+            Some(_) => None,
+            None => script::parse_script(&text).ok(),
+        };
+        Self { line, text, nodes }
+    }
+}
+
+/// Whether `input` needs more lines: an open compound command or fenced block
+pub fn is_incomplete(input: &str) -> bool {
+    inline::is_incomplete(input) || (inline::parse(input).is_none() && script::is_incomplete(input))
+}
+
+/// Whether `input` ends inside a here-document or quotes
+fn in_text(input: &str) -> bool {
+    matches!(parser::split_sequence(input), Err(SplitError::Unterminated(_) | SplitError::Unclosed(_)))
+}
+
+/// Groups script lines into statements as they are read
+#[derive(Debug, Default)]
+pub struct Splitter {
+    buffer: String,
+    start: usize,
+}
+
+impl Splitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add line `index` (0-based), returning a statement once one is complete
+    pub fn push(&mut self, index: usize, raw: &str) -> Option<Statement> {
+        // Inside a fenced block, here-document or quotes, lines are kept untouched
+        let in_fence = inline::is_incomplete(&self.buffer) || in_text(&self.buffer);
+        let line = if in_fence { raw } else { raw.trim() };
+        // Blank lines, comments and the shebang
+        if !in_fence && (line.starts_with('#') || (self.buffer.is_empty() && line.is_empty())) {
+            return None;
+        }
+        if self.buffer.is_empty() {
+            self.start = index + 1;
+        }
+
+        // Compound commands span lines; they are complete once closed
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        if is_incomplete(&self.buffer) {
+            return None;
+        }
+        Some(Statement::new(self.start, std::mem::take(&mut self.buffer)))
+    }
+
+    /// The unterminated statement left at the end of input, if any
+    pub fn finish(self) -> Option<Statement> {
+        (!self.buffer.is_empty()).then(|| Statement::new(self.start, self.buffer))
+    }
+}
+
+/// Split a whole script into statements
+pub fn split(reader: impl BufRead) -> ShellResult<Vec<Statement>> {
+    let mut splitter = Splitter::new();
+    let mut statements = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        statements.extend(splitter.push(index, &line?));
+    }
+    statements.extend(splitter.finish());
+    Ok(statements)
+}
+
+/// Cached statements of one script, valid while the script's contents hash
+/// the same
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// `PARSE_FORMAT` when the entry was written
+    format: u32,
+    path: PathBuf,
+    /// SHA-256 of the script the statements were parsed from
+    sha256: String,
+    statements: Vec<Statement>,
+}
+
+/// Statements of the script at `path`, parsed once and cached on disk.
+///
+/// Entries are stored by the script's path and checked against a hash of its
+/// contents, so any edit invalidates its entry. Cache failures are logged and
+/// otherwise ignored.
+pub fn load(path: &Path, config: &ParseCacheConfig) -> ShellResult<Vec<Statement>> {
+    let source = fs::read(path)?;
+    let cache = match (config.enabled, cache_file(path, config)) {
+        (true, Some(cache)) => cache,
+        _ => return split(source.as_slice()),
+    };
+
+    let sha256 = sha256_hex(&source);
+    let cached = fs::read(&cache).ok().and_then(|data| serde_json::from_slice::<Entry>(&data).ok());
+    if let Some(entry) = cached {
+        if entry.format == PARSE_FORMAT && entry.sha256 == sha256 {
+            tracing::debug!(path = %path.display(), "using cached parse");
+            return Ok(entry.statements);
+        }
+    }
+
+    let statements = split(source.as_slice())?;
+    let entry = Entry { format: PARSE_FORMAT, path: path.to_path_buf(), sha256, statements };
+    if let Err(e) = store(&cache, &entry) {
+        tracing::debug!(path = %cache.display(), error = %e, "could not write parse cache");
+    }
+    Ok(entry.statements)
+}
+
+/// Cache file for the script at `path`
+fn cache_file(path: &Path, config: &ParseCacheConfig) -> Option<PathBuf> {
+    let dir = config.dir.clone().or_else(|| config::cache_dir().map(|dir| dir.join("parse")))?;
+    let path = fs::canonicalize(path).ok()?;
+    Some(dir.join(format!("{}.json", sha256_hex(path.to_string_lossy().as_bytes()))))
+}
+
+/// Write an entry, replacing any previous one in a single rename
+fn store(cache: &Path, entry: &Entry) -> std::io::Result<()> {
+    if let Some(dir) = cache.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = cache.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&partial, serde_json::to_vec(entry)?)?;
+    fs::rename(partial, cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_split_groups_compound_commands() {
+        let script = "#!/usr/bin/env shell-t\necho a\n\nif true\nthen echo b\nfi\n# done\nfor x in a; do\n";
+        let statements = split(Cursor::new(script)).unwrap();
+        let lines: Vec<(usize, &str)> = statements.iter().map(|s| (s.line, s.text.as_str())).collect();
+        assert_eq!(lines, vec![(2, "echo a\n"), (4, "if true\nthen echo b\nfi\n"), (8, "for x in a; do\n")]);
+        assert!(statements[1].nodes.is_some());
+        // The unterminated loop is left for the runner to report
+        assert!(statements[2].nodes.is_none());
+
+        // Lines inside quotes are kept as they are
+        let statements = split(Cursor::new("echo \"a\n  # b\n\"\n")).unwrap();
+        assert_eq!(statements[0].text, "echo \"a\n  # b\n\"\n");
+    }
+
+    #[test]
+    fn test_load_caches_until_the_script_changes() {
+        let dir = std::env::temp_dir().join(format!("shell-t-parse-cache-{}", std::process::id()));
+        let script = dir.join("rc.sht");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&script, "echo one\n").unwrap();
+        let config = ParseCacheConfig { enabled: true, dir: Some(dir.join("cache")) };
+
+        let first = load(&script, &config).unwrap();
+        let cache = cache_file(&script, &config).unwrap();
+        assert!(cache.exists());
+        assert_eq!(load(&script, &config).unwrap(), first);
+
+        // Entries are used only for the contents and format they were written for
+        let mut entry: Entry = serde_json::from_slice(&fs::read(&cache).unwrap()).unwrap();
+        entry.statements[0].text = "echo cached\n".to_string();
+        store(&cache, &entry).unwrap();
+        assert_eq!(load(&script, &config).unwrap()[0].text, "echo cached\n");
+        entry.format = PARSE_FORMAT + 1;
+        store(&cache, &entry).unwrap();
+        assert_eq!(load(&script, &config).unwrap(), first);
+
+        // An edit keeping the size and mtime is still seen
+        let modified = fs::metadata(&script).unwrap().modified().unwrap();
+        fs::write(&script, "echo two\n").unwrap();
+        fs::File::options().write(true).open(&script).unwrap().set_modified(modified).unwrap();
+        assert_eq!(load(&script, &config).unwrap()[0].text, "echo two\n");
+
+        fs::write(&script, "echo one\necho two\n").unwrap();
+        let statements = load(&script, &config).unwrap();
+        assert_eq!(statements.len(), 2);
+        assert_eq!(statements[0].text, "echo one\n");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
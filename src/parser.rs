@@ -1,5 +1,10 @@
+use std::ops::Range;
 use std::process::Stdio;
 
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ErrorContext, ErrorStage, ShellError, ShellResult, Span};
+
 /// Represents a parsed command with its arguments and redirections
 #[derive(Debug, Clone)]
 pub struct Command {
@@ -8,62 +13,700 @@ pub struct Command {
     pub input_redirect: Option<String>,
     pub output_redirect: Option<String>,
     pub append: bool,
-    #[allow(dead_code)]
+    /// `>|`: the output file is replaced even under `set -C`
+    pub clobber: bool,
+    /// File stderr goes to with `2>`, or `2>>` when `stderr_append` is set
+    pub stderr_redirect: Option<String>,
+    pub stderr_append: bool,
+    /// `2>&1` or `&>`: stderr goes wherever stdout does
+    pub stderr_to_stdout: bool,
+    /// Text fed to stdin by a here-string (`<<< WORD`), which is also what
+    /// a here-document becomes
+    pub here_doc: Option<String>,
+    /// Arguments with unquoted wildcards, by index into `args`, as glob
+    /// patterns in which quoted wildcard characters are escaped as `[*]`
+    pub globs: Vec<(usize, String)>,
+    /// Other descriptors redirected, like `3> log` or `>&2`, in order
+    pub fd_redirects: Vec<FdRedirect>,
+    /// `NAME=VALUE` words before the program, set only in its environment
+    pub env: Vec<(String, String)>,
     pub background: bool,
+    /// Where the program and each argument are in the parsed line, for
+    /// errors; empty for commands not parsed from a line
+    pub spans: Vec<Span>,
+}
+
+impl Command {
+    /// Where word `index` is in the parsed line: 0 is the program and
+    /// `i + 1` is `args[i]`
+    pub fn span_of(&self, index: usize) -> Option<Span> {
+        self.spans.get(index).copied()
+    }
+}
+
+/// What a redirected descriptor is connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectMode {
+    /// `N< FILE`
+    Read,
+    /// `N> FILE`
+    Write,
+    /// `N>> FILE`
+    Append,
+    /// `N>&M` or `N<&M`: a copy of descriptor `M`
+    Duplicate,
+    /// `N>&-`
+    Close,
+}
+
+/// A redirection of descriptor `fd`; `target` is the file, or the
+/// descriptor copied for `Duplicate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FdRedirect {
+    pub fd: i32,
+    pub target: String,
+    pub mode: RedirectMode,
+}
+
+impl FdRedirect {
+    /// Parse `N>`, `N>>`, `N<` (which need a file after them), `N>&M`,
+    /// `N<&M` and `N>&-`. `N` defaults to 1 for `>&` and 0 for `<&`.
+    fn parse(operator: &str) -> Option<(i32, RedirectMode, Option<String>)> {
+        let digits = operator.len() - operator.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (fd, rest) = operator.split_at(digits);
+        let fd = match (fd.parse().ok(), rest.starts_with('<')) {
+            (Some(fd), _) => fd,
+            (None, true) => 0,
+            (None, false) => 1,
+        };
+        let (mode, target) = match rest {
+            ">" => (RedirectMode::Write, None),
+            ">>" => (RedirectMode::Append, None),
+            "<" => (RedirectMode::Read, None),
+            ">&-" | "<&-" => (RedirectMode::Close, Some(String::new())),
+            _ => {
+                let target = rest.strip_prefix(">&").or_else(|| rest.strip_prefix("<&"))?;
+                if target.is_empty() || !target.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                (RedirectMode::Duplicate, Some(target.to_string()))
+            }
+        };
+        // Bare `>`, `>>` and `<` are handled as stdout and stdin
+        if digits == 0 && target.is_none() {
+            return None;
+        }
+        Some((fd, mode, target))
+    }
+}
+
+/// Length of the operator at the start of `rest`, which begins with `<` or
+/// `>`: `<<<`, `>>`, `>|`, a duplication like `>&2` or `<&-`, or the one
+/// character
+fn operator_len(rest: &str) -> usize {
+    if let Some(op) = ["<<<", ">>", ">|"].into_iter().find(|op| rest.starts_with(op)) {
+        return op.len();
+    }
+    let Some(target) = rest[1..].strip_prefix('&') else {
+        return 1;
+    };
+    let digits = target.len() - target.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    match digits {
+        0 if target.starts_with('-') => 3,
+        _ => 2 + digits,
+    }
+}
+
+/// Why a line failed to split into a sequence
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitError {
+    /// Input ended inside a here-document closed by this delimiter; more
+    /// lines may complete it
+    Unterminated(String),
+    /// Input ended in a `\` continuing its last line; another line completes it
+    Continued,
+    /// Input ended before this closing quote or `)` of a `$(`
+    Unclosed(char),
+    Invalid(String),
+}
+
+/// A `<<WORD` seen on the current line, waiting for the lines after it
+struct PendingHereDoc {
+    /// Which of the line's commands it feeds
+    command: usize,
+    /// Where the operator is in that command's text
+    range: Range<usize>,
+    delimiter: String,
+    /// The delimiter was unquoted, so variables in the body expand
+    expand: bool,
+    /// `<<-`: leading tabs are stripped from the body and the delimiter line
+    strip_tabs: bool,
+}
+
+/// Where a scan of a line has got to: inside quotes, inside `$(...)`, where
+/// quoting starts afresh, or inside a `( ... )` subshell; in the last two,
+/// separators don't count
+#[derive(Debug, Default)]
+pub struct Quoting {
+    quote: Option<char>,
+    /// For each enclosing `$(`: the quote it opened in, and the parentheses
+    /// opened inside it since
+    outer: Vec<(Option<char>, usize)>,
+    /// Subshells opened and not yet closed
+    groups: usize,
+    /// The current command has a word other than a keyword like `then`, so
+    /// a `(` no longer opens a subshell
+    started: bool,
+    /// The word being read while the command hasn't started
+    word: String,
+    prev: Option<char>,
+    /// The open single quote is a `$'...'`, in which `\'` doesn't close it
+    ansi: bool,
+    /// Just after a `\` outside single quotes, or inside `$'...'`
+    escaped: bool,
+}
+
+/// The characters a `\` escapes inside double quotes; before any other, it
+/// stays
+pub const DOUBLE_QUOTE_ESCAPES: &[char] = &['$', '`', '"', '\\', '\n'];
+
+impl Quoting {
+    /// Outside quotes, substitutions and subshells, where operators count
+    pub fn is_plain(&self) -> bool {
+        self.quote.is_none() && self.outer.is_empty() && self.groups == 0 && !self.escaped
+    }
+
+    /// Just after a `\`, so the next character is taken literally
+    pub fn escaped(&self) -> bool {
+        self.escaped
+    }
+
+    /// The quote open at this point, counting from the innermost `$(`
+    pub fn quote(&self) -> Option<char> {
+        self.quote
+    }
+
+    /// Whether `$(` substitutions are still open
+    pub fn in_substitution(&self) -> bool {
+        !self.outer.is_empty()
+    }
+
+    /// Whether `( ... )` subshells are still open
+    pub fn in_group(&self) -> bool {
+        self.groups > 0
+    }
+
+    /// Move past `ch`
+    pub fn step(&mut self, ch: char) {
+        if self.escaped {
+            self.escaped = false;
+            self.prev = None;
+            if self.quote.is_none() && !self.in_substitution() && !self.started && !ch.is_whitespace() {
+                self.word.push(ch);
+            }
+            return;
+        }
+        let after_dollar = self.prev == Some('$');
+        self.prev = Some(ch);
+        match (ch, self.quote) {
+            ('(', None | Some('"')) if after_dollar => self.outer.push((self.quote.take(), 0)),
+            ('(', None) if self.in_substitution() => self.outer.last_mut().unwrap().1 += 1,
+            (')', None) if self.in_substitution() => {
+                let last = self.outer.len() - 1;
+                if self.outer[last].1 > 0 {
+                    self.outer[last].1 -= 1;
+                } else {
+                    self.quote = self.outer.pop().and_then(|(quote, _)| quote);
+                }
+            }
+            ('"' | '\'', None) => {
+                self.quote = Some(ch);
+                self.ansi = ch == '\'' && after_dollar;
+                self.started = true;
+            }
+            ('\\', Some('\'')) if self.ansi => self.escaped = true,
+            ('\\', None | Some('"')) => self.escaped = true,
+            (c, Some(q)) if c == q => self.quote = None,
+            (_, None) if !self.in_substitution() => self.step_command(ch),
+            _ => {}
+        }
+    }
+
+    /// Track where commands start, and the subshells opened there
+    fn step_command(&mut self, ch: char) {
+        match ch {
+            '(' if !self.started && self.word.is_empty() => self.groups += 1,
+            ')' if self.groups > 0 => {
+                self.groups -= 1;
+                self.started = true;
+            }
+            ';' | '&' | '|' | '\n' => {
+                self.started = false;
+                self.word.clear();
+            }
+            c if c.is_whitespace() && !self.word.is_empty() => {
+                self.started = !crate::script::RESERVED.contains(&self.word.as_str());
+                self.word.clear();
+            }
+            c if !self.started && !c.is_whitespace() => self.word.push(c),
+            _ => {}
+        }
+    }
+}
+
+/// A word of a command
+struct Word {
+    text: String,
+    /// Byte range in the line, quotes included
+    start: usize,
+    end: usize,
+    /// Some of it was quoted, so it is never an operator
+    quoted: bool,
+    /// Glob pattern, if it has unquoted wildcards
+    pattern: Option<String>,
+}
+
+/// A word being read, tracking whether it is a glob pattern
+#[derive(Default)]
+struct GlobWord {
+    pattern: String,
+    /// Seen a quote
+    quoted: bool,
+    /// Seen an unquoted `*` or `?`, or `[` closed by `]`
+    wild: bool,
+    /// Seen an unquoted `[` not yet closed
+    bracket: bool,
+}
+
+impl GlobWord {
+    fn push(&mut self, ch: char, quoted: bool) {
+        match (ch, quoted) {
+            ('*' | '?' | '[', true) => self.pattern.push_str(&format!("[{}]", ch)),
+            (_, true) => self.pattern.push(ch),
+            ('*' | '?', false) => self.wild = true,
+            ('[', false) => self.bracket = true,
+            (']', false) => self.wild |= self.bracket,
+            _ => {}
+        }
+        if !quoted {
+            self.pattern.push(ch);
+        }
+    }
+
+    /// Finish the word read as `text`, from `start` to `end` in the line
+    fn take(&mut self, text: String, start: usize, end: usize) -> Word {
+        let word = std::mem::take(self);
+        Word { text, start, end, quoted: word.quoted, pattern: word.wild.then_some(word.pattern) }
+    }
+}
+
+/// Split a line into the commands of a sequence, on `;`, `&` and newlines
+/// outside quotes. Each command keeps its own redirections, and a `&` ending
+/// it; blank lines are dropped, but a `;` or `&` with no command before it is
+/// an error.
+///
+/// The lines after a `<<WORD` up to a line reading `WORD` are its
+/// here-document: they become a `<<<` here-string in the command, quoted so
+/// later stages see one word.
+pub fn split_sequence(input: &str) -> Result<Vec<String>, SplitError> {
+    let mut commands = Vec::new();
+    // Commands of the current line, untrimmed so here-document ranges hold
+    let mut line = vec![String::new()];
+    let mut heredocs = Vec::new();
+    let mut quoting = Quoting::default();
+    let mut pos = 0;
+
+    while let Some(ch) = input[pos..].chars().next() {
+        pos += ch.len_utf8();
+        let index = line.len() - 1;
+        let current = &mut line[index];
+        let plain = quoting.is_plain();
+        let quote = quoting.quote();
+        let escaped = quoting.escaped();
+        quoting.step(ch);
+        match ch {
+            // `\` at the end of a line joins the next one to it
+            '\\' if !escaped && quote != Some('\'') && input[pos..].starts_with('\n') => {
+                quoting.step('\n');
+                pos += 1;
+                if pos == input.len() {
+                    return Err(SplitError::Continued);
+                }
+                continue;
+            }
+            _ if !plain => {}
+            // `<<`, but not a `<<<` here-string
+            '<' if input[pos..].starts_with('<') && !input[pos + 1..].starts_with('<') && !current.ends_with('<') => {
+                let (heredoc, len) = here_doc_operator(index, &input[pos + 1..])?;
+                let start = current.len();
+                current.push_str(&input[pos - 1..pos + 1 + len]);
+                pos += 1 + len;
+                heredocs.push(PendingHereDoc { range: start..current.len(), ..heredoc });
+                continue;
+            }
+            ';' => {
+                if current.trim().is_empty() {
+                    return Err(SplitError::Invalid("Missing command before ';'".to_string()));
+                }
+                line.push(String::new());
+                continue;
+            }
+            // `sleep 1 & echo next`: a `&` ends its command too, which keeps
+            // it to run in the background; `&&`, `|&`, `>&` and `&>` stay
+            '&' if !current.ends_with(['&', '|', '<', '>']) && !input[pos..].starts_with(['&', '>']) => {
+                if current.trim().is_empty() {
+                    return Err(SplitError::Invalid("Missing command before '&'".to_string()));
+                }
+                current.push(ch);
+                line.push(String::new());
+                continue;
+            }
+            '\n' => {
+                pos = read_here_docs(input, pos, &mut line, std::mem::take(&mut heredocs))?;
+                commands.extend(line.drain(..).map(|command| command.trim().to_string()));
+                line.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        current.push(ch);
+    }
+    if let Some(heredoc) = heredocs.first() {
+        return Err(SplitError::Unterminated(heredoc.delimiter.clone()));
+    }
+    if let Some(quote) = quoting.quote() {
+        return Err(SplitError::Unclosed(quote));
+    }
+    if quoting.in_substitution() || quoting.in_group() {
+        return Err(SplitError::Unclosed(')'));
+    }
+    commands.extend(line.into_iter().map(|command| command.trim().to_string()));
+
+    commands.retain(|command| !command.is_empty());
+    Ok(commands)
+}
+
+/// Parse what follows `<<`: an optional `-`, then the delimiter word, which
+/// may be quoted. Returns the here-document and the bytes read.
+fn here_doc_operator(command: usize, rest: &str) -> Result<(PendingHereDoc, usize), SplitError> {
+    let strip_tabs = rest.starts_with('-');
+    let mut len = usize::from(strip_tabs);
+    len += rest[len..].len() - rest[len..].trim_start_matches([' ', '\t']).len();
+
+    let mut delimiter = String::new();
+    let mut expand = true;
+    let mut quote = None;
+    for ch in rest[len..].chars() {
+        match (ch, quote) {
+            (c, Some(q)) if c == q => quote = None,
+            (c, Some(_)) => delimiter.push(c),
+            ('"' | '\'', None) => {
+                quote = Some(ch);
+                expand = false;
+            }
+            ('\\', None) => expand = false,
+            (c, None) if c.is_whitespace() || ";|&<>()".contains(c) => break,
+            (c, None) => delimiter.push(c),
+        }
+        len += ch.len_utf8();
+    }
+    if delimiter.is_empty() || quote.is_some() {
+        return Err(SplitError::Invalid("Missing here-document delimiter after '<<'".to_string()));
+    }
+    Ok((PendingHereDoc { command, range: 0..0, delimiter, expand, strip_tabs }, len))
+}
+
+/// Read the bodies of `heredocs` from the lines of `input` starting at `pos`,
+/// writing each into its command; returns where the lines after them start
+fn read_here_docs(input: &str, mut pos: usize, line: &mut [String], heredocs: Vec<PendingHereDoc>) -> Result<usize, SplitError> {
+    let mut bodies = Vec::with_capacity(heredocs.len());
+    for heredoc in &heredocs {
+        let mut body = String::new();
+        loop {
+            if pos >= input.len() {
+                return Err(SplitError::Unterminated(heredoc.delimiter.clone()));
+            }
+            let end = input[pos..].find('\n').map_or(input.len(), |offset| pos + offset);
+            let text = &input[pos..end];
+            let text = if heredoc.strip_tabs { text.trim_start_matches('\t') } else { text };
+            pos = (end + 1).min(input.len());
+            if text == heredoc.delimiter {
+                break;
+            }
+            body.push_str(text);
+            body.push('\n');
+        }
+        bodies.push(body);
+    }
+    // From the last, so earlier ranges in the same command stay valid
+    for (heredoc, body) in heredocs.iter().zip(bodies).rev() {
+        line[heredoc.command].replace_range(heredoc.range.clone(), &here_string(&body, heredoc.expand));
+    }
+    Ok(pos)
+}
+
+/// `<<< WORD` feeding `body`, double-quoted when its variables expand and
+/// single-quoted otherwise
+fn here_string(body: &str, expand: bool) -> String {
+    let body = body.strip_suffix('\n').unwrap_or(body);
+    if !expand {
+        return format!("<<< '{}'", body.replace('\'', "'\"'\"'"));
+    }
+    // A `\` before `"` stays in a here-document, unlike in double quotes
+    let mut quoted = String::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => quoted.push_str("\"'\"'\""),
+            '\\' => match chars.next_if(|next| *next != '"' && DOUBLE_QUOTE_ESCAPES.contains(next)) {
+                Some(next) => {
+                    quoted.push(ch);
+                    quoted.push(next);
+                }
+                None => quoted.push_str("\\\\"),
+            },
+            _ => quoted.push(ch),
+        }
+    }
+    format!("<<< \"{}\"", quoted)
+}
+
+/// How a pipeline of an `&&`/`||` list is joined to the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Connector {
+    /// `&&`: runs if the previous pipeline succeeded
+    And,
+    /// `||`: runs if it failed
+    Or,
+}
+
+impl Connector {
+    /// Whether the pipeline after this runs when the one before exited with `status`
+    pub fn runs_after(self, status: i32) -> bool {
+        match self {
+            Connector::And => status == 0,
+            Connector::Or => status != 0,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Connector::And => "&&",
+            Connector::Or => "||",
+        }
+    }
+}
+
+/// Split a line on `&&` and `||` outside quotes: the first pipeline, then
+/// each later one with the operator joining it to the previous one
+pub fn split_and_or(input: &str) -> Result<(String, Vec<(Connector, String)>), String> {
+    let mut pipelines = Vec::new();
+    let mut connector = None;
+    let mut start = 0;
+    let mut quoting = Quoting::default();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((idx, ch)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        let plain = quoting.is_plain();
+        quoting.step(ch);
+        let found = match ch {
+            '&' if plain && next == Some('&') => Some(Connector::And),
+            '|' if plain && next == Some('|') => Some(Connector::Or),
+            _ => None,
+        };
+        if let Some(found) = found {
+            chars.next();
+            let pipeline = input[start..idx].trim();
+            if pipeline.is_empty() {
+                return Err(format!("Missing command before '{}'", found.symbol()));
+            }
+            pipelines.push((connector, pipeline.to_string()));
+            connector = Some(found);
+            start = idx + 2;
+        }
+    }
+    let last = input[start..].trim();
+    if let Some(connector) = connector {
+        if last.is_empty() {
+            return Err(format!("Missing command after '{}'", connector.symbol()));
+        }
+    }
+    pipelines.push((connector, last.to_string()));
+
+    let mut pipelines = pipelines.into_iter();
+    let first = pipelines.next().map(|(_, pipeline)| pipeline).unwrap_or_default();
+    Ok((first, pipelines.filter_map(|(connector, pipeline)| Some((connector?, pipeline))).collect()))
+}
+
+/// The commands inside `( ... )` when `source` is a subshell; nothing may
+/// follow the `)`
+pub fn subshell_body(source: &str) -> Result<Option<&str>, String> {
+    let source = source.trim();
+    if !source.starts_with('(') {
+        return Ok(None);
+    }
+    let mut quoting = Quoting::default();
+    for (idx, ch) in source.char_indices() {
+        quoting.step(ch);
+        if !quoting.in_group() {
+            let rest = source[idx + 1..].trim();
+            return match rest.split_whitespace().next() {
+                Some(word) => Err(format!("syntax error near unexpected '{}' after ')'", word)),
+                None if source[1..idx].trim().is_empty() => Err("syntax error near unexpected ')'".to_string()),
+                None => Ok(Some(&source[1..idx])),
+            };
+        }
+    }
+    Err("syntax error: missing ')'".to_string())
 }
 
 /// Parse a command line string into a vector of Commands
 pub fn parse_command(input: &str) -> Result<Vec<Command>, String> {
+    parse_spanned(input).map_err(|(msg, _)| msg)
+}
+
+/// Parse a command line, reporting errors with the offending span of `input`
+pub fn parse_line(input: &str) -> ShellResult<Vec<Command>> {
+    parse_spanned(input).map_err(|(msg, span)| {
+        ShellError::Parse(msg).with_context(
+            ErrorContext::new(ErrorStage::Parse)
+                .with_span(span)
+                .with_source(input),
+        )
+    })
+}
+
+fn parse_spanned(input: &str) -> Result<Vec<Command>, (String, Span)> {
+    tracing::trace!(input, "parsing command line");
+    let offset = input.len() - input.trim_start().len();
     let input = input.trim();
     if input.is_empty() {
-        return Err("Empty command".to_string());
+        return Err(("Empty command".to_string(), Span::new(0, 0)));
     }
 
-    let pipe_commands: Vec<&str> = input.split('|').map(|s| s.trim()).collect();
+    // Split on pipes outside quotes, remembering where each segment starts
+    let mut pipe_commands: Vec<(usize, &str)> = Vec::new();
+    let mut seg_start = 0;
+    // Segments ending in `|&`, whose stderr goes down the pipe too
+    let mut merged = Vec::new();
+    let mut quoting = Quoting::default();
+    for (idx, ch) in input.char_indices() {
+        let plain = quoting.is_plain();
+        quoting.step(ch);
+        // The `|` of `>|` is part of the redirection
+        if ch == '|' && plain && !input[..idx].ends_with('>') {
+            pipe_commands.push((seg_start, &input[seg_start..idx]));
+            seg_start = idx + 1;
+            if input[seg_start..].starts_with('&') {
+                merged.push(pipe_commands.len() - 1);
+                seg_start += 1;
+            }
+        }
+    }
+    pipe_commands.push((seg_start, &input[seg_start..]));
 
     // Check for empty commands in pipeline
-    for (i, cmd_str) in pipe_commands.iter().enumerate() {
-        if cmd_str.is_empty() && i < pipe_commands.len() - 1 {
-            return Err("Missing command after pipe".to_string());
-        }
-        if cmd_str.is_empty() && i == pipe_commands.len() - 1 && pipe_commands.len() > 1 {
-            return Err("Missing command after pipe".to_string());
+    if pipe_commands.len() > 1 {
+        for (i, (start, cmd_str)) in pipe_commands.iter().enumerate() {
+            if cmd_str.trim().is_empty() {
+                let pipe_pos = match i {
+                    0 => start + cmd_str.len(),
+                    _ if merged.contains(&(i - 1)) => start - 2,
+                    _ => start - 1,
+                };
+                return Err((
+                    "Missing command after pipe".to_string(),
+                    Span::new(offset + pipe_pos, offset + pipe_pos + 1),
+                ));
+            }
         }
     }
 
     let mut commands = Vec::new();
 
-    for cmd_str in pipe_commands.iter() {
-        let mut parts: Vec<String> = Vec::new();
+    for (index, (seg_offset, cmd_str)) in pipe_commands.iter().enumerate() {
+        let seg_offset = offset + seg_offset;
+        let mut parts: Vec<Word> = Vec::new();
         let mut current_part = String::new();
+        let mut part_start = 0;
         let mut in_quotes = false;
         let mut quote_char = ' ';
-        let chars = cmd_str.chars().peekable();
+        let mut word = GlobWord::default();
 
-        for ch in chars {
+        let mut chars = cmd_str.char_indices().peekable();
+        while let Some((idx, ch)) = chars.next() {
+            if current_part.is_empty() && !word.quoted && ch != ' ' {
+                part_start = idx;
+            }
             match ch {
                 '"' | '\'' if !in_quotes => {
                     in_quotes = true;
                     quote_char = ch;
+                    word.quoted = true;
                 }
                 '"' | '\'' if in_quotes && ch == quote_char => {
                     in_quotes = false;
                     quote_char = ' ';
                 }
+                // `a\ b`, `"\$HOME"`: a `\` takes the next character literally,
+                // and a newline away; in double quotes, only those it escapes
+                '\\' if !in_quotes || quote_char == '"' => match chars.next_if(|&(_, next)| !in_quotes || DOUBLE_QUOTE_ESCAPES.contains(&next)) {
+                    Some((_, '\n')) => {}
+                    Some((_, next)) => {
+                        current_part.push(next);
+                        word.push(next, true);
+                        word.quoted = true;
+                    }
+                    None => {
+                        current_part.push(ch);
+                        word.push(ch, in_quotes);
+                    }
+                },
+                // `""` is a word too, an empty one
                 ' ' if !in_quotes => {
-                    if !current_part.is_empty() {
-                        parts.push(current_part.clone());
+                    if !current_part.is_empty() || word.quoted {
+                        parts.push(word.take(current_part.clone(), seg_offset + part_start, seg_offset + idx));
                         current_part.clear();
                     }
                 }
+                // `ls 2>/dev/null`, `cat<in`: an operator is split off the
+                // words around it, taking a descriptor number or `&` before it
+                '<' | '>' if !in_quotes => {
+                    let prefixed = !word.quoted
+                        && (current_part == "&" || (!current_part.is_empty() && current_part.bytes().all(|b| b.is_ascii_digit())));
+                    let (mut operator, start) = if prefixed {
+                        word = GlobWord::default();
+                        (std::mem::take(&mut current_part), part_start)
+                    } else {
+                        if !current_part.is_empty() || word.quoted {
+                            parts.push(word.take(std::mem::take(&mut current_part), seg_offset + part_start, seg_offset + idx));
+                        }
+                        (String::new(), idx)
+                    };
+                    let end = idx + operator_len(&cmd_str[idx..]);
+                    operator.push_str(&cmd_str[idx..end]);
+                    while chars.next_if(|&(next, _)| next < end).is_some() {}
+                    parts.push(Word { text: operator, start: seg_offset + start, end: seg_offset + end, quoted: false, pattern: None });
+                }
+                // `sleep 10&`: a `&` ending a word is split off
+                '&' if !in_quotes
+                    && !current_part.is_empty()
+                    && !current_part.ends_with('&')
+                    && (cmd_str[idx + 1..].is_empty() || cmd_str[idx + 1..].starts_with([' ', '\t'])) =>
+                {
+                    parts.push(word.take(std::mem::take(&mut current_part), seg_offset + part_start, seg_offset + idx));
+                    parts.push(word.take("&".to_string(), seg_offset + idx, seg_offset + idx + 1));
+                }
                 _ => {
                     current_part.push(ch);
+                    word.push(ch, in_quotes);
                 }
             }
         }
 
-        if !current_part.is_empty() {
-            parts.push(current_part);
+        if !current_part.is_empty() || word.quoted {
+            parts.push(word.take(current_part, seg_offset + part_start, seg_offset + cmd_str.len()));
         }
 
         if parts.is_empty() {
@@ -75,56 +718,158 @@ pub fn parse_command(input: &str) -> Result<Vec<Command>, String> {
         let mut input_redirect = None;
         let mut output_redirect = None;
         let mut append = false;
+        let mut clobber = false;
+        let mut stderr_redirect = None;
+        let mut stderr_append = false;
+        let mut stderr_to_stdout = false;
+        let mut here_doc = None;
+        let mut globs = Vec::new();
+        let mut fd_redirects = Vec::new();
+        let mut env = Vec::new();
         let mut background = false;
+        let mut spans = Vec::new();
+        let mut env_spans = Vec::new();
 
         let mut i = 0;
         while i < parts.len() {
-            let part = &parts[i];
+            let Word { text: part, start, end, quoted, pattern } = &parts[i];
+            let span = Span::new(*start, *end);
+
+            // A quoted `">"` is a word, not an operator
+            let operator = if *quoted { "" } else { part.as_str() };
 
-            match part.as_str() {
+            match operator {
                 "<" => {
                     if i + 1 < parts.len() {
-                        input_redirect = Some(parts[i + 1].clone());
+                        input_redirect = Some(parts[i + 1].text.clone());
                         i += 2;
                     } else {
-                        return Err("Missing input file after '<'".to_string());
+                        return Err(("Missing input file after '<'".to_string(), span));
                     }
                 }
-                ">" => {
+                "<<<" => {
                     if i + 1 < parts.len() {
-                        output_redirect = Some(parts[i + 1].clone());
+                        here_doc = Some(format!("{}\n", parts[i + 1].text));
+                        i += 2;
+                    } else {
+                        return Err(("Missing word after '<<<'".to_string(), span));
+                    }
+                }
+                ">" | ">|" => {
+                    if i + 1 < parts.len() {
+                        output_redirect = Some(parts[i + 1].text.clone());
                         append = false;
+                        clobber = part == ">|";
                         i += 2;
                     } else {
-                        return Err("Missing output file after '>'".to_string());
+                        return Err((format!("Missing output file after '{}'", part), span));
                     }
                 }
                 ">>" => {
                     if i + 1 < parts.len() {
-                        output_redirect = Some(parts[i + 1].clone());
+                        output_redirect = Some(parts[i + 1].text.clone());
                         append = true;
                         i += 2;
                     } else {
-                        return Err("Missing output file after '>>'".to_string());
+                        return Err(("Missing output file after '>>'".to_string(), span));
+                    }
+                }
+                "2>" | "2>>" => {
+                    if i + 1 < parts.len() {
+                        stderr_redirect = Some(parts[i + 1].text.clone());
+                        stderr_append = part == "2>>";
+                        stderr_to_stdout = false;
+                        i += 2;
+                    } else {
+                        return Err((format!("Missing error file after '{}'", part), span));
+                    }
+                }
+                "2>&1" => {
+                    stderr_redirect = None;
+                    stderr_to_stdout = true;
+                    i += 1;
+                }
+                // `>&file` is `&>file`, as only a number after it duplicates
+                "&>" | "&>>" | ">&" => {
+                    if i + 1 < parts.len() {
+                        output_redirect = Some(parts[i + 1].text.clone());
+                        append = part == "&>>";
+                        stderr_redirect = None;
+                        stderr_to_stdout = true;
+                        i += 2;
+                    } else {
+                        return Err((format!("Missing output file after '{}'", part), span));
                     }
                 }
                 "&" => {
+                    if i + 1 < parts.len() || index + 1 < pipe_commands.len() {
+                        return Err(("'&' may only end a pipeline".to_string(), span));
+                    }
                     background = true;
                     i += 1;
                 }
                 _ => {
+                    if let Some((fd, mode, target)) = FdRedirect::parse(operator) {
+                        let target = match target {
+                            Some(target) => target,
+                            None if i + 1 < parts.len() => {
+                                i += 1;
+                                parts[i].text.clone()
+                            }
+                            None => return Err((format!("Missing file after '{}'", part), span)),
+                        };
+                        match (fd, mode) {
+                            (0, RedirectMode::Read) => input_redirect = Some(target),
+                            (1, RedirectMode::Write | RedirectMode::Append) => {
+                                output_redirect = Some(target);
+                                append = mode == RedirectMode::Append;
+                            }
+                            _ => fd_redirects.push(FdRedirect { fd, target, mode }),
+                        }
+                        i += 1;
+                        continue;
+                    }
+                    // `NAME=VALUE` before the program, with the name unquoted
+                    let raw = &input[start - offset..end - offset];
+                    if program.is_empty() && crate::state::parse_assignment(raw).is_some() {
+                        let (name, value) = part.split_once('=').unwrap_or_default();
+                        env.push((name.to_string(), value.to_string()));
+                        env_spans.push(span);
+                        i += 1;
+                        continue;
+                    }
                     if program.is_empty() {
                         program = part.clone();
                     } else {
+                        if let Some(pattern) = pattern {
+                            globs.push((args.len(), pattern.clone()));
+                        }
                         args.push(part.clone());
                     }
+                    spans.push(span);
                     i += 1;
                 }
             }
         }
 
+        // `|&` is `2>&1 |`, after the command's own redirections
+        if merged.contains(&index) {
+            stderr_redirect = None;
+            stderr_to_stdout = true;
+        }
+
+        // A line of assignments alone sets shell variables instead
+        if program.is_empty() && !env.is_empty() {
+            let mut words = std::mem::take(&mut env).into_iter().map(|(name, value)| format!("{}={}", name, value));
+            program = words.next().unwrap_or_default();
+            args = words.collect();
+            spans = std::mem::take(&mut env_spans);
+        }
         if program.is_empty() {
-            return Err("No command specified".to_string());
+            return Err((
+                "No command specified".to_string(),
+                Span::new(seg_offset, seg_offset + cmd_str.len()),
+            ));
         }
 
         commands.push(Command {
@@ -133,21 +878,37 @@ pub fn parse_command(input: &str) -> Result<Vec<Command>, String> {
             input_redirect,
             output_redirect,
             append,
-            background: background && i == pipe_commands.len() - 1,
+            clobber,
+            stderr_redirect,
+            stderr_append,
+            stderr_to_stdout,
+            here_doc,
+            globs,
+            fd_redirects,
+            env,
+            background,
+            spans,
         });
     }
 
     if commands.is_empty() {
-        return Err("No commands to execute".to_string());
+        return Err(("No commands to execute".to_string(), Span::new(offset, offset + input.len())));
+    }
+    // `&` at the end sends the whole pipeline to the background
+    if commands.last().is_some_and(|command| command.background) {
+        for command in &mut commands {
+            command.background = true;
+        }
     }
 
+    tracing::debug!(stages = commands.len(), "parsed pipeline");
     Ok(commands)
 }
 
 /// Get the standard input/output configuration for a command
 #[allow(dead_code)]
 pub fn get_stdio_config(cmd: &Command) -> (Stdio, Stdio, Stdio) {
-    let stdin = if cmd.input_redirect.is_some() {
+    let stdin = if cmd.input_redirect.is_some() || cmd.here_doc.is_some() {
         Stdio::piped()
     } else {
         Stdio::inherit()
@@ -159,7 +920,11 @@ pub fn get_stdio_config(cmd: &Command) -> (Stdio, Stdio, Stdio) {
         Stdio::inherit()
     };
 
-    let stderr = Stdio::inherit();
+    let stderr = if cmd.stderr_redirect.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::inherit()
+    };
 
     (stdin, stdout, stderr)
 }
@@ -178,7 +943,7 @@ mod tests {
         assert_eq!(commands[0].args, vec!["-la"]);
         assert_eq!(commands[0].input_redirect, None);
         assert_eq!(commands[0].output_redirect, None);
-        assert_eq!(commands[0].append, false);
+        assert!(!commands[0].append);
     }
 
     #[test]
@@ -221,7 +986,7 @@ mod tests {
         assert_eq!(commands[0].program, "echo");
         assert_eq!(commands[0].args, vec!["hello"]);
         assert_eq!(commands[0].output_redirect, Some("output.txt".to_string()));
-        assert_eq!(commands[0].append, false);
+        assert!(!commands[0].append);
     }
 
     #[test]
@@ -233,7 +998,7 @@ mod tests {
         assert_eq!(commands[0].program, "echo");
         assert_eq!(commands[0].args, vec!["hello"]);
         assert_eq!(commands[0].output_redirect, Some("output.txt".to_string()));
-        assert_eq!(commands[0].append, true);
+        assert!(commands[0].append);
     }
 
     #[test]
@@ -313,6 +1078,322 @@ mod tests {
         assert_eq!(result.unwrap_err(), "Missing command after pipe");
     }
 
+    #[test]
+    fn test_parse_line_error_span() {
+        let err = parse_line("ls | grep x <").unwrap_err();
+        let ctx = err.context().expect("parse errors carry context");
+        assert_eq!(ctx.stage, ErrorStage::Parse);
+        assert_eq!(ctx.source.as_deref(), Some("ls | grep x <"));
+        assert_eq!(ctx.span, Some(Span::new(12, 13)));
+    }
+
+    #[test]
+    fn test_parse_background_pipeline() {
+        let commands = parse_command("sleep 30 | cat &").unwrap();
+        assert!(commands[0].background && commands[1].background);
+        assert_eq!(commands[1].args, Vec::<String>::new());
+        assert!(!parse_command("sleep 30").unwrap()[0].background);
+
+        let commands = parse_command("sleep 10&").unwrap();
+        assert_eq!((commands[0].args.as_slice(), commands[0].background), (["10".to_string()].as_slice(), true));
+        let commands = parse_command("echo 'a&' b&c >&2 &> log").unwrap();
+        assert_eq!(commands[0].args, ["a&", "b&c"]);
+        assert!(!commands[0].background);
+
+        // `&` ends a command in a sequence, which goes on after it
+        let commands = split_sequence("sleep 1 & echo hi").unwrap();
+        assert_eq!(commands, ["sleep 1 &", "echo hi"]);
+        assert!(parse_command(&commands[0]).unwrap()[0].background);
+        assert!(!parse_command(&commands[1]).unwrap()[0].background);
+        assert_eq!(split_sequence("sleep 1& echo $!&").unwrap(), ["sleep 1&", "echo $!&"]);
+        assert_eq!(split_sequence("a && b |& c 2>&1 &> log >& f; echo '&' \"$(x &)\"").unwrap().len(), 2);
+        assert!(split_sequence("& ls").is_err());
+        assert!(parse_command("sleep 1& | cat").is_err());
+    }
+
+    #[test]
+    fn test_parse_mixed_nested_quotes() {
+        let commands = parse_command(r#"echo 'he said "hi"' "it's" "a'b'c" 'x"y'"z'w" "" ok"#).unwrap();
+        assert_eq!(commands[0].args, [r#"he said "hi""#, "it's", "a'b'c", r#"x"yz'w"#, "", "ok"]);
+        let commands = parse_command(r#"echo "'|'" '"&"' | wc"#).unwrap();
+        assert_eq!((commands.len(), commands[0].args.as_slice()), (2, ["'|'".to_string(), "\"&\"".to_string()].as_slice()));
+    }
+
+    #[test]
+    fn test_parse_stderr_pipe() {
+        let commands = parse_command("make 2> err.log |& grep error | wc -l").unwrap();
+        assert_eq!(commands.len(), 3);
+        assert!(commands[0].stderr_to_stdout && commands[0].stderr_redirect.is_none());
+        assert_eq!((commands[1].program.as_str(), commands[1].stderr_to_stdout), ("grep", false));
+        assert!(!parse_command("echo '|&' | cat").unwrap()[0].stderr_to_stdout);
+
+        let err = parse_spanned("make |& ").unwrap_err();
+        assert_eq!(err, ("Missing command after pipe".to_string(), Span::new(5, 6)));
+    }
+
+    #[test]
+    fn test_parse_clobber_redirection() {
+        let commands = parse_command("echo hi >| out.txt | wc").unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!((commands[0].output_redirect.as_deref(), commands[0].clobber), (Some("out.txt"), true));
+        let commands = parse_command("echo hi > out.txt").unwrap();
+        assert!(!commands[0].clobber);
+    }
+
+    #[test]
+    fn test_parse_quoted_redirect_targets() {
+        let commands = parse_command("cat < 'in file' >\"my notes.txt\" 2>>'err log' 3<\"fd 3\"").unwrap();
+        assert_eq!(commands[0].input_redirect.as_deref(), Some("in file"));
+        assert_eq!((commands[0].output_redirect.as_deref(), commands[0].append), (Some("my notes.txt"), false));
+        assert_eq!((commands[0].stderr_redirect.as_deref(), commands[0].stderr_append), (Some("err log"), true));
+        assert_eq!(commands[0].fd_redirects, [FdRedirect { fd: 3, target: "fd 3".to_string(), mode: RedirectMode::Read }]);
+        assert!(commands[0].args.is_empty());
+
+        let commands = parse_command("cat<'a b'>>\"c d\"2 4>'e'").unwrap();
+        assert_eq!((commands[0].input_redirect.as_deref(), commands[0].output_redirect.as_deref()), (Some("a b"), Some("c d2")));
+        assert_eq!(commands[0].fd_redirects[0].target, "e");
+
+        let commands = parse_command("wc <<<\"a b\" > out").unwrap();
+        assert_eq!(commands[0].here_doc.as_deref(), Some("a b\n"));
+        // An operator is split off a word before it; a quoted one stays a word
+        let commands = parse_command("echo x>\"y\" \">\"z").unwrap();
+        assert_eq!((commands[0].args.as_slice(), commands[0].output_redirect.as_deref()), (["x".to_string(), ">z".to_string()].as_slice(), Some("y")));
+    }
+
+    #[test]
+    fn test_parse_env_assignments() {
+        let commands = parse_command("LANG=C TZ='Europe/Paris x' sort -k1=2 | A=1 wc").unwrap();
+        assert_eq!(commands[0].env, [("LANG".to_string(), "C".to_string()), ("TZ".to_string(), "Europe/Paris x".to_string())]);
+        assert_eq!((commands[0].program.as_str(), commands[0].args.as_slice()), ("sort", ["-k1=2".to_string()].as_slice()));
+        assert_eq!(commands[0].spans, [Span::new(27, 31), Span::new(32, 37)]);
+        assert_eq!(commands[1].env, [("A".to_string(), "1".to_string())]);
+
+        // Quoted names and assignments alone are words
+        let commands = parse_command("'A=1' x=2").unwrap();
+        assert_eq!((commands[0].program.as_str(), commands[0].env.len()), ("A=1", 0));
+        let commands = parse_command("x=1 y='a b'").unwrap();
+        assert_eq!((commands[0].program.as_str(), commands[0].args.as_slice()), ("x=1", ["y=a b".to_string()].as_slice()));
+        assert!(commands[0].env.is_empty());
+    }
+
+    #[test]
+    fn test_parse_word_spans() {
+        let commands = parse_command("  cat 'my file' > out | grep -v x&").unwrap();
+        assert_eq!(commands[0].spans, [Span::new(2, 5), Span::new(6, 15)]);
+        assert_eq!(commands[1].spans, [Span::new(24, 28), Span::new(29, 31), Span::new(32, 33)]);
+        assert_eq!(commands[1].span_of(2), Some(Span::new(32, 33)));
+        assert_eq!(commands[0].span_of(2), None);
+        // Repeated words each have their own span
+        let commands = parse_command("rm rm").unwrap();
+        assert_eq!((commands[0].span_of(0), commands[0].span_of(1)), (Some(Span::new(0, 2)), Some(Span::new(3, 5))));
+    }
+
+    #[test]
+    fn test_parse_line_missing_pipe_command_span() {
+        let err = parse_line("ls | | wc").unwrap_err();
+        assert_eq!(err.context().and_then(|c| c.span), Some(Span::new(3, 4)));
+    }
+
+    #[test]
+    fn test_parse_stderr_redirection() {
+        let commands = parse_command("make 2> errors.log | tee out.txt").unwrap();
+        assert_eq!(commands[0].stderr_redirect, Some("errors.log".to_string()));
+        assert!(!commands[0].stderr_append);
+        assert_eq!(commands[1].stderr_redirect, None);
+
+        let commands = parse_command("cargo build > build.log 2>> errors.log").unwrap();
+        assert_eq!(commands[0].output_redirect, Some("build.log".to_string()));
+        assert_eq!(commands[0].stderr_redirect, Some("errors.log".to_string()));
+        assert!(commands[0].stderr_append && !commands[0].append);
+        assert!(parse_command("ls 2>").is_err());
+    }
+
+    #[test]
+    fn test_parse_combined_redirection() {
+        let commands = parse_command("make &> build.log").unwrap();
+        assert_eq!(commands[0].output_redirect, Some("build.log".to_string()));
+        assert!(commands[0].stderr_to_stdout && !commands[0].append && !commands[0].background);
+
+        let commands = parse_command("make > out.log 2>&1 &").unwrap();
+        assert_eq!(commands[0].output_redirect, Some("out.log".to_string()));
+        assert!(commands[0].stderr_to_stdout && commands[0].background);
+        assert!(commands[0].args.is_empty());
+
+        // The last stderr redirection wins
+        let commands = parse_command("make 2>&1 2> errors.log &>> all.log").unwrap();
+        assert!(commands[0].stderr_to_stdout && commands[0].append && commands[0].stderr_redirect.is_none());
+        assert!(!parse_command("make 2>&1 2> errors.log").unwrap()[0].stderr_to_stdout);
+        assert!(parse_command("make &>").is_err());
+    }
+
+    #[test]
+    fn test_parse_quoted_operators_are_words() {
+        let commands = parse_command("echo '|' \">\" a'&'b \"2>&1\" | wc -l").unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].args, vec!["|", ">", "a&b", "2>&1"]);
+        assert!(commands[0].output_redirect.is_none() && !commands[0].stderr_to_stdout);
+        assert!(split_sequence("echo $(cd /tmp; ls | wc -l); pwd").unwrap().len() == 2);
+        assert_eq!(split_and_or("echo \"$(a && b)\" && c").unwrap().1.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_marks_unquoted_wildcards() {
+        let commands = parse_command("ls *.rs 'a*' \"src\"/?.[ch] [ x]y \"[*]\"/b*").unwrap();
+        assert_eq!(commands[0].args, vec!["*.rs", "a*", "src/?.[ch]", "[", "x]y", "[*]/b*"]);
+        assert_eq!(
+            commands[0].globs,
+            vec![(0, "*.rs".to_string()), (2, "src/?.[ch]".to_string()), (5, "[[][*]]/b*".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_parse_fd_redirections() {
+        let parsed = parse_command("cmd 3> log 4>> all.log 0< in 5< data >&2 1<&0 7>&- 1> out").unwrap();
+        let redirect = |fd, target: &str, mode| FdRedirect { fd, target: target.to_string(), mode };
+        assert_eq!(
+            parsed[0].fd_redirects,
+            vec![
+                redirect(3, "log", RedirectMode::Write),
+                redirect(4, "all.log", RedirectMode::Append),
+                redirect(5, "data", RedirectMode::Read),
+                redirect(1, "2", RedirectMode::Duplicate),
+                redirect(1, "0", RedirectMode::Duplicate),
+                redirect(7, "", RedirectMode::Close),
+            ]
+        );
+        assert_eq!((parsed[0].input_redirect.as_deref(), parsed[0].output_redirect.as_deref()), (Some("in"), Some("out")));
+        assert!(parsed[0].args.is_empty());
+
+        let parsed = parse_command("echo 3 2>x '3>' >&x").unwrap();
+        assert_eq!(parsed[0].args, ["3", "3>"]);
+        assert_eq!(parsed[0].output_redirect.as_deref(), Some("x"));
+        assert!(parsed[0].stderr_to_stdout && parsed[0].stderr_redirect.is_none());
+        assert!(parse_command("cmd 3>").is_err());
+    }
+
+    #[test]
+    fn test_parse_attached_redirections() {
+        let parsed = parse_command("ls /x 2>/dev/null").unwrap();
+        assert_eq!((parsed[0].args.as_slice(), parsed[0].stderr_redirect.as_deref()), (&["/x".to_string()][..], Some("/dev/null")));
+        let parsed = parse_command("cmd 2>>log").unwrap();
+        assert_eq!((parsed[0].stderr_redirect.as_deref(), parsed[0].stderr_append), (Some("log"), true));
+        let parsed = parse_command(">out sort<in>>all").unwrap();
+        assert_eq!(parsed[0].program, "sort");
+        assert_eq!((parsed[0].input_redirect.as_deref(), parsed[0].output_redirect.as_deref()), (Some("in"), Some("all")));
+        assert!(parsed[0].append && parsed[0].args.is_empty());
+        let parsed = parse_command("make >/dev/null 2>&1").unwrap();
+        assert_eq!(parsed[0].output_redirect.as_deref(), Some("/dev/null"));
+        assert!(parsed[0].stderr_to_stdout && parsed[0].args.is_empty());
+        let parsed = parse_command("echo hi>&2 3>log 4>>all cat<<<word").unwrap();
+        assert_eq!(parsed[0].args, ["hi", "cat"]);
+        assert_eq!(parsed[0].fd_redirects[0], FdRedirect { fd: 1, target: "2".to_string(), mode: RedirectMode::Duplicate });
+        assert_eq!(parsed[0].fd_redirects[1].target, "log");
+        assert_eq!(parsed[0].fd_redirects[2].mode, RedirectMode::Append);
+        assert_eq!(parsed[0].here_doc.as_deref(), Some("word\n"));
+        // Only a whole number before the operator is its descriptor
+        let parsed = parse_command("echo a2>f '2'>g").unwrap();
+        assert_eq!(parsed[0].args, ["a2", "2"]);
+        assert_eq!(parsed[0].output_redirect.as_deref(), Some("g"));
+        let parsed = parse_command("build &>>log").unwrap();
+        assert_eq!((parsed[0].output_redirect.as_deref(), parsed[0].append), (Some("log"), true));
+        assert_eq!(parse_command("echo a>\"b c\"").unwrap()[0].output_redirect.as_deref(), Some("b c"));
+    }
+
+    #[test]
+    fn test_split_sequence_keeps_redirections() {
+        let commands = split_sequence("cd /tmp; echo 'a; b' > out.txt;sort < out.txt >> sorted.txt;").unwrap();
+        assert_eq!(commands, vec!["cd /tmp", "echo 'a; b' > out.txt", "sort < out.txt >> sorted.txt"]);
+        let parsed = parse_command(&commands[2]).unwrap();
+        assert_eq!((parsed[0].input_redirect.as_deref(), parsed[0].output_redirect.as_deref()), (Some("out.txt"), Some("sorted.txt")));
+        assert!(parsed[0].append);
+
+        assert_eq!(split_sequence("ls\n\n  pwd\n").unwrap(), vec!["ls", "pwd"]);
+        assert_eq!(split_sequence("; ls").unwrap_err(), SplitError::Invalid("Missing command before ';'".to_string()));
+        assert!(split_sequence("ls;\n; pwd").is_err());
+    }
+
+    #[test]
+    fn test_split_sequence_joins_continued_lines() {
+        let input = "cargo build \\\n  --release; echo \"a \\\nb\" 'c \\\nd'\nls\\\n";
+        assert_eq!(split_sequence(input).unwrap_err(), SplitError::Continued);
+        let commands = split_sequence(input.strip_suffix("\\\n").unwrap()).unwrap();
+        assert_eq!(commands, vec!["cargo build   --release", "echo \"a b\" 'c \\\nd'", "ls"]);
+        // Not at the end of a line, it stays
+        assert_eq!(split_sequence("echo a\\ b").unwrap(), vec!["echo a\\ b"]);
+    }
+
+    #[test]
+    fn test_split_sequence_needs_closed_quotes() {
+        assert_eq!(split_sequence("echo \"hello\n").unwrap_err(), SplitError::Unclosed('"'));
+        assert_eq!(split_sequence("echo 'it\"s; ls\n").unwrap_err(), SplitError::Unclosed('\''));
+        assert_eq!(split_sequence("echo $(ls \"a)\"\n").unwrap_err(), SplitError::Unclosed(')'));
+        assert_eq!(split_sequence("echo \"a\n  b; c\"; ls").unwrap(), vec!["echo \"a\n  b; c\"", "ls"]);
+        let parsed = parse_command("echo \"a\n  b\"").unwrap();
+        assert_eq!(parsed[0].args, ["a\n  b"]);
+    }
+
+    #[test]
+    fn test_parse_backslash_escapes() {
+        let commands = parse_command(r#"echo "\$HOME" "a\"b" a\ b "x\\y" "\q" '\$' \* \> c\|d"#).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].args, ["$HOME", "a\"b", "a b", "x\\y", "\\q", "\\$", "*", ">", "c|d"]);
+        assert!(commands[0].globs.is_empty() && commands[0].output_redirect.is_none());
+        assert_eq!(parse_command("echo \"a\\\nb\" end\\").unwrap()[0].args, ["ab", "end\\"]);
+
+        assert_eq!(split_sequence("echo a\\; b\\\\; c").unwrap(), ["echo a\\; b\\\\", "c"]);
+        assert_eq!(split_sequence("echo \\\\\nls").unwrap(), ["echo \\\\", "ls"]);
+        assert_eq!(split_sequence("echo \"\\\"; ls\" \\&& x").unwrap().len(), 1);
+        assert_eq!(split_and_or("echo a \\&\\& b").unwrap().1.len(), 0);
+    }
+
+    #[test]
+    fn test_split_sequence_reads_ansi_c_quotes() {
+        assert_eq!(split_sequence("echo $'a\\'; b'; ls").unwrap(), ["echo $'a\\'; b'", "ls"]);
+        assert_eq!(split_sequence("echo 'a\\'; ls").unwrap(), ["echo 'a\\'", "ls"]);
+        assert_eq!(split_sequence("echo $'a\\'"), Err(SplitError::Unclosed('\'')));
+    }
+
+    #[test]
+    fn test_split_sequence_reads_here_documents() {
+        let input = "cat <<EOF > out.txt; echo done\nhello $USER\nit's \"here\"\nEOF\nsort <<-'END'\n\tb $x\n\ta\n\tEND\n";
+        let commands = split_sequence(input).unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                "cat <<< \"hello $USER\nit's \"'\"'\"here\"'\"'\"\" > out.txt",
+                "echo done",
+                "sort <<< 'b $x\na'",
+            ]
+        );
+        let parsed = parse_command(&commands[0]).unwrap();
+        assert_eq!(parsed[0].here_doc.as_deref(), Some("hello $USER\nit's \"here\"\n"));
+        assert_eq!(parsed[0].output_redirect.as_deref(), Some("out.txt"));
+
+        // A `\` escapes what it does in double quotes, but not a `"`
+        let commands = split_sequence("cat <<EOF\n\\$x \\\\ \\\"a\\\" \\q\nEOF").unwrap();
+        assert_eq!(parse_command(&commands[0]).unwrap()[0].here_doc.as_deref(), Some("$x \\ \\\"a\\\" \\q\n"));
+
+        // Here-strings and quoted `<<` are left alone
+        assert_eq!(split_sequence("cat <<< 'a b'; echo '<<EOF'").unwrap(), vec!["cat <<< 'a b'", "echo '<<EOF'"]);
+        assert_eq!(split_sequence("cat <<EOF\nno end\n").unwrap_err(), SplitError::Unterminated("EOF".to_string()));
+        assert!(matches!(split_sequence("cat << ; ls"), Err(SplitError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_split_and_or_respects_quotes() {
+        let (first, rest) = split_and_or("cargo build && echo 'a && b' || echo \"x||y\" | wc").unwrap();
+        assert_eq!(first, "cargo build");
+        assert_eq!(
+            rest,
+            vec![(Connector::And, "echo 'a && b'".to_string()), (Connector::Or, "echo \"x||y\" | wc".to_string())]
+        );
+        assert_eq!(split_and_or("ls | wc -l &").unwrap(), ("ls | wc -l &".to_string(), vec![]));
+        assert_eq!(split_and_or("&& ls").unwrap_err(), "Missing command before '&&'");
+        assert_eq!(split_and_or("ls ||").unwrap_err(), "Missing command after '||'");
+        assert!(Connector::And.runs_after(0) && !Connector::And.runs_after(1));
+        assert!(Connector::Or.runs_after(2) && !Connector::Or.runs_after(0));
+    }
+
     #[test]
     fn test_get_stdio_config_no_redirection() {
         let cmd = Command {
@@ -321,7 +1402,16 @@ mod tests {
             input_redirect: None,
             output_redirect: None,
             append: false,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
             background: false,
+            spans: Vec::new(),
         };
 
         let (stdin, stdout, stderr) = get_stdio_config(&cmd);
@@ -338,7 +1428,16 @@ mod tests {
             input_redirect: Some("input.txt".to_string()),
             output_redirect: None,
             append: false,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
             background: false,
+            spans: Vec::new(),
         };
 
         let (stdin, stdout, stderr) = get_stdio_config(&cmd);
@@ -354,7 +1453,16 @@ mod tests {
             input_redirect: None,
             output_redirect: Some("output.txt".to_string()),
             append: false,
+            clobber: false,
+            stderr_redirect: None,
+            stderr_append: false,
+            stderr_to_stdout: false,
+            here_doc: None,
+            globs: Vec::new(),
+            fd_redirects: Vec::new(),
+            env: Vec::new(),
             background: false,
+            spans: Vec::new(),
         };
 
         let (stdin, stdout, stderr) = get_stdio_config(&cmd);
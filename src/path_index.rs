@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Executables found on a `PATH`, by name. Scanning every directory can be
+/// slow (network mounts, huge `/usr/bin`), so the index is saved to disk and
+/// only rebuilt when `PATH` changes or on `rehash`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PathIndex {
+    /// The `PATH` value scanned
+    path: String,
+    /// Command name to the executable a lookup would run
+    commands: BTreeMap<String, PathBuf>,
+}
+
+impl PathIndex {
+    /// Scan the directories of `path` in order; the first executable with a
+    /// name wins, as in a lookup. Unreadable directories are skipped.
+    pub fn build(path: &str) -> Self {
+        let mut commands = BTreeMap::new();
+        for dir in env::split_paths(path) {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                let file = entry.path();
+                if commands.contains_key(command_name(&name)) || !is_executable(&file) {
+                    continue;
+                }
+                commands.insert(command_name(&name).to_string(), file);
+            }
+        }
+        Self { path: path.to_string(), commands }
+    }
+
+    /// Index for the current `PATH`: the one saved in `file` if it was built
+    /// for the same value, else a fresh scan, saved for next time
+    pub fn current(file: Option<&Path>) -> Self {
+        let path = env::var("PATH").unwrap_or_default();
+        if let Some(saved) = file.and_then(Self::load) {
+            if saved.path == path {
+                return saved;
+            }
+        }
+        let index = Self::build(&path);
+        if let Some(file) = file {
+            index.save_or_warn(file);
+        }
+        index
+    }
+
+    /// Read an index saved by `save`
+    pub fn load(file: &Path) -> Option<Self> {
+        serde_json::from_slice(&fs::read(file).ok()?).ok()
+    }
+
+    /// Write the index to `file`, replacing it in one step so concurrent
+    /// sessions never read half an index
+    pub fn save(&self, file: &Path) -> io::Result<()> {
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let partial = file.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&partial, serde_json::to_vec(self)?)?;
+        fs::rename(&partial, file)
+    }
+
+    pub fn save_or_warn(&self, file: &Path) {
+        if let Err(e) = self.save(file) {
+            tracing::debug!("Can't save the PATH index to {}: {}", file.display(), e);
+        }
+    }
+
+    /// Whether this index was built for `path`
+    pub fn is_for(&self, path: &str) -> bool {
+        self.path == path
+    }
+
+    /// Command names, sorted
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        self.commands.keys().map(String::as_str)
+    }
+
+    /// The executable `name` runs
+    pub fn lookup(&self, name: &str) -> Option<&Path> {
+        self.commands.get(name).map(PathBuf::as_path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+/// Where each command run so far was found on `PATH`, so running it again
+/// skips the search. Entries are dropped when `PATH` changes, on `rehash`,
+/// and when the file found is gone.
+#[derive(Debug, Default)]
+pub struct PathCache {
+    /// The `PATH` value the entries were found on
+    path: String,
+    found: HashMap<String, PathBuf>,
+}
+
+impl PathCache {
+    /// The executable `name` runs with `path` as `PATH`. Names with a
+    /// directory part are looked up each time, not remembered.
+    pub fn resolve(&mut self, name: &str, path: &str) -> Option<PathBuf> {
+        if name.contains(std::path::is_separator) {
+            return which::which(name).ok();
+        }
+        if self.path != path {
+            self.found.clear();
+            self.path = path.to_string();
+        }
+        if let Some(file) = self.found.get(name).filter(|file| is_executable(file)) {
+            return Some(file.clone());
+        }
+        let file = which::which_in(name, Some(path), env::current_dir().ok()?).ok()?;
+        self.found.insert(name.to_string(), file.clone());
+        Some(file)
+    }
+
+    /// Forget every entry, returning how many there were
+    pub fn clear(&mut self) -> usize {
+        let count = self.found.len();
+        self.found.clear();
+        count
+    }
+}
+
+/// Name a file is run by: on Windows `git.exe` is `git`
+#[cfg(windows)]
+fn command_name(file_name: &str) -> &str {
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if ["exe", "bat", "cmd", "com"].contains(&extension.to_ascii_lowercase().as_str()) => stem,
+        _ => file_name,
+    }
+}
+
+#[cfg(not(windows))]
+fn command_name(file_name: &str) -> &str {
+    file_name
+}
+
+#[cfg(unix)]
+fn is_executable(file: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    // Follows symlinks, as running it would
+    fs::metadata(file).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(file: &Path) -> bool {
+    let name = file.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    fs::metadata(file).is_ok_and(|metadata| metadata.is_file()) && command_name(name) != name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_build_save_and_reload() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("shell-t-path-index-{}", std::process::id()));
+        let (first, second) = (root.join("first"), root.join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        for (dir, name, mode) in [(&first, "tool", 0o755), (&first, "notes", 0o644), (&second, "tool", 0o755), (&second, "other", 0o700)] {
+            fs::write(dir.join(name), "").unwrap();
+            fs::set_permissions(dir.join(name), fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        let path = env::join_paths([&first, &root.join("missing"), &second]).unwrap().into_string().unwrap();
+        let index = PathIndex::build(&path);
+        assert_eq!(index.commands().collect::<Vec<_>>(), ["other", "tool"]);
+        assert_eq!(index.lookup("tool"), Some(first.join("tool").as_path()));
+        assert!(index.is_for(&path));
+
+        let file = root.join("cache").join("path-index.json");
+        index.save(&file).unwrap();
+        assert_eq!(PathIndex::load(&file), Some(index));
+
+        let mut cache = PathCache::default();
+        assert_eq!(cache.resolve("tool", &path), Some(first.join("tool")));
+        assert_eq!(cache.resolve("notes", &path), None);
+        // A new PATH starts over, and a file that is gone is searched for again
+        let reordered = env::join_paths([&second, &first]).unwrap().into_string().unwrap();
+        assert_eq!(cache.resolve("tool", &reordered), Some(second.join("tool")));
+        assert_eq!(cache.resolve("tool", &path), Some(first.join("tool")));
+        fs::remove_file(first.join("tool")).unwrap();
+        assert_eq!(cache.resolve("tool", &path), Some(second.join("tool")));
+        assert_eq!(cache.clear(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
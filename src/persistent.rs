@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::inline::Language;
+use crate::jobs::JobManager;
+use crate::limits::{self, Budget, Rlimits};
+
+/// Python side of the pipe protocol.
+///
+/// Requests arrive on stdin as a decimal length line followed by a JSON body
+/// (`code`, `file`, `argv`, `cwd`, `env`, `interactive`, `vars`). Replies on
+/// stdout are frames of at most `FRAME_LIMIT` bytes: `OUT <len>\n<bytes>`,
+/// `ERR <len>\n<bytes>`, then `EXIT <status>\n`. Interactive requests share one namespace and echo
+/// expression values in their last statement, as in Python's own REPL.
+const PYTHON_SERVER: &str = r#"
+import ast, io, json, os, sys, traceback, types
+_out, _in = sys.stdout.buffer, sys.stdin.buffer
+_repl = {"__name__": "__main__"}
+def _frame(kind, data):
+    for i in range(0, len(data), 65536):
+        _out.write(kind + b" %d\n" % len(data[i:i + 65536])); _out.write(data[i:i + 65536])
+    _out.flush()
+class _Writer(io.TextIOBase):
+    def __init__(self, kind): self.kind = kind
+    def writable(self): return True
+    def write(self, text): _frame(self.kind, text.encode()); return len(text)
+while True:
+    try:
+        header = _in.readline()
+        if not header: break
+        req = json.loads(_in.read(int(header)))
+    except KeyboardInterrupt:
+        continue
+    status = 0
+    sys.stdout, sys.stderr, sys.stdin = _Writer(b"OUT"), _Writer(b"ERR"), open(os.devnull)
+    sys.argv = req["argv"]
+    try:
+        os.chdir(req["cwd"]); os.environ.clear(); os.environ.update(req["env"])
+        sys.path[0] = os.path.dirname(os.path.abspath(req["file"]))
+        if req["interactive"]:
+            _repl["shell"] = types.MappingProxyType(req["vars"])
+            body = ast.parse(req["code"], req["file"]).body
+            exec(compile(ast.Module(body[:-1], []), req["file"], "exec"), _repl)
+            exec(compile(ast.Interactive(body[-1:]), req["file"], "single"), _repl)
+        else:
+            exec(compile(req["code"], req["file"], "exec"), {"__name__": "__main__", "__file__": req["file"]})
+    except SystemExit as e:
+        status = e.code if isinstance(e.code, int) else (0 if e.code is None else 1)
+    except KeyboardInterrupt:
+        status = 130
+    except BaseException:
+        traceback.print_exc(); status = 1
+    sys.stdin.close()
+    sys.stdout, sys.stderr, sys.stdin = sys.__stdout__, sys.__stderr__, sys.__stdin__
+    _out.write(b"EXIT %d\n" % status); _out.flush()
+"#;
+
+/// Largest output frame a server may send; longer writes are split
+const FRAME_LIMIT: usize = 65536;
+
+/// Server program for languages that support persistent sessions
+fn server_program(language: Language) -> Option<&'static str> {
+    match language {
+        Language::Python => Some(PYTHON_SERVER),
+        Language::Ruby | Language::Node => None,
+    }
+}
+
+/// One program run by a server
+#[derive(Debug, Serialize)]
+pub struct Request {
+    pub code: String,
+    /// Name reported in tracebacks; `<inline>` for snippets
+    pub file: String,
+    pub argv: Vec<String>,
+    pub cwd: String,
+    pub env: BTreeMap<String, String>,
+    /// Run in the server's REPL namespace, echoing expression values
+    pub interactive: bool,
+    /// Shell variables, visible read-only to interactive code as `shell`
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Request {
+    /// Build a request carrying the shell's current directory and environment
+    pub fn new(code: String, file: &str, args: &[String]) -> io::Result<Self> {
+        Ok(Self {
+            code,
+            file: file.to_string(),
+            argv: std::iter::once(file.to_string()).chain(args.iter().cloned()).collect(),
+            cwd: env::current_dir()?.display().to_string(),
+            env: env::vars().collect(),
+            interactive: false,
+            vars: BTreeMap::new(),
+        })
+    }
+
+    /// Build a REPL request; state persists between interactive requests
+    pub fn interactive(code: String, vars: BTreeMap<String, String>) -> io::Result<Self> {
+        Ok(Self { interactive: true, vars, ..Self::new(code, "<stdin>", &[])? })
+    }
+}
+
+/// Which stream an output frame belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Write an output frame to the shell's own stdout or stderr
+pub fn relay(stream: Stream, data: &[u8]) -> io::Result<()> {
+    match stream {
+        Stream::Stdout => {
+            io::stdout().write_all(data)?;
+            io::stdout().flush()
+        }
+        Stream::Stderr => io::stderr().write_all(data),
+    }
+}
+
+/// A running interpreter fed over its stdin
+struct Server {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    /// Lists the server under `jobs` while it runs
+    jobs: Option<Arc<JobManager>>,
+}
+
+impl Server {
+    fn spawn(interpreter: &str, program: &str, rlimits: Rlimits, jobs: Option<Arc<JobManager>>) -> io::Result<Self> {
+        let mut command = Command::new(interpreter);
+        command.args(["-u", "-c", program]).stdin(Stdio::piped()).stdout(Stdio::piped());
+        limits::restrict(&mut command, rlimits);
+        let mut child = command.spawn()?;
+        tracing::info!(interpreter, pid = child.id(), "started persistent interpreter");
+
+        let stdin = child.stdin.take().ok_or_else(|| io::Error::other("interpreter stdin unavailable"))?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("interpreter stdout unavailable"))?;
+        if let Some(jobs) = &jobs {
+            jobs.add_server(child.id(), format!("{} (persistent interpreter)", interpreter));
+        }
+        Ok(Self { child, stdin, stdout: BufReader::new(stdout), jobs })
+    }
+
+    /// Send a request and pass output frames to `output` until the exit status arrives
+    fn run(&mut self, request: &Request, output: &mut dyn FnMut(Stream, &[u8]) -> io::Result<()>) -> io::Result<i32> {
+        let body = serde_json::to_vec(request)?;
+        writeln!(self.stdin, "{}", body.len())?;
+        self.stdin.write_all(&body)?;
+        self.stdin.flush()?;
+
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if self.stdout.read_line(&mut header)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "interpreter exited"));
+            }
+            let (kind, value) = header
+                .trim_end()
+                .split_once(' ')
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed frame"))?;
+            let value: i32 = value
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed frame length"))?;
+
+            match kind {
+                "EXIT" => return Ok(value),
+                "OUT" | "ERR" => {
+                    let len = usize::try_from(value)
+                        .ok()
+                        .filter(|len| (1..=FRAME_LIMIT).contains(len))
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad frame length"))?;
+                    let mut data = vec![0; len];
+                    self.stdout.read_exact(&mut data)?;
+                    output(if kind == "OUT" { Stream::Stdout } else { Stream::Stderr }, &data)?;
+                }
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown frame")),
+            }
+        }
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some(jobs) = &self.jobs {
+            jobs.remove_server(self.child.id());
+        }
+    }
+}
+
+/// Interpreter servers kept warm between runs, one per language
+#[derive(Default)]
+pub struct InterpreterPool {
+    servers: Mutex<HashMap<(Language, String), Server>>,
+    /// Set on each server as it starts
+    rlimits: Rlimits,
+    /// Where running servers are listed, for `jobs`
+    jobs: Option<Arc<JobManager>>,
+}
+
+impl InterpreterPool {
+    /// Create an empty pool; servers start on first use
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty pool whose servers start with `rlimits`. CPU time adds up
+    /// over a server's whole life, so it is left to each run's deadline.
+    pub fn with_rlimits(rlimits: Rlimits) -> Self {
+        Self { rlimits: Rlimits { cpu_secs: 0, ..rlimits }, ..Self::default() }
+    }
+
+    /// List servers with `jobs` while they run
+    pub fn with_jobs(self, jobs: Arc<JobManager>) -> Self {
+        Self { jobs: Some(jobs), ..self }
+    }
+
+    /// Whether `language` can run in a persistent server
+    pub fn supports(language: Language) -> bool {
+        server_program(language).is_some()
+    }
+
+    /// Run a request on the server for `language`, starting or restarting it as
+    /// needed; output goes to the shell's stdout and stderr
+    pub fn run(&self, language: Language, interpreter: &str, request: &Request) -> io::Result<i32> {
+        self.run_with(language, interpreter, request, Budget::default(), &mut relay)
+    }
+
+    /// Run a request, passing its output frames to `output`. A server still
+    /// busy at the budget's deadline, or writing past its output cap, is
+    /// killed, to be restarted on the next request.
+    pub fn run_with(
+        &self,
+        language: Language,
+        interpreter: &str,
+        request: &Request,
+        budget: Budget,
+        output: &mut dyn FnMut(Stream, &[u8]) -> io::Result<()>,
+    ) -> io::Result<i32> {
+        let program = server_program(language)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no persistent server for language"))?;
+        let mut servers = self.servers.lock().unwrap();
+        let key = (language, interpreter.to_string());
+
+        if !servers.get_mut(&key).is_some_and(Server::is_alive) {
+            servers.insert(key.clone(), Server::spawn(interpreter, program, self.rlimits, self.jobs.clone())?);
+        }
+        let server = servers.get_mut(&key).expect("server was just started");
+
+        let mut written = 0u64;
+        let mut capped = |stream: Stream, data: &[u8]| {
+            let allowed = (budget.max_output.unwrap_or(u64::MAX) - written).min(data.len() as u64) as usize;
+            output(stream, &data[..allowed])?;
+            written += allowed as u64;
+            match allowed < data.len() {
+                true => Err(Budget::output_exceeded()),
+                false => Ok(()),
+            }
+        };
+        let result = match budget.deadline {
+            Some(deadline) => {
+                let pid = server.child.id();
+                let (finished, done) = mpsc::channel::<()>();
+                let watcher = thread::spawn(move || {
+                    let expired = done.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                        == Err(RecvTimeoutError::Timeout);
+                    if expired {
+                        limits::kill(pid);
+                    }
+                    expired
+                });
+                let result = server.run(request, &mut capped);
+                drop(finished);
+                match watcher.join() {
+                    Ok(true) => Err(io::Error::new(io::ErrorKind::TimedOut, "interpreter ran past its deadline")),
+                    _ => result,
+                }
+            }
+            None => server.run(request, &mut capped),
+        };
+        if result.is_err() {
+            // A broken protocol stream can't be resynchronised; start afresh next time
+            servers.remove(&key);
+        }
+        result
+    }
+
+    /// Stop every server
+    pub fn shutdown(&self) {
+        self.servers.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_python_server_keeps_state_between_runs() {
+        if which::which("python3").is_err() {
+            return;
+        }
+        let jobs = Arc::new(JobManager::new());
+        let pool = InterpreterPool::new().with_jobs(jobs.clone());
+        let run = |code: &str| pool.run(Language::Python, "python3", &Request::new(code.to_string(), "<inline>", &[]).unwrap());
+
+        assert_eq!(run("import sys; sys.modules['shell_t_probe'] = 1").unwrap(), 0);
+        assert_eq!(run("import sys; sys.exit(0 if 'shell_t_probe' in sys.modules else 3)").unwrap(), 0);
+        assert_eq!(run("raise SystemExit(4)").unwrap(), 4);
+        let servers = jobs.servers();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].1, "python3 (persistent interpreter)");
+
+        pool.shutdown();
+        assert!(jobs.servers().is_empty());
+    }
+
+    #[test]
+    fn test_frames_are_split_and_bad_lengths_refused() {
+        if which::which("python3").is_err() {
+            return;
+        }
+        let pool = InterpreterPool::new();
+        let request = Request::new("print('x' * 200000, end='')".to_string(), "<inline>", &[]).unwrap();
+        let mut frames = Vec::new();
+        let status = pool.run_with(Language::Python, "python3", &request, Budget::default(), &mut |_, data: &[u8]| {
+            frames.push(data.len());
+            Ok(())
+        });
+        assert_eq!(status.unwrap(), 0);
+        assert_eq!(frames, [FRAME_LIMIT, FRAME_LIMIT, FRAME_LIMIT, 200000 - 3 * FRAME_LIMIT]);
+
+        for header in ["OUT 0", "OUT -1", "ERR 65537", "OUT 99999999999"] {
+            let program = format!("import sys; sys.stdout.write('{}\\n'); sys.stdout.flush(); sys.stdin.read()", header);
+            let mut server = Server::spawn("python3", &program, Rlimits::default(), None).unwrap();
+            let err = server.run(&request, &mut |_, _| Ok(())).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", header);
+        }
+    }
+
+    #[test]
+    fn test_interactive_requests_share_a_namespace() {
+        if which::which("python3").is_err() {
+            return;
+        }
+        let pool = InterpreterPool::new();
+        let vars = BTreeMap::from([("name".to_string(), "t".to_string())]);
+        let run = |code: &str| pool.run(Language::Python, "python3", &Request::interactive(code.to_string(), vars.clone()).unwrap());
+
+        assert_eq!(run("x = 41\n").unwrap(), 0);
+        assert_eq!(run("import sys; sys.exit(0 if x + 1 == 42 and shell['name'] == 't' else 3)\n").unwrap(), 0);
+        // Shell variables are read-only
+        assert_eq!(run("shell['name'] = 'u'\n").unwrap(), 1);
+        // Scripts still start from a clean namespace
+        let script = Request::new("import sys; sys.exit(4 if 'x' in globals() else 0)".to_string(), "<inline>", &[]).unwrap();
+        assert_eq!(pool.run(Language::Python, "python3", &script).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_deadline_kills_and_restarts_server() {
+        if which::which("python3").is_err() {
+            return;
+        }
+        let jobs = Arc::new(JobManager::new());
+        let pool = InterpreterPool::new().with_jobs(jobs.clone());
+        let request = |code: &str| Request::new(code.to_string(), "<inline>", &[]).unwrap();
+        let budget = Budget { deadline: Some(Instant::now() + std::time::Duration::from_millis(200)), max_output: None };
+        let err = pool.run_with(Language::Python, "python3", &request("while True: pass"), budget, &mut relay).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(jobs.servers().is_empty());
+        assert_eq!(pool.run(Language::Python, "python3", &request("raise SystemExit(5)")).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_output_cap_and_rlimits() {
+        if which::which("python3").is_err() {
+            return;
+        }
+        let jobs = Arc::new(JobManager::new());
+        let pool = InterpreterPool::with_rlimits(Rlimits { open_files: 20, ..Rlimits::default() }).with_jobs(jobs.clone());
+        let request = |code: &str| Request::new(code.to_string(), "<inline>", &[]).unwrap();
+        let mut shown = Vec::new();
+        let budget = Budget { deadline: None, max_output: Some(10) };
+        let err = pool
+            .run_with(Language::Python, "python3", &request("print('x' * 100)"), budget, &mut |_, data: &[u8]| {
+                shown.extend_from_slice(data);
+                Ok(())
+            })
+            .unwrap_err();
+        assert_eq!((err.kind(), shown.len()), (io::ErrorKind::FileTooLarge, 10));
+        assert!(jobs.servers().is_empty());
+
+        let limit = "import resource, sys; sys.exit(resource.getrlimit(resource.RLIMIT_NOFILE)[0])";
+        assert_eq!(pool.run(Language::Python, "python3", &request(limit)).unwrap(), 20);
+    }
+
+    #[test]
+    fn test_unsupported_language() {
+        assert!(!InterpreterPool::supports(Language::Ruby));
+        assert!(InterpreterPool::supports(Language::Python));
+    }
+}
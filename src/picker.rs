@@ -0,0 +1,235 @@
+use std::io::{self, Write};
+
+use crossterm::cursor::{MoveTo, MoveToNextLine, RestorePosition, SavePosition};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::QueueableCommand;
+
+use crate::ui;
+
+// Match scoring, in the spirit of skim and fzf
+const SCORE_MATCH: i64 = 16;
+/// Extra for a match right after the previous one
+const BONUS_CONSECUTIVE: i64 = 12;
+/// Extra for a match at the start of a word or path component
+const BONUS_BOUNDARY: i64 = 8;
+const PENALTY_GAP: i64 = 1;
+/// Longest gap that still costs more
+const MAX_GAP_PENALTY: i64 = 8;
+
+/// Score `candidate` against `query`, returning the score and the char
+/// indices that matched; `None` unless every query char appears in order.
+/// Matching ignores case unless the query has an uppercase letter.
+pub fn score(query: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let smart_case = query.chars().any(char::is_uppercase);
+    let fold = |c: char| if smart_case { c } else { c.to_ascii_lowercase() };
+    let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).map(fold).collect();
+    let text: Vec<char> = candidate.chars().collect();
+    if query.is_empty() {
+        return Some((0, Vec::new()));
+    }
+
+    // Try every position the first char matches at and keep the best greedy run
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    for start in (0..text.len()).filter(|&i| fold(text[i]) == query[0]) {
+        let mut positions = vec![start];
+        let mut next = start + 1;
+        for &wanted in &query[1..] {
+            match (next..text.len()).find(|&i| fold(text[i]) == wanted) {
+                Some(found) => {
+                    positions.push(found);
+                    next = found + 1;
+                }
+                None => break,
+            }
+        }
+        if positions.len() < query.len() {
+            // Later starts can't match more of the query either
+            break;
+        }
+        let total = rate(&text, &positions);
+        if best.as_ref().is_none_or(|(score, _)| total > *score) {
+            best = Some((total, positions));
+        }
+    }
+    best
+}
+
+/// Score matched `positions` within `text`
+fn rate(text: &[char], positions: &[usize]) -> i64 {
+    let mut total = 0;
+    for (n, &position) in positions.iter().enumerate() {
+        total += SCORE_MATCH;
+        if position == 0 || matches!(text[position - 1], ' ' | '/' | '-' | '_' | '.' | '=' | '|' | ';') {
+            total += BONUS_BOUNDARY;
+        }
+        if n > 0 {
+            match position - positions[n - 1] - 1 {
+                0 => total += BONUS_CONSECUTIVE,
+                gap => total -= (gap as i64 * PENALTY_GAP).min(MAX_GAP_PENALTY),
+            }
+        }
+    }
+    // Among equals, prefer shorter candidates
+    total * 1000 - text.len() as i64
+}
+
+/// Indices of `items` matching `query`, best first; ties keep their order
+pub fn filter(query: &str, items: &[String]) -> Vec<(usize, Vec<usize>)> {
+    let mut matches: Vec<(i64, usize, Vec<usize>)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| score(query, item).map(|(score, positions)| (score, index, positions)))
+        .collect();
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    matches.into_iter().map(|(_, index, positions)| (index, positions)).collect()
+}
+
+/// Restores the screen and leaves raw mode when the picker closes. In a
+/// multiplexer pane, already on the alternate screen, it only clears it and
+/// puts the cursor back.
+struct Screen {
+    in_pane: bool,
+}
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let in_pane = ui::in_pane();
+        if in_pane {
+            io::stdout().queue(SavePosition)?.flush()?;
+        } else {
+            io::stdout().queue(EnterAlternateScreen)?.flush()?;
+        }
+        Ok(Self { in_pane })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        let _ = if self.in_pane {
+            out.queue(Clear(ClearType::All)).and_then(|out| out.queue(RestorePosition)).and_then(|out| out.flush())
+        } else {
+            out.queue(LeaveAlternateScreen).and_then(|out| out.flush())
+        };
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Full-screen fuzzy finder over `items`, starting from `query`. Type to
+/// narrow the list, move with the arrow keys (or Ctrl-P/Ctrl-N), Enter picks
+/// and Esc or Ctrl-C cancels. Returns the picked item.
+pub fn pick(items: &[String], query: &str) -> io::Result<Option<String>> {
+    let _screen = Screen::enter()?;
+    let mut query = query.to_string();
+    let mut selected = 0;
+    let mut top = 0;
+
+    loop {
+        let matches = filter(&query, items);
+        selected = selected.min(matches.len().saturating_sub(1));
+        let (width, height) = ui::terminal_size();
+        let rows = height.saturating_sub(2).max(1);
+        if selected < top {
+            top = selected;
+        } else if selected >= top + rows {
+            top = selected + 1 - rows;
+        }
+        draw(&query, items, &matches, selected, top, rows, width)?;
+
+        let key = match event::read()? {
+            Event::Key(key @ KeyEvent { kind: KeyEventKind::Press, .. }) => key,
+            _ => continue,
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c' | 'g' | 'd') if ctrl => return Ok(None),
+            KeyCode::Enter => return Ok(matches.get(selected).map(|(index, _)| items[*index].clone())),
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Char('p' | 'k') if ctrl => selected = selected.saturating_sub(1),
+            KeyCode::Down => selected += 1,
+            KeyCode::Char('n' | 'j') if ctrl => selected += 1,
+            KeyCode::PageUp => selected = selected.saturating_sub(rows),
+            KeyCode::PageDown => selected += rows,
+            KeyCode::Char('u') if ctrl => query.clear(),
+            KeyCode::Backspace => {
+                query.pop();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Draw the query line, a match count and one screenful of matches
+fn draw(
+    query: &str,
+    items: &[String],
+    matches: &[(usize, Vec<usize>)],
+    selected: usize,
+    top: usize,
+    rows: usize,
+    width: usize,
+) -> io::Result<()> {
+    let mut out = io::stdout();
+    out.queue(MoveTo(0, 0))?.queue(Clear(ClearType::All))?;
+    out.queue(Print(format!("> {}", query)))?.queue(MoveToNextLine(1))?;
+    out.queue(SetAttribute(Attribute::Dim))?
+        .queue(Print(format!("  {}/{}", matches.len(), items.len())))?
+        .queue(SetAttribute(Attribute::Reset))?;
+
+    for (row, (index, positions)) in matches.iter().enumerate().skip(top).take(rows) {
+        out.queue(MoveToNextLine(1))?;
+        let current = row == selected;
+        out.queue(Print(if current { "> " } else { "  " }))?;
+        if current {
+            out.queue(SetAttribute(Attribute::Reverse))?;
+        }
+        // Multi-line commands show on one line
+        let text = items[*index].replace('\n', " ");
+        for (n, c) in text.chars().take(width.saturating_sub(2)).enumerate() {
+            if positions.contains(&n) {
+                out.queue(SetAttribute(Attribute::Bold))?.queue(Print(c))?.queue(SetAttribute(Attribute::NormalIntensity))?;
+            } else {
+                out.queue(Print(c))?;
+            }
+        }
+        out.queue(SetAttribute(Attribute::Reset))?;
+    }
+    out.queue(MoveTo((2 + query.chars().count()).min(width.saturating_sub(1)) as u16, 0))?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked(query: &str, items: &[&str]) -> Vec<String> {
+        let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+        filter(query, &items).into_iter().map(|(index, _)| items[index].clone()).collect()
+    }
+
+    #[test]
+    fn test_score_matches_in_order_with_smart_case() {
+        assert_eq!(score("gst", "git status").unwrap().1, vec![0, 4, 5]);
+        assert!(score("tsg", "git status").is_none());
+        assert!(score("GIT", "git status").is_none());
+        assert!(score("git", "GIT STATUS").is_some());
+        assert_eq!(score("", "anything"), Some((0, Vec::new())));
+    }
+
+    #[test]
+    fn test_filter_prefers_consecutive_and_boundary_matches() {
+        let items = ["cargo build --release", "cat README.md", "echo carrot", "cargo test"];
+        assert_eq!(ranked("cargo", &items), ["cargo test", "cargo build --release"]);
+        assert_eq!(ranked("rel", &items)[0], "cargo build --release");
+        // Without a query every item stays, in its original order
+        assert_eq!(ranked("", &items), items);
+    }
+}
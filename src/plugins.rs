@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use wasmtime::{Config as EngineConfig, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::builtins::BuiltinCommand;
+use crate::config::PluginConfig;
+use crate::error::{SecurityError, ShellError, ShellResult};
+
+/// WebAssembly builtins.
+///
+/// A plugin is a core wasm module with no imports, so it can reach nothing
+/// outside its own memory. It exports:
+///
+/// - `memory`
+/// - `shell_t_alloc(len: i32) -> i32`: space for the host to write input
+/// - `shell_t_name() -> i64`: builtin name
+/// - `shell_t_schema() -> i64`: JSON [`ArgSchema`]
+/// - `shell_t_run(ptr: i32, len: i32) -> i64`: JSON [`RunInput`] in, JSON [`RunOutput`] out
+///
+/// Strings are returned packed as `(ptr << 32) | len`. Every call gets a fresh
+/// instance with fuel and memory limits from `[plugins]`.
+pub struct PluginManager {
+    engine: Engine,
+    config: PluginConfig,
+    plugins: BTreeMap<String, Plugin>,
+}
+
+struct Plugin {
+    module: Module,
+    schema: ArgSchema,
+    path: PathBuf,
+}
+
+/// Arguments a plugin accepts; the host validates them before calling `run`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ArgSchema {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub args: Vec<ArgSpec>,
+    /// Accept any number of extra string arguments
+    #[serde(default)]
+    pub variadic: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArgSpec {
+    pub name: String,
+    #[serde(rename = "type", default = "default_arg_type")]
+    pub kind: String,
+    #[serde(default = "default_required")]
+    pub required: bool,
+}
+
+fn default_arg_type() -> String {
+    "string".to_string()
+}
+
+fn default_required() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+struct RunInput<'a> {
+    args: &'a [String],
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RunOutput {
+    #[serde(default)]
+    status: i32,
+    #[serde(default)]
+    stdout: String,
+    #[serde(default)]
+    stderr: String,
+}
+
+/// Store data: memory limits for the instance
+struct PluginState {
+    limits: StoreLimits,
+}
+
+impl PluginManager {
+    /// Create a manager with no plugins loaded
+    pub fn new(config: &PluginConfig) -> ShellResult<Self> {
+        let mut engine_config = EngineConfig::new();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config).map_err(plugin_error)?;
+        Ok(Self { engine, config: config.clone(), plugins: BTreeMap::new() })
+    }
+
+    /// Load every `*.wasm` (or `*.wat`) module in `dir`, returning how many loaded
+    pub fn load_dir(&mut self, dir: &Path) -> ShellResult<usize> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Ok(0);
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "wasm" || ext == "wat"))
+            .collect();
+        paths.sort();
+
+        for path in &paths {
+            let bytes = fs::read(path)?;
+            let name = self.load(&bytes, path)?;
+            tracing::debug!(plugin = %name, path = %path.display(), "loaded wasm plugin");
+        }
+        Ok(paths.len())
+    }
+
+    /// Compile and register one plugin, returning its builtin name
+    pub fn load(&mut self, bytes: &[u8], path: &Path) -> ShellResult<String> {
+        let module = Module::new(&self.engine, bytes).map_err(plugin_error)?;
+        if module.imports().next().is_some() {
+            return Err(SecurityError::PermissionDenied(format!(
+                "plugin {} imports host functions; plugins must be self-contained",
+                path.display()
+            ))
+            .into());
+        }
+
+        let (mut store, instance, memory) = self.instantiate(&module)?;
+        let name = call_string(&mut store, &instance, &memory, "shell_t_name")?;
+        let schema = call_string(&mut store, &instance, &memory, "shell_t_schema")?;
+        let schema: ArgSchema = serde_json::from_str(&schema)
+            .map_err(|e| plugin_error(format!("{}: invalid schema: {}", path.display(), e)))?;
+
+        if BuiltinCommand::is_builtin(&name) || name.is_empty() {
+            return Err(plugin_error(format!("{}: invalid plugin name '{}'", path.display(), name)));
+        }
+        self.plugins.insert(name.clone(), Plugin { module, schema, path: path.to_path_buf() });
+        Ok(name)
+    }
+
+    /// Whether a plugin provides the builtin `name`
+    pub fn has(&self, name: &str) -> bool {
+        self.plugins.contains_key(name)
+    }
+
+    /// Loaded plugins as `(name, description, path)`
+    pub fn list(&self) -> Vec<(&str, &str, &Path)> {
+        self.plugins
+            .iter()
+            .map(|(name, plugin)| (name.as_str(), plugin.schema.description.as_str(), plugin.path.as_path()))
+            .collect()
+    }
+
+    /// Validate `args` against the plugin's schema and run it
+    pub fn run(&self, name: &str, args: &[String]) -> ShellResult<i32> {
+        let plugin = self
+            .plugins
+            .get(name)
+            .ok_or_else(|| plugin_error(format!("no plugin named '{}'", name)))?;
+        validate_args(&plugin.schema, args).map_err(|e| ShellError::CommandExecution(format!("{}: {}", name, e)))?;
+
+        let (mut store, instance, memory) = self.instantiate(&plugin.module)?;
+        let input = serde_json::to_vec(&RunInput { args }).map_err(plugin_error)?;
+
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "shell_t_alloc")
+            .map_err(plugin_error)?;
+        let ptr = alloc.call(&mut store, input.len() as i32).map_err(plugin_error)?;
+        memory.write(&mut store, ptr as usize, &input).map_err(plugin_error)?;
+
+        let run = instance
+            .get_typed_func::<(i32, i32), i64>(&mut store, "shell_t_run")
+            .map_err(plugin_error)?;
+        let packed = run.call(&mut store, (ptr, input.len() as i32)).map_err(plugin_error)?;
+        let output = read_packed(&store, &memory, packed)?;
+        let output: RunOutput = serde_json::from_str(&output)
+            .map_err(|e| plugin_error(format!("{}: invalid output: {}", name, e)))?;
+
+        print!("{}", output.stdout);
+        eprint!("{}", output.stderr);
+        Ok(output.status)
+    }
+
+    fn instantiate(&self, module: &Module) -> ShellResult<(Store<PluginState>, Instance, Memory)> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.config.max_memory_mb * 1024 * 1024)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&self.engine, PluginState { limits });
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.config.fuel).map_err(plugin_error)?;
+
+        let instance = Instance::new(&mut store, module, &[]).map_err(plugin_error)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| plugin_error("plugin does not export memory"))?;
+        Ok((store, instance, memory))
+    }
+}
+
+/// Check argument count and types against a schema
+fn validate_args(schema: &ArgSchema, args: &[String]) -> Result<(), String> {
+    let required = schema.args.iter().filter(|spec| spec.required).count();
+    if args.len() < required {
+        let missing: Vec<&str> = schema.args[args.len()..].iter().map(|spec| spec.name.as_str()).collect();
+        return Err(format!("missing argument: {}", missing.join(", ")));
+    }
+    if !schema.variadic && args.len() > schema.args.len() {
+        return Err(format!("expected at most {} arguments", schema.args.len()));
+    }
+
+    for (spec, arg) in schema.args.iter().zip(args) {
+        let valid = match spec.kind.as_str() {
+            "int" => arg.parse::<i64>().is_ok(),
+            "number" => arg.parse::<f64>().is_ok(),
+            "bool" => matches!(arg.as_str(), "true" | "false"),
+            _ => true,
+        };
+        if !valid {
+            return Err(format!("{}: expected {}, got '{}'", spec.name, spec.kind, arg));
+        }
+    }
+    Ok(())
+}
+
+fn call_string(store: &mut Store<PluginState>, instance: &Instance, memory: &Memory, export: &str) -> ShellResult<String> {
+    let function = instance
+        .get_typed_func::<(), i64>(&mut *store, export)
+        .map_err(plugin_error)?;
+    let packed = function.call(&mut *store, ()).map_err(plugin_error)?;
+    read_packed(store, memory, packed)
+}
+
+/// Read a `(ptr << 32) | len` string out of plugin memory
+fn read_packed(store: &Store<PluginState>, memory: &Memory, packed: i64) -> ShellResult<String> {
+    let ptr = (packed as u64 >> 32) as usize;
+    let len = (packed as u64 & 0xffff_ffff) as usize;
+    let data = memory
+        .data(store)
+        .get(ptr..ptr + len)
+        .ok_or_else(|| plugin_error("plugin returned an out-of-bounds string"))?;
+    String::from_utf8(data.to_vec()).map_err(|_| plugin_error("plugin returned invalid UTF-8"))
+}
+
+fn plugin_error(e: impl std::fmt::Display) -> ShellError {
+    ShellError::CommandExecution(format!("plugin: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal plugin: `hello`, one optional int argument, prints a fixed line
+    const HELLO: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "hello")
+          (data (i32.const 16) "{\"description\":\"says hi\",\"args\":[{\"name\":\"n\",\"type\":\"int\",\"required\":false}]}")
+          (data (i32.const 128) "{\"status\":0,\"stdout\":\"hi\\n\"}")
+          (func (export "shell_t_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "shell_t_name") (result i64) (i64.const 5))
+          (func (export "shell_t_schema") (result i64) (i64.const 0x100000004d))
+          (func (export "shell_t_run") (param i32 i32) (result i64) (i64.const 0x800000001c)))
+    "#;
+
+    fn manager(fuel: u64) -> PluginManager {
+        PluginManager::new(&PluginConfig { fuel, ..PluginConfig::default() }).unwrap()
+    }
+
+    #[test]
+    fn test_load_and_run_plugin() {
+        let mut plugins = manager(1_000_000);
+        let name = plugins.load(HELLO.as_bytes(), Path::new("hello.wat")).unwrap();
+        assert_eq!(name, "hello");
+        assert_eq!(plugins.list()[0].1, "says hi");
+
+        assert_eq!(plugins.run("hello", &[]).unwrap(), 0);
+        assert_eq!(plugins.run("hello", &["3".to_string()]).unwrap(), 0);
+        assert!(plugins.run("hello", &["three".to_string()]).is_err());
+        assert!(plugins.run("hello", &["1".to_string(), "2".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_plugins_with_imports_are_rejected() {
+        let mut plugins = manager(1_000_000);
+        let wat = r#"(module (import "env" "system" (func)) (memory (export "memory") 1))"#;
+        assert!(plugins.load(wat.as_bytes(), Path::new("evil.wat")).is_err());
+    }
+
+    #[test]
+    fn test_fuel_stops_runaway_plugins() {
+        let mut plugins = manager(10_000);
+        let spin = HELLO.replace(
+            r#"(func (export "shell_t_run") (param i32 i32) (result i64) (i64.const 0x800000001c))"#,
+            r#"(func (export "shell_t_run") (param i32 i32) (result i64) (loop (br 0)) (i64.const 0))"#,
+        );
+        plugins.load(spin.as_bytes(), Path::new("spin.wat")).unwrap();
+        assert!(plugins.run("hello", &[]).is_err());
+    }
+
+    #[test]
+    fn test_validate_args() {
+        let schema = ArgSchema {
+            args: vec![ArgSpec { name: "count".to_string(), kind: "int".to_string(), required: true }],
+            variadic: true,
+            ..ArgSchema::default()
+        };
+        assert!(validate_args(&schema, &[]).is_err());
+        assert!(validate_args(&schema, &["2".to_string(), "extra".to_string()]).is_ok());
+        assert!(validate_args(&schema, &["x".to_string()]).is_err());
+    }
+}
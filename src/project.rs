@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+use serde::Deserialize;
+
+use crate::inline::Language;
+
+/// Per-project settings file, looked for in the current directory and its parents
+pub const PROJECT_FILE: &str = ".shell-t.toml";
+
+/// Python inside a virtualenv, relative to its root
+#[cfg(windows)]
+const VENV_PYTHON: &str = "Scripts/python.exe";
+#[cfg(not(windows))]
+const VENV_PYTHON: &str = "bin/python";
+
+/// Virtualenv directory names looked for in a project
+const VENV_DIRS: [&str; 2] = [".venv", "venv"];
+
+/// Poetry environments by project root, since finding one means running `poetry`
+static POETRY_ENVS: OnceLock<Mutex<HashMap<PathBuf, Option<PathBuf>>>> = OnceLock::new();
+
+/// Interpreter versions reported by `--version`, by interpreter path
+static VERSIONS: OnceLock<Mutex<HashMap<String, Option<String>>>> = OnceLock::new();
+
+/// Interpreter versions a project requires, e.g. `python = "3.11"`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct VersionPins {
+    pub python: Option<String>,
+    pub ruby: Option<String>,
+    pub node: Option<String>,
+}
+
+impl VersionPins {
+    fn get(&self, language: Language) -> Option<&str> {
+        match language {
+            Language::Python => self.python.as_deref(),
+            Language::Ruby => self.ruby.as_deref(),
+            Language::Node => self.node.as_deref(),
+        }
+    }
+}
+
+/// Where a project-local interpreter came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvKind {
+    Venv,
+    Poetry,
+    Rbenv,
+    Nvm,
+}
+
+/// A project-local interpreter to use instead of the configured one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectEnv {
+    pub kind: EnvKind,
+    pub interpreter: PathBuf,
+    /// Short description for the prompt, e.g. `.venv` or `node 18.2.0`
+    pub label: String,
+    /// Directory that chose it: the project, or an activated virtualenv
+    pub root: PathBuf,
+}
+
+/// Find the project-local interpreter for `language`, searching up from `dir`.
+/// Only environments chosen inside one of the `trusted` directories count.
+pub fn detect(dir: &Path, language: Language, trusted: &[PathBuf]) -> Option<ProjectEnv> {
+    find(dir, language, true).filter(|env| is_trusted(&env.root, trusted))
+}
+
+/// Labels of every trusted project environment active in `dir`, for the
+/// prompt. Nothing is run to find them: a Poetry environment shows once a
+/// script has looked it up.
+pub fn prompt_labels(dir: &Path, trusted: &[PathBuf]) -> Vec<String> {
+    [Language::Python, Language::Ruby, Language::Node]
+        .into_iter()
+        .filter_map(|language| find(dir, language, false))
+        .filter(|env| is_trusted(&env.root, trusted))
+        .map(|env| format!("({})", env.label))
+        .collect()
+}
+
+fn find(dir: &Path, language: Language, run_poetry: bool) -> Option<ProjectEnv> {
+    match language {
+        Language::Python => detect_python(dir, run_poetry),
+        Language::Ruby => detect_ruby(dir),
+        Language::Node => detect_node(dir),
+    }
+}
+
+/// Whether `root` is one of the `trusted` directories or inside one
+fn is_trusted(root: &Path, trusted: &[PathBuf]) -> bool {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    trusted.iter().any(|dir| root.starts_with(dir.canonicalize().unwrap_or_else(|_| dir.clone())))
+}
+
+/// Check `interpreter` against the version pinned for `language` in the nearest
+/// `.shell-t.toml`, returning guidance when it doesn't match
+pub fn check_pin(dir: &Path, language: Language, interpreter: &str) -> Result<(), String> {
+    let Some((pin, file)) = pinned_version(dir, language) else {
+        return Ok(());
+    };
+    let name = language.name();
+    match interpreter_version(interpreter) {
+        Some(actual) if version_matches(&pin, &actual) => Ok(()),
+        Some(actual) => Err(format!(
+            "{} is {} {}, but {} pins {} {}; install it, activate a matching environment or set interpreters.{}_path",
+            interpreter, name, actual, file.display(), name, pin, name
+        )),
+        None => Err(format!("cannot determine the version of {}, which {} pins to {}", interpreter, file.display(), pin)),
+    }
+}
+
+/// Version pinned for `language` by the nearest `.shell-t.toml`, and that file
+fn pinned_version(dir: &Path, language: Language) -> Option<(String, PathBuf)> {
+    let file = dir.ancestors().map(|ancestor| ancestor.join(PROJECT_FILE)).find(|path| path.is_file())?;
+    let parsed = fs::read_to_string(&file)
+        .map_err(|e| e.to_string())
+        .and_then(|text| toml::from_str::<VersionPins>(&text).map_err(|e| e.to_string()));
+    let pins = match parsed {
+        Ok(pins) => pins,
+        Err(e) => {
+            tracing::warn!(file = %file.display(), error = %e, "ignoring unreadable project file");
+            return None;
+        }
+    };
+    pins.get(language).map(|pin| (pin.to_string(), file))
+}
+
+/// Version reported by `interpreter --version`, run once per interpreter
+fn interpreter_version(interpreter: &str) -> Option<String> {
+    let cache = VERSIONS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(cached) = cache.lock().unwrap().get(interpreter) {
+        return cached.clone();
+    }
+
+    // Python 2 prints its version on stderr
+    let version = Command::new(interpreter).arg("--version").output().ok().and_then(|output| {
+        extract_version(&String::from_utf8_lossy(&output.stdout))
+            .or_else(|| extract_version(&String::from_utf8_lossy(&output.stderr)))
+    });
+    cache.lock().unwrap().insert(interpreter.to_string(), version.clone());
+    version
+}
+
+/// First dotted version in `--version` output: `Python 3.11.7`, `ruby 3.2.2p53`, `v18.19.1`
+fn extract_version(output: &str) -> Option<String> {
+    output.split_whitespace().find_map(|word| {
+        let word = word.trim_start_matches('v');
+        let version: String = word.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+        let version = version.trim_end_matches('.');
+        (version.contains('.') && version.starts_with(|c: char| c.is_ascii_digit())).then(|| version.to_string())
+    })
+}
+
+/// Whether `actual` satisfies `pin` component-wise: `3.11` accepts `3.11.7`, not `3.1.2`
+fn version_matches(pin: &str, actual: &str) -> bool {
+    match (parse_version(pin), parse_version(actual)) {
+        (Some(pin), Some(actual)) => actual.starts_with(&pin),
+        _ => false,
+    }
+}
+
+/// An activated virtualenv wins; otherwise the nearest `.venv`/`venv` or
+/// Poetry project. Poetry is only asked when `run_poetry`; otherwise an
+/// environment it reported earlier is used.
+fn detect_python(dir: &Path, run_poetry: bool) -> Option<ProjectEnv> {
+    if let Some(root) = env::var_os("VIRTUAL_ENV").map(PathBuf::from) {
+        let interpreter = root.join(VENV_PYTHON);
+        if interpreter.exists() {
+            let label = root.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            return Some(ProjectEnv { kind: EnvKind::Venv, interpreter, label, root });
+        }
+    }
+
+    for ancestor in dir.ancestors() {
+        for name in VENV_DIRS {
+            let interpreter = ancestor.join(name).join(VENV_PYTHON);
+            if interpreter.exists() {
+                let root = ancestor.to_path_buf();
+                return Some(ProjectEnv { kind: EnvKind::Venv, interpreter, label: name.to_string(), root });
+            }
+        }
+        let pyproject = ancestor.join("pyproject.toml");
+        if pyproject.exists() {
+            let is_poetry = fs::read_to_string(&pyproject).is_ok_and(|text| text.contains("[tool.poetry]"));
+            if !is_poetry {
+                return None;
+            }
+            let interpreter = poetry_env(ancestor, run_poetry)?;
+            let root = ancestor.to_path_buf();
+            return Some(ProjectEnv { kind: EnvKind::Poetry, interpreter, label: "poetry".to_string(), root });
+        }
+    }
+    None
+}
+
+/// Ask Poetry for the project's interpreter, once per project; without
+/// `run`, only an answer it already gave
+fn poetry_env(root: &Path, run: bool) -> Option<PathBuf> {
+    let cache = POETRY_ENVS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(cached) = cache.lock().unwrap().get(root) {
+        return cached.clone();
+    }
+    if !run {
+        return None;
+    }
+
+    let found = Command::new("poetry")
+        .args(["env", "info", "--executable"])
+        .current_dir(root)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+        .filter(|path| path.exists());
+    if found.is_none() {
+        tracing::debug!(root = %root.display(), "no poetry environment found");
+    }
+    cache.lock().unwrap().insert(root.to_path_buf(), found.clone());
+    found
+}
+
+/// `.ruby-version` resolved against the rbenv install directory
+fn detect_ruby(dir: &Path) -> Option<ProjectEnv> {
+    let (version, root_dir) = read_version_file(dir, ".ruby-version")?;
+    let version = version.strip_prefix("ruby-").unwrap_or(&version).to_string();
+    let root = env::var_os("RBENV_ROOT")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".rbenv")))?;
+
+    let interpreter = root.join("versions").join(&version).join("bin/ruby");
+    if !interpreter.exists() {
+        tracing::warn!(version, "ruby version from .ruby-version is not installed with rbenv");
+        return None;
+    }
+    Some(ProjectEnv { kind: EnvKind::Rbenv, interpreter, label: format!("ruby {}", version), root: root_dir })
+}
+
+/// `.nvmrc` resolved to the newest matching version under `$NVM_DIR`
+fn detect_node(dir: &Path) -> Option<ProjectEnv> {
+    let (wanted, project) = read_version_file(dir, ".nvmrc")?;
+    let root = env::var_os("NVM_DIR")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".nvm")))?;
+
+    let installed: Vec<String> = fs::read_dir(root.join("versions/node"))
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.file_name().to_string_lossy().into_owned()))
+        .collect();
+    let Some(version) = best_match(&wanted, &installed) else {
+        tracing::warn!(version = wanted, "node version from .nvmrc is not installed with nvm");
+        return None;
+    };
+
+    let interpreter = root.join("versions/node").join(&version).join("bin/node");
+    let label = format!("node {}", version.trim_start_matches('v'));
+    Some(ProjectEnv { kind: EnvKind::Nvm, interpreter, label, root: project })
+}
+
+/// First line of the nearest `name` file above `dir`, and the directory it is in
+fn read_version_file(dir: &Path, name: &str) -> Option<(String, PathBuf)> {
+    let (text, found) = dir
+        .ancestors()
+        .find_map(|ancestor| fs::read_to_string(ancestor.join(name)).ok().map(|text| (text, ancestor.to_path_buf())))?;
+    let version = text.lines().next()?.trim().to_string();
+    (!version.is_empty()).then_some((version, found))
+}
+
+/// Highest installed version whose components start with `wanted` (`18` matches `v18.2.0`)
+fn best_match(wanted: &str, installed: &[String]) -> Option<String> {
+    let wanted = parse_version(wanted)?;
+    installed
+        .iter()
+        .filter_map(|name| parse_version(name).map(|version| (version, name)))
+        .filter(|(version, _)| version.starts_with(&wanted))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, name)| name.clone())
+}
+
+fn parse_version(text: &str) -> Option<Vec<u64>> {
+    text.trim_start_matches('v').split('.').map(|part| part.parse().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("shell-t-project-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_detect_venv_from_subdirectory() {
+        if env::var_os("VIRTUAL_ENV").is_some() {
+            return;
+        }
+        let dir = project_dir("venv");
+        let python = dir.join(".venv").join(VENV_PYTHON);
+        fs::create_dir_all(python.parent().unwrap()).unwrap();
+        fs::write(&python, "").unwrap();
+
+        // Nothing is used from a directory that isn't trusted
+        assert!(detect(&dir.join("src/nested"), Language::Python, &[]).is_none());
+        assert!(prompt_labels(&dir, &[dir.join("src")]).is_empty());
+
+        let trusted = [dir.clone()];
+        let env = detect(&dir.join("src/nested"), Language::Python, &trusted).unwrap();
+        assert_eq!(env.kind, EnvKind::Venv);
+        assert_eq!((env.interpreter, env.root), (python, dir.clone()));
+        assert_eq!(prompt_labels(&dir, &trusted), vec!["(.venv)"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_plain_pyproject_stops_search() {
+        let dir = project_dir("pyproject");
+        let trusted = [dir.clone()];
+        fs::write(dir.join("src/pyproject.toml"), "[project]\nname = \"x\"\n").unwrap();
+        if env::var_os("VIRTUAL_ENV").is_none() {
+            assert!(detect(&dir.join("src/nested"), Language::Python, &trusted).is_none());
+        }
+        assert!(detect(&dir, Language::Ruby, &trusted).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pinned_version_from_parent() {
+        let dir = project_dir("pin");
+        fs::write(dir.join(PROJECT_FILE), "python = \"3.11\"\n").unwrap();
+
+        let (pin, file) = pinned_version(&dir.join("src/nested"), Language::Python).unwrap();
+        assert_eq!(pin, "3.11");
+        assert_eq!(file, dir.join(PROJECT_FILE));
+        assert!(pinned_version(&dir, Language::Node).is_none());
+        assert!(check_pin(&dir, Language::Ruby, "ruby").is_ok());
+        assert!(check_pin(&dir, Language::Python, "shell-t-no-such-python").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_version_parsing_and_matching() {
+        assert_eq!(extract_version("Python 3.11.7\n").as_deref(), Some("3.11.7"));
+        assert_eq!(extract_version("ruby 3.2.2p53 (2023-03-30 revision e51014f9c0) [x86_64-linux]").as_deref(), Some("3.2.2"));
+        assert_eq!(extract_version("v18.19.1").as_deref(), Some("18.19.1"));
+        assert_eq!(extract_version("no version here"), None);
+
+        assert!(version_matches("3.11", "3.11.7"));
+        assert!(!version_matches("3.1", "3.11.7"));
+        assert!(!version_matches("3.11", "3.12.0"));
+    }
+
+    #[test]
+    fn test_best_match() {
+        let installed = vec!["v16.20.0".to_string(), "v18.2.0".to_string(), "v18.19.1".to_string()];
+        assert_eq!(best_match("18", &installed).as_deref(), Some("v18.19.1"));
+        assert_eq!(best_match("v16.20.0", &installed).as_deref(), Some("v16.20.0"));
+        assert_eq!(best_match("20", &installed), None);
+        assert_eq!(best_match("lts/*", &installed), None);
+    }
+}
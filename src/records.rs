@@ -0,0 +1,110 @@
+use crate::inline::Language;
+
+/// Python adapter: `records()` and `emit()` as builtins, then the script runs as `__main__`
+const PYTHON_PRELUDE: &str = r#"
+import builtins, json, os, runpy, sys
+def records():
+    for line in sys.stdin:
+        if line.strip():
+            yield json.loads(line)
+def emit(record):
+    sys.stdout.write(json.dumps(record) + "\n")
+builtins.records, builtins.emit = records, emit
+sys.argv = sys.argv[1:]
+sys.path[0] = os.path.dirname(os.path.abspath(sys.argv[0]))
+runpy.run_path(sys.argv[0], run_name="__main__")
+"#;
+
+/// Ruby adapter: `records` (an enumerator without a block) and `emit` as top-level methods
+const RUBY_PRELUDE: &str = r#"
+require "json"
+def records
+  return enum_for(:records) unless block_given?
+  $stdin.each_line { |line| yield JSON.parse(line) unless line.strip.empty? }
+end
+def emit(record)
+  $stdout.puts(JSON.generate(record))
+end
+$0 = ARGV.shift
+load $0
+"#;
+
+/// Node adapter: `records()` generator and `emit()` as globals
+const NODE_PRELUDE: &str = r#"
+const fs = require("fs"), path = require("path");
+globalThis.records = function* () {
+  for (const line of fs.readFileSync(0, "utf8").split("\n")) if (line.trim()) yield JSON.parse(line);
+};
+globalThis.emit = (record) => process.stdout.write(JSON.stringify(record) + "\n");
+require(path.resolve(process.argv[1]));
+"#;
+
+/// Adapter run before a script in a typed pipeline.
+///
+/// Stages exchange JSON Lines: one JSON value per line. The adapter gives the
+/// script `records()`, which decodes its input, and `emit(record)`, which
+/// encodes a record for the next stage.
+fn prelude(language: Language) -> &'static str {
+    match language {
+        Language::Python => PYTHON_PRELUDE,
+        Language::Ruby => RUBY_PRELUDE,
+        Language::Node => NODE_PRELUDE,
+    }
+}
+
+/// Rewrite resolved interpreter arguments (`script args...`) so the script runs
+/// behind its language's record adapter
+pub fn adapt(language: Language, args: &[String]) -> Vec<String> {
+    [language.eval_flag().to_string(), prelude(language).to_string()]
+        .into_iter()
+        .chain(args.iter().cloned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    /// Run `script` behind its adapter with `input` on stdin
+    fn run_adapted(language: Language, interpreter: &str, script: &str, input: &str) -> Option<String> {
+        which::which(interpreter).ok()?;
+        let ext = match language {
+            Language::Python => "py",
+            Language::Ruby => "rb",
+            Language::Node => "js",
+        };
+        let path = std::env::temp_dir().join(format!("shell-t-records-{}.{}", std::process::id(), ext));
+        fs::write(&path, script).unwrap();
+
+        let mut child = Command::new(interpreter)
+            .args(adapt(language, &[path.display().to_string()]))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        fs::remove_file(&path).unwrap();
+        Some(String::from_utf8(output.stdout).unwrap())
+    }
+
+    #[test]
+    fn test_python_adapter_round_trips_records() {
+        let script = "for r in records():\n    emit({'name': r['name'], 'double': r['n'] * 2})\n";
+        let input = "{\"name\": \"a\", \"n\": 1}\n\n{\"name\": \"b\", \"n\": 2}\n";
+        if let Some(output) = run_adapted(Language::Python, "python3", script, input) {
+            assert_eq!(output, "{\"name\": \"a\", \"double\": 2}\n{\"name\": \"b\", \"double\": 4}\n");
+        }
+    }
+
+    #[test]
+    fn test_node_adapter_round_trips_records() {
+        let script = "for (const r of records()) emit({ total: r.n + 1 });\n";
+        if let Some(output) = run_adapted(Language::Node, "node", script, "{\"n\":1}\n{\"n\":2}\n") {
+            assert_eq!(output, "{\"total\":2}\n{\"total\":3}\n");
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+
+use crate::config::{self, SourceConfig};
+use crate::error::{SecurityError, ShellError, ShellResult};
+
+/// Whether a `source` argument names a script to download
+pub fn is_remote(target: &str) -> bool {
+    target.starts_with("https://") || target.starts_with("http://")
+}
+
+/// Lowercase hex SHA-256 of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Normalize a pinned hash, rejecting anything but 64 hex digits
+fn parse_pin(pin: &str) -> ShellResult<String> {
+    if pin.len() == 64 && pin.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(pin.to_ascii_lowercase())
+    } else {
+        Err(ShellError::Parse(format!("source: invalid sha256 pin: {}", pin)))
+    }
+}
+
+/// Fetch a remote script and return the path of a verified local copy.
+///
+/// With a pin, a cached copy whose hash still matches is used without
+/// touching the network; otherwise the download must hash to the pin before it
+/// is cached. Unpinned sources are refused unless `source.allow_unpinned` is set.
+pub fn fetch(url: &str, pin: Option<&str>, config: &SourceConfig) -> ShellResult<PathBuf> {
+    let pin = match pin {
+        Some(pin) => Some(parse_pin(pin)?),
+        None if config.allow_unpinned => None,
+        None => {
+            return Err(SecurityError::PermissionDenied(format!(
+                "remote source {} is not pinned; add sha256=HASH or set source.allow_unpinned",
+                url
+            ))
+            .into())
+        }
+    };
+    let dir = config
+        .cache_dir
+        .clone()
+        .or_else(|| config::cache_dir().map(|dir| dir.join("sources")))
+        .ok_or_else(|| ShellError::Config("no cache directory for remote sources".to_string()))?;
+
+    if let Some(pin) = &pin {
+        let cached = dir.join(format!("{}.sht", pin));
+        if fs::read(&cached).is_ok_and(|data| sha256_hex(&data) == *pin) {
+            tracing::debug!(url, path = %cached.display(), "using cached remote source");
+            return Ok(cached);
+        }
+    }
+
+    let data = download(url, config)?;
+    let digest = sha256_hex(&data);
+    if let Some(pin) = &pin {
+        if digest != *pin {
+            return Err(SecurityError::InvalidInput(format!(
+                "checksum mismatch for {}: expected sha256 {}, got {}",
+                url, pin, digest
+            ))
+            .into());
+        }
+    }
+
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.sht", digest));
+    fs::write(&path, &data)?;
+    tracing::info!(url, sha256 = %digest, "fetched remote source");
+    Ok(path)
+}
+
+/// Download `url`, refusing bodies larger than `source.max_size_kb`
+fn download(url: &str, config: &SourceConfig) -> ShellResult<Vec<u8>> {
+    let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(config.timeout_secs)).build();
+    let response = agent
+        .get(url)
+        .call()
+        .map_err(|e| ShellError::CommandExecution(format!("source: cannot fetch {}: {}", url, e)))?;
+
+    let limit = config.max_size_kb * 1024;
+    let mut data = Vec::new();
+    response.into_reader().take(limit + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > limit {
+        return Err(SecurityError::ResourceLimitExceeded(format!(
+            "remote source {} is larger than {} KB",
+            url, config.max_size_kb
+        ))
+        .into());
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve `body` over HTTP for `requests` connections, returning the URL
+    fn serve(body: &'static str, requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/setup.sht", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+            }
+        });
+        url
+    }
+
+    fn test_config(name: &str) -> SourceConfig {
+        let dir = std::env::temp_dir().join(format!("shell-t-sources-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        SourceConfig { cache_dir: Some(dir), timeout_secs: 5, ..SourceConfig::default() }
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn test_unpinned_and_malformed_pins_refused() {
+        let config = test_config("unpinned");
+        let err = fetch("https://example.com/setup.sht", None, &config).unwrap_err();
+        assert_eq!(err.code(), "ST0205");
+        assert!(fetch("https://example.com/setup.sht", Some("abc"), &config).is_err());
+    }
+
+    #[test]
+    fn test_fetch_verifies_and_caches() {
+        let body = "echo hello\n";
+        let config = test_config("fetch");
+        let url = serve(body, 1);
+
+        let path = fetch(&url, Some(&sha256_hex(body.as_bytes()).to_uppercase()), &config).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), body);
+        // The server is gone; the pinned copy comes from the cache
+        assert_eq!(fetch(&url, Some(&sha256_hex(body.as_bytes())), &config).unwrap(), path);
+
+        let _ = fs::remove_dir_all(config.cache_dir.unwrap());
+    }
+
+    #[test]
+    fn test_fetch_rejects_checksum_mismatch() {
+        let config = test_config("mismatch");
+        let url = serve("rm -rf ~\n", 1);
+        let err = fetch(&url, Some(&sha256_hex(b"echo hello\n")), &config).unwrap_err();
+        assert_eq!(err.code(), "ST0204");
+        assert!(!config.cache_dir.unwrap().exists());
+    }
+}
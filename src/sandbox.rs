@@ -0,0 +1,220 @@
+use std::env;
+use std::io;
+use std::process::Command;
+
+use crate::config::SandboxConfig;
+
+/// Keywords followed by a command, as in `then cmd` or `while cmd`
+const OPENERS: &[&str] = &["if", "then", "else", "elif", "do", "while", "until", "!", "{"];
+
+/// Keywords that can only be followed by redirections
+const CLOSERS: &[&str] = &["fi", "done", "esac", "}"];
+
+/// Command names a POSIX shell script appears to run, in order of first use.
+///
+/// This is a best-effort static scan for the audit log and the command policy:
+/// the first word of every simple command, skipping keywords and leading
+/// `NAME=value` assignments. `for` and `case` headers are skipped entirely.
+/// Names built at run time are listed as written, like `$cmd` or `$(...)`;
+/// see `is_dynamic`.
+pub fn script_commands(source: &str) -> Vec<String> {
+    let mut commands: Vec<String> = Vec::new();
+    for line in source.lines() {
+        for (segment, opens) in split_commands(strip_comment(line)) {
+            let mut words = segment.split_whitespace().skip_while(|word| is_assignment(word));
+            let Some(first) = words.next() else {
+                // `$(cmd) args` or `` `cmd` args ``, but not `x=$(cmd)`
+                let command_position = segment.trim().is_empty() || segment.ends_with(char::is_whitespace);
+                if opens && command_position && !commands.iter().any(|c| c == "$(...)") {
+                    commands.push("$(...)".to_string());
+                }
+                continue;
+            };
+            if matches!(first, "for" | "case") || CLOSERS.contains(&first) {
+                continue;
+            }
+            let name = if OPENERS.contains(&first) {
+                match words.find(|word| !OPENERS.contains(word) && !is_assignment(word)) {
+                    Some(word) => word,
+                    None => continue,
+                }
+            } else {
+                first
+            };
+            let name = name.trim_matches(|c| c == '"' || c == '\'');
+            // A word ending in `$` goes on in the substitution that follows
+            let name = if opens && words.next().is_none() && segment.ends_with('$') {
+                format!("{}(...)", name)
+            } else {
+                name.to_string()
+            };
+            if !name.is_empty() && !commands.contains(&name) {
+                commands.push(name);
+            }
+        }
+    }
+    commands
+}
+
+/// Whether a name from `script_commands` is only known at run time, from a
+/// variable or a command's output
+pub fn is_dynamic(name: &str) -> bool {
+    name.contains(['$', '`'])
+}
+
+/// Text before an unquoted `#` that starts a word
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '#') if previous.is_whitespace() => return &line[..i],
+            _ => {}
+        }
+        previous = c;
+    }
+    line
+}
+
+/// Split a line on command separators and substitutions outside single
+/// quotes; inside double quotes, where `$(...)` still runs, it splits too.
+/// Each segment comes with whether a substitution opens right after it.
+fn split_commands(line: &str) -> Vec<(&str, bool)> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut in_backticks = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None | Some('"'), ';' | '|' | '&' | '(' | ')' | '`') => {
+                let opens = match c {
+                    '(' => line[..i].ends_with('$'),
+                    '`' => !in_backticks,
+                    _ => false,
+                };
+                in_backticks ^= c == '`';
+                segments.push((&line[start..i], opens));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push((&line[start..], false));
+    segments
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| crate::script::is_name(name))
+}
+
+/// Restrict a script's process: only allowlisted environment variables and,
+/// unless the config allows it, no network access
+pub fn restrict(command: &mut Command, config: &SandboxConfig) -> io::Result<()> {
+    command.env_clear();
+    for name in &config.pass_env {
+        if let Some(value) = env::var_os(name) {
+            command.env(name, value);
+        }
+    }
+    if !config.allow_network {
+        isolate_network(command)?;
+    }
+    Ok(())
+}
+
+/// Run the child in fresh user and network namespaces: it sees only a
+/// loopback device that is down. The user namespace maps the caller's own ids,
+/// so file access and `id -u` are unchanged.
+#[cfg(target_os = "linux")]
+fn isolate_network(command: &mut Command) -> io::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    // Formatted before fork: the child may only make async-signal-safe calls
+    // SAFETY: getuid and getgid cannot fail
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let uid_map = format!("{} {} 1\0", uid, uid).into_bytes();
+    let gid_map = format!("{} {} 1\0", gid, gid).into_bytes();
+
+    // SAFETY: the closure only calls unshare, open, write and close
+    unsafe {
+        command.pre_exec(move || {
+            if libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            write_proc(b"/proc/self/setgroups\0", b"deny\0")?;
+            write_proc(b"/proc/self/uid_map\0", &uid_map)?;
+            write_proc(b"/proc/self/gid_map\0", &gid_map)
+        });
+    }
+    Ok(())
+}
+
+/// Write a NUL-terminated value to a NUL-terminated path without allocating
+#[cfg(target_os = "linux")]
+unsafe fn write_proc(path: &[u8], value: &[u8]) -> io::Result<()> {
+    let fd = libc::open(path.as_ptr().cast(), libc::O_WRONLY);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = value.len() - 1;
+    let written = libc::write(fd, value.as_ptr().cast(), len);
+    libc::close(fd);
+    if written != len as isize {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn isolate_network(_command: &mut Command) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "network isolation is only available on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_commands() {
+        let script = r#"#!/bin/sh
+# build the thing
+set -e
+CC=gcc make all  # trailing comment
+if test -f out; then cat out | grep ok; fi
+for f in a b; do echo "$f"; done
+while read line; do wc -l; done < list
+x=$(date) && echo 'a;b'
+echo "it's" ; curl -s example.com; echo 'say "hi"; uname'
+"#;
+        assert_eq!(
+            script_commands(script),
+            vec!["set", "make", "test", "cat", "grep", "echo", "read", "wc", "date", "curl"]
+        );
+    }
+
+    #[test]
+    fn test_script_commands_lists_dynamic_names() {
+        let script = "c=rm; $c -rf dir\n\"${c}\" x\n$(echo rm) file\n`echo rm` file\nx=`date` y=$(id)\nif $c; then :; fi\n";
+        let commands = script_commands(script);
+        assert_eq!(commands, ["$c", "${c}", "$(...)", "echo", "file", "date", "id", ":"]);
+        let dynamic: Vec<_> = commands.iter().filter(|name| is_dynamic(name)).collect();
+        assert_eq!(dynamic, ["$c", "${c}", "$(...)"]);
+    }
+
+    #[test]
+    fn test_restrict_clears_environment() {
+        let mut command = Command::new("sh");
+        let config = SandboxConfig { allow_network: true, pass_env: vec!["PATH".to_string()] };
+        restrict(&mut command, &config).unwrap();
+
+        let envs: Vec<_> = command.get_envs().filter(|(_, value)| value.is_some()).collect();
+        assert!(envs.iter().all(|(name, _)| *name == "PATH"));
+    }
+}
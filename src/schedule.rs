@@ -0,0 +1,347 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::error;
+use crate::jobs;
+
+/// Latest a `next` search looks ahead; covers `0 0 29 2 *` in leap years
+const SEARCH_MINUTES: i64 = 8 * 366 * 24 * 60;
+
+/// A parsed five-field cron expression: minute, hour, day of month, month
+/// and day of week, each a set of allowed values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Day of month and day of week were both restricted; as in cron, either
+    /// one matching is enough
+    either_day: bool,
+}
+
+impl Cron {
+    /// Parse `MIN HOUR DOM MON DOW` (with `*`, `a-b`, `*/n`, `a-b/n` and
+    /// lists), or `@hourly`, `@daily`, `@weekly`, `@monthly`, `@yearly`
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("{}: expected 5 fields (minute hour day month weekday)", expression));
+        };
+        let mut weekdays = field(weekday, 0, 7)?;
+        // 7 is Sunday too
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(day, 1, 31)?,
+            months: field(month, 1, 12)?,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    /// Whether the expression fires in the minute of `time`
+    pub fn matches<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        self.minutes & (1 << time.minute()) != 0
+            && self.hours & (1 << time.hour()) != 0
+            && self.months & (1 << time.month()) != 0
+            && if self.either_day { day || weekday } else { day && weekday }
+    }
+
+    /// First minute after `time` the expression fires in
+    pub fn next<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let start = time.with_second(0)?.with_nanosecond(0)?;
+        (1..=SEARCH_MINUTES).map(|minutes| start.clone() + chrono::Duration::minutes(minutes)).find(|time| self.matches(time))
+    }
+}
+
+/// Bits for the values a cron field allows between `min` and `max`
+fn field(text: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0).ok_or_else(|| invalid(part))?),
+            None => (part, 1),
+        };
+        let (low, high) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((low, high)) => (number(low, part)?, number(high, part)?),
+                // `5/15` runs from 5 to the end of the range
+                None if step > 1 => (number(range, part)?, max),
+                None => (number(range, part)?, number(range, part)?),
+            },
+        };
+        if low < min || high > max || low > high {
+            return Err(format!("{}: out of range {}-{}", part, min, max));
+        }
+        for value in (low..=high).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn number(text: &str, part: &str) -> Result<u32, String> {
+    text.parse().map_err(|_| invalid(part))
+}
+
+fn invalid(part: &str) -> String {
+    format!("{}: invalid cron field", part)
+}
+
+/// A command run on a schedule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: usize,
+    pub cron: String,
+    /// A script to run, or a command line
+    pub command: String,
+    /// Directory the command runs in
+    pub cwd: PathBuf,
+    /// Unix time of the minute it last ran in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<i64>,
+}
+
+impl Entry {
+    /// Whether the entry fires at `time` and hasn't run in that minute yet
+    pub fn is_due(&self, time: &DateTime<Local>) -> bool {
+        let minute = time.timestamp() / 60 * 60;
+        self.last_run.is_none_or(|last| last < minute) && Cron::parse(&self.cron).is_ok_and(|cron| cron.matches(time))
+    }
+}
+
+/// Scheduled commands, shared by every session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub entries: Vec<Entry>,
+}
+
+impl Schedule {
+    /// File the schedule is kept in
+    pub fn file() -> Option<PathBuf> {
+        config::data_dir().map(|dir| dir.join("schedule.toml"))
+    }
+
+    /// The schedule saved in `file`; empty if it is missing or unreadable
+    pub fn load(file: &Path) -> Self {
+        match fs::read_to_string(file) {
+            Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring schedule in {}: {}", file.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Write the schedule to `file`, replacing it in one step
+    pub fn save(&self, file: &Path) -> io::Result<()> {
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let partial = file.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&partial, text)?;
+        fs::rename(&partial, file)
+    }
+
+    /// Add a command, returning its id
+    pub fn add(&mut self, cron: &str, command: &str, cwd: PathBuf) -> usize {
+        let id = self.entries.iter().map(|entry| entry.id).max().unwrap_or(0) + 1;
+        self.entries.push(Entry { id, cron: cron.to_string(), command: command.to_string(), cwd, last_run: None });
+        id
+    }
+
+    /// Remove an entry by id; false if there is none
+    pub fn remove(&mut self, id: usize) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.id != id);
+        self.entries.len() < before
+    }
+}
+
+/// Run scheduled commands from a background thread for as long as the
+/// process lives
+pub fn start() {
+    if let Err(e) = thread::Builder::new().name("scheduler".to_string()).spawn(run) {
+        tracing::warn!("Can't start the scheduler: {}", e);
+    }
+}
+
+/// Run due commands at the start of every minute, forever. Only one process
+/// runs them at a time: the one holding the lock next to the schedule file;
+/// the others check each minute whether it has become free.
+pub fn run() {
+    let Some(file) = Schedule::file() else {
+        tracing::warn!("No data directory; scheduled commands won't run");
+        return;
+    };
+    let mut lock = None;
+    loop {
+        let now = Local::now();
+        thread::sleep(Duration::from_secs(60 - now.second() as u64));
+        if lock.is_none() {
+            lock = try_lock(&file.with_extension("lock"));
+        }
+        if lock.is_some() {
+            run_due(&file, &Local::now());
+        }
+    }
+}
+
+fn try_lock(path: &Path) -> Option<File> {
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(path).ok()?;
+    file.try_lock().ok()?;
+    tracing::debug!(path = %path.display(), "running scheduled commands");
+    Some(file)
+}
+
+/// Start the entries due at `time` and record that they ran
+fn run_due(file: &Path, time: &DateTime<Local>) {
+    let minute = time.timestamp() / 60 * 60;
+    let mut started = Vec::new();
+    for entry in Schedule::load(file).entries.iter().filter(|entry| entry.is_due(time)) {
+        started.push(entry.id);
+        if let Err(e) = launch(entry) {
+            tracing::warn!(id = entry.id, command = %entry.command, "can't start scheduled command: {}", e);
+        }
+    }
+    if !started.is_empty() {
+        // Reloaded, so entries added meanwhile by other sessions are kept
+        let mut schedule = Schedule::load(file);
+        for entry in schedule.entries.iter_mut().filter(|entry| started.contains(&entry.id)) {
+            entry.last_run = Some(minute);
+        }
+        if let Err(e) = schedule.save(file) {
+            tracing::warn!("Can't save the schedule to {}: {}", file.display(), e);
+        }
+    }
+}
+
+/// Run an entry in a child shell-t, so it goes through the same executor and
+/// policy as typed commands, with its output appended to its log
+fn launch(entry: &Entry) -> io::Result<()> {
+    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    error::logging::log_command_execution(&format!("{} (schedule #{})", entry.command, entry.id), &user);
+
+    let log = log_file(entry.id).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+    if let Some(dir) = log.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let output = OpenOptions::new().create(true).append(true).open(&log)?;
+    let mut command = Command::new(std::env::current_exe()?);
+    if is_script(&entry.command, &entry.cwd) {
+        command.arg(&entry.command);
+    } else {
+        command.arg("-c").arg(&entry.command);
+    }
+    command.env("SHELL_T_ENABLE_COLORS", "false");
+    command.current_dir(&entry.cwd).stdin(Stdio::null()).stdout(output.try_clone()?).stderr(output);
+    // Out of the terminal's process group, so Ctrl-C at the prompt spares it
+    jobs::set_process_group(&mut command, None, false);
+
+    let mut child = command.spawn()?;
+    let (id, name) = (entry.id, entry.command.clone());
+    thread::spawn(move || match child.wait() {
+        Ok(status) => tracing::info!(id, command = %name, ?status, "scheduled command finished"),
+        Err(e) => tracing::warn!(id, command = %name, "can't wait for scheduled command: {}", e),
+    });
+    Ok(())
+}
+
+/// Where the output of entry `id` goes
+pub fn log_file(id: usize) -> Option<PathBuf> {
+    config::data_dir().map(|dir| dir.join("schedule-logs").join(format!("{}.log", id)))
+}
+
+/// Whether a scheduled command names a script file rather than a command line
+pub fn is_script(command: &str, cwd: &Path) -> bool {
+    !command.contains(char::is_whitespace) && cwd.join(command).is_file()
+}
+
+/// A time as `schedule list` shows it
+pub fn format_time(time: &DateTime<Local>) -> String {
+    time.format("%Y-%m-%d %H:%M").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn local(text: &str) -> DateTime<Local> {
+        Local.from_local_datetime(&NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()).single().unwrap()
+    }
+
+    #[test]
+    fn test_cron_parse_and_match() {
+        let weekdays = Cron::parse("0 9 * * 1-5").unwrap();
+        // 2026-10-16 is a Friday
+        assert!(weekdays.matches(&local("2026-10-16 09:00")));
+        assert!(!weekdays.matches(&local("2026-10-17 09:00")));
+        assert!(!weekdays.matches(&local("2026-10-16 09:01")));
+        assert_eq!(weekdays.next(&local("2026-10-16 09:00")), Some(local("2026-10-19 09:00")));
+
+        let every = Cron::parse("*/15 8-18/2 * * *").unwrap();
+        assert!(every.matches(&local("2026-10-16 10:45")));
+        assert!(!every.matches(&local("2026-10-16 11:45")));
+        // Day of month or Sunday (written 7)
+        let either = Cron::parse("0 0 1 * 7").unwrap();
+        assert!(either.matches(&local("2026-10-18 00:00")) && either.matches(&local("2026-11-01 00:00")));
+        assert_eq!(Cron::parse("@daily").unwrap(), Cron::parse("0 0 * * *").unwrap());
+
+        for bad in ["* * * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "x * * * *"] {
+            assert!(Cron::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_schedule_save_load_and_due() {
+        let dir = std::env::temp_dir().join(format!("shell-t-schedule-{}", std::process::id()));
+        let file = dir.join("schedule.toml");
+        assert_eq!(Schedule::load(&file), Schedule::default());
+
+        let mut schedule = Schedule::default();
+        assert_eq!(schedule.add("0 9 * * 1-5", "backup.sht", dir.clone()), 1);
+        assert_eq!(schedule.add("@hourly", "echo tick", dir.clone()), 2);
+        schedule.save(&file).unwrap();
+        let mut loaded = Schedule::load(&file);
+        assert_eq!(loaded, schedule);
+
+        let nine = local("2026-10-16 09:00");
+        let due: Vec<usize> = loaded.entries.iter().filter(|entry| entry.is_due(&nine)).map(|entry| entry.id).collect();
+        assert_eq!(due, [1, 2]);
+        loaded.entries[0].last_run = Some(nine.timestamp());
+        assert!(!loaded.entries[0].is_due(&nine));
+        assert!(loaded.remove(1) && !loaded.remove(1));
+
+        assert!(!is_script("echo tick", &dir));
+        fs::write(dir.join("backup.sht"), "echo ok\n").unwrap();
+        assert!(is_script("backup.sht", &dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ShellError, ShellResult};
+use crate::parser::{self, Connector, SplitError};
+
+/// Words that open, separate or close compound commands
+pub const RESERVED: [&str; 10] = ["if", "then", "elif", "else", "fi", "for", "while", "until", "do", "done"];
+
+/// A node of a parsed script
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Node {
+    /// A simple pipeline, kept as source text and parsed by `parser::parse_line` when run
+    Pipeline(String),
+    /// `( LIST )`: commands run in a subshell, whose changes to the directory,
+    /// variables and options are undone when it ends
+    Subshell(Vec<Node>),
+    /// `A && B || C`: pipelines or subshells run in turn while their
+    /// connector allows
+    AndOr {
+        first: Box<Node>,
+        rest: Vec<(Connector, Node)>,
+    },
+    /// `if COND; then BODY; [elif COND; then BODY;]... [else BODY;] fi`
+    If {
+        branches: Vec<(Vec<Node>, Vec<Node>)>,
+        otherwise: Option<Vec<Node>>,
+    },
+    /// `for VAR in WORDS; do BODY; done`; words are expanded when the loop starts
+    For {
+        variable: String,
+        words: String,
+        body: Vec<Node>,
+    },
+    /// `while COND; do BODY; done`, or `until` when `until` is set
+    While {
+        condition: Vec<Node>,
+        body: Vec<Node>,
+        until: bool,
+    },
+}
+
+/// Why a block failed to parse
+#[derive(Debug, Clone, PartialEq)]
+enum BlockError {
+    /// Input ended inside a compound command; more lines may complete it.
+    /// Holds what was expected, as worded in the error.
+    Incomplete(String),
+    /// Input can never parse
+    Invalid(String),
+}
+
+/// Parse a script (one or more lines) into nodes
+pub fn parse_script(input: &str) -> ShellResult<Vec<Node>> {
+    match split_statements(input).and_then(|mut statements| parse_block(&mut statements, &[])) {
+        Ok((nodes, _)) => Ok(nodes),
+        Err(BlockError::Incomplete(expected)) => {
+            Err(ShellError::Parse(format!("unexpected end of input, expected {}", expected)))
+        }
+        Err(BlockError::Invalid(message)) => Err(ShellError::Parse(message)),
+    }
+}
+
+/// Whether `input` is an unfinished compound command that needs more lines
+pub fn is_incomplete(input: &str) -> bool {
+    let parsed = split_statements(input).and_then(|mut statements| parse_block(&mut statements, &[]));
+    matches!(parsed, Err(BlockError::Incomplete(_)))
+}
+
+/// The statements of a script, in order
+fn split_statements(input: &str) -> Result<VecDeque<String>, BlockError> {
+    match parser::split_sequence(input) {
+        Ok(statements) => Ok(statements.into()),
+        Err(SplitError::Unterminated(delimiter)) => Err(BlockError::Incomplete(format!("'{}'", delimiter))),
+        Err(SplitError::Continued) => Err(BlockError::Incomplete("a line after '\\'".to_string())),
+        Err(SplitError::Unclosed(quote)) => Err(BlockError::Incomplete(format!("a closing '{}'", quote))),
+        Err(SplitError::Invalid(message)) => Err(BlockError::Invalid(message)),
+    }
+}
+
+/// Split off the first word of a statement
+fn first_word(statement: &str) -> (&str, &str) {
+    match statement.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (statement, ""),
+    }
+}
+
+/// Parse statements until one starts with a word in `terminators`, returning
+/// the nodes and the terminator found (empty at end of input)
+fn parse_block(
+    statements: &mut VecDeque<String>,
+    terminators: &[&'static str],
+) -> Result<(Vec<Node>, &'static str), BlockError> {
+    let mut nodes = Vec::new();
+
+    while let Some(statement) = statements.pop_front() {
+        let (word, rest) = first_word(&statement);
+
+        if let Some(terminator) = terminators.iter().find(|t| **t == word) {
+            if !rest.is_empty() {
+                statements.push_front(rest.to_string());
+            }
+            return Ok((nodes, terminator));
+        }
+
+        match word {
+            "if" => {
+                if !rest.is_empty() {
+                    statements.push_front(rest.to_string());
+                }
+                nodes.push(parse_if(statements)?);
+            }
+            "for" => nodes.push(parse_for(rest, statements)?),
+            "while" | "until" => {
+                if !rest.is_empty() {
+                    statements.push_front(rest.to_string());
+                }
+                nodes.push(parse_while(statements, word == "until")?);
+            }
+            _ if RESERVED.contains(&word) => {
+                return Err(BlockError::Invalid(format!("syntax error near unexpected '{}'", word)));
+            }
+            _ => nodes.push(parse_and_or(statement)?),
+        }
+    }
+
+    match terminators.first() {
+        Some(expected) => Err(BlockError::Incomplete(format!("'{}'", expected))),
+        None => Ok((nodes, "")),
+    }
+}
+
+/// A plain statement: a pipeline, or pipelines joined by `&&` and `||`
+fn parse_and_or(statement: String) -> Result<Node, BlockError> {
+    let (first, rest) = parser::split_and_or(&statement).map_err(BlockError::Invalid)?;
+    if rest.is_empty() {
+        return parse_pipeline(statement);
+    }
+    let rest = rest
+        .into_iter()
+        .map(|(connector, source)| Ok((connector, parse_pipeline(source)?)))
+        .collect::<Result<_, BlockError>>()?;
+    Ok(Node::AndOr { first: Box::new(parse_pipeline(first)?), rest })
+}
+
+/// A pipeline, or `( LIST )`
+fn parse_pipeline(source: String) -> Result<Node, BlockError> {
+    let Some(body) = parser::subshell_body(&source).map_err(BlockError::Invalid)? else {
+        return Ok(Node::Pipeline(source));
+    };
+    // The `)` has been seen, so nothing inside can still be open
+    match split_statements(body).and_then(|mut statements| parse_block(&mut statements, &[])) {
+        Ok((nodes, _)) => Ok(Node::Subshell(nodes)),
+        Err(BlockError::Incomplete(expected)) => {
+            Err(BlockError::Invalid(format!("syntax error: expected {} before ')'", expected)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Parse the rest of an `if`, after the keyword itself
+fn parse_if(statements: &mut VecDeque<String>) -> Result<Node, BlockError> {
+    let mut branches = Vec::new();
+    let mut otherwise = None;
+
+    loop {
+        let (condition, _) = parse_block(statements, &["then"])?;
+        if condition.is_empty() {
+            return Err(BlockError::Invalid("syntax error: missing condition before 'then'".to_string()));
+        }
+        let (body, terminator) = parse_block(statements, &["fi", "elif", "else"])?;
+        branches.push((condition, body));
+
+        match terminator {
+            "elif" => continue,
+            "else" => {
+                let (body, _) = parse_block(statements, &["fi"])?;
+                otherwise = Some(body);
+                break;
+            }
+            _ => break,
+        }
+    }
+
+    Ok(Node::If { branches, otherwise })
+}
+
+/// Parse the rest of a `for`; `header` is the statement after the keyword
+fn parse_for(header: &str, statements: &mut VecDeque<String>) -> Result<Node, BlockError> {
+    let (variable, rest) = first_word(header);
+    if !is_name(variable) {
+        return Err(BlockError::Invalid(format!("syntax error: invalid loop variable '{}'", variable)));
+    }
+    let words = match first_word(rest) {
+        ("in", words) => words.to_string(),
+        ("", _) => return Err(BlockError::Invalid("syntax error: expected 'in' after loop variable".to_string())),
+        (word, _) => return Err(BlockError::Invalid(format!("syntax error: expected 'in', found '{}'", word))),
+    };
+
+    let body = parse_loop_body(statements)?;
+    Ok(Node::For { variable: variable.to_string(), words, body })
+}
+
+/// Parse the rest of a `while`/`until`, after the keyword itself
+fn parse_while(statements: &mut VecDeque<String>, until: bool) -> Result<Node, BlockError> {
+    let (condition, _) = parse_block(statements, &["do"])?;
+    if condition.is_empty() {
+        return Err(BlockError::Invalid("syntax error: missing condition before 'do'".to_string()));
+    }
+    let (body, _) = parse_block(statements, &["done"])?;
+    Ok(Node::While { condition, body, until })
+}
+
+/// Parse `do BODY done`
+fn parse_loop_body(statements: &mut VecDeque<String>) -> Result<Vec<Node>, BlockError> {
+    let Some(statement) = statements.pop_front() else {
+        return Err(BlockError::Incomplete("'do'".to_string()));
+    };
+    match first_word(&statement) {
+        ("do", rest) => {
+            if !rest.is_empty() {
+                statements.push_front(rest.to_string());
+            }
+        }
+        (word, _) => return Err(BlockError::Invalid(format!("syntax error: expected 'do', found '{}'", word))),
+    }
+
+    let (body, _) = parse_block(statements, &["done"])?;
+    Ok(body)
+}
+
+/// Whether `word` is a valid variable name
+pub fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split `for` words on whitespace, keeping quoted and escaped words together
+pub fn split_words(input: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut in_word = false;
+
+    let mut chars = input.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match (ch, quote) {
+            ('\\', None | Some('"')) => {
+                let escaped = chars.next_if(|next| quote.is_none() || parser::DOUBLE_QUOTE_ESCAPES.contains(next));
+                if escaped != Some('\n') {
+                    current.push(escaped.unwrap_or(ch));
+                    in_word = true;
+                }
+            }
+            ('"' | '\'', None) => {
+                quote = Some(ch);
+                in_word = true;
+            }
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (c, _) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(source: &str) -> Node {
+        Node::Pipeline(source.to_string())
+    }
+
+    #[test]
+    fn test_split_statements_respects_quotes() {
+        let statements: Vec<String> = split_statements("echo 'a;b'; ls\n\npwd").unwrap().into();
+        assert_eq!(statements, vec!["echo 'a;b'", "ls", "pwd"]);
+        assert!(parse_script("ls;; pwd").is_err());
+    }
+
+    #[test]
+    fn test_parse_if_elif_else() {
+        let nodes = parse_script("if test -d /tmp; then echo a\nelif false; then echo b\nelse echo c; fi").unwrap();
+        assert_eq!(
+            nodes,
+            vec![Node::If {
+                branches: vec![
+                    (vec![pipeline("test -d /tmp")], vec![pipeline("echo a")]),
+                    (vec![pipeline("false")], vec![pipeline("echo b")]),
+                ],
+                otherwise: Some(vec![pipeline("echo c")]),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_incomplete_if_needs_more_input() {
+        assert!(is_incomplete("if true; then"));
+        assert!(is_incomplete("if true\nthen echo a\nelse echo b"));
+        assert!(!is_incomplete("if true; then echo a; fi"));
+        assert!(!is_incomplete("echo if"));
+    }
+
+    #[test]
+    fn test_parse_loops() {
+        let nodes = parse_script("for f in a 'b c'; do echo $f; done\nwhile false\ndo\n  break\ndone").unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                Node::For {
+                    variable: "f".to_string(),
+                    words: "a 'b c'".to_string(),
+                    body: vec![pipeline("echo $f")],
+                },
+                Node::While { condition: vec![pipeline("false")], body: vec![pipeline("break")], until: false },
+            ]
+        );
+        assert!(is_incomplete("for x in a b; do"));
+        assert!(is_incomplete("until true"));
+        assert!(is_incomplete("echo a \\\n"));
+        assert!(is_incomplete("echo \"hello\n"));
+        assert!(parse_script("for 1x in a; do echo; done").is_err());
+    }
+
+    #[test]
+    fn test_parse_and_or_lists() {
+        let nodes = parse_script("while test -f lock && sleep 1; do echo wait; done; make || echo failed").unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                Node::While {
+                    condition: vec![Node::AndOr {
+                        first: Box::new(pipeline("test -f lock")),
+                        rest: vec![(Connector::And, pipeline("sleep 1"))],
+                    }],
+                    body: vec![pipeline("echo wait")],
+                    until: false,
+                },
+                Node::AndOr { first: Box::new(pipeline("make")), rest: vec![(Connector::Or, pipeline("echo failed"))] },
+            ]
+        );
+        assert_eq!(parse_script("echo '&&'").unwrap(), vec![pipeline("echo '&&'")]);
+        assert!(parse_script("make &&").is_err());
+    }
+
+    #[test]
+    fn test_parse_subshells() {
+        let nodes = parse_script("(cd /tmp; ls) && (if true; then (pwd); fi)\n( export A=1\n  echo $A )").unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                Node::AndOr {
+                    first: Box::new(Node::Subshell(vec![pipeline("cd /tmp"), pipeline("ls")])),
+                    rest: vec![(
+                        Connector::And,
+                        Node::Subshell(vec![Node::If {
+                            branches: vec![(vec![pipeline("true")], vec![Node::Subshell(vec![pipeline("pwd")])])],
+                            otherwise: None,
+                        }]),
+                    )],
+                },
+                Node::Subshell(vec![pipeline("export A=1"), pipeline("echo $A")]),
+            ]
+        );
+        assert_eq!(parse_script("echo (a) ')'").unwrap(), vec![pipeline("echo (a) ')'")]);
+        assert!(is_incomplete("(cd /tmp\n"));
+        assert!(parse_script("(ls) | wc").is_err());
+        assert!(parse_script("()").is_err());
+        assert!(parse_script("(if true)").is_err());
+    }
+
+    #[test]
+    fn test_split_words_keeps_quoted_words() {
+        assert_eq!(split_words("a 'b c' \"\" d"), vec!["a", "b c", "", "d"]);
+        assert_eq!(split_words("a\\ b \"c\\\"d\\q\" '\\'"), vec!["a b", "c\"d\\q", "\\"]);
+    }
+
+    #[test]
+    fn test_stray_keyword_is_an_error() {
+        assert!(parse_script("fi").is_err());
+        assert!(parse_script("if; then echo a; fi").is_err());
+        assert!(!is_incomplete("fi"));
+    }
+}
@@ -1,6 +1,5 @@
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
-use std::process;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
@@ -8,27 +7,140 @@ use std::time::{Duration, Instant};
 use crate::error::{SecurityError, ShellResult};
 use crate::config::Config;
 
+/// Period `security.max_runs_per_minute` counts runs over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
 /// Global security state
 pub struct SecurityManager {
     active_processes: AtomicUsize,
-    command_history: Mutex<HashMap<String, CommandStats>>,
-    rate_limiter: Mutex<HashMap<String, Vec<Instant>>>,
+    command_history: Mutex<CommandHistory>,
+    rate_limiter: Mutex<RateLimiter>,
 }
 
+/// Runs of one command recorded with `record_command`
 #[derive(Debug, Clone)]
-struct CommandStats {
-    count: usize,
-    last_execution: Instant,
-    total_time: Duration,
+pub struct CommandStats {
+    pub count: usize,
+    pub last_execution: Instant,
+    pub total_time: Duration,
+}
+
+impl CommandStats {
+    fn new(now: Instant) -> Self {
+        Self { count: 0, last_execution: now, total_time: Duration::ZERO }
+    }
+
+    fn add(&mut self, other: &CommandStats) {
+        self.count += other.count;
+        self.total_time += other.total_time;
+        self.last_execution = self.last_execution.max(other.last_execution);
+    }
+}
+
+/// Stats for at most `capacity` commands. Past it, the least recently run
+/// are folded into one aggregate, so counts and times still add up.
+#[derive(Debug)]
+struct CommandHistory {
+    commands: HashMap<String, CommandStats>,
+    /// Commands folded into `evicted`
+    evicted_commands: usize,
+    evicted: Option<CommandStats>,
+    capacity: usize,
+}
+
+impl CommandHistory {
+    fn record(&mut self, command: &str, execution_time: Duration, now: Instant) {
+        let stats = self.commands.entry(command.to_string()).or_insert_with(|| CommandStats::new(now));
+        stats.count += 1;
+        stats.last_execution = now;
+        stats.total_time += execution_time;
+        if self.commands.len() > self.capacity {
+            self.prune();
+        }
+    }
+
+    /// Fold the least recently run quarter into `evicted`, so pruning is
+    /// rare rather than once per new command
+    fn prune(&mut self) {
+        let keep = self.capacity - self.capacity / 4;
+        let mut by_age: Vec<(Instant, String)> =
+            self.commands.iter().map(|(command, stats)| (stats.last_execution, command.clone())).collect();
+        by_age.sort();
+        for (_, command) in by_age.into_iter().take(self.commands.len().saturating_sub(keep)) {
+            if let Some(stats) = self.commands.remove(&command) {
+                self.evicted.get_or_insert_with(|| CommandStats::new(stats.last_execution)).add(&stats);
+                self.evicted_commands += 1;
+            }
+        }
+        tracing::debug!(evicted = self.evicted_commands, "pruned command stats");
+    }
+}
+
+/// Recent run times per key, at most the rate limit each, for at most
+/// `capacity` keys. Keys with nothing inside the window are dropped first;
+/// past that the least recently used go, which only forgets their recent runs.
+#[derive(Debug)]
+struct RateLimiter {
+    keys: HashMap<String, VecDeque<Instant>>,
+    capacity: usize,
+}
+
+impl RateLimiter {
+    /// Record a run for `key` unless it already has `limit` within the window
+    fn check(&mut self, key: &str, now: Instant, limit: usize) -> bool {
+        let entries = self.keys.entry(key.to_string()).or_default();
+        while entries.front().is_some_and(|&time| now.duration_since(time) >= RATE_WINDOW) {
+            entries.pop_front();
+        }
+        if entries.len() >= limit {
+            return false;
+        }
+        entries.push_back(now);
+        if self.keys.len() > self.capacity {
+            self.prune(now);
+        }
+        true
+    }
+
+    fn prune(&mut self, now: Instant) {
+        self.keys.retain(|_, entries| entries.back().is_some_and(|&time| now.duration_since(time) < RATE_WINDOW));
+        if self.keys.len() > self.capacity {
+            let keep = self.capacity - self.capacity / 4;
+            let mut by_age: Vec<(Instant, String)> =
+                self.keys.iter().filter_map(|(key, entries)| Some((*entries.back()?, key.clone()))).collect();
+            by_age.sort();
+            for (_, key) in by_age.into_iter().take(self.keys.len().saturating_sub(keep)) {
+                self.keys.remove(&key);
+            }
+        }
+    }
+}
+
+impl Default for SecurityManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SecurityManager {
-    /// Create a new security manager
+    /// Create a new security manager with the default bookkeeping caps
     pub fn new() -> Self {
+        let defaults = crate::config::SecurityConfig::default();
+        Self::with_capacity(defaults.max_tracked_commands, defaults.max_rate_limit_keys)
+    }
+
+    /// Create a security manager keeping stats for at most `commands`
+    /// commands and rate-limit history for at most `rate_limit_keys` keys
+    pub fn with_capacity(commands: usize, rate_limit_keys: usize) -> Self {
         Self {
             active_processes: AtomicUsize::new(0),
-            command_history: Mutex::new(HashMap::new()),
-            rate_limiter: Mutex::new(HashMap::new()),
+            command_history: Mutex::new(CommandHistory {
+                commands: HashMap::new(),
+                evicted_commands: 0,
+                evicted: None,
+                capacity: commands.max(1),
+            }),
+            rate_limiter: Mutex::new(RateLimiter { keys: HashMap::new(), capacity: rate_limit_keys.max(1) }),
         }
     }
 
@@ -44,44 +156,48 @@ impl SecurityManager {
     }
 
     /// Register a new process
-    pub fn register_process(&self) -> ProcessGuard {
+    pub fn register_process(&self) -> ProcessGuard<'_> {
         self.active_processes.fetch_add(1, Ordering::SeqCst);
         ProcessGuard {
             manager: self,
         }
     }
 
-    /// Check rate limiting for a user/command combination
+    /// Count a run for `key`, refusing it once `security.max_runs_per_minute`
+    /// runs were counted in the last minute; a limit of 0 counts nothing
     pub fn check_rate_limit(&self, key: &str, config: &Config) -> ShellResult<()> {
-        let mut limiter = self.rate_limiter.lock().unwrap();
-        let now = Instant::now();
-
-        let entries = limiter.entry(key.to_string()).or_insert_with(Vec::new);
-
-        entries.retain(|&time| now.duration_since(time) < Duration::from_secs(60));
-
-        if entries.len() >= 10 {
-            return Err(SecurityError::ResourceLimitExceeded(
-                "Rate limit exceeded".to_string()
-            ).into());
+        let limit = config.security.max_runs_per_minute;
+        if limit > 0 && !self.rate_limiter.lock().unwrap().check(key, Instant::now(), limit) {
+            tracing::warn!(key, "rate limit exceeded");
+            return Err(SecurityError::RateLimited(key.to_string()).into());
         }
-
-        entries.push(now);
         Ok(())
     }
 
     /// Record command execution for monitoring
     pub fn record_command(&self, command: &str, execution_time: Duration) {
-        let mut history = self.command_history.lock().unwrap();
-        let stats = history.entry(command.to_string()).or_insert(CommandStats {
-            count: 0,
-            last_execution: Instant::now(),
-            total_time: Duration::new(0, 0),
-        });
+        tracing::trace!(command, ?execution_time, "recording command");
+        self.command_history.lock().unwrap().record(command, execution_time, Instant::now());
+    }
 
-        stats.count += 1;
-        stats.last_execution = Instant::now();
-        stats.total_time += execution_time;
+    /// Processes currently registered
+    pub fn active_processes(&self) -> usize {
+        self.active_processes.load(Ordering::SeqCst)
+    }
+
+    /// Recorded runs per command, sorted by command
+    pub fn stats(&self) -> Vec<(String, CommandStats)> {
+        let history = self.command_history.lock().unwrap();
+        let mut stats: Vec<_> = history.commands.iter().map(|(command, stats)| (command.clone(), stats.clone())).collect();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        stats
+    }
+
+    /// How many commands have been dropped from `stats` to stay within the
+    /// cap, with their runs added up
+    pub fn evicted_stats(&self) -> Option<(usize, CommandStats)> {
+        let history = self.command_history.lock().unwrap();
+        history.evicted.clone().map(|stats| (history.evicted_commands, stats))
     }
 
     /// Validate user input for security violations
@@ -127,7 +243,6 @@ impl<'a> Drop for ProcessGuard<'a> {
 pub mod validation {
     use super::*;
     use regex::Regex;
-    use std::ffi::OsStr;
 
     /// Validate and sanitize user input
     pub fn sanitize_input(input: &str, config: &Config) -> ShellResult<String> {
@@ -143,7 +258,7 @@ pub mod validation {
             .filter(|&c| !c.is_control() || c == '\n' || c == '\t')
             .collect();
 
-        check_suspicious_patterns(&sanitized)?;
+        check_suspicious_patterns(&sanitized, config.security.command_substitution)?;
 
         if sanitized.len() > config.security.max_command_length {
             sanitized = sanitized[..config.security.max_command_length].to_string();
@@ -152,18 +267,19 @@ pub mod validation {
         Ok(sanitized)
     }
 
-    /// Check for suspicious patterns that might indicate attacks
-    fn check_suspicious_patterns(input: &str) -> ShellResult<()> {
-        let suspicious_patterns = [
-            r"\$\(.*\)",
+    /// Check for suspicious patterns that might indicate attacks. `$(...)`
+    /// is only suspicious when command substitution is turned off.
+    fn check_suspicious_patterns(input: &str, command_substitution: bool) -> ShellResult<()> {
+        let substitution = if command_substitution { None } else { Some(r"\$\(.*\)") };
+        let suspicious_patterns = substitution.into_iter().chain([
             r"`.*`",
             r"\$\{.*\}",
             r";.*;",
             r"&&.*&&",
             r"\|\|.*\|\|",
-        ];
+        ]);
 
-        for pattern in &suspicious_patterns {
+        for pattern in suspicious_patterns {
             if let Ok(regex) = Regex::new(pattern) {
                 if regex.is_match(input) {
                     return Err(SecurityError::DangerousCommand(
@@ -199,12 +315,6 @@ pub mod validation {
             }
         }
 
-        if let Ok(metadata) = path_buf.metadata() {
-            if metadata.permissions().readonly() {
-            } else {
-            }
-        }
-
         Ok(path_buf)
     }
 
@@ -231,57 +341,6 @@ pub mod validation {
     }
 }
 
-/// Process monitoring and resource management
-pub mod monitoring {
-    use super::*;
-    use std::sync::Arc;
-    use tokio::time::timeout;
-
-    /// Execute a command with resource monitoring
-    pub async fn execute_with_monitoring(
-        command: &str,
-        args: &[String],
-        config: &Config,
-        security_manager: Arc<SecurityManager>,
-    ) -> ShellResult<process::Output> {
-        security_manager.check_rate_limit(&format!("cmd:{}", command), config)?;
-
-        security_manager.can_start_process(config)?;
-
-        let start_time = Instant::now();
-
-        let result = timeout(
-            Duration::from_secs(config.limits.command_timeout),
-            tokio::process::Command::new(command)
-                .args(args)
-                .output()
-        ).await;
-
-        let execution_time = start_time.elapsed();
-
-        security_manager.record_command(command, execution_time);
-
-        match result {
-            Ok(output_result) => {
-                match output_result {
-                    Ok(output) => {
-                        if output.stdout.len() > config.limits.max_memory_mb * 1024 * 1024 {
-                            return Err(SecurityError::ResourceLimitExceeded(
-                                "Output too large".to_string()
-                            ).into());
-                        }
-                        Ok(output)
-                    }
-                    Err(e) => Err(crate::error::ShellError::CommandExecution(e.to_string())),
-                }
-            }
-            Err(_) => Err(SecurityError::ResourceLimitExceeded(
-                "Command execution timeout".to_string()
-            ).into()),
-        }
-    }
-}
-
 /// Environment security
 pub mod environment {
     use super::*;
@@ -346,3 +405,46 @@ pub mod environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_stats_are_capped_and_aggregated() {
+        let mut history =
+            CommandHistory { commands: HashMap::new(), evicted_commands: 0, evicted: None, capacity: 4 };
+        let start = Instant::now();
+        for (i, command) in ["a", "b", "c", "d", "a", "e"].iter().enumerate() {
+            history.record(command, Duration::from_millis(10), start + Duration::from_secs(i as u64));
+        }
+        // Adding `e` went over the cap: `b` and `c` were the least recently run
+        let mut kept: Vec<&str> = history.commands.keys().map(String::as_str).collect();
+        kept.sort();
+        assert_eq!(kept, ["a", "d", "e"]);
+        let evicted = history.evicted.as_ref().unwrap();
+        assert_eq!((history.evicted_commands, evicted.count), (2, 2));
+        let total: usize = history.commands.values().map(|stats| stats.count).sum::<usize>() + evicted.count;
+        assert_eq!(total, 6);
+    }
+
+    #[test]
+    fn test_rate_limiter_window_and_key_cap() {
+        let mut limiter = RateLimiter { keys: HashMap::new(), capacity: 2 };
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert!(limiter.check("cmd:ls", start, 10));
+        }
+        assert!(!limiter.check("cmd:ls", start, 10));
+        assert_eq!(limiter.keys["cmd:ls"].len(), 10);
+        assert!(limiter.check("cmd:ls", start + RATE_WINDOW, 10));
+
+        // Idle keys go first once there are too many
+        let later = start + RATE_WINDOW * 3;
+        assert!(limiter.check("cmd:cat", later, 10));
+        assert!(limiter.check("cmd:wc", later, 10));
+        let mut keys: Vec<&str> = limiter.keys.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["cmd:cat", "cmd:wc"]);
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::error::{self, SecurityError, ShellError, ShellResult};
+use crate::executor::exit_code;
+use crate::jobs;
+use crate::limits;
+use crate::security::validation;
+
+/// Variables a request may not set even if `server.allowed_env` lists them:
+/// they would change which programs run, what an interpreter loads at
+/// startup, or which configuration and policy the command runs under
+const PROTECTED_ENV: &[&str] = &[
+    "PATH", "IFS", "HOME", "ENV", "BASH_ENV", "PYTHONPATH", "PYTHONSTARTUP", "NODE_OPTIONS", "RUBYOPT", "PERL5OPT", "LD_", "DYLD_",
+    "SHELL_T_", "XDG_",
+];
+
+/// Whether `name` is one of `names`, or starts with one ending in `_`
+fn listed(name: &str, names: &[impl AsRef<str>]) -> bool {
+    names.iter().map(AsRef::as_ref).any(|listed| name == listed || (listed.ends_with('_') && name.starts_with(listed)))
+}
+
+/// Status reported for a command killed at `server.timeout_secs`, as for SIGKILL
+const TIMED_OUT_STATUS: i32 = 128 + 9;
+
+/// A command sent to `shell-t --serve`, one JSON object per line
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Request {
+    /// Echoed back in the response, to match them up
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub command: String,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub cwd: Option<String>,
+}
+
+impl Request {
+    /// Refuse malformed variables, those `server.allowed_env` doesn't list or
+    /// that are protected, and working directories the path policy refuses
+    pub fn check(&self, config: &Config) -> ShellResult<()> {
+        if self.command.trim().is_empty() {
+            return Err(ShellError::Parse("empty command".to_string()));
+        }
+        for name in self.env.keys() {
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(SecurityError::InvalidInput(format!("Invalid variable name: {}", name)).into());
+            }
+            if listed(name, PROTECTED_ENV) || !listed(name, &config.server.allowed_env) {
+                return Err(ShellError::SecurityViolation(format!("Variable may not be set by a request: {}", name)));
+            }
+        }
+        if let Some(cwd) = &self.cwd {
+            validation::validate_file_path(cwd, config)?;
+            if !Path::new(cwd).is_dir() {
+                return Err(SecurityError::InvalidInput(format!("No such directory: {}", cwd)).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serves requests on a Unix socket, each run by a fresh `shell-t -c` so it
+/// gets the same validation, policy and audit log as an interactive command
+pub struct Server {
+    config: Config,
+    /// The shell run for each request
+    program: PathBuf,
+    /// The server's config file, which each request's shell is told to read
+    /// rather than look for one itself
+    config_file: Option<PathBuf>,
+}
+
+impl Server {
+    pub fn new(config: Config) -> io::Result<Self> {
+        Ok(Self { config, program: std::env::current_exe()?, config_file: Config::file() })
+    }
+
+    /// Run one request, returning its status and captured output
+    pub fn run(&self, request: &Request) -> ShellResult<Value> {
+        request.check(&self.config)?;
+        if self.config.security.enable_auditing {
+            let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            error::logging::log_command_execution(&format!("{} (serve)", request.command), &user);
+        }
+
+        let mut command = Command::new(&self.program);
+        // Warnings silenced for the server stay silent in its commands' stderr
+        for category in &self.config.warnings.suppressed {
+            command.arg("--no-warn").arg(category);
+        }
+        command.arg("-c").arg(&request.command).envs(&request.env).env("SHELL_T_ENABLE_COLORS", "false");
+        command.env("SHELL_T_CONFIG", self.config_file.as_deref().unwrap_or(Path::new("")));
+        if let Some(cwd) = &request.cwd {
+            command.current_dir(cwd);
+        }
+        command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        // Its own group, so a timeout takes the command's children with it
+        jobs::set_process_group(&mut command, None, false);
+
+        let start = Instant::now();
+        let mut child = command.spawn().map_err(|e| ShellError::Spawn { program: self.program.display().to_string(), source: e })?;
+        let limit = self.config.server.max_output_kb * 1024;
+        let stdout = child.stdout.take().map(|stdout| capture(stdout, limit));
+        let stderr = child.stderr.take().map(|stderr| capture(stderr, limit));
+
+        let deadline = start + Duration::from_secs(self.config.server.timeout_secs);
+        let pid = child.id();
+        let exit = limits::wait_until(&mut child, Some(deadline))?;
+        if exit.is_none() {
+            #[cfg(unix)]
+            // SAFETY: sending a signal has no memory-safety requirements
+            unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+            }
+        }
+        let (stdout, stdout_truncated) = stdout.map(|handle| handle.join().unwrap_or_default()).unwrap_or_default();
+        let (stderr, stderr_truncated) = stderr.map(|handle| handle.join().unwrap_or_default()).unwrap_or_default();
+
+        Ok(json!({
+            "status": exit.map_or(TIMED_OUT_STATUS, exit_code),
+            "stdout": String::from_utf8_lossy(&stdout),
+            "stderr": String::from_utf8_lossy(&stderr),
+            "duration_ms": start.elapsed().as_millis() as u64,
+            "timed_out": exit.is_none(),
+            "truncated": stdout_truncated || stderr_truncated,
+        }))
+    }
+
+    /// Answer requests from one client, one response line per request line,
+    /// until it disconnects
+    pub fn handle(&self, input: impl Read, mut output: impl Write) -> io::Result<()> {
+        for line in BufReader::new(input).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (id, result) = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => (request.id.clone(), self.run(&request)),
+                Err(e) => (None, Err(ShellError::Parse(format!("invalid request: {}", e)))),
+            };
+            let mut response = match result {
+                Ok(result) => result,
+                Err(e) => json!({ "error": { "code": e.code(), "message": e.to_string(), "status": e.exit_status() } }),
+            };
+            if let Some(id) = id {
+                response["id"] = id;
+            }
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Listen on `path` until killed, serving each client on its own thread.
+    /// The socket is only accessible to the shell's user.
+    #[cfg(unix)]
+    pub fn serve(self, path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        use std::os::unix::net::{UnixListener, UnixStream};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // A socket left behind by a server that is gone is replaced
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() || UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use", path.display())));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        tracing::info!(path = %path.display(), "serving requests");
+
+        let server = Arc::new(self);
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to accept a connection");
+                    continue;
+                }
+            };
+            if connections.fetch_add(1, Ordering::SeqCst) >= server.config.server.max_connections {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                let _ = writeln!(stream, "{}", json!({ "error": { "code": "busy", "message": "too many connections" } }));
+                continue;
+            }
+            let (server, connections) = (Arc::clone(&server), Arc::clone(&connections));
+            thread::spawn(move || {
+                let result = stream.try_clone().and_then(|input| server.handle(input, stream));
+                if let Err(e) = result {
+                    tracing::debug!(error = %e, "connection ended");
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn serve(self, _path: &Path) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "--serve needs Unix sockets"))
+    }
+}
+
+/// Read `source` to the end on a thread, keeping the first `limit` bytes;
+/// yields them and whether anything was dropped
+fn capture(mut source: impl Read + Send + 'static, limit: u64) -> JoinHandle<(Vec<u8>, bool)> {
+    thread::spawn(move || {
+        let mut kept = Vec::new();
+        let _ = (&mut source).take(limit).read_to_end(&mut kept);
+        let dropped = io::copy(&mut source, &mut io::sink()).unwrap_or(0);
+        (kept, dropped > 0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(line: &str) -> Request {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn test_check_refuses_protected_env_and_cwd() {
+        let mut config = Config::default();
+        assert!(request(r#"{"command": "ls", "env": {"LANG": "C", "LC_ALL": "C"}, "cwd": "/tmp"}"#).check(&config).is_ok());
+        // Only listed variables may be set
+        assert!(request(r#"{"command": "ls", "env": {"GREETING": "hi"}}"#).check(&config).is_err());
+        config.server.allowed_env.extend(["GREETING".to_string(), "PYTHON".to_string(), "PATH".to_string()]);
+        assert!(request(r#"{"command": "ls", "env": {"GREETING": "hi"}}"#).check(&config).is_ok());
+        assert!(request(r#"{"command": "ls", "env": {"PYTHONPATH": "/evil"}}"#).check(&config).is_err());
+        for name in ["BASH_ENV", "ENV", "PYTHONSTARTUP", "NODE_OPTIONS", "RUBYOPT", "PERL5OPT"] {
+            config.server.allowed_env.push(name.to_string());
+            let line = format!(r#"{{"command": "ls", "env": {{"{}": "/tmp/x"}}}}"#, name);
+            assert!(request(&line).check(&config).is_err(), "{}", name);
+        }
+        // Protected even when listed
+        assert!(request(r#"{"command": "ls", "env": {"PATH": "/evil"}}"#).check(&config).is_err());
+        assert!(request(r#"{"command": "ls", "env": {"SHELL_T_ALLOWED_COMMANDS": "rm"}}"#).check(&config).is_err());
+        assert!(request(r#"{"command": "ls", "env": {"LD_PRELOAD": "x.so"}}"#).check(&config).is_err());
+        assert!(request(r#"{"command": "ls", "env": {"HOME": "/tmp"}}"#).check(&config).is_err());
+        assert!(request(r#"{"command": "ls", "env": {"A-B": "x"}}"#).check(&config).is_err());
+        assert!(request(r#"{"command": "ls", "cwd": "/etc"}"#).check(&config).is_err());
+        assert!(request(r#"{"command": " "}"#).check(&config).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"command": "ls", "shell": "bash"}"#).is_err());
+    }
+
+    #[test]
+    fn test_handle_returns_structured_results() {
+        // `sh -c` stands in for the shell-t binary the server normally runs
+        let mut config = Config::default();
+        config.server.allowed_env.push("GREETING".to_string());
+        config.server.max_output_kb = 1;
+        config.server.timeout_secs = 1;
+        let server = Server { config, program: PathBuf::from("sh"), config_file: Some(PathBuf::from("/etc/shell-t.toml")) };
+        let requests = concat!(
+            "{\"id\": 1, \"command\": \"echo $GREETING; echo oops >&2; exit 3\", \"env\": {\"GREETING\": \"hi\"}}\n",
+            "{\"id\": 2, \"command\": \"pwd\", \"cwd\": \"/tmp\"}\n",
+            "not json\n",
+            "{\"id\": 3, \"command\": \"head -c 5000 /dev/zero\"}\n",
+            "{\"id\": 4, \"command\": \"sleep 5\"}\n",
+            "{\"id\": 5, \"command\": \"echo $SHELL_T_CONFIG\"}\n",
+        );
+        let mut output = Vec::new();
+        server.handle(requests.as_bytes(), &mut output).unwrap();
+        let responses: Vec<Value> =
+            String::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!((responses[0]["status"].as_i64(), responses[0]["stdout"].as_str()), (Some(3), Some("hi\n")));
+        assert_eq!(responses[0]["stderr"], "oops\n");
+        assert_eq!(responses[1]["stdout"], "/tmp\n");
+        assert!(responses[2]["error"]["message"].as_str().unwrap().contains("invalid request"));
+        assert_eq!((responses[3]["stdout"].as_str().unwrap().len(), &responses[3]["truncated"]), (1024, &json!(true)));
+        assert_eq!((&responses[4]["timed_out"], &responses[4]["status"]), (&json!(true), &json!(137)));
+        // The shell is pointed at the server's config, not left to find one
+        assert_eq!(responses[5]["stdout"], "/etc/shell-t.toml\n");
+    }
+}
@@ -0,0 +1,1687 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::builtins::{self, BuiltinCommand, BuiltinManager, BuiltinResult};
+use crate::config::Config;
+use crate::container::Container;
+use crate::debug::{Action, Debugger};
+use crate::completions::Completer;
+use crate::editor::LineEditor;
+use crate::envdiff::{self, Snapshot};
+use crate::error::{self, Repeat, RepeatFilter, SecurityError, ShellError, ShellResult};
+use crate::expand;
+use crate::history::{self, Entry, History};
+use crate::inline::{self, Language};
+use crate::jobs::{self, JobManager};
+use crate::kernel;
+#[cfg(feature = "lua")]
+use crate::lua::LuaRuntime;
+use crate::executor::CommandExecutor;
+use crate::parse_cache::{self, is_incomplete, Splitter, Statement};
+use crate::parser::{self, Command, Connector};
+use crate::project;
+use crate::remote;
+#[cfg(feature = "wasm")]
+use crate::plugins::PluginManager;
+use crate::script::{self, Node};
+use crate::security::SecurityManager;
+use crate::signals;
+use crate::state::{self, ShellState};
+use crate::tabs::{self, Pane, Tabs};
+use crate::telemetry;
+use crate::ui::UiManager;
+use crate::warnings::{self, WarningCategory, WarningLevel};
+
+/// Number of back-to-back input failures tolerated before giving up
+const MAX_CONSECUTIVE_FAILURES: usize = 5;
+
+/// Startup file sourced by login shells, relative to the home directory
+const PROFILE_FILE: &str = ".shellt_profile";
+
+/// Startup file sourced by interactive shells, relative to the home directory
+const RC_FILE: &str = ".shelltrc";
+
+/// Exit status of a command killed by SIGINT
+const SIGINT_STATUS: i32 = 130;
+
+/// What a loop should do next
+enum LoopStep {
+    Run,
+    Stop,
+    Leave(Flow),
+}
+
+/// What the caller should do after running a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    /// `break N`: leave N enclosing loops
+    Break(usize),
+    /// `continue N`: start the next iteration of the Nth enclosing loop
+    Next(usize),
+    /// Ctrl-C: abandon the rest of the line
+    Interrupted,
+    Exit,
+    Fatal,
+}
+
+/// How a run of parsed commands ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Finished with this exit status
+    Status(i32),
+    /// `exit` asked the shell to stop, with this status
+    Exit(i32),
+}
+
+impl Outcome {
+    /// The exit status, whether or not the shell is to stop
+    pub fn status(self) -> i32 {
+        match self {
+            Outcome::Status(status) | Outcome::Exit(status) => status,
+        }
+    }
+}
+
+/// A shell session: the managers plus the state shared between lines
+pub struct Session {
+    config: Config,
+    state: Arc<ShellState>,
+    jobs: Arc<JobManager>,
+    history: Arc<History>,
+    /// Tab completion rules and specs, shared with the `complete` builtin
+    completer: Arc<Completer>,
+    /// Line editor for the interactive prompt, when on a terminal
+    editor: Option<LineEditor>,
+    builtins: BuiltinManager,
+    executor: CommandExecutor,
+    ui: UiManager,
+    error_repeats: RepeatFilter,
+    /// `file:line` of the script line being run, for error reports
+    location: Option<String>,
+    /// Number of loops currently running, for `break`/`continue`
+    loop_depth: usize,
+    /// Step debugger stopping between script statements (`--debug`)
+    debugger: Option<Debugger>,
+    /// Errors kept for the caller instead of displayed, for embedding
+    errors: Option<Vec<ShellError>>,
+    /// Tabs and panes on the alternate screen, once `tab new` opens one
+    tabs: Option<Tabs>,
+    #[cfg(feature = "lua")]
+    lua: Option<LuaRuntime>,
+    #[cfg(feature = "wasm")]
+    plugins: Option<PluginManager>,
+}
+
+impl Session {
+    /// Create a session from a validated configuration
+    pub fn new(config: Config) -> Self {
+        let security = Arc::new(SecurityManager::with_capacity(
+            config.security.max_tracked_commands,
+            config.security.max_rate_limit_keys,
+        ));
+        let state = Arc::new(ShellState::new());
+        state.update_options(|options| {
+            options.nullglob = config.glob.nullglob;
+            options.failglob = config.glob.failglob;
+            options.dotglob = config.glob.dotglob;
+            options.noclobber = config.security.noclobber;
+            options.nosplit = config.expansion.nosplit;
+        });
+        let jobs = Arc::new(JobManager::new());
+        let history = Arc::new(History::open(&config.history));
+        let completer = Arc::new(Completer::new(&config, Arc::clone(&state), Arc::clone(&history)));
+
+        Self {
+            builtins: BuiltinManager::new(
+                Arc::clone(&security),
+                config.clone(),
+                Arc::clone(&state),
+                Arc::clone(&jobs),
+                Arc::clone(&history),
+                Arc::clone(&completer),
+            ),
+            executor: CommandExecutor::new(security, config.clone(), Arc::clone(&state), Arc::clone(&jobs)),
+            ui: UiManager::new(config.clone()),
+            error_repeats: RepeatFilter::new(Duration::from_millis(config.ui.repeat_window_ms)),
+            editor: None,
+            location: None,
+            loop_depth: 0,
+            debugger: None,
+            errors: None,
+            tabs: None,
+            #[cfg(feature = "lua")]
+            lua: load_lua(&config),
+            #[cfg(feature = "wasm")]
+            plugins: load_plugins(&config),
+            state,
+            jobs,
+            history,
+            completer,
+            config,
+        }
+    }
+
+    /// Background jobs started in this session
+    pub fn jobs(&self) -> &Arc<JobManager> {
+        &self.jobs
+    }
+
+    /// Session state (options, last exit status)
+    pub fn state(&self) -> &Arc<ShellState> {
+        &self.state
+    }
+
+    /// Parse and run one line (or a complete multi-line compound command),
+    /// recording its exit status
+    pub fn run_line(&mut self, input: &str) -> Flow {
+        // A Ctrl-C pressed at the prompt shouldn't cancel this line
+        signals::take_interrupt();
+        if let Some(block) = inline::parse(input) {
+            let result = self.executor.execute_inline(&block);
+            return self.finish(result, input);
+        }
+        match script::parse_script(input) {
+            Ok(nodes) => self.run_nodes(&nodes, false),
+            Err(e) => self.report_error(e, input),
+        }
+    }
+
+    /// Run nodes in order; `condition` suppresses errexit, as in `if` tests
+    fn run_nodes(&mut self, nodes: &[Node], condition: bool) -> Flow {
+        for node in nodes {
+            let flow = match node {
+                Node::Pipeline(source) => self.run_pipeline(source),
+                Node::Subshell(body) => self.run_subshell(body, condition),
+                Node::AndOr { first, rest } => self.run_and_or(first, rest, condition),
+                Node::If { branches, otherwise } => self.run_if(branches, otherwise.as_deref()),
+                Node::For { variable, words, body } => self.run_for(variable, words, body),
+                Node::While { condition, body, until } => self.run_while(condition, body, *until),
+            };
+            if flow != Flow::Continue {
+                return flow;
+            }
+            if !condition && matches!(node, Node::Pipeline(_) | Node::Subshell(_)) && self.errexit_triggered() {
+                return Flow::Exit;
+            }
+        }
+        Flow::Continue
+    }
+
+    /// Run `body` in a subshell: changes it makes to the directory,
+    /// variables, aliases and options are undone after, and `exit` leaves
+    /// only the subshell
+    fn run_subshell(&mut self, body: &[Node], condition: bool) -> Flow {
+        let before = Snapshot::take(&self.state);
+        let scope = self.state.scope();
+        let flow = self.run_nodes(body, condition);
+
+        envdiff::revert(&before.changes(&Snapshot::take(&self.state)), &self.state);
+        self.state.restore(scope);
+        if let Some(cwd) = &before.cwd {
+            if let Err(e) = std::env::set_current_dir(cwd) {
+                eprintln!("shell-t: can't return to {}: {}", cwd.display(), e);
+            }
+        }
+        match flow {
+            Flow::Exit => Flow::Continue,
+            flow => flow,
+        }
+    }
+
+    /// Run `first`, then each later pipeline or subshell its connector
+    /// allows. As in other shells, only a failure of the last one can
+    /// trigger errexit.
+    fn run_and_or(&mut self, first: &Node, rest: &[(Connector, Node)], condition: bool) -> Flow {
+        let mut flow = self.run_and_or_member(first);
+        let mut last_ran = rest.is_empty();
+        for (index, (connector, node)) in rest.iter().enumerate() {
+            if flow != Flow::Continue {
+                return flow;
+            }
+            if connector.runs_after(self.state.last_status()) {
+                flow = self.run_and_or_member(node);
+                last_ran = index == rest.len() - 1;
+            }
+        }
+        if flow == Flow::Continue && !condition && last_ran && self.errexit_triggered() {
+            return Flow::Exit;
+        }
+        flow
+    }
+
+    /// Run a pipeline or subshell of an `&&`/`||` list; errexit doesn't
+    /// apply inside
+    fn run_and_or_member(&mut self, node: &Node) -> Flow {
+        match node {
+            Node::Subshell(body) => self.run_subshell(body, true),
+            Node::Pipeline(source) => self.run_pipeline(source),
+            node => self.run_nodes(std::slice::from_ref(node), true),
+        }
+    }
+
+    fn run_if(&mut self, branches: &[(Vec<Node>, Vec<Node>)], otherwise: Option<&[Node]>) -> Flow {
+        for (condition, body) in branches {
+            let flow = self.run_nodes(condition, true);
+            if flow != Flow::Continue {
+                return flow;
+            }
+            if self.state.last_status() == 0 {
+                return self.run_nodes(body, false);
+            }
+        }
+
+        match otherwise {
+            Some(body) => self.run_nodes(body, false),
+            None => {
+                // No branch taken: the if itself succeeds
+                self.state.set_last_status(0);
+                Flow::Continue
+            }
+        }
+    }
+
+    fn run_for(&mut self, variable: &str, words: &str, body: &[Node]) -> Flow {
+        let words = script::split_words(&expand::expand_variables(words, &self.state));
+        self.state.set_last_status(0);
+
+        self.run_loop(body, |session, iteration| match words.get(iteration) {
+            Some(word) => {
+                session.state.set_var(variable, word);
+                LoopStep::Run
+            }
+            None => LoopStep::Stop,
+        })
+    }
+
+    fn run_while(&mut self, condition: &[Node], body: &[Node], until: bool) -> Flow {
+        self.state.set_last_status(0);
+        // The loop's status is the last body's, not the condition that ended it
+        let mut finished = None;
+
+        let flow = self.run_loop(body, |session, _| {
+            let status = session.state.last_status();
+            match session.run_nodes(condition, true) {
+                Flow::Continue if (session.state.last_status() == 0) != until => LoopStep::Run,
+                Flow::Continue => {
+                    finished = Some(status);
+                    LoopStep::Stop
+                }
+                flow => LoopStep::Leave(flow),
+            }
+        });
+        if let Some(status) = finished {
+            self.state.set_last_status(status);
+        }
+        flow
+    }
+
+    /// Drive a loop: `step` decides before each iteration whether to run the
+    /// body; handles `break`/`continue`, Ctrl-C and the iteration limit
+    fn run_loop(&mut self, body: &[Node], mut step: impl FnMut(&mut Self, usize) -> LoopStep) -> Flow {
+        let limit = self.config.limits.max_loop_iterations;
+        self.loop_depth += 1;
+
+        let mut iteration = 0;
+        let flow = loop {
+            if signals::take_interrupt() {
+                break Flow::Interrupted;
+            }
+            if limit > 0 && iteration >= limit {
+                let e = SecurityError::ResourceLimitExceeded(format!("loop exceeded {} iterations", limit));
+                self.report_error(e.into(), "");
+                break Flow::Continue;
+            }
+            match step(self, iteration) {
+                LoopStep::Run => {}
+                LoopStep::Stop => break Flow::Continue,
+                LoopStep::Leave(flow) => break flow,
+            }
+            iteration += 1;
+
+            match self.run_nodes(body, false) {
+                Flow::Continue | Flow::Next(1) => {}
+                Flow::Break(1) => break Flow::Continue,
+                Flow::Break(n) => break Flow::Break(n - 1),
+                Flow::Next(n) => break Flow::Next(n - 1),
+                flow => break flow,
+            }
+        };
+
+        self.loop_depth -= 1;
+        flow
+    }
+
+    /// Handle `break [N]` / `continue [N]`, or None if `input` is neither
+    fn loop_control(&mut self, input: &str) -> Option<Flow> {
+        let mut words = input.split_whitespace();
+        let keyword = words.next()?;
+        if keyword != "break" && keyword != "continue" {
+            return None;
+        }
+
+        let count = match words.next().map(str::parse::<usize>) {
+            None => 1,
+            Some(Ok(n)) if n > 0 => n,
+            Some(_) => {
+                eprintln!("{}: loop count must be a positive number", keyword);
+                self.state.set_last_status(1);
+                return Some(Flow::Continue);
+            }
+        };
+        if self.loop_depth == 0 {
+            eprintln!("{}: only meaningful in a loop", keyword);
+            self.state.set_last_status(0);
+            return Some(Flow::Continue);
+        }
+
+        self.state.set_last_status(0);
+        let count = count.min(self.loop_depth);
+        Some(if keyword == "break" { Flow::Break(count) } else { Flow::Next(count) })
+    }
+
+    /// Whether `set -e` is on and the last command failed
+    fn errexit_triggered(&self) -> bool {
+        let status = self.state.last_status();
+        if status != 0 && self.state.options().errexit {
+            tracing::debug!(status, "errexit: stopping");
+            return true;
+        }
+        false
+    }
+
+    /// Parse and run a single pipeline
+    fn run_pipeline(&mut self, input: &str) -> Flow {
+        if let Some(flow) = self.loop_control(input) {
+            return flow;
+        }
+        let expanded = expand::expand_variables(input, &self.state);
+        let ifs = expand::field_separators(&self.state);
+        // Status of the last `$(...)`, which an assignment-only line exits with
+        let mut substituted = None;
+        let expanded = match expand::substitute_commands(&expanded, ifs.as_deref(), |inner| {
+            let output = self.substitute(inner);
+            substituted = Some(self.state.last_status());
+            output
+        }) {
+            Ok(expanded) => expanded,
+            Err(e) => return self.report_error(e, &expanded),
+        };
+        let input = expanded.as_str();
+
+        let commands = match parser::parse_line(input) {
+            Ok(commands) => self.state.expand_aliases(commands),
+            Err(e) => return self.report_error(e, input),
+        };
+        let commands = match expand::expand_globs(commands, &self.state.options()) {
+            Ok(commands) => commands,
+            Err(e) => return self.report_error(e, input),
+        };
+        if let [command] = commands.as_slice() {
+            match command.program.as_str() {
+                "exit" => return self.exit(&command.args),
+                "source" | "." => {
+                    let result = self.source(&command.args);
+                    return self.finish(result, input);
+                }
+                "tab" => {
+                    let result = self.tab(&command.args);
+                    return self.finish(result, input);
+                }
+                _ => {}
+            }
+        }
+        match self.dispatch(&commands, substituted) {
+            Ok(Outcome::Exit(status)) => {
+                self.state.set_last_status(status);
+                Flow::Exit
+            }
+            result => self.finish(result.map(Outcome::status), input),
+        }
+    }
+
+    /// Run the commands of a `$(...)` through the same validation as any
+    /// other and return what they wrote to stdout. Pipelines may be joined by
+    /// `;`, `&&` and `||`; compound commands are refused.
+    fn substitute(&self, inner: &str) -> ShellResult<String> {
+        if !self.config.security.command_substitution {
+            return Err(ShellError::SecurityViolation("Command substitution is disabled".to_string()));
+        }
+        let mut output = String::new();
+        for node in script::parse_script(inner)? {
+            output += &self.substitute_node(&node)?;
+        }
+        Ok(output)
+    }
+
+    /// Run one statement of a `$(...)`, returning its output
+    fn substitute_node(&self, node: &Node) -> ShellResult<String> {
+        match node {
+            Node::Pipeline(source) => self.substitute_pipeline(source),
+            Node::AndOr { first, rest } => {
+                let mut output = self.substitute_node(first)?;
+                for (connector, node) in rest {
+                    if connector.runs_after(self.state.last_status()) {
+                        output += &self.substitute_node(node)?;
+                    }
+                }
+                Ok(output)
+            }
+            _ => Err(ShellError::Parse("compound commands are not supported in $(...)".to_string())),
+        }
+    }
+
+    /// Run one pipeline of a `$(...)`, returning its output
+    fn substitute_pipeline(&self, inner: &str) -> ShellResult<String> {
+        let ifs = expand::field_separators(&self.state);
+        let inner = expand::substitute_commands(inner, ifs.as_deref(), |nested| self.substitute(nested))?;
+        let commands = self.state.expand_aliases(parser::parse_line(&inner)?);
+        let commands = expand::expand_globs(commands, &self.state.options())?;
+
+        if let [command] = commands.as_slice() {
+            if let Some(result) = self.builtins.execute_builtin(&command.program, &command.args)? {
+                let (status, output) = match result {
+                    BuiltinResult::Success(msg) => (0, msg.map(|msg| msg + "\n").unwrap_or_default()),
+                    BuiltinResult::Info(msg) => (0, msg + "\n"),
+                    BuiltinResult::Error(msg) => {
+                        eprintln!("{}", msg);
+                        (1, String::new())
+                    }
+                    BuiltinResult::Warning(msg) => {
+                        warnings::warn(WarningLevel::Warning, WarningCategory::Builtin, &msg);
+                        (0, String::new())
+                    }
+                    BuiltinResult::Status(status) | BuiltinResult::Exit(status) => (status, String::new()),
+                };
+                self.state.set_last_status(status);
+                return Ok(output);
+            }
+        }
+        // Spans point into the inner pipeline, not the line around it
+        let (status, output) = self.executor.capture_pipeline(&commands).map_err(|e| e.with_source(&inner))?;
+        self.state.set_last_status(status);
+        Ok(output)
+    }
+
+    /// Handle `source FILE [ARG...]` and `source URL sha256=HASH`: run a script in this
+    /// session, line by line through the usual validation
+    fn source(&mut self, args: &[String]) -> ShellResult<i32> {
+        let path = match args {
+            [url] if remote::is_remote(url) => remote::fetch(url, None, &self.config.source)?,
+            [url, pin] if remote::is_remote(url) => match pin.strip_prefix("sha256=") {
+                Some(hash) => remote::fetch(url, Some(hash), &self.config.source)?,
+                None => return Err(ShellError::Parse(format!("source: expected sha256=HASH, got {}", pin))),
+            },
+            [file, ..] => file.into(),
+            _ => {
+                let usage = "source: usage: source FILE [ARG...] | source URL sha256=HASH";
+                return Err(ShellError::Parse(usage.to_string()));
+            }
+        };
+        // Arguments after a file are its `$1..$N` while it runs
+        let script_args = match args {
+            [url, ..] if remote::is_remote(url) => &[],
+            [_, script_args @ ..] => script_args,
+            [] => &[],
+        };
+        let params = (!script_args.is_empty()).then(|| {
+            let params = self.state.params();
+            self.state.set_params(script_args.to_vec());
+            params
+        });
+        let location = self.location.take();
+        let result = self.run_script(&path);
+        self.location = location;
+        if let Some(params) = params {
+            self.state.set_params(params);
+        }
+        result
+    }
+
+    /// Handle `exit [N]`: record the status and unwind to the caller, which
+    /// returns it as the script's or shell's exit status
+    fn exit(&mut self, args: &[String]) -> Flow {
+        match builtins::exit_status(args, self.state.last_status()) {
+            Ok(status) => {
+                self.state.set_last_status(status);
+                Flow::Exit
+            }
+            Err(msg) => {
+                eprintln!("{}", msg);
+                self.state.set_last_status(2);
+                Flow::Continue
+            }
+        }
+    }
+
+    /// Handle `tab`: open, switch, split and close tabs and panes on the
+    /// alternate screen, each pane with its own jobs, directory and history
+    fn tab(&mut self, args: &[String]) -> ShellResult<i32> {
+        let usage = "tab: usage: tab [list | new [NAME] | next | prev | N | split | focus | close]";
+        if self.editor.is_none() {
+            return Err(ShellError::CommandExecution("tab: needs an interactive terminal".to_string()));
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        if let [] | ["list"] = args.as_slice() {
+            match &self.tabs {
+                Some(tabs) => tabs.list().iter().for_each(|line| println!("{}", line)),
+                None => println!("no tabs; tab new opens one"),
+            }
+            return Ok(0);
+        }
+        if let ["new"] | ["new", _] = args.as_slice() {
+            let pane = self.new_pane()?;
+            self.leave_pane();
+            if self.tabs.is_none() {
+                let first = self.new_pane_with(Arc::clone(&self.jobs))?;
+                self.ui.enter_tabs()?;
+                self.tabs = Some(Tabs::new(first));
+            }
+            if let Some(tabs) = &mut self.tabs {
+                tabs.add(args.get(1).map(|name| name.to_string()), pane);
+            }
+            self.enter_pane(true);
+            return Ok(0);
+        }
+
+        let Some(tabs) = &self.tabs else {
+            return Err(ShellError::CommandExecution("tab: no tabs; tab new opens one".to_string()));
+        };
+        let split = args == ["split"];
+        let pane = if split { Some(self.new_pane()?) } else { None };
+        if args == ["close"] && tabs.pane().jobs.active() > 0 {
+            return Err(ShellError::CommandExecution("tab: the pane has running jobs; finish or kill them first".to_string()));
+        }
+        self.leave_pane();
+        let Some(tabs) = &mut self.tabs else {
+            return Ok(0);
+        };
+        let result = match (args.as_slice(), pane) {
+            (["next"], _) => {
+                tabs.cycle(1);
+                Ok(())
+            }
+            (["prev"], _) => {
+                tabs.cycle(-1);
+                Ok(())
+            }
+            (["split"], Some(pane)) => match tabs::regions(self.ui.get_terminal_size()?.1, 2) {
+                Some(_) => tabs.split(pane),
+                None => Err("the terminal is too small to split".to_string()),
+            },
+            (["focus"], _) => tabs.focus(),
+            (["close"], _) => {
+                tabs.close();
+                if tabs.is_empty() {
+                    self.tabs = None;
+                    self.ui.leave_tabs()?;
+                    return Ok(0);
+                }
+                Ok(())
+            }
+            ([number], _) => match number.parse() {
+                Ok(number) => tabs.select(number),
+                Err(_) => return Err(ShellError::Parse(usage.to_string())),
+            },
+            _ => return Err(ShellError::Parse(usage.to_string())),
+        };
+        result.map_err(|e| ShellError::CommandExecution(format!("tab: {}", e)))?;
+        // Moving between the panes of a tab keeps what they show
+        self.enter_pane(!matches!(args.as_slice(), ["focus"]));
+        Ok(0)
+    }
+
+    /// A pane in the current directory with a job table of its own
+    fn new_pane(&self) -> ShellResult<Pane> {
+        let jobs = Arc::new(JobManager::new());
+        if self.jobs.job_control() {
+            jobs.enable_job_control();
+        }
+        jobs::start_reaper(&jobs);
+        self.new_pane_with(jobs)
+    }
+
+    fn new_pane_with(&self, jobs: Arc<JobManager>) -> ShellResult<Pane> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64);
+        Ok(Pane::new(jobs, std::env::current_dir()?, now))
+    }
+
+    /// Remember the directory of the active pane and where its cursor was
+    fn leave_pane(&mut self) {
+        let row = self.ui.cursor_row().ok();
+        if let Some(tabs) = &mut self.tabs {
+            let pane = tabs.pane_mut();
+            if let Ok(cwd) = std::env::current_dir() {
+                pane.cwd = cwd;
+            }
+            pane.cursor = row;
+        }
+    }
+
+    /// Make the active pane's jobs and directory the session's and draw it;
+    /// with `clear`, its tab starts over on a blank screen
+    fn enter_pane(&mut self, clear: bool) {
+        let Some(tabs) = &self.tabs else {
+            return;
+        };
+        let pane = tabs.pane();
+        self.jobs = Arc::clone(&pane.jobs);
+        self.builtins.set_jobs(Arc::clone(&pane.jobs));
+        self.executor.set_jobs(Arc::clone(&pane.jobs));
+        if let Err(e) = std::env::set_current_dir(&pane.cwd) {
+            eprintln!("tab: {}: {}", pane.cwd.display(), e);
+        }
+        if let Err(e) = self.draw_tabs(clear) {
+            tracing::debug!(error = %e, "failed to draw tabs");
+        }
+    }
+
+    /// Draw the tab bar and any divider, and keep output to the active pane.
+    /// With `clear`, blank the screen below the bar first and start each pane
+    /// at its top.
+    fn draw_tabs(&mut self, clear: bool) -> ShellResult<()> {
+        let Some(tabs) = &mut self.tabs else {
+            return Ok(());
+        };
+        let (width, rows) = self.ui.get_terminal_size()?;
+        let resized = std::mem::replace(&mut tabs.screen, (width, rows)) != (width, rows);
+        let bar = tabs.bar(width as usize);
+        let tab = tabs.tab_mut();
+        let split = tabs::regions(rows, tab.panes.len());
+        let dividers: Vec<u16> = split.iter().flatten().skip(1).map(|&(top, _)| top - 1).collect();
+        // A split that no longer fits shows only the active pane
+        let Some(regions) = split.or_else(|| Some(vec![tabs::regions(rows, 1)?[0]; tab.panes.len()])) else {
+            return Ok(());
+        };
+        let region = regions[tab.active];
+        if clear {
+            self.ui.clear_rows(1, rows - 1)?;
+            for (pane, &(top, _)) in tab.panes.iter_mut().zip(&regions) {
+                pane.cursor = Some(top);
+            }
+        }
+        // After a resize the cursor may be outside the pane
+        let cursor = match tab.panes[tab.active].cursor.take() {
+            Some(row) => Some(row.clamp(region.0, region.1)),
+            None => resized.then_some(region.1),
+        };
+        self.ui.draw_tabs(&bar, &dividers, region, cursor)
+    }
+
+    /// Run parsed commands: assignments, then Lua and WASM builtins, then
+    /// builtins and programs. Assignments end with the status of the line's
+    /// last `$(...)`, `substituted`, or 0.
+    fn dispatch(&self, commands: &[Command], substituted: Option<i32>) -> ShellResult<Outcome> {
+        if let Some(assignments) = state::assignments(commands) {
+            for (name, value) in assignments {
+                self.state.set_var(name, value);
+            }
+            return Ok(Outcome::Status(substituted.unwrap_or(0)));
+        }
+
+        #[cfg(feature = "lua")]
+        if let (Some(lua), [command]) = (&self.lua, commands) {
+            if lua.has_builtin(&command.program) {
+                let (output, status) = lua.call_builtin(&command.program, &command.args)?;
+                if let Some(output) = output {
+                    println!("{}", output);
+                }
+                return Ok(Outcome::Status(status));
+            }
+        }
+
+        #[cfg(feature = "wasm")]
+        if let (Some(plugins), [command]) = (&self.plugins, commands) {
+            if plugins.has(&command.program) {
+                return plugins.run(&command.program, &command.args).map(Outcome::Status);
+            }
+        }
+
+        // `in docker:NAME cmd ...`: the line runs in the container, then the
+        // previous setting comes back
+        if let Some(first) = commands.first() {
+            if let ("in", [target, program, args @ ..]) = (first.program.as_str(), first.args.as_slice()) {
+                let container = Container::parse(target).map_err(|e| ShellError::Parse(format!("in: {}", e)))?;
+                let mut commands = commands.to_vec();
+                let spans = first.spans.get(2..).unwrap_or_default().to_vec();
+                commands[0] = Command { program: program.clone(), args: args.to_vec(), spans, ..first.clone() };
+                let previous = self.state.set_container(Some(container));
+                let result = run_commands(&commands, &self.builtins, &self.executor);
+                self.state.set_container(previous);
+                return result;
+            }
+        }
+
+        run_commands(commands, &self.builtins, &self.executor)
+    }
+
+    /// Record the outcome of a command, reporting any error
+    fn finish(&mut self, result: ShellResult<i32>, input: &str) -> Flow {
+        match result {
+            Ok(status) => {
+                self.state.set_last_status(status);
+                report_repeats(self.error_repeats.flush());
+                // A child killed by Ctrl-C stops any enclosing loop too
+                if signals::take_interrupt() || status == SIGINT_STATUS {
+                    Flow::Interrupted
+                } else {
+                    Flow::Continue
+                }
+            }
+            Err(e) => self.report_error(e, input),
+        }
+    }
+
+    /// Record and display a failed command, returning whether to carry on
+    fn report_error(&mut self, e: ShellError, input: &str) -> Flow {
+        let e = e.with_source(input);
+        self.state.set_last_status(e.exit_status());
+        telemetry::global().record_error(&e, input);
+        if self.config.security.enable_logging && e.code().starts_with("ST02") {
+            error::logging::log_security_event("blocked", &e.root().to_string());
+        }
+        let flow = if e.is_fatal() { Flow::Fatal } else { Flow::Continue };
+        if let Some(errors) = &mut self.errors {
+            errors.push(e);
+            return flow;
+        }
+
+        // Collapse bursts of the same failure (loops, history replay)
+        match self.error_repeats.observe(&format!("{}: {}", e.code(), e.root())) {
+            Repeat::Repeated => {}
+            Repeat::New { previous_repeats } => {
+                report_repeats(previous_repeats);
+                if let Some(location) = &self.location {
+                    eprintln!("{}:", location);
+                }
+                if self.ui.display_error_context(&e).is_err() {
+                    eprintln!("Error: {}", e);
+                }
+            }
+        }
+        flow
+    }
+
+    /// Source the startup files for this invocation: the profile for login
+    /// shells, then `~/.shelltrc` for interactive ones
+    pub fn source_startup_files(&mut self, login: bool, interactive: bool) {
+        let Some(home) = dirs::home_dir() else {
+            return;
+        };
+
+        let mut files = Vec::new();
+        if login {
+            files.push(home.join(PROFILE_FILE));
+        }
+        if interactive {
+            files.push(home.join(RC_FILE));
+        }
+
+        for path in files.into_iter().filter(|path| path.is_file()) {
+            tracing::debug!(path = %path.display(), "sourcing startup file");
+            if let Err(e) = self.run_script(&path) {
+                eprintln!("shell-t: {}: {}", path.display(), e);
+            }
+        }
+        // Startup files don't count towards the session's status
+        self.state.set_last_status(0);
+    }
+
+    /// Run scripts under `debugger`
+    pub fn attach_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Keep errors for `take_errors` rather than displaying them
+    pub fn collect_errors(&mut self) {
+        self.errors.get_or_insert_with(Vec::new);
+    }
+
+    /// Errors collected since the last call, oldest first
+    pub fn take_errors(&mut self) -> Vec<ShellError> {
+        self.errors.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Set the script name (`$0`) and arguments (`$1..$N`) seen by commands
+    pub fn set_positional(&self, name: &str, args: Vec<String>) {
+        self.state.set_positional(name, args);
+    }
+
+    /// Stop servers the session keeps in the background, such as persistent
+    /// interpreters; called before the shell exits
+    pub fn shutdown(&self) {
+        self.executor.shutdown();
+    }
+
+    /// Run a single command string (`-c`), returning its status
+    pub fn run_command(&mut self, input: &str) -> i32 {
+        let input = input.trim();
+        if !input.is_empty() {
+            self.run_line(input);
+        }
+        report_repeats(self.error_repeats.flush());
+
+        self.state.last_status()
+    }
+
+    /// Run a script file statement by statement, returning the last command's status
+    pub fn run_script(&mut self, path: &Path) -> ShellResult<i32> {
+        let statements = parse_cache::load(path, &self.config.parse_cache)?;
+        let name = path.display().to_string();
+        for statement in &statements {
+            if self.run_statement(&name, statement) != Flow::Continue {
+                break;
+            }
+        }
+        report_repeats(self.error_repeats.flush());
+
+        Ok(self.state.last_status())
+    }
+
+    /// Serve the kernel protocol on stdin and stdout until `shutdown` or EOF
+    pub fn run_kernel(&mut self) -> i32 {
+        match kernel::serve(&self.config, &self.executor, &self.state, io::stdin().lock(), io::stdout().lock()) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("shell-t: kernel: {}", e);
+                1
+            }
+        }
+    }
+
+    /// Run commands piped on stdin without prompting, until EOF or `exit`
+    pub fn run_batch(&mut self) -> ShellResult<i32> {
+        self.run_source("<stdin>", io::stdin().lock())
+    }
+
+    /// Run lines from `reader` non-interactively as they arrive; `name` labels error reports
+    fn run_source(&mut self, name: &str, reader: impl BufRead) -> ShellResult<i32> {
+        let mut splitter = Splitter::new();
+        let mut flow = Flow::Continue;
+        for (index, line) in reader.lines().enumerate() {
+            if let Some(statement) = splitter.push(index, &line?) {
+                flow = self.run_statement(name, &statement);
+                if flow != Flow::Continue {
+                    break;
+                }
+            }
+        }
+        if flow == Flow::Continue {
+            // Reports the unterminated compound command
+            if let Some(statement) = splitter.finish() {
+                self.run_chunk(name, &statement);
+            }
+        }
+        report_repeats(self.error_repeats.flush());
+
+        Ok(self.state.last_status())
+    }
+
+    /// Run one statement of a script, first handing control to the debugger if
+    /// it wants to stop there
+    fn run_statement(&mut self, name: &str, statement: &Statement) -> Flow {
+        let Some(mut debugger) = self.debugger.take() else {
+            return self.run_chunk(name, statement);
+        };
+        let (line, text) = (statement.line, statement.text.as_str());
+        let location = format!("{}:{}", name, line);
+        let flow = loop {
+            let action = if debugger.should_stop(line, text) {
+                debugger.prompt(&self.ui, &location, line, text, &self.state)
+            } else {
+                Action::Run
+            };
+            match action {
+                Action::Run => {
+                    debugger.ran(line, text);
+                    break self.run_chunk(name, statement);
+                }
+                Action::Skip => break Flow::Continue,
+                Action::Rerun(previous_line, previous) => {
+                    self.run_chunk(name, &Statement::new(previous_line, previous));
+                }
+                Action::Quit => break Flow::Exit,
+            }
+        };
+        self.debugger = Some(debugger);
+        flow
+    }
+
+    /// Run one complete statement of a script, labelling errors with its first line
+    fn run_chunk(&mut self, name: &str, statement: &Statement) -> Flow {
+        self.location = Some(format!("{}:{}", name, statement.line));
+        let flow = match &statement.nodes {
+            Some(nodes) => {
+                signals::take_interrupt();
+                self.run_nodes(nodes, false)
+            }
+            None => self.run_line(statement.text.trim_end()),
+        };
+        self.location = None;
+        flow
+    }
+
+    /// Show the prompt, led by the job count, the last command's status if
+    /// it failed, active project environments and Lua segments; returns its
+    /// width
+    fn display_prompt(&self) -> ShellResult<usize> {
+        let mut segments = Vec::new();
+        let active = self.jobs.active();
+        if active > 0 {
+            segments.push(format!("[{}]", active));
+        }
+        let status = self.state.last_status();
+        if status != 0 && self.config.ui.show_exit_status {
+            segments.push(format!("[exit {}]", status));
+        }
+        if self.config.interpreters.detect_project_envs {
+            if let Ok(dir) = std::env::current_dir() {
+                segments.extend(project::prompt_labels(&dir, &self.config.interpreters.trusted_projects));
+            }
+        }
+        if let Some(container) = self.state.container() {
+            segments.push(format!("[{}]", container));
+        }
+        #[cfg(feature = "lua")]
+        if let Some(lua) = &self.lua {
+            segments.extend(lua.prompt_segments());
+        }
+        self.ui.display_prompt_with_segments(&segments)
+    }
+
+    /// Read and run lines from stdin until EOF or `exit`
+    pub fn repl(&mut self) -> i32 {
+        let mut consecutive_failures = 0;
+        self.jobs.enable_job_control();
+        if io::stdin().is_terminal() && io::stdout().is_terminal() {
+            let completer = self.config.ui.enable_completion.then(|| Arc::clone(&self.completer));
+            self.editor = Some(LineEditor::new(Arc::clone(&self.history), completer));
+        }
+
+        loop {
+            for job in self.jobs.take_finished() {
+                match job.buffered {
+                    0 => eprintln!("{}", job.line(false)),
+                    _ => eprintln!("{}  (output: jobs -o %{})", job.line(false), job.id),
+                }
+            }
+            if self.tabs.is_some() {
+                // Programs may have scrolled, cleared or resized the screen
+                if let Err(e) = self.draw_tabs(false) {
+                    tracing::debug!(error = %e, "failed to draw tabs");
+                }
+            }
+            if let Some(editor) = &mut self.editor {
+                editor.set_recall(self.tabs.as_ref().map(|tabs| tabs.pane().recall.clone()));
+            }
+            let mode = self.state.language_mode();
+            // Display prompt using UI manager; a failed render is not worth dying over
+            let prompt = match mode {
+                Some(language) => self.ui.display_language_prompt(language.name(), false),
+                None => self.display_prompt(),
+            };
+            let width = match prompt {
+                Ok(width) => width,
+                Err(e) if e.is_fatal() => {
+                    eprintln!("UI error: {}", e);
+                    break;
+                }
+                Err(_) => 0,
+            };
+
+            let mut input = match self.read_input(width, mode.is_none()) {
+                Ok(None) if mode.is_some() => {
+                    // Ctrl-D leaves the sub-REPL, not the shell
+                    println!();
+                    self.state.set_language_mode(None);
+                    continue;
+                }
+                Ok(None) => {
+                    // EOF (Ctrl-D)
+                    println!();
+                    break;
+                }
+                Ok(Some(input)) => {
+                    consecutive_failures = 0;
+                    input
+                }
+                Err(e) => {
+                    let e = error::ShellError::from(e);
+                    consecutive_failures += 1;
+                    if e.is_fatal() || consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+                        eprintln!("Input error: {}", e);
+                        break;
+                    }
+                    continue;
+                }
+            };
+            if let Some(language) = mode {
+                self.run_language_input(language, input);
+                continue;
+            }
+            if input.trim().is_empty() {
+                continue;
+            }
+
+            // Keep reading while a compound command is open
+            while is_incomplete(&input) {
+                let Ok(width) = self.ui.display_continuation_prompt() else {
+                    break;
+                };
+                match self.read_input(width, true) {
+                    Ok(Some(more)) => input.push_str(&more),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            let input = match self.expand_history(input) {
+                Some(input) => input,
+                None => continue,
+            };
+            if let Some(tabs) = &mut self.tabs {
+                tabs.pane_mut().recall.typed.push(input.trim_end().to_string());
+            }
+            let started = SystemTime::now();
+            let cwd = std::env::current_dir().ok();
+            let flow = self.run_line(input.trim());
+            self.history.add(Entry {
+                command: input.trim_end().to_string(),
+                time: started.duration_since(UNIX_EPOCH).ok().map(|since| since.as_secs() as i64),
+                duration_ms: started.elapsed().ok().map(|elapsed| elapsed.as_millis() as u64),
+                cwd,
+                status: Some(self.state.last_status()),
+            });
+            match flow {
+                Flow::Exit => {
+                    println!("Goodbye!");
+                    break;
+                }
+                Flow::Fatal => break,
+                _ => {}
+            }
+        }
+        report_repeats(self.error_repeats.flush());
+        if self.tabs.take().is_some() {
+            let _ = self.ui.leave_tabs();
+        }
+
+        self.state.last_status()
+    }
+
+    /// Read a line typed after a prompt `prompt_width` columns wide, with
+    /// line editing when `editing` and on a terminal. `Ok(None)` is end of input.
+    fn read_input(&mut self, prompt_width: usize, editing: bool) -> io::Result<Option<String>> {
+        if let (true, Some(editor)) = (editing, &mut self.editor) {
+            let initial = self.state.take_next_input().unwrap_or_default();
+            // Completion functions registered with `shell.complete`
+            #[cfg(feature = "lua")]
+            let lua = |command: &str, word: &str, args: &[String]| match self.lua.as_ref()?.complete(command, word, args) {
+                Ok(found) => found,
+                Err(e) => {
+                    tracing::warn!(error = %e, "completion function failed");
+                    None
+                }
+            };
+            #[cfg(feature = "lua")]
+            let function: Option<crate::completions::Function> = Some(&lua);
+            #[cfg(not(feature = "lua"))]
+            let function = None;
+            return Ok(editor.read_line(prompt_width, &initial, function)?.map(|line| line + "\n"));
+        }
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Ok(None);
+        }
+        trim_cr(&mut input);
+        Ok(Some(input))
+    }
+
+    /// Apply history expansion to a typed line, echoing the result. Returns
+    /// `None` when the line should not run: the expansion failed or, with
+    /// `history.verify_expansion`, was not confirmed.
+    fn expand_history(&mut self, input: String) -> Option<String> {
+        let config = &self.config.history;
+        let wanted = input.contains('!') || input.starts_with('^');
+        if !config.expansion || !wanted || inline::parse(&input).is_some() {
+            return Some(input);
+        }
+        let expanded = match history::expand(input.trim_end(), &self.history.entries()) {
+            Ok(Some(expanded)) => expanded,
+            Ok(None) => return Some(input),
+            Err(e) => {
+                eprintln!("shell-t: {}", e);
+                self.state.set_last_status(1);
+                return None;
+            }
+        };
+        println!("{}", expanded);
+        if config.verify_expansion {
+            print!("Run this? [y/N] ");
+            let _ = io::stdout().flush();
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer).is_err() || !matches!(answer.trim(), "y" | "Y" | "yes") {
+                return None;
+            }
+        }
+        Some(expanded)
+    }
+
+    /// Run a line typed in a language sub-REPL. A line ending in `:` opens a
+    /// block that continues until a blank line, as in Python's own REPL.
+    fn run_language_input(&mut self, language: Language, mut input: String) {
+        match input.trim() {
+            "" => return,
+            "exit" | "exit()" | "quit()" => {
+                self.state.set_language_mode(None);
+                return;
+            }
+            _ => {}
+        }
+
+        if input.trim_end().ends_with(':') {
+            loop {
+                if self.ui.display_language_prompt(language.name(), true).is_err() {
+                    break;
+                }
+                let mut more = String::new();
+                match io::stdin().read_line(&mut more) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) if more.trim().is_empty() => break,
+                    Ok(_) => {
+                        trim_cr(&mut more);
+                        input.push_str(&more);
+                    }
+                }
+            }
+        }
+
+        let vars = self.state.vars().into_iter().collect();
+        let result = self.executor.execute_repl(language, &input, vars);
+        self.finish(result, input.trim());
+    }
+}
+
+/// Turn a CRLF line ending, as Windows consoles and files send, into `\n`
+fn trim_cr(line: &mut String) {
+    if line.ends_with("\r\n") {
+        line.truncate(line.len() - 2);
+        line.push('\n');
+    }
+}
+
+/// Start the Lua runtime and load scripts from `<config dir>/lua`
+#[cfg(feature = "lua")]
+fn load_lua(config: &Config) -> Option<LuaRuntime> {
+    if !config.lua.enabled {
+        return None;
+    }
+    let result = LuaRuntime::new(&config.lua).and_then(|lua| {
+        if let Some(dir) = crate::config::config_dir() {
+            lua.load_dir(&dir.join("lua"))?;
+        }
+        Ok(lua)
+    });
+    match result {
+        Ok(lua) => Some(lua),
+        Err(e) => {
+            warnings::warn(WarningLevel::Warning, WarningCategory::Config, &e.to_string());
+            None
+        }
+    }
+}
+
+/// Compile the WASM plugins in `<config dir>/plugins`
+#[cfg(feature = "wasm")]
+fn load_plugins(config: &Config) -> Option<PluginManager> {
+    if !config.plugins.enabled {
+        return None;
+    }
+    let result = PluginManager::new(&config.plugins).and_then(|mut plugins| {
+        if let Some(dir) = crate::config::config_dir() {
+            plugins.load_dir(&dir.join("plugins"))?;
+        }
+        Ok(plugins)
+    });
+    match result {
+        Ok(plugins) => Some(plugins),
+        Err(e) => {
+            warnings::warn(WarningLevel::Warning, WarningCategory::Config, &e.to_string());
+            None
+        }
+    }
+}
+
+/// Print the summary for a run of suppressed identical errors
+fn report_repeats(repeats: usize) {
+    if repeats > 0 {
+        eprintln!("last error repeated {} more time{}", repeats, if repeats == 1 { "" } else { "s" });
+    }
+}
+
+/// Run parsed commands, returning the exit status of the pipeline. `exit`
+/// only gives its status here; use `run_commands` to tell it apart.
+pub fn execute_commands(
+    commands: &[Command],
+    builtin_manager: &BuiltinManager,
+    executor: &CommandExecutor,
+) -> ShellResult<i32> {
+    run_commands(commands, builtin_manager, executor).map(Outcome::status)
+}
+
+/// Run parsed commands, returning how the pipeline ended
+pub fn run_commands(
+    commands: &[Command],
+    builtin_manager: &BuiltinManager,
+    executor: &CommandExecutor,
+) -> ShellResult<Outcome> {
+    if commands.is_empty() {
+        return Ok(Outcome::Status(0));
+    }
+
+    // Handle single command (no pipeline)
+    if commands.len() == 1 {
+        let cmd = &commands[0];
+        if cmd.program.is_empty() {
+            return Ok(Outcome::Status(0));
+        }
+
+        // A builtin writes to its `>` file itself
+        if BuiltinCommand::is_builtin(&cmd.program) {
+            if let Some(mut file) = executor.open_output(cmd)? {
+                let status = builtin_manager.execute_builtin_to(&cmd.program, &cmd.args, &mut file)?.unwrap_or(0);
+                return Ok(Outcome::Status(executor.finish_pipeline(vec![status])));
+            }
+        }
+
+        // Try builtin commands first
+        if let Some(result) = builtin_manager.execute_builtin(&cmd.program, &cmd.args)? {
+            let status = match result {
+                BuiltinResult::Success(msg) => {
+                    if let Some(msg) = msg {
+                        println!("{}", msg);
+                    }
+                    0
+                }
+                BuiltinResult::Error(msg) => {
+                    eprintln!("{}", msg);
+                    1
+                }
+                BuiltinResult::Info(msg) => {
+                    println!("{}", msg);
+                    0
+                }
+                BuiltinResult::Warning(msg) => {
+                    warnings::warn(WarningLevel::Warning, WarningCategory::Builtin, &msg);
+                    0
+                }
+                BuiltinResult::Status(status) => status,
+                BuiltinResult::Exit(status) => return Ok(Outcome::Exit(status)),
+            };
+            return Ok(Outcome::Status(executor.finish_pipeline(vec![status])));
+        }
+    }
+
+    if !commands.iter().any(|cmd| BuiltinCommand::is_builtin(&cmd.program)) {
+        let statuses = executor.execute_pipeline(commands)?;
+        return Ok(Outcome::Status(executor.finish_pipeline(statuses)));
+    }
+
+    // Builtins in a pipeline run in the shell, in turn with the programs
+    // between them: what one prints is the next stage's input, and what it is
+    // given is ignored
+    let mut statuses = Vec::with_capacity(commands.len());
+    let mut input = None;
+    let mut start = 0;
+    for (i, cmd) in commands.iter().enumerate() {
+        if !BuiltinCommand::is_builtin(&cmd.program) {
+            continue;
+        }
+        if start < i {
+            let mut stages = commands[start..i].to_vec();
+            feed(&mut stages[0], input.take());
+            let last = stages.len() - 1;
+            stages[last].output_redirect.get_or_insert_with(|| NULL_DEVICE.to_string());
+            statuses.extend(executor.execute_pipeline(&stages)?);
+        }
+        let mut output = Vec::new();
+        let status = match executor.open_output(cmd)? {
+            Some(mut file) => builtin_manager.execute_builtin_to(&cmd.program, &cmd.args, &mut file)?,
+            None if i == commands.len() - 1 => builtin_manager.execute_builtin_to(&cmd.program, &cmd.args, &mut io::stdout())?,
+            None => builtin_manager.execute_builtin_to(&cmd.program, &cmd.args, &mut output)?,
+        };
+        statuses.push(status.unwrap_or(0));
+        input = Some(String::from_utf8_lossy(&output).into_owned());
+        start = i + 1;
+    }
+    if start < commands.len() {
+        let mut stages = commands[start..].to_vec();
+        feed(&mut stages[0], input);
+        statuses.extend(executor.execute_pipeline(&stages)?);
+    }
+    Ok(Outcome::Status(executor.finish_pipeline(statuses)))
+}
+
+/// Where the output of programs feeding a builtin goes
+#[cfg(unix)]
+const NULL_DEVICE: &str = "/dev/null";
+#[cfg(not(unix))]
+const NULL_DEVICE: &str = "NUL";
+
+/// Give `cmd` what the builtin before it printed as its input, unless it
+/// takes its input from elsewhere
+fn feed(cmd: &mut Command, text: Option<String>) {
+    if cmd.input_redirect.is_none() && cmd.here_doc.is_none() {
+        cmd.here_doc = text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Default config without the command whitelist, so `true`/`false` can run
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.security.allowed_commands.clear();
+        config.parse_cache.enabled = false;
+        config
+    }
+
+    fn script(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("shell-t-{}-{}.sht", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_run_script_returns_last_status() {
+        let path = script("status", "#!/usr/bin/env shell-t\n# comment\n\nfalse\ntrue\n");
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_script(&path).unwrap(), 0);
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_run_script_errexit_stops_at_failure() {
+        let marker = std::env::temp_dir().join(format!("shell-t-errexit-{}.txt", std::process::id()));
+        let path = script(
+            "errexit",
+            &format!("set -e\nfalse\necho reached > {}\n", marker.display()),
+        );
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_script(&path).unwrap(), 1);
+        assert!(!marker.exists());
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_run_command_returns_pipeline_status() {
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_command("echo hi | wc -l"), 0);
+        assert_eq!(session.run_command("true | false"), 1);
+        assert_eq!(session.run_command("ls |"), 2);
+    }
+
+    #[test]
+    fn test_run_command_substitutes_output() {
+        let out = std::env::temp_dir().join(format!("shell-t-subst-{}.txt", std::process::id()));
+        let mut session = Session::new(test_config());
+        let line = format!("echo $(echo a   b | wc -w) \"$(printf 'x  y\\n\\n')\" > {}", out.display());
+        assert_eq!(session.run_command(&line), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "2 x  y\n");
+        assert_eq!(session.run_command("echo $(false)"), 0);
+        assert_eq!(session.run_command("echo $(echo"), 2);
+        // Lists run their pipelines in turn instead of passing operators on as words
+        let line = format!("echo $(echo a && echo b; false || echo c; false && echo d) > {}", out.display());
+        assert_eq!(session.run_command(&line), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "a b c\n");
+        assert_eq!(session.run_command("echo $(if true; then echo x; fi)"), 2);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "a b c\n");
+
+        let mut config = test_config();
+        config.security.command_substitution = false;
+        let mut session = Session::new(config);
+        assert_ne!(session.run_command(&format!("echo $(echo no) > {}", out.display())), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "a b c\n");
+
+        // `exit` leaves only the substitution, and an assignment-only line
+        // ends with the status of its last one
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_command(&format!("x=$(exit 6); echo $? > {}", out.display())), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "6\n");
+        assert_eq!(session.run_command("x=$(false)"), 1);
+        assert_eq!(session.run_command("false; x=1"), 0);
+        let _ = fs::remove_file(out);
+    }
+
+    #[test]
+    fn test_run_command_noclobber_keeps_files() {
+        let out = std::env::temp_dir().join(format!("shell-t-noclobber-{}.txt", std::process::id()));
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_command(&format!("echo one > {}", out.display())), 0);
+        assert_eq!(session.run_command("set -C"), 0);
+        assert_ne!(session.run_command(&format!("echo two > {}", out.display())), 0);
+        assert_eq!(session.run_command("echo two > /dev/null"), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "one\n");
+
+        assert_eq!(session.run_command(&format!("echo three >| {}", out.display())), 0);
+        assert_eq!(session.run_command(&format!("echo four >> {}", out.display())), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "three\nfour\n");
+        let _ = fs::remove_file(out);
+    }
+
+    #[test]
+    fn test_run_command_subshell_changes_stay_inside() {
+        let mut session = Session::new(test_config());
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(session.run_command("(cd /tmp; export SHELL_T_SUBSHELL=1; x=2; set -e; exit 3)"), 3);
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+        assert!(std::env::var_os("SHELL_T_SUBSHELL").is_none());
+        assert_eq!(session.state.var("x"), None);
+        assert!(!session.state.options().errexit);
+        assert_eq!(session.run_command("(false) || (true && false)"), 1);
+    }
+
+    #[test]
+    fn test_run_source_stops_at_exit() {
+        let mut session = Session::new(test_config());
+        let input = io::Cursor::new("false\nexit\ntrue\n");
+        assert_eq!(session.run_source("<test>", input).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_run_script_exit_status() {
+        let marker = std::env::temp_dir().join(format!("shell-t-exit-{}.txt", std::process::id()));
+        let path = script("exit", &format!("true\nexit 3\necho reached > {}\n", marker.display()));
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_script(&path).unwrap(), 3);
+        assert!(!marker.exists());
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_run_command_exit_status() {
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_command("exit 300"), 44);
+        assert_eq!(session.run_command("false; exit"), 1);
+        assert_eq!(session.run_command("exit nope"), 2);
+        assert_eq!(session.run_command("if true; then exit 5; fi"), 5);
+    }
+
+    #[test]
+    fn test_run_script_positional_params() {
+        let out = std::env::temp_dir().join(format!("shell-t-params-{}.txt", std::process::id()));
+        let path = script(
+            "params",
+            &format!("echo $# $1 > {0}\nshift\necho \"$@\" >> {0}\nshift 5\n", out.display()),
+        );
+        let mut session = Session::new(test_config());
+        session.set_positional(&path.display().to_string(), vec!["release".to_string(), "--verbose".to_string()]);
+        assert_eq!(session.run_script(&path).unwrap(), 1);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "2 release\n--verbose\n");
+
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(out);
+    }
+
+    #[test]
+    fn test_debugger_breakpoint_skip_and_rerun() {
+        let out = std::env::temp_dir().join(format!("shell-t-debug-{}.txt", std::process::id()));
+        let path = script(
+            "debug",
+            &format!("echo a > {0}\necho b >> {0}\necho c >> {0}\necho d >> {0}\n", out.display()),
+        );
+        let mut session = Session::new(test_config());
+        // Stops at line 1, continues to the breakpoint on 3 and skips it, then
+        // at line 4 reruns line 2 before continuing
+        let commands = "b 3\nc\nskip\nr\nc\n";
+        session.attach_debugger(Debugger::new(Box::new(io::Cursor::new(commands))));
+        assert_eq!(session.run_script(&path).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "a\nb\nb\nd\n");
+
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(out);
+    }
+
+    #[test]
+    fn test_source_runs_in_session() {
+        let path = script("sourced", "shell_t_sourced=yes\n");
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_command(&format!("source {}", path.display())), 0);
+        assert_eq!(session.state.var("shell_t_sourced").as_deref(), Some("yes"));
+        // Remote sources need a pin
+        assert_eq!(session.run_command("source https://example.com/setup.sht"), 126);
+        assert_eq!(session.run_command("source https://example.com/setup.sht md5=abc"), 2);
+
+        // Its arguments are its own, and the session's come back after
+        let _ = fs::remove_file(path);
+        let path = script("sourced-args", "shell_t_sourced=\"$#:$2\"\nset -- x\n");
+        assert_eq!(session.run_command("set -- a 'b c'"), 0);
+        assert_eq!(session.run_command(&format!("source {} one 'two words'", path.display())), 0);
+        assert_eq!(session.state.var("shell_t_sourced").as_deref(), Some("2:two words"));
+        assert_eq!(session.state.params(), ["a", "b c"]);
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_run_script_if_spans_lines() {
+        let marker = std::env::temp_dir().join(format!("shell-t-if-{}.txt", std::process::id()));
+        let path = script(
+            "if",
+            &format!(
+                "set -e\nif test -d /nonexistent\nthen\n  echo wrong > {0}\nelif false; then echo wrong > {0}\nelse\n  echo right > {0}\nfi\n",
+                marker.display()
+            ),
+        );
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_script(&path).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&marker).unwrap().trim(), "right");
+
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(marker);
+    }
+
+    #[test]
+    fn test_exit_builtin_returns_instead_of_exiting() {
+        let session = Session::new(test_config());
+        let commands = parser::parse_command("exit 3").unwrap();
+        let outcome = run_commands(&commands, &session.builtins, &session.executor).unwrap();
+        assert_eq!(outcome, Outcome::Exit(3));
+        assert_eq!(execute_commands(&commands, &session.builtins, &session.executor).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_run_command_unterminated_if() {
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_command("if true; then echo a"), 2);
+    }
+
+    #[test]
+    fn test_for_loop_with_break_and_continue() {
+        let out = std::env::temp_dir().join(format!("shell-t-for-{}.txt", std::process::id()));
+        let mut session = Session::new(test_config());
+        session.run_command(&format!(
+            "for x in a b c d; do if test $x = b; then continue; fi; if test $x = d; then break; fi; echo $x >> {}; done",
+            out.display()
+        ));
+        assert_eq!(fs::read_to_string(&out).unwrap(), "a\nc\n");
+        assert_eq!(session.state().var("x").as_deref(), Some("d"));
+
+        let _ = fs::remove_file(out);
+    }
+
+    #[test]
+    fn test_while_loop_iteration_limit() {
+        let mut config = test_config();
+        config.limits.max_loop_iterations = 3;
+        let mut session = Session::new(config);
+        assert_eq!(session.run_command("while true; do true; done"), 126);
+        assert_eq!(session.run_command("until true; do false; done"), 0);
+    }
+
+    #[test]
+    fn test_variables_assign_expand_and_export() {
+        let mut session = Session::new(test_config());
+        session.run_command("shell_t_test_x=5");
+        session.run_command("shell_t_test_y=${shell_t_test_x}0");
+        assert_eq!(session.state().var("shell_t_test_y").as_deref(), Some("50"));
+        assert!(std::env::var("shell_t_test_y").is_err());
+
+        session.run_command("export shell_t_test_y");
+        assert!(session.state().var("shell_t_test_y").is_none());
+        assert_eq!(std::env::var("shell_t_test_y").as_deref(), Ok("50"));
+
+        session.run_command("unset shell_t_test_x shell_t_test_y");
+        assert!(session.state().var("shell_t_test_x").is_none());
+        assert!(std::env::var("shell_t_test_y").is_err());
+    }
+
+    #[test]
+    fn test_assignment_values_are_not_split() {
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_command("y=\"c  d\"; z=$y"), 0);
+        assert_eq!(session.state().var("z").as_deref(), Some("c  d"));
+        assert_eq!(session.run_command("w=$z$y"), 0);
+        assert_eq!(session.state().var("w").as_deref(), Some("c  dc  d"));
+        // Nor is the output of a substitution in one
+        assert_eq!(session.run_command("x=$(echo a b)"), 0);
+        assert_eq!(session.state().var("x").as_deref(), Some("a b"));
+    }
+
+    #[test]
+    fn test_inline_block_honors_whitelist() {
+        let mut config = test_config();
+        config.security.allowed_commands.insert("echo".to_string());
+        let mut session = Session::new(config);
+        assert_eq!(session.run_command("py> print(1)"), 126);
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let mut session = Session::new(test_config());
+        assert_eq!(session.run_line("break"), Flow::Continue);
+    }
+
+    #[test]
+    fn test_trim_cr() {
+        let mut line = "ls -l\r\n".to_string();
+        trim_cr(&mut line);
+        assert_eq!(line, "ls -l\n");
+        let mut line = "echo a\rb\n".to_string();
+        trim_cr(&mut line);
+        assert_eq!(line, "echo a\rb\n");
+    }
+
+    #[test]
+    fn test_run_script_missing_file() {
+        let mut session = Session::new(Config::default());
+        assert!(session.run_script(Path::new("/nonexistent/deploy.sht")).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use crate::config::Config;
+use crate::error::ShellResult;
+use crate::session::Session;
+
+/// A shell-t instance embedded in another program: lines run through the same
+/// parser, expansion, security policy and builtins as the interactive shell,
+/// with shell variables and the working directory kept between them.
+///
+/// Errors are returned rather than printed; commands' own output still goes
+/// to the process's stdout and stderr.
+pub struct Shell {
+    session: Session,
+}
+
+impl Shell {
+    /// Create a shell enforcing `config`, which should already have passed
+    /// `Config::validate`
+    pub fn new(config: Config) -> Self {
+        let mut session = Session::new(config);
+        session.collect_errors();
+        Self { session }
+    }
+
+    /// Run a line, or a complete multi-line compound command, returning its
+    /// exit status. A command the policy refuses or that fails to start is an
+    /// error; one that runs and fails is a non-zero status.
+    pub fn eval(&mut self, line: &str) -> ShellResult<i32> {
+        let line = line.trim();
+        if !line.is_empty() {
+            self.session.run_line(line);
+        }
+        match self.session.take_errors().into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(self.session.state().last_status()),
+        }
+    }
+
+    /// Value of a shell variable
+    pub fn var(&self, name: &str) -> Option<String> {
+        self.session.state().var(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_runs_lines_under_the_policy() {
+        let mut config = Config::default();
+        config.security.allowed_commands.insert("echo".to_string());
+        let mut shell = Shell::new(config);
+        assert_eq!(shell.eval("FRUIT=apple").unwrap(), 0);
+        assert_eq!(shell.var("FRUIT").as_deref(), Some("apple"));
+        assert_eq!(shell.eval("grep -q needle /dev/null").unwrap(), 1);
+        assert!(shell.eval("definitely-not-a-command-xyz").is_err());
+        assert!(shell.eval("rm -rf /").is_err());
+        assert_eq!(shell.eval("echo $FRUIT > /dev/null").unwrap(), 0);
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+/// Set by job-control signals the shell catches only so they don't stop it
+static IGNORED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+fn flag() -> &'static Arc<AtomicBool> {
+    INTERRUPTED.get_or_init(|| Arc::new(AtomicBool::new(false)))
+}
+
+/// Catch SIGINT so Ctrl-C stops the running command or loop, not the shell
+pub fn init() {
+    #[cfg(unix)]
+    if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(flag())) {
+        tracing::warn!(error = %e, "failed to install SIGINT handler");
+    }
+}
+
+/// Whether Ctrl-C was pressed since the last call, clearing the flag
+pub fn take_interrupt() -> bool {
+    flag().swap(false, Ordering::SeqCst)
+}
+
+/// Keep the interactive shell from being stopped by Ctrl-Z (SIGTSTP) or by
+/// using the terminal while a job owns it (SIGTTIN, SIGTTOU). The signals are
+/// caught rather than ignored, so programs the shell starts get the default
+/// behaviour back when they exec.
+pub fn catch_job_control() {
+    #[cfg(unix)]
+    for signal in [signal_hook::consts::SIGTSTP, signal_hook::consts::SIGTTIN, signal_hook::consts::SIGTTOU] {
+        let flag = IGNORED.get_or_init(|| Arc::new(AtomicBool::new(false)));
+        if let Err(e) = signal_hook::flag::register(signal, Arc::clone(flag)) {
+            tracing::warn!(error = %e, signal, "failed to install job control signal handler");
+        }
+    }
+}
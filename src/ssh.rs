@@ -0,0 +1,122 @@
+use std::process::Command;
+
+use crate::completions::wildcard_match;
+use crate::config::{validation, Config, SshConfig};
+use crate::error::{SecurityError, ShellError, ShellResult};
+use crate::security;
+
+/// A command for `ssh-run` to run on another host
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteCommand {
+    /// `host` or `user@host`
+    pub host: String,
+    pub port: Option<u16>,
+    /// Program and arguments, as parsed locally
+    pub words: Vec<String>,
+}
+
+impl RemoteCommand {
+    /// Parse `ssh-run` arguments: `[-p PORT] [USER@]HOST COMMAND [ARGS...]`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let usage = "ssh-run: usage: ssh-run [-p PORT] [USER@]HOST COMMAND [ARGS...]";
+        let mut args = args.iter();
+        let mut port = None;
+        let host = loop {
+            match args.next().map(String::as_str) {
+                Some("-p") => {
+                    let value = args.next().ok_or(usage)?;
+                    port = Some(value.parse().map_err(|_| format!("ssh-run: {}: invalid port", value))?);
+                }
+                Some(host) if !host.starts_with('-') => break host.to_string(),
+                _ => return Err(usage.to_string()),
+            }
+        };
+        let words: Vec<String> = args.cloned().collect();
+        if words.is_empty() {
+            return Err(usage.to_string());
+        }
+        Ok(Self { host, port, words })
+    }
+
+    /// Refuse hosts outside `ssh.allowed_hosts` and commands the local policy
+    /// would refuse, including `ssh` itself. Arguments may not carry shell
+    /// syntax, since the remote side runs them through its shell.
+    pub fn check(&self, config: &Config) -> ShellResult<()> {
+        let name = self.host.rsplit('@').next().unwrap_or_default();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | ':')) {
+            return Err(SecurityError::InvalidInput(format!("Invalid host: {}", self.host)).into());
+        }
+        let allowed = &config.ssh.allowed_hosts;
+        if !allowed.is_empty() && !allowed.iter().any(|pattern| wildcard_match(pattern, &self.host) || wildcard_match(pattern, name)) {
+            return Err(ShellError::SecurityViolation(format!("Host not in ssh.allowed_hosts: {}", self.host)));
+        }
+        validation::validate_command(config, &config.ssh.program)?;
+        validation::validate_command(config, &self.words[0])?;
+        security::validation::validate_arguments(&self.words, config)
+    }
+
+    /// The command line the remote shell runs
+    pub fn remote_line(&self) -> String {
+        self.words.iter().map(|word| quote(word)).collect::<Vec<_>>().join(" ")
+    }
+
+    /// `ssh` invocation running the command, with the terminal passed through
+    pub fn command(&self, config: &SshConfig) -> Command {
+        let mut command = Command::new(&config.program);
+        command.arg("-o").arg(format!("ConnectTimeout={}", config.connect_timeout_secs));
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command.arg("--").arg(&self.host).arg(self.remote_line());
+        command
+    }
+}
+
+/// Single-quote `word` for a POSIX shell unless it is plain
+fn quote(word: &str) -> String {
+    if !word.is_empty() && word.chars().all(|c| c.is_alphanumeric() || "-_./=:,+@%".contains(c)) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_check_and_command() {
+        let remote = RemoteCommand::parse(&args("-p 2222 deploy@web1 ls -l /var/log")).unwrap();
+        assert_eq!(remote.host, "deploy@web1");
+        assert_eq!(remote.port, Some(2222));
+        assert_eq!(remote.words, args("ls -l /var/log"));
+        assert!(RemoteCommand::parse(&args("web1")).is_err());
+        assert!(RemoteCommand::parse(&args("-p x web1 ls")).is_err());
+        assert!(RemoteCommand::parse(&args("-oProxyCommand=x web1 ls")).is_err());
+
+        let mut config = Config::default();
+        assert!(remote.check(&config).is_err(), "ssh itself is not whitelisted");
+        config.security.allowed_commands.insert("ssh".to_string());
+        assert!(remote.check(&config).is_ok());
+        config.ssh.allowed_hosts = vec!["db*".to_string()];
+        assert!(remote.check(&config).is_err());
+        config.ssh.allowed_hosts = vec!["web*".to_string()];
+        assert!(remote.check(&config).is_ok());
+        config.security.blocked_commands.insert("ls".to_string());
+        assert!(remote.check(&config).is_err());
+        config.security.blocked_commands.clear();
+        let injected = RemoteCommand { words: args("ls ;rm"), ..remote.clone() };
+        assert!(injected.check(&config).is_err());
+
+        let spaced = RemoteCommand { words: vec!["echo".to_string(), "it's here".to_string()], ..remote.clone() };
+        assert_eq!(spaced.remote_line(), "echo 'it'\\''s here'");
+        let command = remote.command(&config.ssh);
+        let argv: Vec<_> = command.get_args().map(|arg| arg.to_string_lossy().to_string()).collect();
+        assert_eq!(argv, ["-o", "ConnectTimeout=10", "-p", "2222", "--", "deploy@web1", "ls -l /var/log"]);
+    }
+}
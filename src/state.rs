@@ -0,0 +1,459 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use crate::container::Container;
+use crate::envdiff::Snapshot;
+use crate::inline::Language;
+use crate::parser::{self, Command};
+use crate::path_index::PathCache;
+use crate::script;
+
+/// Options toggled with the `set` builtin
+#[derive(Debug, Clone, Default)]
+pub struct ShellOptions {
+    /// `set -e`: stop a script at the first failing command
+    pub errexit: bool,
+    /// `set -o jsonpipes`: script stages in a pipeline exchange JSON Lines records
+    pub jsonpipes: bool,
+    /// `set -f`: wildcards are passed on without filename expansion
+    pub noglob: bool,
+    /// `set -o nullglob`: a pattern matching nothing expands to nothing
+    pub nullglob: bool,
+    /// `set -o failglob`: a pattern matching nothing fails the command
+    pub failglob: bool,
+    /// `set -o dotglob`: wildcards match names starting with `.` too
+    pub dotglob: bool,
+    /// `set -C`: `>` refuses to replace an existing file; `>|` still does
+    pub noclobber: bool,
+    /// `set -o nosplit`: unquoted `$VAR` and `$(...)` stay one word, as in zsh
+    pub nosplit: bool,
+    /// `set -o pipefail`: a pipeline fails with its last failing command's status
+    pub pipefail: bool,
+}
+
+/// Names accepted by `set -o NAME`, with their short flag if any
+const OPTION_NAMES: [(&str, Option<char>); 9] = [
+    ("dotglob", None),
+    ("errexit", Some('e')),
+    ("failglob", None),
+    ("jsonpipes", None),
+    ("noclobber", Some('C')),
+    ("noglob", Some('f')),
+    ("nosplit", None),
+    ("nullglob", None),
+    ("pipefail", None),
+];
+
+impl ShellOptions {
+    /// Set an option by long name (`errexit`) or short flag (`e`)
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        match name {
+            "errexit" | "e" => self.errexit = value,
+            "jsonpipes" => self.jsonpipes = value,
+            "noglob" | "f" => self.noglob = value,
+            "nullglob" => self.nullglob = value,
+            "failglob" => self.failglob = value,
+            "dotglob" => self.dotglob = value,
+            "noclobber" | "C" => self.noclobber = value,
+            "nosplit" => self.nosplit = value,
+            "pipefail" => self.pipefail = value,
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+        Ok(())
+    }
+
+    /// Current value of an option by long name
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "errexit" => Some(self.errexit),
+            "jsonpipes" => Some(self.jsonpipes),
+            "noglob" => Some(self.noglob),
+            "nullglob" => Some(self.nullglob),
+            "failglob" => Some(self.failglob),
+            "dotglob" => Some(self.dotglob),
+            "noclobber" => Some(self.noclobber),
+            "nosplit" => Some(self.nosplit),
+            "pipefail" => Some(self.pipefail),
+            _ => None,
+        }
+    }
+
+    /// Every option as `(name, enabled)`, in listing order
+    pub fn list(&self) -> Vec<(&'static str, bool)> {
+        OPTION_NAMES
+            .iter()
+            .map(|(name, _)| (*name, self.get(name).unwrap_or(false)))
+            .collect()
+    }
+}
+
+/// The parts of the state a subshell may change, put back when it ends
+#[derive(Debug, Clone)]
+pub struct Scope {
+    options: ShellOptions,
+    aliases: BTreeMap<String, String>,
+    variables: BTreeMap<String, String>,
+    positional: (String, Vec<String>),
+    container: Option<Container>,
+}
+
+/// Mutable session state shared between the REPL, builtins and the executor
+#[derive(Debug, Default)]
+pub struct ShellState {
+    options: Mutex<ShellOptions>,
+    aliases: Mutex<BTreeMap<String, String>>,
+    variables: Mutex<BTreeMap<String, String>>,
+    last_status: AtomicI32,
+    /// Process ID of the last command started in the background (`$!`)
+    last_background: AtomicU32,
+    /// Exit status of each command of the last pipeline (`$PIPESTATUS`)
+    pipe_status: Mutex<Vec<i32>>,
+    /// Language whose sub-REPL is active, set by the `lang` builtin
+    language_mode: Mutex<Option<Language>>,
+    /// Script name (`$0`) and arguments (`$1..$N`)
+    positional: Mutex<(String, Vec<String>)>,
+    /// Text to start the next prompt's edit buffer with, as picked by `hist`
+    next_input: Mutex<Option<String>>,
+    /// Container external commands run in, set by the `in` builtin
+    container: Mutex<Option<Container>>,
+    /// State saved by `envdiff snapshot` to compare against
+    env_snapshot: Mutex<Option<Snapshot>>,
+    /// Where commands run so far were found on `PATH`
+    commands: Mutex<PathCache>,
+}
+
+impl ShellState {
+    /// Create fresh session state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Snapshot of the current options
+    pub fn options(&self) -> ShellOptions {
+        self.options.lock().unwrap().clone()
+    }
+
+    /// Modify options in place
+    pub fn update_options<R>(&self, f: impl FnOnce(&mut ShellOptions) -> R) -> R {
+        f(&mut self.options.lock().unwrap())
+    }
+
+    /// Exit status of the most recent command
+    pub fn last_status(&self) -> i32 {
+        self.last_status.load(Ordering::SeqCst)
+    }
+
+    /// Record the exit status of a finished command
+    pub fn set_last_status(&self, status: i32) {
+        self.last_status.store(status, Ordering::SeqCst);
+    }
+
+    /// Exit status of each command of the last pipeline, first to last
+    pub fn pipe_status(&self) -> Vec<i32> {
+        self.pipe_status.lock().unwrap().clone()
+    }
+
+    /// Record the exit statuses of a finished pipeline's commands
+    pub fn set_pipe_status(&self, statuses: Vec<i32>) {
+        *self.pipe_status.lock().unwrap() = statuses;
+    }
+
+    /// The executable `name` runs, found on `PATH` the first time only
+    pub fn find_command(&self, name: &str) -> Option<PathBuf> {
+        let path = env::var("PATH").unwrap_or_default();
+        self.commands.lock().unwrap().resolve(name, &path)
+    }
+
+    /// Forget where commands were found, so the next run searches `PATH`
+    pub fn forget_commands(&self) -> usize {
+        self.commands.lock().unwrap().clear()
+    }
+
+    /// Record the process ID of a pipeline's last command started in the background
+    pub fn set_last_background(&self, pid: u32) {
+        self.last_background.store(pid, Ordering::SeqCst);
+    }
+
+    /// Set a shell variable; already exported variables are updated in place
+    pub fn set_var(&self, name: &str, value: &str) {
+        if env::var_os(name).is_some() {
+            env::set_var(name, value);
+            self.variables.lock().unwrap().remove(name);
+        } else {
+            self.variables.lock().unwrap().insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /// Look up a shell variable
+    pub fn var(&self, name: &str) -> Option<String> {
+        self.variables.lock().unwrap().get(name).cloned()
+    }
+
+    /// Remove a shell variable, returning its value
+    pub fn unset_var(&self, name: &str) -> Option<String> {
+        self.variables.lock().unwrap().remove(name)
+    }
+
+    /// All shell (unexported) variables, sorted by name
+    pub fn vars(&self) -> Vec<(String, String)> {
+        self.variables.lock().unwrap().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// Put `text` in the edit buffer of the next prompt
+    pub fn set_next_input(&self, text: &str) {
+        *self.next_input.lock().unwrap() = Some(text.to_string());
+    }
+
+    /// Take the text queued for the next prompt, if any
+    pub fn take_next_input(&self) -> Option<String> {
+        self.next_input.lock().unwrap().take()
+    }
+
+    /// Set `$0` and the positional parameters `$1..$N`
+    pub fn set_positional(&self, name: &str, args: Vec<String>) {
+        *self.positional.lock().unwrap() = (name.to_string(), args);
+    }
+
+    /// Replace the positional parameters `$1..$N`, keeping `$0`
+    pub fn set_params(&self, args: Vec<String>) {
+        self.positional.lock().unwrap().1 = args;
+    }
+
+    /// Positional parameters `$1..$N`
+    pub fn params(&self) -> Vec<String> {
+        self.positional.lock().unwrap().1.clone()
+    }
+
+    /// Look up a special parameter: `$0`, `$1..$N`, `$#`, `$@` or `$*`
+    pub fn param(&self, name: &str) -> Option<String> {
+        let positional = self.positional.lock().unwrap();
+        let (script, args) = &*positional;
+        match name {
+            "0" if script.is_empty() => Some("shell-t".to_string()),
+            "0" => Some(script.clone()),
+            "#" => Some(args.len().to_string()),
+            "?" => Some(self.last_status().to_string()),
+            "$" => Some(std::process::id().to_string()),
+            "!" => match self.last_background.load(Ordering::SeqCst) {
+                0 => None,
+                pid => Some(pid.to_string()),
+            },
+            "@" | "*" => Some(args.join(" ")),
+            _ => {
+                let index: usize = name.parse().ok()?;
+                args.get(index.checked_sub(1)?).cloned()
+            }
+        }
+    }
+
+    /// Drop the first `n` positional parameters; fails without changing
+    /// anything if there are fewer than `n`
+    pub fn shift(&self, n: usize) -> bool {
+        let args = &mut self.positional.lock().unwrap().1;
+        if n > args.len() {
+            return false;
+        }
+        args.drain(..n);
+        true
+    }
+
+    /// Language sub-REPL the interactive loop should read for, if any
+    pub fn language_mode(&self) -> Option<Language> {
+        *self.language_mode.lock().unwrap()
+    }
+
+    /// Enter a language sub-REPL, or return to shell mode with `None`
+    pub fn set_language_mode(&self, language: Option<Language>) {
+        *self.language_mode.lock().unwrap() = language;
+    }
+
+    /// Container external commands are sent into, if any
+    pub fn container(&self) -> Option<Container> {
+        self.container.lock().unwrap().clone()
+    }
+
+    /// Send external commands into a container, or run them locally again
+    /// with `None`; returns the previous setting
+    pub fn set_container(&self, container: Option<Container>) -> Option<Container> {
+        std::mem::replace(&mut *self.container.lock().unwrap(), container)
+    }
+
+    /// Options, aliases, variables, positional parameters and container as
+    /// they are now, for `restore` after a subshell
+    pub fn scope(&self) -> Scope {
+        Scope {
+            options: self.options(),
+            aliases: self.aliases.lock().unwrap().clone(),
+            variables: self.variables.lock().unwrap().clone(),
+            positional: self.positional.lock().unwrap().clone(),
+            container: self.container(),
+        }
+    }
+
+    /// Put back what `scope` saved
+    pub fn restore(&self, scope: Scope) {
+        *self.options.lock().unwrap() = scope.options;
+        *self.aliases.lock().unwrap() = scope.aliases;
+        *self.variables.lock().unwrap() = scope.variables;
+        *self.positional.lock().unwrap() = scope.positional;
+        *self.container.lock().unwrap() = scope.container;
+    }
+
+    /// State saved by the last `envdiff snapshot`, if any
+    pub fn env_snapshot(&self) -> Option<Snapshot> {
+        self.env_snapshot.lock().unwrap().clone()
+    }
+
+    /// Save `snapshot` for `envdiff show` to compare against
+    pub fn set_env_snapshot(&self, snapshot: Snapshot) {
+        *self.env_snapshot.lock().unwrap() = Some(snapshot);
+    }
+
+    /// Define or replace an alias
+    pub fn set_alias(&self, name: &str, value: &str) {
+        self.aliases.lock().unwrap().insert(name.to_string(), value.to_string());
+    }
+
+    /// Remove an alias, returning whether it existed
+    pub fn remove_alias(&self, name: &str) -> bool {
+        self.aliases.lock().unwrap().remove(name).is_some()
+    }
+
+    /// Look up a single alias
+    pub fn alias(&self, name: &str) -> Option<String> {
+        self.aliases.lock().unwrap().get(name).cloned()
+    }
+
+    /// All aliases, sorted by name
+    pub fn aliases(&self) -> Vec<(String, String)> {
+        self.aliases.lock().unwrap().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// Replace aliased program names with their definitions (one level, like `alias ll='ls -l'`)
+    pub fn expand_aliases(&self, commands: Vec<Command>) -> Vec<Command> {
+        let aliases = self.aliases.lock().unwrap();
+        if aliases.is_empty() {
+            return commands;
+        }
+
+        commands
+            .into_iter()
+            .map(|mut cmd| {
+                let expansion = aliases.get(&cmd.program).and_then(|value| parser::parse_command(value).ok());
+                if let Some([alias]) = expansion.as_deref() {
+                    cmd.program = alias.program.clone();
+                    cmd.args = alias.args.iter().cloned().chain(cmd.args).collect();
+                    // The alias's words are all where its name was typed
+                    if let Some(&span) = cmd.spans.first() {
+                        cmd.spans.splice(1..1, vec![span; alias.args.len()]);
+                    }
+                    // Patterns keep pointing at their words, now after the alias's
+                    let shifted = cmd.globs.into_iter().map(|(index, pattern)| (index + alias.args.len(), pattern));
+                    cmd.globs = alias.globs.iter().cloned().chain(shifted).collect();
+                    cmd.input_redirect = cmd.input_redirect.or_else(|| alias.input_redirect.clone());
+                    if cmd.output_redirect.is_none() {
+                        cmd.output_redirect = alias.output_redirect.clone();
+                        cmd.append = alias.append;
+                        cmd.clobber = alias.clobber;
+                    }
+                    if cmd.stderr_redirect.is_none() {
+                        cmd.stderr_redirect = alias.stderr_redirect.clone();
+                        cmd.stderr_append = alias.stderr_append;
+                    }
+                    cmd.stderr_to_stdout |= alias.stderr_to_stdout;
+                    cmd.here_doc = cmd.here_doc.or_else(|| alias.here_doc.clone());
+                    cmd.fd_redirects = alias.fd_redirects.iter().cloned().chain(cmd.fd_redirects).collect();
+                    cmd.env = alias.env.iter().cloned().chain(cmd.env).collect();
+                }
+                cmd
+            })
+            .collect()
+    }
+}
+
+/// Split a `NAME=VALUE` word, if it is one
+pub fn parse_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    script::is_name(name).then_some((name, value))
+}
+
+/// Collect the assignments of a line made only of `NAME=VALUE` words
+pub fn assignments(commands: &[Command]) -> Option<Vec<(&str, &str)>> {
+    let [command] = commands else {
+        return None;
+    };
+    if command.input_redirect.is_some() || command.output_redirect.is_some() || command.stderr_redirect.is_some() || command.stderr_to_stdout || command.here_doc.is_some() || !command.fd_redirects.is_empty() || !command.env.is_empty() {
+        return None;
+    }
+    std::iter::once(&command.program)
+        .chain(&command.args)
+        .map(|word| parse_assignment(word))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positional_params_and_shift() {
+        let state = ShellState::new();
+        assert_eq!(state.param("0").as_deref(), Some("shell-t"));
+        assert_eq!(state.param("#").as_deref(), Some("0"));
+
+        state.set_positional("build.sht", vec!["release".to_string(), "--verbose".to_string()]);
+        assert_eq!(state.param("0").as_deref(), Some("build.sht"));
+        assert_eq!(state.param("2").as_deref(), Some("--verbose"));
+        assert_eq!(state.param("3"), None);
+        assert_eq!(state.param("*").as_deref(), Some("release --verbose"));
+
+        assert!(!state.shift(3));
+        assert!(state.shift(1));
+        assert_eq!(state.param("1").as_deref(), Some("--verbose"));
+        assert_eq!(state.param("#").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_special_params() {
+        let state = ShellState::new();
+        assert_eq!((state.param("?").as_deref(), state.param("!")), (Some("0"), None));
+        state.set_last_status(127);
+        state.set_last_background(4242);
+        assert_eq!((state.param("?").as_deref(), state.param("!").as_deref()), (Some("127"), Some("4242")));
+        assert_eq!(state.param("$"), Some(std::process::id().to_string()));
+    }
+
+    #[test]
+    fn test_assignments_only_when_every_word_assigns() {
+        let commands = parser::parse_command("x=5 greeting='hello world'").unwrap();
+        assert_eq!(assignments(&commands), Some(vec![("x", "5"), ("greeting", "hello world")]));
+
+        assert!(assignments(&parser::parse_command("x=5 echo").unwrap()).is_none());
+        assert!(assignments(&parser::parse_command("1x=5").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_expand_aliases_prepends_arguments() {
+        let state = ShellState::new();
+        state.set_alias("ll", "ls -la");
+
+        let commands = state.expand_aliases(parser::parse_command("ll /tmp | wc -l").unwrap());
+        assert_eq!(commands[0].program, "ls");
+        assert_eq!(commands[0].args, vec!["-la", "/tmp"]);
+        assert_eq!(commands[1].program, "wc");
+
+        let commands = state.expand_aliases(parser::parse_command("ll *.rs").unwrap());
+        assert_eq!(commands[0].globs, vec![(1, "*.rs".to_string())]);
+    }
+
+    #[test]
+    fn test_set_option_by_short_flag() {
+        let mut options = ShellOptions::default();
+        assert!(options.set("e", true).is_ok());
+        assert!(options.errexit);
+        assert!(options.set("bogus", true).is_err());
+    }
+}
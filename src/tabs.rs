@@ -0,0 +1,243 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::editor::Recall;
+use crate::jobs::JobManager;
+
+/// Rows a pane needs to be worth splitting into
+const MIN_PANE_ROWS: u16 = 3;
+
+/// Where the commands of one pane run: its own jobs, directory and history
+pub struct Pane {
+    pub jobs: Arc<JobManager>,
+    pub cwd: PathBuf,
+    /// What Up recalls here: history from before the pane opened, then the
+    /// commands typed in it
+    pub recall: Recall,
+    /// Screen row of the cursor when the pane was last left
+    pub cursor: Option<u16>,
+}
+
+impl Pane {
+    /// A pane in `cwd` with no jobs, opened at `now` (seconds since the epoch)
+    pub fn new(jobs: Arc<JobManager>, cwd: PathBuf, now: i64) -> Self {
+        Self { jobs, cwd, recall: Recall { before: now, typed: Vec::new() }, cursor: None }
+    }
+}
+
+/// A tab: one pane, or two split horizontally
+pub struct Tab {
+    /// Set by `tab new NAME`; otherwise the tab shows its directory
+    pub name: Option<String>,
+    pub panes: Vec<Pane>,
+    pub active: usize,
+}
+
+impl Tab {
+    /// Name shown in the tab bar
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => dir_name(&self.panes[self.active].cwd),
+        }
+    }
+}
+
+/// The tabs of a session in multiplexer mode
+pub struct Tabs {
+    tabs: Vec<Tab>,
+    active: usize,
+    /// Columns and rows of the terminal when the tabs were last drawn
+    pub screen: (u16, u16),
+}
+
+impl Tabs {
+    /// Tabs holding just `first`, the session as it was
+    pub fn new(first: Pane) -> Self {
+        Self { tabs: vec![Tab { name: None, panes: vec![first], active: 0 }], active: 0, screen: (0, 0) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tabs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tabs.is_empty()
+    }
+
+    pub fn tab(&self) -> &Tab {
+        &self.tabs[self.active]
+    }
+
+    pub fn tab_mut(&mut self) -> &mut Tab {
+        &mut self.tabs[self.active]
+    }
+
+    pub fn pane(&self) -> &Pane {
+        let tab = self.tab();
+        &tab.panes[tab.active]
+    }
+
+    pub fn pane_mut(&mut self) -> &mut Pane {
+        let tab = &mut self.tabs[self.active];
+        &mut tab.panes[tab.active]
+    }
+
+    /// Open a tab after the others and make it the active one
+    pub fn add(&mut self, name: Option<String>, pane: Pane) {
+        self.tabs.push(Tab { name, panes: vec![pane], active: 0 });
+        self.active = self.tabs.len() - 1;
+    }
+
+    /// Make tab `number` (counting from 1) the active one
+    pub fn select(&mut self, number: usize) -> Result<(), String> {
+        if number == 0 || number > self.tabs.len() {
+            return Err(format!("no tab {}", number));
+        }
+        self.active = number - 1;
+        Ok(())
+    }
+
+    /// Move `offset` tabs right (left when negative), wrapping around
+    pub fn cycle(&mut self, offset: isize) {
+        let count = self.tabs.len() as isize;
+        self.active = (self.active as isize + offset).rem_euclid(count) as usize;
+    }
+
+    /// Split the active tab, `pane` going below and becoming active
+    pub fn split(&mut self, pane: Pane) -> Result<(), String> {
+        let tab = &mut self.tabs[self.active];
+        if tab.panes.len() > 1 {
+            return Err("the tab is already split".to_string());
+        }
+        tab.panes.push(pane);
+        tab.active = 1;
+        Ok(())
+    }
+
+    /// Make the other pane of a split tab the active one
+    pub fn focus(&mut self) -> Result<(), String> {
+        let tab = &mut self.tabs[self.active];
+        if tab.panes.len() < 2 {
+            return Err("the tab isn't split".to_string());
+        }
+        tab.active = 1 - tab.active;
+        Ok(())
+    }
+
+    /// Close the active pane, and its tab with it if that was the only one
+    pub fn close(&mut self) -> Pane {
+        let tab = &mut self.tabs[self.active];
+        let pane = tab.panes.remove(tab.active);
+        tab.active = 0;
+        if tab.panes.is_empty() {
+            self.tabs.remove(self.active);
+            self.active = self.active.min(self.tabs.len().saturating_sub(1));
+        }
+        pane
+    }
+
+    /// Tab bar for a `width`-column screen: `1:src  [2:logs]  3:build`
+    pub fn bar(&self, width: usize) -> String {
+        let bar = self
+            .tabs
+            .iter()
+            .enumerate()
+            .map(|(index, tab)| match index == self.active {
+                true => format!("[{}:{}]", index + 1, tab.label()),
+                false => format!(" {}:{} ", index + 1, tab.label()),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let bar: String = bar.chars().take(width).collect();
+        format!("{:width$}", bar)
+    }
+
+    /// One line per tab for `tab list`: number, name, directory, jobs and panes
+    pub fn list(&self) -> Vec<String> {
+        self.tabs
+            .iter()
+            .enumerate()
+            .map(|(index, tab)| {
+                let marker = if index == self.active { '*' } else { ' ' };
+                let jobs: usize = tab.panes.iter().map(|pane| pane.jobs.active()).sum();
+                let mut line = format!("{}{:>2}  {:<12}  {}", marker, index + 1, tab.label(), tab.panes[tab.active].cwd.display());
+                if tab.panes.len() > 1 {
+                    line.push_str("  (split)");
+                }
+                if jobs > 0 {
+                    line.push_str(&format!("  [{} job{}]", jobs, if jobs == 1 { "" } else { "s" }));
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+/// First and last row of each of `panes` panes on a screen `rows` high,
+/// below the tab bar; a split leaves a divider row between them. `None` if
+/// they don't fit.
+pub fn regions(rows: u16, panes: usize) -> Option<Vec<(u16, u16)>> {
+    let content = rows.checked_sub(1)?;
+    match panes {
+        1 if content >= 1 => Some(vec![(1, rows - 1)]),
+        2 if content > 2 * MIN_PANE_ROWS => {
+            let upper = (content - 1) / 2;
+            Some(vec![(1, upper), (upper + 2, rows - 1)])
+        }
+        _ => None,
+    }
+}
+
+/// Last component of `dir`, or `/`
+fn dir_name(dir: &Path) -> String {
+    dir.file_name().map_or_else(|| dir.display().to_string(), |name| name.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pane(dir: &str) -> Pane {
+        Pane::new(Arc::new(JobManager::new()), PathBuf::from(dir), 0)
+    }
+
+    #[test]
+    fn test_tabs_open_switch_split_and_close() {
+        let mut tabs = Tabs::new(pane("/home/user/src"));
+        tabs.add(Some("logs".to_string()), pane("/var/log"));
+        assert_eq!(tabs.bar(30), " 1:src  [2:logs]              ");
+        assert_eq!(tabs.bar(8), " 1:src  ");
+
+        tabs.cycle(1);
+        assert_eq!(tabs.pane().cwd, Path::new("/home/user/src"));
+        assert!(tabs.select(3).is_err() && tabs.select(0).is_err());
+        tabs.select(2).unwrap();
+        tabs.cycle(-1);
+        assert_eq!(tabs.tab().label(), "src");
+
+        assert!(tabs.focus().is_err());
+        tabs.split(pane("/")).unwrap();
+        assert!(tabs.split(pane("/tmp")).is_err());
+        assert_eq!(tabs.tab().label(), "/");
+        tabs.focus().unwrap();
+        assert_eq!(tabs.pane().cwd, Path::new("/home/user/src"));
+        assert!(tabs.list()[0].starts_with("* 1  src") && tabs.list()[0].ends_with("(split)"));
+
+        assert_eq!(tabs.close().cwd, Path::new("/home/user/src"));
+        assert_eq!(tabs.pane().cwd, Path::new("/"));
+        tabs.close();
+        assert_eq!((tabs.len(), tabs.tab().label().as_str()), (1, "logs"));
+        tabs.close();
+        assert!(tabs.is_empty());
+    }
+
+    #[test]
+    fn test_regions_leave_room_for_bar_and_divider() {
+        assert_eq!(regions(24, 1), Some(vec![(1, 23)]));
+        assert_eq!(regions(24, 2), Some(vec![(1, 11), (13, 23)]));
+        assert_eq!(regions(25, 2), Some(vec![(1, 11), (13, 24)]));
+        assert_eq!(regions(7, 2), None);
+        assert_eq!(regions(1, 1), None);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, TelemetryConfig};
+use crate::error::ShellError;
+
+/// A single recorded failure; only the error code and program name are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailureRecord {
+    pub time: DateTime<Utc>,
+    pub code: String,
+    pub program: String,
+}
+
+/// Aggregated counts persisted between sessions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TelemetryData {
+    pub error_counts: BTreeMap<String, u64>,
+    pub failed_commands: BTreeMap<String, u64>,
+    pub recent: VecDeque<FailureRecord>,
+}
+
+/// Local-only error telemetry; nothing is recorded unless enabled in config
+pub struct Telemetry {
+    enabled: bool,
+    path: Option<PathBuf>,
+    max_recent: usize,
+    data: Mutex<TelemetryData>,
+}
+
+static TELEMETRY: OnceLock<Telemetry> = OnceLock::new();
+
+/// Install the session-wide telemetry store; later calls are ignored
+pub fn init(config: &TelemetryConfig) {
+    let _ = TELEMETRY.set(Telemetry::new(config));
+}
+
+/// The session-wide telemetry store, disabled if not initialized
+pub fn global() -> &'static Telemetry {
+    TELEMETRY.get_or_init(|| Telemetry::new(&TelemetryConfig::default()))
+}
+
+impl Telemetry {
+    /// Create a telemetry store, loading previously persisted counts
+    pub fn new(config: &TelemetryConfig) -> Self {
+        let path = config
+            .file
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(|| config::data_dir().map(|d| d.join("telemetry.json")));
+        let data = match (&path, config.enabled) {
+            (Some(path), true) => load(path),
+            _ => TelemetryData::default(),
+        };
+
+        Self {
+            enabled: config.enabled,
+            path,
+            max_recent: config.max_recent,
+            data: Mutex::new(data),
+        }
+    }
+
+    /// Whether telemetry is being collected
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record a failed command line
+    pub fn record_error(&self, error: &ShellError, input: &str) {
+        if !self.enabled {
+            return;
+        }
+
+        let program = anonymize_program(input);
+        let code = error.code().to_string();
+        let mut data = self.data.lock().unwrap();
+
+        *data.error_counts.entry(code.clone()).or_insert(0) += 1;
+        *data.failed_commands.entry(program.clone()).or_insert(0) += 1;
+        data.recent.push_back(FailureRecord { time: Utc::now(), code, program });
+        while data.recent.len() > self.max_recent {
+            data.recent.pop_front();
+        }
+
+        if let Some(path) = &self.path {
+            if let Err(e) = save(path, &data) {
+                tracing::debug!(error = %e, "failed to persist telemetry");
+            }
+        }
+    }
+
+    /// Snapshot of the collected data
+    pub fn snapshot(&self) -> TelemetryData {
+        self.data.lock().unwrap().clone()
+    }
+}
+
+/// Reduce an input line to the bare program name. Leading `NAME=VALUE`
+/// words are skipped so their values aren't recorded; a line of nothing but
+/// assignments is `<assignment>`.
+fn anonymize_program(input: &str) -> String {
+    let mut words = input.split_whitespace().peekable();
+    if words.peek().is_none() {
+        return "<empty>".to_string();
+    }
+    let is_assignment = |word: &&str| word.split_once('=').is_some_and(|(name, _)| crate::script::is_name(name));
+    let Some(first) = words.find(|word| !is_assignment(word)) else {
+        return "<assignment>".to_string();
+    };
+    Path::new(first)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "<empty>".to_string())
+}
+
+fn load(path: &Path) -> TelemetryData {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save(path: &Path, data: &TelemetryData) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymize_program_drops_arguments_and_paths() {
+        assert_eq!(anonymize_program("/usr/bin/grep secret-token file"), "grep");
+        assert_eq!(anonymize_program("   "), "<empty>");
+        // Values of env prefixes and assignments never reach the program name
+        assert_eq!(anonymize_program("TOKEN=hunter2 API_KEY=abc curl https://x"), "curl");
+        assert_eq!(anonymize_program("password=hunter2"), "<assignment>");
+    }
+
+    #[test]
+    fn test_disabled_telemetry_records_nothing() {
+        let telemetry = Telemetry::new(&TelemetryConfig::default());
+        telemetry.record_error(&ShellError::Parse("bad".to_string()), "ls |");
+        assert!(telemetry.snapshot().recent.is_empty());
+    }
+
+    #[test]
+    fn test_enabled_telemetry_aggregates_counts() {
+        let path = std::env::temp_dir().join(format!("shell-t-telemetry-{}.json", std::process::id()));
+        let config = TelemetryConfig {
+            enabled: true,
+            file: Some(path.display().to_string()),
+            max_recent: 2,
+        };
+        let telemetry = Telemetry::new(&config);
+
+        for _ in 0..3 {
+            telemetry.record_error(&ShellError::Parse("bad".to_string()), "cat <");
+        }
+
+        let data = telemetry.snapshot();
+        assert_eq!(data.error_counts.get("ST0101"), Some(&3));
+        assert_eq!(data.failed_commands.get("cat"), Some(&3));
+        assert_eq!(data.recent.len(), 2);
+        assert!(path.exists());
+
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,280 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, TrashConfig};
+
+/// A file or directory moved to the trash by `rm` under safe delete
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: usize,
+    /// Absolute path it was deleted from, and is restored to
+    pub original: PathBuf,
+    pub deleted: DateTime<Local>,
+    /// Bytes it takes up, counted when it was deleted
+    pub size: u64,
+}
+
+/// The trash: an index of entries, each kept under `files/ID` next to it
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Trash {
+    #[serde(default)]
+    pub entries: Vec<Entry>,
+    #[serde(skip)]
+    dir: PathBuf,
+    /// Held from load until dropped, so another shell's `rm` or `restore`
+    /// can't change the index in between
+    #[serde(skip)]
+    lock: Option<File>,
+}
+
+impl Trash {
+    /// Directory the trash is kept in
+    pub fn dir() -> Option<PathBuf> {
+        config::data_dir().map(|dir| dir.join("trash"))
+    }
+
+    /// The trash kept in `dir`, locked against other shells until dropped;
+    /// empty if it is missing or unreadable
+    pub fn load(dir: &Path) -> Self {
+        let lock = lock(dir).map_err(|e| tracing::warn!("Can't lock trash index in {}: {}", dir.display(), e)).ok();
+        let index = dir.join("index.toml");
+        let mut trash = match fs::read_to_string(&index) {
+            Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring trash index {}: {}", index.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        trash.dir = dir.to_path_buf();
+        trash.lock = lock;
+        trash
+    }
+
+    /// Write the index, replacing it in one step
+    pub fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let text = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let index = self.dir.join("index.toml");
+        let partial = index.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&partial, text)?;
+        fs::rename(&partial, index)
+    }
+
+    /// Where entry `id` is kept
+    fn item(&self, id: usize) -> PathBuf {
+        self.dir.join("files").join(id.to_string())
+    }
+
+    /// Move `path` into the trash, returning its entry id. Refuses anything
+    /// larger than `trash.max_size_mb`, which purging could never make room for.
+    pub fn put(&mut self, path: &Path, config: &TrashConfig) -> io::Result<usize> {
+        let original = std::path::absolute(path)?;
+        let size = size_of(&original)?;
+        if size > config.max_size_mb * 1024 * 1024 {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!("larger than the trash ({} MB)", config.max_size_mb),
+            ));
+        }
+        let mut id = self.entries.iter().map(|entry| entry.id).max().unwrap_or(0) + 1;
+        // Skip leftovers of an index that was lost
+        while fs::symlink_metadata(self.item(id)).is_ok() {
+            id += 1;
+        }
+        let item = self.item(id);
+        fs::create_dir_all(self.dir.join("files"))?;
+        move_path(&original, &item)?;
+        self.entries.push(Entry { id, original, deleted: Local::now(), size });
+        Ok(id)
+    }
+
+    /// Move entry `id` back where it was deleted from, returning that path.
+    /// Refuses to replace anything created there since.
+    pub fn restore(&mut self, id: usize) -> io::Result<PathBuf> {
+        let index = self.entries.iter().position(|entry| entry.id == id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no such entry")
+        })?;
+        let original = self.entries[index].original.clone();
+        if fs::symlink_metadata(&original).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists", original.display())));
+        }
+        if let Some(parent) = original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(&self.item(id), &original)?;
+        self.entries.remove(index);
+        Ok(original)
+    }
+
+    /// Delete entries older than `trash.max_age_days`, then the oldest until
+    /// the rest fit in `trash.max_size_mb`, never touching the entries in
+    /// `keep`; returns how many went
+    pub fn purge(&mut self, config: &TrashConfig, keep: &[usize]) -> usize {
+        let cutoff = Local::now() - Duration::days(config.max_age_days as i64);
+        let limit = config.max_size_mb * 1024 * 1024;
+        self.entries.sort_by_key(|entry| entry.deleted);
+        let mut total: u64 = self.entries.iter().map(|entry| entry.size).sum();
+        let mut expired = Vec::new();
+        for entry in &self.entries {
+            if entry.deleted >= cutoff && total <= limit {
+                break;
+            }
+            if keep.contains(&entry.id) {
+                continue;
+            }
+            total -= entry.size;
+            expired.push(entry.id);
+        }
+        for &id in &expired {
+            if let Err(e) = remove(&self.item(id)) {
+                tracing::warn!(id, "can't purge trash entry: {}", e);
+            }
+        }
+        self.entries.retain(|entry| !expired.contains(&entry.id));
+        expired.len()
+    }
+
+    /// Delete every entry for good, returning how many there were
+    pub fn empty(&mut self) -> io::Result<usize> {
+        let count = self.entries.len();
+        let files = self.dir.join("files");
+        if files.exists() {
+            fs::remove_dir_all(files)?;
+        }
+        self.entries.clear();
+        Ok(count)
+    }
+}
+
+/// Open `index.lock` in `dir` and take an exclusive lock on it, waiting for
+/// any other shell holding it. The index itself is replaced on each save, so
+/// the lock lives in a file of its own.
+fn lock(dir: &Path) -> io::Result<File> {
+    fs::create_dir_all(dir)?;
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(dir.join("index.lock"))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// `size` bytes for `trash list`: `512 B`, `1.5 KB`, `20.0 MB`
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Rename `from` to `to`, copying and deleting when they are on different
+/// filesystems
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy(from, to)?;
+            remove(from)
+        }
+        result => result,
+    }
+}
+
+fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, metadata.permissions())
+    } else if metadata.file_type().is_symlink() {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(fs::read_link(from)?, to);
+        #[cfg(not(unix))]
+        return fs::copy(from, to).map(|_| ());
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Bytes taken up by `path` and, for a directory, everything in it
+fn size_of(path: &Path) -> io::Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += size_of(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_put_restore_and_purge() {
+        let root = std::env::temp_dir().join(format!("shell-t-trash-{}", std::process::id()));
+        let work = root.join("work");
+        fs::create_dir_all(work.join("build")).unwrap();
+        fs::write(work.join("notes.txt"), "keep me").unwrap();
+        fs::write(work.join("build").join("out.o"), [0; 100]).unwrap();
+
+        let config = TrashConfig::default();
+        let mut trash = Trash::load(&root.join("trash"));
+        let notes = trash.put(&work.join("notes.txt"), &config).unwrap();
+        let build = trash.put(&work.join("build"), &config).unwrap();
+        assert!(!work.join("notes.txt").exists() && !work.join("build").exists());
+        assert_eq!(trash.entries[1].size, 100);
+        assert_eq!((format_size(100), format_size(1536), format_size(20 << 20)), ("100 B".into(), "1.5 KB".into(), "20.0 MB".into()));
+        trash.save().unwrap();
+        // Until it is dropped, no other shell can load the trash
+        let lock = File::open(root.join("trash").join("index.lock")).unwrap();
+        assert!(lock.try_lock().is_err());
+        drop(trash);
+        lock.try_lock().unwrap();
+        lock.unlock().unwrap();
+
+        let mut trash = Trash::load(&root.join("trash"));
+        assert_eq!(trash.restore(notes).unwrap(), work.join("notes.txt"));
+        assert_eq!(fs::read_to_string(work.join("notes.txt")).unwrap(), "keep me");
+        assert!(trash.restore(notes).is_err());
+
+        // Something new in the way stops a restore
+        fs::create_dir(work.join("build")).unwrap();
+        assert_eq!(trash.restore(build).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+
+        let config = TrashConfig { max_age_days: 30, max_size_mb: 0, ..TrashConfig::default() };
+        // What was just trashed is kept even past the limits
+        assert_eq!(trash.purge(&config, &[build]), 0);
+        assert!(trash.item(build).exists());
+        assert_eq!(trash.purge(&config, &[]), 1);
+        assert!(trash.entries.is_empty() && !trash.item(build).exists());
+
+        // Something that can never fit is refused and left in place
+        fs::remove_dir(work.join("build")).unwrap();
+        let err = trash.put(&work.join("notes.txt"), &config).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+        assert!(work.join("notes.txt").exists() && trash.entries.is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
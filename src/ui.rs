@@ -1,9 +1,18 @@
-use std::io::{self, Write};
-use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
-use crossterm::ExecutableCommand;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crossterm::cursor::{MoveTo, RestorePosition, SavePosition};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{ExecutableCommand, QueueableCommand};
 
 use crate::config::Config;
-use crate::error::ShellResult;
+use crate::error::{ShellError, ShellResult};
+
+/// The shell's own prompt, after any segments
+const PROMPT: &str = "shell-t> ";
+
+/// Rows of the pane the shell is drawing in, or 0 outside multiplexer mode
+static PANE_ROWS: AtomicUsize = AtomicUsize::new(0);
 
 /// Terminal UI manager
 pub struct UiManager {
@@ -16,14 +25,19 @@ impl UiManager {
         Self { config }
     }
 
-    /// Display the shell prompt
-    pub fn display_prompt(&self) -> ShellResult<()> {
-        if self.config.ui.enable_colors {
-            self.display_colored_prompt()?;
-        } else {
+    /// Whether to color what goes to stderr: only when enabled and a
+    /// terminal shows it, not a file or pipe
+    fn stderr_colors(&self) -> bool {
+        self.config.ui.enable_colors && io::stderr().is_terminal()
+    }
+
+    /// Display the shell prompt, returning its width in columns
+    pub fn display_prompt(&self) -> ShellResult<usize> {
+        // Fall back to the plain prompt if the terminal rejects styling
+        if !self.config.ui.enable_colors || self.display_colored_prompt().is_err() {
             self.display_plain_prompt()?;
         }
-        Ok(())
+        Ok(PROMPT.len())
     }
 
     /// Display colored prompt
@@ -41,7 +55,7 @@ impl UiManager {
 
         io::stdout()
             .execute(SetForegroundColor(color))?
-            .execute(Print("shell-t> "))?
+            .execute(Print(PROMPT))?
             .execute(ResetColor)?;
 
         io::stdout().flush()?;
@@ -50,8 +64,56 @@ impl UiManager {
 
     /// Display plain text prompt
     fn display_plain_prompt(&self) -> ShellResult<()> {
-        print!("shell-t> ");
+        print!("{}", PROMPT);
+        io::stdout().flush()?;
+        Ok(())
+    }
+
+    /// Display the prompt after custom segments (e.g. from Lua scripts),
+    /// returning its width in columns
+    pub fn display_prompt_with_segments(&self, segments: &[String]) -> ShellResult<usize> {
+        let mut width = 0;
+        if !segments.is_empty() {
+            let lead = segments.join(" ");
+            print!("{} ", lead);
+            width = lead.chars().count() + 1;
+        }
+        Ok(width + self.display_prompt()?)
+    }
+
+    /// Display the prompt shown while a compound command is still open,
+    /// returning its width in columns
+    pub fn display_continuation_prompt(&self) -> ShellResult<usize> {
+        print!("> ");
+        io::stdout().flush()?;
+        Ok(2)
+    }
+
+    /// Display the prompt of a language sub-REPL, or its continuation prompt,
+    /// returning its width in columns
+    pub fn display_language_prompt(&self, language: &str, continuation: bool) -> ShellResult<usize> {
+        if continuation {
+            print!("{:>width$} ", "...", width = language.len() + 1);
+        } else {
+            print!("{}> ", language);
+        }
         io::stdout().flush()?;
+        Ok(language.len() + 2)
+    }
+
+    /// Display where the debugger stopped: the location, then the statement with line numbers
+    pub fn display_debug_stop(&self, location: &str, line: usize, statement: &str) -> ShellResult<()> {
+        if self.config.ui.enable_colors {
+            io::stdout()
+                .execute(SetForegroundColor(Color::Yellow))?
+                .execute(Print(format!("→ {}\n", location)))?
+                .execute(ResetColor)?;
+        } else {
+            println!("→ {}", location);
+        }
+        for (offset, text) in statement.lines().enumerate() {
+            println!("{:>5}  {}", line + offset, text);
+        }
         Ok(())
     }
 
@@ -81,15 +143,53 @@ impl UiManager {
         Ok(())
     }
 
+    /// Display an error together with the offending part of the input line
+    pub fn display_error_context(&self, error: &ShellError) -> ShellResult<()> {
+        eprintln!("Error[{}]: {}", error.code(), error);
+        if let Some(backtrace) = error.backtrace() {
+            eprintln!("{}", backtrace);
+        }
+
+        let ctx = match error.context() {
+            Some(ctx) => ctx,
+            None => return Ok(()),
+        };
+        let (source, span) = match (&ctx.source, ctx.span) {
+            (Some(source), Some(span)) => (source, span),
+            _ => return Ok(()),
+        };
+
+        let start = source[..span.start.min(source.len())].chars().count();
+        let width = source.get(span.start..span.end).map_or(1, |s| s.chars().count().max(1));
+        let marker = format!("  {}{}", " ".repeat(start), "^".repeat(width));
+
+        eprintln!("  {}", source);
+        if self.stderr_colors() {
+            io::stderr()
+                .execute(SetForegroundColor(Color::Red))?
+                .execute(Print(format!("{}\n", marker)))?
+                .execute(ResetColor)?;
+        } else {
+            eprintln!("{}", marker);
+        }
+        Ok(())
+    }
+
     /// Display a warning message
     pub fn display_warning(&self, message: &str) -> ShellResult<()> {
-        if self.config.ui.enable_colors {
-            io::stdout()
+        let message = if self.config.ui.show_timestamps {
+            format!("[{}] {}", chrono::Utc::now().format("%H:%M:%S"), message)
+        } else {
+            message.to_string()
+        };
+
+        if self.stderr_colors() {
+            io::stderr()
                 .execute(SetForegroundColor(Color::Yellow))?
                 .execute(Print(format!("⚠ {}\n", message)))?
                 .execute(ResetColor)?;
         } else {
-            println!("⚠ {}", message);
+            eprintln!("⚠ {}", message);
         }
         Ok(())
     }
@@ -119,25 +219,115 @@ impl UiManager {
 
     /// Clear the screen
     pub fn clear_screen(&self) -> ShellResult<()> {
-        print!("\x1B[2J\x1B[1;1H");
-        io::stdout().flush()?;
+        io::stdout().execute(Clear(ClearType::All))?.execute(MoveTo(0, 0))?;
         Ok(())
     }
 
     /// Move cursor to position
     pub fn move_cursor(&self, x: u16, y: u16) -> ShellResult<()> {
-        use crossterm::cursor::MoveTo;
         io::stdout().execute(MoveTo(x, y))?;
         Ok(())
     }
 
+    /// Row the cursor is on, asked of the terminal
+    pub fn cursor_row(&self) -> ShellResult<u16> {
+        Ok(crossterm::cursor::position()?.1)
+    }
+
+    /// Switch to the alternate screen for tabs and panes
+    pub fn enter_tabs(&self) -> ShellResult<()> {
+        io::stdout().execute(EnterAlternateScreen)?.execute(Clear(ClearType::All))?;
+        Ok(())
+    }
+
+    /// Give scrolling back to the whole screen and return to the normal one
+    pub fn leave_tabs(&self) -> ShellResult<()> {
+        set_pane_rows(None);
+        io::stdout().execute(Print("\x1b[r"))?.execute(LeaveAlternateScreen)?;
+        Ok(())
+    }
+
+    /// Draw the tab bar on the top row and a divider on each of `dividers`,
+    /// then confine scrolling to the rows of the active pane, `region`. The
+    /// cursor goes back where it was, or to the start of row `cursor`.
+    pub fn draw_tabs(&self, bar: &str, dividers: &[u16], region: (u16, u16), cursor: Option<u16>) -> ShellResult<()> {
+        let (width, _) = terminal_size();
+        let mut out = io::stdout();
+        out.queue(SavePosition)?.queue(MoveTo(0, 0))?;
+        if self.config.ui.enable_colors {
+            out.queue(SetAttribute(Attribute::Reverse))?.queue(Print(bar))?.queue(SetAttribute(Attribute::Reset))?;
+        } else {
+            out.queue(Print(bar))?;
+        }
+        for &divider in dividers {
+            out.queue(MoveTo(0, divider))?.queue(Print("─".repeat(width)))?;
+        }
+        // Setting the scroll region homes the cursor
+        out.queue(Print(format!("\x1b[{};{}r", region.0 + 1, region.1 + 1)))?.queue(RestorePosition)?;
+        if let Some(row) = cursor {
+            out.queue(MoveTo(0, row))?;
+        }
+        out.flush()?;
+        set_pane_rows(Some((region.1 - region.0 + 1) as usize));
+        Ok(())
+    }
+
+    /// Blank rows `top` to `bottom` and put the cursor on `top`
+    pub fn clear_rows(&self, top: u16, bottom: u16) -> ShellResult<()> {
+        let mut out = io::stdout();
+        for row in top..=bottom {
+            out.queue(MoveTo(0, row))?.queue(Clear(ClearType::CurrentLine))?;
+        }
+        out.queue(MoveTo(0, top))?.flush()?;
+        Ok(())
+    }
+
     /// Get terminal size
     pub fn get_terminal_size(&self) -> ShellResult<(u16, u16)> {
         use crossterm::terminal::size;
-        size().map_err(|e| crate::error::ShellError::Io(e))
+        size().map_err(crate::error::ShellError::Io)
+    }
+}
+
+/// Get the terminal ready for an interactive session. On Windows this turns
+/// on escape sequence processing, so the colors and cursor movement of
+/// programs run from the shell work in Windows Terminal and ConPTY consoles;
+/// crossterm falls back to console API calls for the shell's own output
+/// where it can't be turned on.
+#[cfg(windows)]
+pub fn prepare_terminal() {
+    if !crossterm::ansi_support::supports_ansi() {
+        tracing::debug!("console has no escape sequence support");
     }
 }
 
+#[cfg(not(windows))]
+pub fn prepare_terminal() {}
+
+/// Columns and rows of the terminal, assuming 80x24 when it doesn't say
+/// (a pseudo-terminal may report a size of zero). In multiplexer mode the
+/// rows are those of the active pane.
+pub fn terminal_size() -> (usize, usize) {
+    let (columns, rows) = match crossterm::terminal::size() {
+        Ok((columns, rows)) if columns > 0 && rows > 0 => (columns as usize, rows as usize),
+        _ => (80, 24),
+    };
+    match PANE_ROWS.load(Ordering::Relaxed) {
+        0 => (columns, rows),
+        pane => (columns, pane.min(rows)),
+    }
+}
+
+/// Whether the shell is drawing in a pane of the multiplexer, on the
+/// alternate screen
+pub fn in_pane() -> bool {
+    PANE_ROWS.load(Ordering::Relaxed) > 0
+}
+
+fn set_pane_rows(rows: Option<usize>) {
+    PANE_ROWS.store(rows.unwrap_or(0), Ordering::Relaxed);
+}
+
 /// Progress indicator for long-running operations
 pub struct ProgressIndicator {
     message: String,
@@ -180,16 +370,14 @@ impl ProgressIndicator {
 pub struct TableFormatter {
     headers: Vec<String>,
     rows: Vec<Vec<String>>,
-    ui: UiManager,
 }
 
 impl TableFormatter {
     /// Create a new table formatter
-    pub fn new(headers: Vec<String>, ui: UiManager) -> Self {
+    pub fn new(headers: Vec<String>) -> Self {
         Self {
             headers,
             rows: Vec::new(),
-            ui,
         }
     }
 
@@ -272,7 +460,7 @@ impl InputReader {
         let input = input.trim().to_string();
 
         if !input.is_empty() &&
-           self.history.last().map_or(true, |last| last != &input) {
+           self.history.last() != Some(&input) {
             self.history.push(input.clone());
             self.history_index = self.history.len();
         }
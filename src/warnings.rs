@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+use crate::config::Config;
+use crate::ui::UiManager;
+
+/// Severity of a warning
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WarningLevel {
+    Notice,
+    Warning,
+    Critical,
+}
+
+/// Area a warning belongs to, used for suppression
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningCategory {
+    Config,
+    Security,
+    Interpreter,
+    Resource,
+    Builtin,
+}
+
+impl WarningLevel {
+    /// Convert string to warning level
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "notice" => Some(WarningLevel::Notice),
+            "warning" | "warn" => Some(WarningLevel::Warning),
+            "critical" => Some(WarningLevel::Critical),
+            _ => None,
+        }
+    }
+}
+
+impl WarningCategory {
+    /// Convert string to warning category
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "config" => Some(WarningCategory::Config),
+            "security" => Some(WarningCategory::Security),
+            "interpreter" => Some(WarningCategory::Interpreter),
+            "resource" => Some(WarningCategory::Resource),
+            "builtin" => Some(WarningCategory::Builtin),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WarningCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarningCategory::Config => write!(f, "config"),
+            WarningCategory::Security => write!(f, "security"),
+            WarningCategory::Interpreter => write!(f, "interpreter"),
+            WarningCategory::Resource => write!(f, "resource"),
+            WarningCategory::Builtin => write!(f, "builtin"),
+        }
+    }
+}
+
+static WARNINGS: OnceLock<WarningManager> = OnceLock::new();
+
+/// Install the session-wide warning manager; later calls are ignored
+pub fn init(config: Config) {
+    let _ = WARNINGS.set(WarningManager::new(config));
+}
+
+/// The session-wide warning manager, falling back to defaults if not initialized
+pub fn global() -> &'static WarningManager {
+    WARNINGS.get_or_init(|| WarningManager::new(Config::default()))
+}
+
+/// Emit a warning through the session-wide manager
+pub fn warn(level: WarningLevel, category: WarningCategory, message: &str) {
+    global().warn(level, category, message);
+}
+
+/// Emit a warning at most once per session through the session-wide manager
+pub fn warn_once(level: WarningLevel, category: WarningCategory, message: &str) {
+    global().warn_once(level, category, message);
+}
+
+/// Central sink for warnings, rendered through the UI manager
+pub struct WarningManager {
+    ui: UiManager,
+    min_level: WarningLevel,
+    suppressed: Mutex<HashSet<WarningCategory>>,
+    seen: Mutex<HashSet<(WarningCategory, String)>>,
+}
+
+impl WarningManager {
+    /// Create a warning manager using the warning settings from `config`
+    pub fn new(config: Config) -> Self {
+        let min_level = WarningLevel::from_name(&config.warnings.min_level).unwrap_or(WarningLevel::Notice);
+        let suppressed = config
+            .warnings
+            .suppressed
+            .iter()
+            .filter_map(|c| WarningCategory::from_name(c))
+            .collect();
+
+        Self {
+            ui: UiManager::new(config),
+            min_level,
+            suppressed: Mutex::new(suppressed),
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Silence all warnings in a category for the rest of the session
+    pub fn suppress(&self, category: WarningCategory) {
+        self.suppressed.lock().unwrap().insert(category);
+    }
+
+    /// Check whether a warning would be shown
+    pub fn is_enabled(&self, level: WarningLevel, category: WarningCategory) -> bool {
+        level >= self.min_level && !self.suppressed.lock().unwrap().contains(&category)
+    }
+
+    /// Emit a warning every time it is raised
+    pub fn warn(&self, level: WarningLevel, category: WarningCategory, message: &str) {
+        if !self.is_enabled(level, category) {
+            return;
+        }
+
+        let text = match level {
+            WarningLevel::Critical => format!("[{}] critical: {}", category, message),
+            _ => format!("[{}] {}", category, message),
+        };
+        if self.ui.display_warning(&text).is_err() {
+            eprintln!("Warning: {}", text);
+        }
+    }
+
+    /// Emit a warning at most once per session
+    pub fn warn_once(&self, level: WarningLevel, category: WarningCategory, message: &str) {
+        let first = self.seen.lock().unwrap().insert((category, message.to_string()));
+        if first {
+            self.warn(level, category, message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiet_config() -> Config {
+        let mut config = Config::default();
+        config.ui.enable_colors = false;
+        config
+    }
+
+    #[test]
+    fn test_level_and_category_parsing() {
+        assert_eq!(WarningLevel::from_name("WARN"), Some(WarningLevel::Warning));
+        assert_eq!(WarningCategory::from_name("interpreter"), Some(WarningCategory::Interpreter));
+        assert_eq!(WarningCategory::from_name("bogus"), None);
+    }
+
+    #[test]
+    fn test_suppressed_category_is_disabled() {
+        let mut config = quiet_config();
+        config.warnings.suppressed.insert("security".to_string());
+        let manager = WarningManager::new(config);
+
+        assert!(!manager.is_enabled(WarningLevel::Critical, WarningCategory::Security));
+        assert!(manager.is_enabled(WarningLevel::Notice, WarningCategory::Config));
+
+        manager.suppress(WarningCategory::Config);
+        assert!(!manager.is_enabled(WarningLevel::Notice, WarningCategory::Config));
+    }
+
+    #[test]
+    fn test_min_level_filters_lower_levels() {
+        let mut config = quiet_config();
+        config.warnings.min_level = "critical".to_string();
+        let manager = WarningManager::new(config);
+
+        assert!(!manager.is_enabled(WarningLevel::Warning, WarningCategory::Resource));
+        assert!(manager.is_enabled(WarningLevel::Critical, WarningCategory::Resource));
+    }
+
+    #[test]
+    fn test_warn_once_records_message() {
+        let manager = WarningManager::new(quiet_config());
+        manager.warn_once(WarningLevel::Notice, WarningCategory::Config, "only once");
+        manager.warn_once(WarningLevel::Notice, WarningCategory::Config, "only once");
+        assert_eq!(manager.seen.lock().unwrap().len(), 1);
+    }
+}